@@ -0,0 +1,12 @@
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    println!("cargo:rerun-if-changed=migrations");
+    
+    #[cfg(feature = "grpc")]
+    {
+        println!("cargo:rerun-if-changed=proto/ingest.proto");
+        std::env::set_var("PROTOC", protoc_bin_vendored::protoc_bin_path()?);
+        tonic_build::compile_protos("proto/ingest.proto")?;
+    }
+    
+    Ok(())
+}
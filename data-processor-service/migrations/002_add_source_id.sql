@@ -0,0 +1,6 @@
+-- Migration: Add source_id to sensor_readings
+-- Description: Records which producer published each reading
+
+ALTER TABLE sensor_readings ADD COLUMN IF NOT EXISTS source_id VARCHAR(255);
+
+CREATE INDEX IF NOT EXISTS idx_sensor_readings_source_id ON sensor_readings(source_id);
@@ -0,0 +1,8 @@
+-- Migration: Consumer checkpoints
+-- Description: Last processed stream offset per queue, used to resume after a restart
+
+CREATE TABLE IF NOT EXISTS consumer_checkpoints (
+    queue_name VARCHAR(255) PRIMARY KEY,
+    stream_offset BIGINT NOT NULL,
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
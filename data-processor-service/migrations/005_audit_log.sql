@@ -0,0 +1,14 @@
+-- Migration: Audit log
+-- Description: Who triggered which operational action, with what parameters and outcome
+
+CREATE TABLE IF NOT EXISTS audit_log (
+    id BIGSERIAL PRIMARY KEY,
+    principal VARCHAR(255) NOT NULL,
+    action VARCHAR(100) NOT NULL,
+    parameters JSONB NOT NULL DEFAULT '{}',
+    outcome TEXT NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_audit_log_created_at ON audit_log(created_at);
+CREATE INDEX IF NOT EXISTS idx_audit_log_principal ON audit_log(principal);
@@ -0,0 +1,4 @@
+-- Migration: Add payload_hash to sensor_readings
+-- Description: SHA-256 of the canonicalized payload, recorded when database.payload_hashing is on
+
+ALTER TABLE sensor_readings ADD COLUMN IF NOT EXISTS payload_hash CHAR(64);
@@ -0,0 +1,16 @@
+-- Migration: Add reading location to sensor_readings
+-- Description: Optional WGS84 coordinates taken from the payload or processing.sensor_locations.
+-- Plain columns rather than PostGIS so the stock postgres image keeps working; radius
+-- queries prefilter on the bounding-box index and compute great-circle distance.
+
+ALTER TABLE sensor_readings ADD COLUMN IF NOT EXISTS latitude DOUBLE PRECISION;
+ALTER TABLE sensor_readings ADD COLUMN IF NOT EXISTS longitude DOUBLE PRECISION;
+
+ALTER TABLE sensor_readings ADD CONSTRAINT sensor_readings_location_range CHECK (
+    (latitude IS NULL AND longitude IS NULL)
+    OR (latitude BETWEEN -90 AND 90 AND longitude BETWEEN -180 AND 180)
+);
+
+CREATE INDEX IF NOT EXISTS idx_sensor_readings_location
+    ON sensor_readings (latitude, longitude)
+    WHERE latitude IS NOT NULL;
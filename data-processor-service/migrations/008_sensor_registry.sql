@@ -0,0 +1,15 @@
+-- Migration: Sensor registry
+-- Description: Per-sensor metadata (owner, calibration, ...) and an optional fixed location,
+-- attached to readings on insert when processing.registry_enrichment is set
+
+CREATE TABLE IF NOT EXISTS sensor_registry (
+    sensor_name VARCHAR(255) PRIMARY KEY,
+    metadata JSONB NOT NULL DEFAULT '{}',
+    latitude DOUBLE PRECISION,
+    longitude DOUBLE PRECISION,
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    CONSTRAINT sensor_registry_location_range CHECK (
+        (latitude IS NULL AND longitude IS NULL)
+        OR (latitude BETWEEN -90 AND 90 AND longitude BETWEEN -180 AND 180)
+    )
+);
@@ -0,0 +1,6 @@
+-- Migration: Add hash_scope to sensor_readings
+-- Description: What payload_hash covers: 'payload', or 'row' for the payload plus the
+-- reading's metadata (type, name, timestamp, source, location). NULL rows predate the
+-- column and were hashed by payload.
+
+ALTER TABLE sensor_readings ADD COLUMN IF NOT EXISTS hash_scope VARCHAR(16);
@@ -0,0 +1,17 @@
+-- Migration: Quarantine
+-- Description: Raw bytes and failure details of messages that could not be processed,
+-- stored when rabbitmq.quarantine is set so poison messages can be inspected with SQL
+
+CREATE TABLE IF NOT EXISTS quarantine (
+    id BIGSERIAL PRIMARY KEY,
+    payload BYTEA NOT NULL,
+    error TEXT NOT NULL,
+    exchange VARCHAR(255) NOT NULL,
+    routing_key VARCHAR(255) NOT NULL,
+    message_id VARCHAR(255),
+    content_type VARCHAR(255),
+    attempt_count INTEGER NOT NULL,
+    quarantined_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_quarantine_quarantined_at ON quarantine(quarantined_at);
@@ -0,0 +1,20 @@
+-- Migration: Logical batches
+-- Description: Parts of multi-message logical batches persisted so far, shared by every
+-- instance so a batch completes once however its parts were spread across consumers
+
+CREATE TABLE IF NOT EXISTS logical_batches (
+    batch_id VARCHAR(255) PRIMARY KEY,
+    total INTEGER NOT NULL,
+    first_part_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    last_part_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    completed_at TIMESTAMP WITH TIME ZONE
+);
+
+CREATE INDEX IF NOT EXISTS idx_logical_batches_last_part_at ON logical_batches(last_part_at);
+
+CREATE TABLE IF NOT EXISTS logical_batch_parts (
+    batch_id VARCHAR(255) NOT NULL REFERENCES logical_batches(batch_id) ON DELETE CASCADE,
+    sequence INTEGER NOT NULL,
+    readings BIGINT NOT NULL,
+    PRIMARY KEY (batch_id, sequence)
+);
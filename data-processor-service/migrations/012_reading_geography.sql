@@ -0,0 +1,44 @@
+-- Migration: PostGIS geography for reading locations
+-- Description: Where the PostGIS extension can be installed, adds a `location` geography
+-- column kept in step with latitude/longitude by a trigger, backfills it and indexes it
+-- for ST_DWithin radius queries. Without PostGIS (or the privilege to create it) nothing
+-- changes and radius queries keep using the latitude/longitude bounding box.
+
+DO $$
+BEGIN
+    IF NOT EXISTS (SELECT 1 FROM pg_available_extensions WHERE name = 'postgis') THEN
+        RAISE NOTICE 'PostGIS is not available, radius queries use latitude/longitude';
+        RETURN;
+    END IF;
+    BEGIN
+        CREATE EXTENSION IF NOT EXISTS postgis;
+    EXCEPTION WHEN insufficient_privilege THEN
+        RAISE NOTICE 'Not allowed to create the PostGIS extension, radius queries use latitude/longitude';
+        RETURN;
+    END;
+
+    EXECUTE 'ALTER TABLE sensor_readings ADD COLUMN IF NOT EXISTS location geography(Point, 4326)';
+
+    EXECUTE $fn$
+        CREATE OR REPLACE FUNCTION sensor_readings_set_location() RETURNS trigger AS $body$
+        BEGIN
+            NEW.location := CASE
+                WHEN NEW.latitude IS NULL OR NEW.longitude IS NULL THEN NULL
+                ELSE ST_SetSRID(ST_MakePoint(NEW.longitude, NEW.latitude), 4326)::geography
+            END;
+            RETURN NEW;
+        END
+        $body$ LANGUAGE plpgsql
+    $fn$;
+    EXECUTE 'DROP TRIGGER IF EXISTS sensor_readings_location ON sensor_readings';
+    EXECUTE 'CREATE TRIGGER sensor_readings_location
+        BEFORE INSERT OR UPDATE OF latitude, longitude ON sensor_readings
+        FOR EACH ROW EXECUTE FUNCTION sensor_readings_set_location()';
+
+    EXECUTE 'UPDATE sensor_readings
+        SET location = ST_SetSRID(ST_MakePoint(longitude, latitude), 4326)::geography
+        WHERE latitude IS NOT NULL AND longitude IS NOT NULL AND location IS NULL';
+    EXECUTE 'CREATE INDEX IF NOT EXISTS idx_sensor_readings_geography
+        ON sensor_readings USING GIST (location)';
+END
+$$;
@@ -0,0 +1,17 @@
+-- Migration: TimescaleDB sink schema
+-- Description: Narrow hypertable with one row per numeric payload field, applied by the timescale sink
+
+CREATE EXTENSION IF NOT EXISTS timescaledb;
+
+CREATE TABLE IF NOT EXISTS sensor_metrics (
+    time TIMESTAMP WITH TIME ZONE NOT NULL,
+    sensor_type VARCHAR(100) NOT NULL,
+    sensor_name VARCHAR(255) NOT NULL,
+    field VARCHAR(255) NOT NULL,
+    value DOUBLE PRECISION NOT NULL
+);
+
+SELECT create_hypertable('sensor_metrics', 'time', if_not_exists => TRUE);
+
+CREATE INDEX IF NOT EXISTS idx_sensor_metrics_type_field_time ON sensor_metrics(sensor_type, field, time DESC);
+CREATE INDEX IF NOT EXISTS idx_sensor_metrics_name_time ON sensor_metrics(sensor_name, time DESC);
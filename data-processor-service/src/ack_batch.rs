@@ -0,0 +1,127 @@
+use lapin::acker::Acker;
+use std::collections::{BTreeMap, BTreeSet};
+use std::time::{Duration, Instant};
+
+// Defers the acks of processed deliveries so one `multiple` ack covers many. A multiple
+// ack settles every earlier tag on the channel, so it is only sent up to the first
+// delivery still being processed.
+pub struct AckBatcher {
+    batch_size: usize,
+    interval: Duration,
+    // Tags of deliveries on the current channel that are being processed
+    outstanding: BTreeSet<u64>,
+    // Processed deliveries waiting for their ack, by delivery tag
+    pending: BTreeMap<u64, Acker>,
+    // When the oldest pending delivery was processed
+    oldest_pending: Option<Instant>,
+}
+
+impl AckBatcher {
+    pub fn new(batch_size: usize, interval: Duration) -> Self {
+        Self {
+            batch_size: batch_size.max(1),
+            interval,
+            outstanding: BTreeSet::new(),
+            pending: BTreeMap::new(),
+            oldest_pending: None,
+        }
+    }
+    
+    pub fn received(&mut self, delivery_tag: u64) {
+        self.outstanding.insert(delivery_tag);
+    }
+    
+    // Acked or rejected on its own, e.g. dead-lettered
+    pub fn settled(&mut self, delivery_tag: u64) {
+        self.outstanding.remove(&delivery_tag);
+    }
+    
+    pub fn processed(&mut self, delivery_tag: u64, acker: Acker) {
+        self.outstanding.remove(&delivery_tag);
+        self.pending.insert(delivery_tag, acker);
+        self.oldest_pending.get_or_insert_with(Instant::now);
+    }
+    
+    // `batch_size` deliveries are pending, or the oldest has waited `interval`
+    pub fn due(&self) -> bool {
+        self.pending.len() >= self.batch_size || self.flush_at().is_some_and(|at| Instant::now() >= at)
+    }
+    
+    pub fn flush_at(&self) -> Option<Instant> {
+        self.oldest_pending.map(|oldest| oldest + self.interval)
+    }
+    
+    // The acker to send a multiple ack with and how many deliveries it covers, or None
+    // when every pending delivery comes after one still being processed
+    pub fn take(&mut self) -> Option<(Acker, usize)> {
+        let limit = self.outstanding.first().copied().unwrap_or(u64::MAX);
+        let tag = self.pending.range(..limit).next_back().map(|(tag, _)| *tag);
+        let acked = tag.map(|tag| {
+            let later = self.pending.split_off(&(tag + 1));
+            std::mem::replace(&mut self.pending, later)
+        });
+        // Those left wait behind an outstanding delivery; restart their clock
+        self.oldest_pending = (!self.pending.is_empty()).then(Instant::now);
+        let acked = acked?;
+        let count = acked.len();
+        acked.into_values().next_back().map(|acker| (acker, count))
+    }
+    
+    // The channel is gone, and with it every unacked delivery, which the broker redelivers
+    pub fn clear(&mut self) {
+        self.outstanding.clear();
+        self.pending.clear();
+        self.oldest_pending = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn process(batcher: &mut AckBatcher, tags: impl IntoIterator<Item = u64>) {
+        for tag in tags {
+            batcher.processed(tag, Acker::default());
+        }
+    }
+    
+    #[test]
+    fn acks_up_to_the_first_outstanding_delivery() {
+        let mut batcher = AckBatcher::new(3, Duration::from_secs(60));
+        (1..=5).for_each(|tag| batcher.received(tag));
+        process(&mut batcher, [1, 2, 4, 5]);
+        assert!(batcher.due());
+        
+        // 3 is still being processed, so a multiple ack may only cover 1 and 2
+        assert_eq!(batcher.take().map(|(_, count)| count), Some(2));
+        assert!(batcher.take().is_none());
+        
+        process(&mut batcher, [3]);
+        assert_eq!(batcher.take().map(|(_, count)| count), Some(3));
+        assert!(!batcher.due());
+    }
+    
+    #[test]
+    fn settled_deliveries_no_longer_hold_back_acks() {
+        let mut batcher = AckBatcher::new(10, Duration::ZERO);
+        (1..=3).for_each(|tag| batcher.received(tag));
+        process(&mut batcher, [2, 3]);
+        assert!(batcher.take().is_none());
+        
+        batcher.settled(1);
+        assert!(batcher.due());
+        assert_eq!(batcher.take().map(|(_, count)| count), Some(2));
+    }
+    
+    #[test]
+    fn is_due_by_size_or_age() {
+        let mut batcher = AckBatcher::new(2, Duration::from_secs(60));
+        process(&mut batcher, [1]);
+        assert!(!batcher.due());
+        process(&mut batcher, [2]);
+        assert!(batcher.due());
+        
+        batcher.clear();
+        assert!(!batcher.due() && batcher.flush_at().is_none());
+    }
+}
@@ -0,0 +1,111 @@
+use crate::config::AdaptiveBatchingConfig;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use tracing::debug;
+
+// AIMD controller for the batch size: grows by a fixed step while inserts finish under the
+// target latency, and shrinks by a factor as soon as one doesn't
+pub struct AdaptiveBatchSize {
+    config: AdaptiveBatchingConfig,
+    current: AtomicUsize,
+}
+
+impl AdaptiveBatchSize {
+    pub fn new(config: AdaptiveBatchingConfig, initial: usize) -> anyhow::Result<Self> {
+        if config.min_batch_size == 0 || config.min_batch_size > config.max_batch_size {
+            anyhow::bail!(
+                "processing.adaptive_batching needs 0 < min_batch_size <= max_batch_size, got {} and {}",
+                config.min_batch_size,
+                config.max_batch_size
+            );
+        }
+        if !(config.decrease_factor > 0.0 && config.decrease_factor < 1.0) {
+            anyhow::bail!(
+                "processing.adaptive_batching.decrease_factor must be between 0 and 1, got {}",
+                config.decrease_factor
+            );
+        }
+        let initial = initial.clamp(config.min_batch_size, config.max_batch_size);
+        Ok(Self {
+            config,
+            current: AtomicUsize::new(initial),
+        })
+    }
+    
+    pub fn current(&self) -> usize {
+        self.current.load(Ordering::Relaxed)
+    }
+    
+    /// Adjusts the size after one batch insert. Only batches that were full at the current
+    /// size can grow it; a small batch finishing quickly says nothing about larger ones.
+    pub fn observe(&self, readings: usize, latency: Duration, succeeded: bool) {
+        let target = Duration::from_millis(self.config.target_latency_ms);
+        let _ = self.current.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |current| {
+            let next = if !succeeded || latency > target {
+                ((current as f64 * self.config.decrease_factor) as usize).max(self.config.min_batch_size)
+            } else if readings >= current {
+                (current + self.config.increase_step).min(self.config.max_batch_size)
+            } else {
+                current
+            };
+            if next != current {
+                debug!("Batch of {} took {:?}, batch size {} -> {}", readings, latency, current, next);
+            }
+            (next != current).then_some(next)
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn controller(initial: usize) -> AdaptiveBatchSize {
+        let config = AdaptiveBatchingConfig {
+            min_batch_size: 10,
+            max_batch_size: 100,
+            target_latency_ms: 250,
+            increase_step: 20,
+            decrease_factor: 0.5,
+        };
+        AdaptiveBatchSize::new(config, initial).unwrap()
+    }
+    
+    #[test]
+    fn grows_additively_on_fast_full_batches() {
+        let adaptive = controller(50);
+        adaptive.observe(50, Duration::from_millis(100), true);
+        assert_eq!(adaptive.current(), 70);
+        // A partial batch says nothing about larger ones
+        adaptive.observe(30, Duration::from_millis(100), true);
+        assert_eq!(adaptive.current(), 70);
+        adaptive.observe(70, Duration::from_millis(100), true);
+        adaptive.observe(90, Duration::from_millis(100), true);
+        assert_eq!(adaptive.current(), 100);
+    }
+    
+    #[test]
+    fn shrinks_multiplicatively_on_slow_or_failed_batches() {
+        let adaptive = controller(80);
+        adaptive.observe(80, Duration::from_millis(400), true);
+        assert_eq!(adaptive.current(), 40);
+        adaptive.observe(5, Duration::from_millis(10), false);
+        assert_eq!(adaptive.current(), 20);
+        adaptive.observe(20, Duration::from_millis(400), true);
+        adaptive.observe(10, Duration::from_millis(400), true);
+        assert_eq!(adaptive.current(), 10);
+    }
+    
+    #[test]
+    fn clamps_the_initial_size_and_rejects_bad_bounds() {
+        assert_eq!(controller(1000).current(), 100);
+        let config = AdaptiveBatchingConfig {
+            min_batch_size: 10,
+            max_batch_size: 5,
+            target_latency_ms: 250,
+            increase_step: 1,
+            decrease_factor: 0.5,
+        };
+        assert!(AdaptiveBatchSize::new(config, 10).is_err());
+    }
+}
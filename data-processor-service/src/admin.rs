@@ -0,0 +1,280 @@
+use axum::extract::{Query, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{delete, get, post};
+use axum::{Json, Router};
+use chrono::{DateTime, Utc};
+use crate::api::{query_error, QuarantineResponse};
+use crate::audit;
+use crate::config::Config;
+use crate::log_level::LogLevel;
+use crate::processor::Pipeline;
+use crate::replay::{self, ReplayOptions};
+use serde::Deserialize;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use subtle::ConstantTimeEq;
+use tracing::error;
+
+// Operational endpoints, nested under /admin. Every call is authenticated with a
+// bearer token from `http.admin_tokens` and audited under the token's principal.
+const MAX_QUARANTINE_LIMIT: i64 = 1000;
+
+#[derive(Clone)]
+pub struct AdminState {
+    pub pipeline: Pipeline,
+    pub config: Arc<Config>,
+    pub tokens: Arc<HashMap<String, String>>,
+    pub log_level: LogLevel,
+}
+
+pub fn router(state: AdminState) -> Router {
+    Router::new()
+        .route("/pause", post(pause))
+        .route("/resume", post(resume))
+        .route("/stats/reset", post(reset_stats))
+        .route("/readings", delete(delete_readings))
+        .route("/dlq/replay", post(replay_dlq))
+        .route("/log-level", get(get_log_level).put(set_log_level))
+        .route("/failback", post(fail_back))
+        .route("/quarantine", get(list_quarantine))
+        .with_state(state)
+}
+
+fn principal(state: &AdminState, headers: &HeaderMap) -> Option<String> {
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .and_then(|token| match_token(&state.tokens, token.trim()))
+}
+
+// Compares the presented token against every configured one in constant time, hashing
+// both sides first so neither the position of the first differing byte nor the token
+// length shows up in the response time.
+fn match_token(tokens: &HashMap<String, String>, presented: &str) -> Option<String> {
+    let presented = Sha256::digest(presented.as_bytes());
+    let mut matched = None;
+    for (token, principal) in tokens {
+        if bool::from(Sha256::digest(token.as_bytes()).ct_eq(&presented)) {
+            matched = Some(principal.clone());
+        }
+    }
+    matched
+}
+
+fn unauthorized() -> Response {
+    (StatusCode::UNAUTHORIZED, "Missing or unknown admin token").into_response()
+}
+
+async fn pause(State(state): State<AdminState>, headers: HeaderMap) -> Response {
+    let Some(principal) = principal(&state, &headers) else {
+        return unauthorized();
+    };
+    state.pipeline.set_paused(true);
+    audit::record(&state.pipeline.database(), &principal, "pause", json!({}), "ok").await;
+    Json(json!({ "paused": true })).into_response()
+}
+
+async fn resume(State(state): State<AdminState>, headers: HeaderMap) -> Response {
+    let Some(principal) = principal(&state, &headers) else {
+        return unauthorized();
+    };
+    state.pipeline.set_paused(false);
+    audit::record(&state.pipeline.database(), &principal, "resume", json!({}), "ok").await;
+    Json(json!({ "paused": false })).into_response()
+}
+
+async fn reset_stats(State(state): State<AdminState>, headers: HeaderMap) -> Response {
+    let Some(principal) = principal(&state, &headers) else {
+        return unauthorized();
+    };
+    state.pipeline.reset_stats().await;
+    audit::record(&state.pipeline.database(), &principal, "stats_reset", json!({}), "ok").await;
+    Json(json!({ "reset": true })).into_response()
+}
+
+async fn fail_back(State(state): State<AdminState>, headers: HeaderMap) -> Response {
+    let Some(principal) = principal(&state, &headers) else {
+        return unauthorized();
+    };
+    let database = state.pipeline.database();
+    let timeout = Duration::from_millis(state.config.database.failover_check_interval_ms.max(100));
+    
+    match database.fail_back(timeout).await {
+        Ok(()) => {
+            audit::record(&database, &principal, "failback", json!({}), "ok").await;
+            Json(json!({ "write_target": database.write_target_name() })).into_response()
+        }
+        Err(e) => {
+            audit::record(&database, &principal, "failback", json!({}), &format!("failed: {}", e)).await;
+            (StatusCode::SERVICE_UNAVAILABLE, e.to_string()).into_response()
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct QuarantineParams {
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+    limit: Option<i64>,
+}
+
+// Quarantined bodies are raw messages, encrypted fields and all, so listing them is an
+// audited admin action
+async fn list_quarantine(
+    State(state): State<AdminState>,
+    headers: HeaderMap,
+    Query(params): Query<QuarantineParams>,
+) -> Response {
+    let Some(principal) = principal(&state, &headers) else {
+        return unauthorized();
+    };
+    let database = state.pipeline.database();
+    let limit = params.limit.unwrap_or(100).clamp(1, MAX_QUARANTINE_LIMIT);
+    let parameters = json!({ "from": params.from, "to": params.to, "limit": limit });
+    let timeout = Duration::from_millis(state.config.api.max_query_timeout_ms);
+    
+    match database.list_quarantined(params.from, params.to, limit, Some(timeout)).await {
+        Ok(messages) => {
+            audit::record(&database, &principal, "list_quarantine", parameters, &format!("listed {}", messages.len())).await;
+            let format = state.config.api.timestamp_format;
+            let messages: Vec<QuarantineResponse> = messages
+                .into_iter()
+                .map(|message| QuarantineResponse::new(message, format))
+                .collect();
+            Json(messages).into_response()
+        }
+        Err(e) => {
+            audit::record(&database, &principal, "list_quarantine", parameters, &format!("failed: {}", e)).await;
+            query_error(e)
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct DeleteParams {
+    before: DateTime<Utc>,
+    sensor_type: Option<String>,
+}
+
+async fn delete_readings(
+    State(state): State<AdminState>,
+    headers: HeaderMap,
+    Query(params): Query<DeleteParams>,
+) -> Response {
+    let Some(principal) = principal(&state, &headers) else {
+        return unauthorized();
+    };
+    let database = state.pipeline.database();
+    let parameters = json!({ "before": params.before, "sensor_type": params.sensor_type });
+    
+    match database.delete_sensor_readings(params.sensor_type.as_deref(), params.before).await {
+        Ok(deleted) => {
+            audit::record(&database, &principal, "delete_readings", parameters, &format!("deleted {}", deleted)).await;
+            Json(json!({ "deleted": deleted })).into_response()
+        }
+        Err(e) => {
+            error!("Failed to delete readings: {}", e);
+            audit::record(&database, &principal, "delete_readings", parameters, &format!("failed: {}", e)).await;
+            (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response()
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ReplayParams {
+    limit: Option<u64>,
+    #[serde(default)]
+    transform: bool,
+}
+
+async fn replay_dlq(
+    State(state): State<AdminState>,
+    headers: HeaderMap,
+    Query(params): Query<ReplayParams>,
+) -> Response {
+    let Some(principal) = principal(&state, &headers) else {
+        return unauthorized();
+    };
+    let database = state.pipeline.database();
+    let parameters = json!({ "limit": params.limit, "transform": params.transform });
+    let options = ReplayOptions { limit: params.limit, transform: params.transform, order_window: None };
+    
+    match replay::run(&state.config, options).await {
+        Ok(report) => {
+            let outcome = format!("replayed {}, skipped {}", report.replayed, report.skipped);
+            audit::record(&database, &principal, "dlq_replay", parameters, &outcome).await;
+            Json(json!({
+                "replayed": report.replayed,
+                "transformed": report.transformed,
+                "skipped": report.skipped,
+            }))
+            .into_response()
+        }
+        Err(e) => {
+            error!("DLQ replay failed: {}", e);
+            audit::record(&database, &principal, "dlq_replay", parameters, &format!("failed: {}", e)).await;
+            (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response()
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct LogLevelBody {
+    level: String,
+}
+
+async fn get_log_level(State(state): State<AdminState>, headers: HeaderMap) -> Response {
+    if principal(&state, &headers).is_none() {
+        return unauthorized();
+    }
+    match state.log_level.current() {
+        Ok(level) => Json(json!({ "level": level })).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+async fn set_log_level(
+    State(state): State<AdminState>,
+    headers: HeaderMap,
+    Json(body): Json<LogLevelBody>,
+) -> Response {
+    let Some(principal) = principal(&state, &headers) else {
+        return unauthorized();
+    };
+    let database = state.pipeline.database();
+    let parameters = json!({ "level": body.level });
+    
+    match state.log_level.set(&body.level) {
+        Ok(()) => {
+            audit::record(&database, &principal, "set_log_level", parameters, "ok").await;
+            Json(json!({ "level": body.level })).into_response()
+        }
+        Err(e) => {
+            audit::record(&database, &principal, "set_log_level", parameters, &format!("failed: {}", e)).await;
+            (StatusCode::BAD_REQUEST, e.to_string()).into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn matches_only_exact_configured_tokens() {
+        let tokens = HashMap::from([
+            ("s3cret-token".to_string(), "ops".to_string()),
+            ("other-token".to_string(), "oncall".to_string()),
+        ]);
+        
+        assert_eq!(match_token(&tokens, "s3cret-token").as_deref(), Some("ops"));
+        assert_eq!(match_token(&tokens, "other-token").as_deref(), Some("oncall"));
+        assert_eq!(match_token(&tokens, "s3cret-toke"), None);
+        assert_eq!(match_token(&tokens, ""), None);
+    }
+}
@@ -0,0 +1,190 @@
+use anyhow::Result;
+use chrono::Utc;
+use crate::config::{AlertCondition, AlertRule, AlertsConfig};
+use crate::database::Database;
+use crate::models::{Alert, AlertState};
+use crate::rabbitmq::RabbitMQProducer;
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tracing::{error, info, warn};
+
+pub struct AlertEvaluator {
+    database: Arc<Database>,
+    rules: Vec<AlertRule>,
+    producer: Option<RabbitMQProducer>,
+    // Rules currently firing, so each alert is emitted once per trigger/resolve
+    firing: HashSet<String>,
+}
+
+impl AlertEvaluator {
+    pub fn new(database: Arc<Database>, rules: Vec<AlertRule>, producer: Option<RabbitMQProducer>) -> Self {
+        Self {
+            database,
+            rules,
+            producer,
+            firing: HashSet::new(),
+        }
+    }
+    
+    // Evaluates every rule once and returns the alerts whose state changed
+    pub async fn evaluate(&mut self) -> Vec<Alert> {
+        let mut changed = Vec::new();
+        
+        for rule in &self.rules {
+            let result = match evaluate_rule(&self.database, rule).await {
+                Ok(result) => result,
+                Err(e) => {
+                    error!("Failed to evaluate alert rule {}: {}", rule.name, e);
+                    continue;
+                }
+            };
+            
+            let was_firing = self.firing.contains(&rule.name);
+            let (state, message) = match (result, was_firing) {
+                (Some(message), false) => (AlertState::Triggered, message),
+                (None, true) => (AlertState::Resolved, format!("{} recovered", rule.name)),
+                _ => continue,
+            };
+            
+            match state {
+                AlertState::Triggered => {
+                    self.firing.insert(rule.name.clone());
+                    warn!("Alert {} triggered for {}: {}", rule.name, rule.sensor_type, message);
+                }
+                AlertState::Resolved => {
+                    self.firing.remove(&rule.name);
+                    info!("Alert {} resolved for {}", rule.name, rule.sensor_type);
+                }
+            }
+            
+            changed.push(Alert {
+                rule: rule.name.clone(),
+                sensor_type: rule.sensor_type.clone(),
+                state,
+                message,
+                at: Utc::now(),
+            });
+        }
+        
+        if let Some(producer) = &self.producer {
+            for alert in &changed {
+                let routing_key = format!("alerts.{}", alert.rule);
+                if let Err(e) = producer.publish_json(&routing_key, alert).await {
+                    error!("Failed to publish alert {}: {}", alert.rule, e);
+                }
+            }
+        }
+        
+        changed
+    }
+}
+
+// Returns a description of the breach when the rule's condition currently holds
+async fn evaluate_rule(database: &Database, rule: &AlertRule) -> Result<Option<String>> {
+    let now = Utc::now();
+    
+    match &rule.condition {
+        AlertCondition::Staleness { max_silence_seconds } => {
+            let max_silence = chrono::Duration::seconds(*max_silence_seconds as i64);
+            let last = database.last_reading_time(&rule.sensor_type).await?;
+            Ok(match last {
+                Some(last) if now - last <= max_silence => None,
+                Some(last) => Some(format!(
+                    "no {} readings for {}s (last at {})",
+                    rule.sensor_type,
+                    (now - last).num_seconds(),
+                    last.to_rfc3339()
+                )),
+                None => Some(format!("no {} readings have been stored", rule.sensor_type)),
+            })
+        }
+        AlertCondition::Threshold { field, above, below, min_occurrences, window_seconds } => {
+            let since = now - chrono::Duration::seconds(*window_seconds as i64);
+            let breaches = database
+                .count_threshold_breaches(&rule.sensor_type, field, *above, *below, since)
+                .await?;
+            Ok((breaches >= *min_occurrences).then(|| format!(
+                "{} readings with {} out of bounds (above: {:?}, below: {:?}) in the last {}s",
+                breaches, field, above, below, window_seconds
+            )))
+        }
+    }
+}
+
+pub fn spawn_alert_evaluation(mut evaluator: AlertEvaluator, config: &AlertsConfig) -> JoinHandle<()> {
+    let check_interval = Duration::from_secs(config.check_interval_seconds.max(1));
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(check_interval);
+        loop {
+            interval.tick().await;
+            evaluator.evaluate().await;
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Config, DatabaseConfig};
+    use crate::models::SensorReadingInput;
+    
+    // Needs a PostgreSQL server; skipped unless TEST_DATABASE_URL is set
+    fn test_database_url() -> Option<String> {
+        std::env::var("TEST_DATABASE_URL").ok()
+    }
+    
+    #[test]
+    fn staleness_rule_fires_once_a_type_stops_reporting() {
+        let Some(url) = test_database_url() else {
+            return;
+        };
+        tokio_test::block_on(async {
+            let config = DatabaseConfig { url, ..Config::default().database };
+            let database = Arc::new(Database::new(&config).await.unwrap());
+            let sensor_type = format!("alert-test-{}", uuid::Uuid::new_v4());
+            let rule = AlertRule {
+                name: "silent".to_string(),
+                sensor_type: sensor_type.clone(),
+                condition: AlertCondition::Staleness { max_silence_seconds: 60 },
+            };
+            let mut evaluator = AlertEvaluator::new(database.clone(), vec![rule.clone()], None);
+            
+            database
+                .insert_sensor_reading(SensorReadingInput {
+                    sensor_type: sensor_type.clone(),
+                    sensor_name: "meter-1".to_string(),
+                    payload: serde_json::json!({ "energy": 1.5 }),
+                    timestamp: Utc::now(),
+                    source_id: None,
+                    location: None,
+                })
+                .await
+                .unwrap();
+            assert_eq!(evaluate_rule(&database, &rule).await.unwrap(), None);
+            assert!(evaluator.evaluate().await.is_empty());
+            
+            // The type goes quiet: its last reading is now two minutes old
+            sqlx::query("UPDATE sensor_readings SET timestamp = timestamp - interval '2 minutes' WHERE sensor_type = $1")
+                .bind(&sensor_type)
+                .execute(database.pool())
+                .await
+                .unwrap();
+            let breach = evaluate_rule(&database, &rule).await.unwrap().expect("rule fires");
+            assert!(breach.starts_with(&format!("no {} readings for 12", sensor_type)), "{}", breach);
+            
+            let alerts = evaluator.evaluate().await;
+            assert_eq!(alerts.len(), 1);
+            assert!(matches!(alerts[0].state, AlertState::Triggered));
+            // Still silent, but already firing
+            assert!(evaluator.evaluate().await.is_empty());
+            
+            sqlx::query("DELETE FROM sensor_readings WHERE sensor_type = $1")
+                .bind(&sensor_type)
+                .execute(database.pool())
+                .await
+                .unwrap();
+        });
+    }
+}
@@ -0,0 +1,453 @@
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use chrono::{DateTime, Utc};
+use crate::config::TimestampFormat;
+use crate::models::{
+    GeoPoint, ProcessingStats, QuarantinedMessage, ReadingField, ReadingGap, ReadingQuery, RollupBucket, RollupQuery,
+    SensorReading, TimeBucket,
+};
+use crate::processor::Pipeline;
+use serde::{Deserialize, Serialize, Serializer};
+use std::time::Duration;
+use tracing::{error, warn};
+use uuid::Uuid;
+
+// SQLSTATE raised when `statement_timeout` cancels a query
+const QUERY_CANCELED: &str = "57014";
+
+// Most buckets a rollup or bucket count may span; each one is a generated row
+const MAX_QUERY_BUCKETS: i64 = 10_000;
+
+// Rows `/readings` returns when the request gives no `limit`
+const DEFAULT_QUERY_LIMIT: i64 = 100;
+
+// JSON query and stats endpoints; timestamps follow `api.timestamp_format`
+#[derive(Clone)]
+pub struct ApiState {
+    pub pipeline: Pipeline,
+    pub timestamp_format: TimestampFormat,
+    pub max_query_timeout: Duration,
+    pub max_query_limit: i64,
+}
+
+impl ApiState {
+    // The request's `timeout_ms`, capped by `api.max_query_timeout_ms`
+    fn query_timeout(&self, requested: &TimeoutParam) -> Duration {
+        match requested.timeout_ms {
+            Some(ms) => Duration::from_millis(ms).min(self.max_query_timeout),
+            None => self.max_query_timeout,
+        }
+    }
+}
+
+// The request's `limit` (default 100), capped by `api.max_query_limit`
+fn query_limit(requested: Option<i64>, max: i64) -> Result<i64, &'static str> {
+    match requested {
+        Some(limit) if limit < 0 => Err("limit must not be negative"),
+        Some(limit) => Ok(limit.min(max)),
+        None => Ok(DEFAULT_QUERY_LIMIT.min(max)),
+    }
+}
+
+// Optional per-request bound on query time, accepted by every read endpoint
+#[derive(Debug, Deserialize)]
+struct TimeoutParam {
+    timeout_ms: Option<u64>,
+}
+
+// Comma-separated `ReadingField` names a reading list is narrowed to
+#[derive(Debug, Deserialize)]
+struct FieldsParam {
+    fields: Option<String>,
+}
+
+pub fn router(state: ApiState) -> Router {
+    Router::new()
+        .route("/stats", get(stats))
+        .route("/readings", get(readings))
+        .route("/readings/near", get(readings_near))
+        .route("/readings/buckets", get(reading_buckets))
+        .route("/sensors/:name/gaps", get(sensor_gaps))
+        .route("/rollups", get(rollups))
+        .with_state(state)
+}
+
+// Timestamp serialized as RFC 3339, epoch milliseconds or epoch seconds
+#[derive(Debug, Clone, Copy)]
+pub struct ApiTimestamp {
+    pub at: DateTime<Utc>,
+    pub format: TimestampFormat,
+}
+
+impl ApiTimestamp {
+    pub fn new(at: DateTime<Utc>, format: TimestampFormat) -> Self {
+        Self { at, format }
+    }
+}
+
+impl Serialize for ApiTimestamp {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.format {
+            TimestampFormat::Rfc3339 => serializer.serialize_str(&self.at.to_rfc3339()),
+            TimestampFormat::EpochMillis => serializer.serialize_i64(self.at.timestamp_millis()),
+            TimestampFormat::EpochSecs => serializer.serialize_i64(self.at.timestamp()),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReadingResponse {
+    pub id: Uuid,
+    pub sensor_type: String,
+    pub sensor_name: String,
+    pub payload: serde_json::Value,
+    pub timestamp: ApiTimestamp,
+    pub created_at: ApiTimestamp,
+    pub source_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub location: Option<GeoPoint>,
+}
+
+impl ReadingResponse {
+    pub fn new(reading: SensorReading, format: TimestampFormat) -> Self {
+        Self {
+            id: reading.id,
+            sensor_type: reading.sensor_type,
+            sensor_name: reading.sensor_name,
+            payload: reading.payload,
+            timestamp: ApiTimestamp::new(reading.timestamp, format),
+            created_at: ApiTimestamp::new(reading.created_at, format),
+            source_id: reading.source_id,
+            location: reading.latitude.zip(reading.longitude).map(|(latitude, longitude)| GeoPoint {
+                latitude,
+                longitude,
+            }),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct StatsResponse {
+    #[serde(flatten)]
+    pub stats: ProcessingStats,
+    pub last_processed_at: Option<ApiTimestamp>,
+}
+
+impl StatsResponse {
+    pub fn new(stats: ProcessingStats, format: TimestampFormat) -> Self {
+        Self {
+            last_processed_at: stats.last_processed_at.map(|at| ApiTimestamp::new(at, format)),
+            stats,
+        }
+    }
+}
+
+async fn stats(State(state): State<ApiState>) -> Response {
+    match state.pipeline.get_stats().await {
+        Ok(stats) => Json(StatsResponse::new(stats, state.timestamp_format)).into_response(),
+        Err(e) => internal_error(e),
+    }
+}
+
+async fn readings(
+    State(state): State<ApiState>,
+    Query(mut query): Query<ReadingQuery>,
+    Query(timeout): Query<TimeoutParam>,
+    Query(fields): Query<FieldsParam>,
+) -> Response {
+    query.limit = match query_limit(query.limit, state.max_query_limit) {
+        Ok(limit) => Some(limit),
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
+    let fields = match fields.fields.as_deref().map(ReadingField::parse_list) {
+        Some(Ok(fields)) => Some(fields),
+        Some(Err(e)) => return (StatusCode::BAD_REQUEST, e).into_response(),
+        None => None,
+    };
+    let timeout = state.query_timeout(&timeout);
+    let columns = fields.as_deref().unwrap_or(&ReadingField::ALL);
+    match state.pipeline.database().query_sensor_readings(&query, columns, Some(timeout)).await {
+        Ok(readings) => {
+            let readings: Vec<ReadingResponse> = readings
+                .into_iter()
+                .map(|reading| ReadingResponse::new(reading, state.timestamp_format))
+                .collect();
+            let Some(fields) = fields else {
+                return Json(readings).into_response();
+            };
+            match readings.iter().map(|reading| project(reading, &fields)).collect::<serde_json::Result<Vec<_>>>() {
+                Ok(projected) => Json(projected).into_response(),
+                Err(e) => internal_error(e.into()),
+            }
+        }
+        Err(e) => query_error(e),
+    }
+}
+
+// Quarantined message with its body decoded as (lossy) UTF-8, served by `GET /admin/quarantine`
+#[derive(Debug, Serialize)]
+pub struct QuarantineResponse {
+    pub id: i64,
+    pub payload: String,
+    pub error: String,
+    pub exchange: String,
+    pub routing_key: String,
+    pub message_id: Option<String>,
+    pub content_type: Option<String>,
+    pub attempt_count: i32,
+    pub quarantined_at: ApiTimestamp,
+}
+
+impl QuarantineResponse {
+    pub fn new(message: QuarantinedMessage, format: TimestampFormat) -> Self {
+        Self {
+            id: message.id,
+            payload: String::from_utf8_lossy(&message.payload).into_owned(),
+            error: message.error,
+            exchange: message.exchange,
+            routing_key: message.routing_key,
+            message_id: message.message_id,
+            content_type: message.content_type,
+            attempt_count: message.attempt_count,
+            quarantined_at: ApiTimestamp::new(message.quarantined_at, format),
+        }
+    }
+}
+
+// The reading as JSON with only the keys of `fields`
+fn project(reading: &ReadingResponse, fields: &[ReadingField]) -> serde_json::Result<serde_json::Value> {
+    let mut value = serde_json::to_value(reading)?;
+    if let Some(object) = value.as_object_mut() {
+        object.retain(|key, _| fields.iter().any(|field| field.name() == key));
+    }
+    Ok(value)
+}
+
+#[derive(Debug, Deserialize)]
+struct NearQuery {
+    lat: f64,
+    lon: f64,
+    radius_m: f64,
+    from: DateTime<Utc>,
+    to: Option<DateTime<Utc>>,
+}
+
+async fn readings_near(
+    State(state): State<ApiState>,
+    Query(query): Query<NearQuery>,
+    Query(timeout): Query<TimeoutParam>,
+) -> Response {
+    let Some(center) = GeoPoint::new(query.lat, query.lon) else {
+        return (StatusCode::BAD_REQUEST, "lat/lon out of range").into_response();
+    };
+    if !(query.radius_m.is_finite() && query.radius_m >= 0.0) {
+        return (StatusCode::BAD_REQUEST, "radius_m must be a non-negative number").into_response();
+    }
+    let to = query.to.unwrap_or_else(Utc::now);
+    let timeout = state.query_timeout(&timeout);
+    match state
+        .pipeline
+        .database()
+        .readings_near(center, query.radius_m, query.from, to, Some(timeout))
+        .await
+    {
+        Ok(readings) => {
+            let readings: Vec<ReadingResponse> = readings
+                .into_iter()
+                .map(|reading| ReadingResponse::new(reading, state.timestamp_format))
+                .collect();
+            Json(readings).into_response()
+        }
+        Err(e) => query_error(e),
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct GapResponse {
+    pub start: ApiTimestamp,
+    pub end: ApiTimestamp,
+    pub duration_secs: i64,
+}
+
+impl GapResponse {
+    pub fn new(gap: ReadingGap, format: TimestampFormat) -> Self {
+        Self {
+            duration_secs: (gap.end - gap.start).num_seconds(),
+            start: ApiTimestamp::new(gap.start, format),
+            end: ApiTimestamp::new(gap.end, format),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct GapQuery {
+    interval_secs: u64,
+    from: DateTime<Utc>,
+    to: Option<DateTime<Utc>>,
+}
+
+async fn sensor_gaps(
+    State(state): State<ApiState>,
+    Path(name): Path<String>,
+    Query(query): Query<GapQuery>,
+    Query(timeout): Query<TimeoutParam>,
+) -> Response {
+    if query.interval_secs == 0 {
+        return (StatusCode::BAD_REQUEST, "interval_secs must be positive").into_response();
+    }
+    let to = query.to.unwrap_or_else(Utc::now);
+    if to <= query.from {
+        return (StatusCode::BAD_REQUEST, "from must be before to").into_response();
+    }
+    let interval = Duration::from_secs(query.interval_secs);
+    let timeout = state.query_timeout(&timeout);
+    match state.pipeline.database().detect_gaps(&name, interval, query.from, to, Some(timeout)).await {
+        Ok(gaps) => {
+            let gaps: Vec<GapResponse> = gaps
+                .into_iter()
+                .map(|gap| GapResponse::new(gap, state.timestamp_format))
+                .collect();
+            Json(gaps).into_response()
+        }
+        Err(e) => query_error(e),
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct RollupResponse {
+    pub bucket: ApiTimestamp,
+    pub count: i64,
+    pub avg: Option<f64>,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+}
+
+impl RollupResponse {
+    pub fn new(bucket: RollupBucket, format: TimestampFormat) -> Self {
+        Self {
+            bucket: ApiTimestamp::new(bucket.bucket, format),
+            count: bucket.count,
+            avg: bucket.avg,
+            min: bucket.min,
+            max: bucket.max,
+        }
+    }
+}
+
+async fn rollups(
+    State(state): State<ApiState>,
+    Query(query): Query<RollupQuery>,
+    Query(timeout): Query<TimeoutParam>,
+) -> Response {
+    if query.to <= query.from {
+        return (StatusCode::BAD_REQUEST, "from must be before to").into_response();
+    }
+    if let Some(response) = too_many_buckets(query.bucket, query.from, query.to) {
+        return response;
+    }
+    let timeout = state.query_timeout(&timeout);
+    match state.pipeline.database().rollup(&query, Some(timeout)).await {
+        Ok(buckets) => {
+            let buckets: Vec<RollupResponse> = buckets
+                .into_iter()
+                .map(|bucket| RollupResponse::new(bucket, state.timestamp_format))
+                .collect();
+            Json(buckets).into_response()
+        }
+        Err(e) => query_error(e),
+    }
+}
+
+// 400 for a range that would generate more than `MAX_QUERY_BUCKETS` rows
+fn too_many_buckets(bucket: TimeBucket, from: DateTime<Utc>, to: DateTime<Utc>) -> Option<Response> {
+    if bucket.max_buckets_between(from, to) <= MAX_QUERY_BUCKETS {
+        return None;
+    }
+    let message = format!(
+        "range spans more than {} {} buckets; use a wider bucket or a shorter range",
+        MAX_QUERY_BUCKETS,
+        bucket.as_str()
+    );
+    Some((StatusCode::BAD_REQUEST, message).into_response())
+}
+
+#[derive(Debug, Deserialize)]
+struct BucketQuery {
+    bucket: TimeBucket,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BucketCountResponse {
+    pub bucket: ApiTimestamp,
+    pub count: i64,
+}
+
+async fn reading_buckets(
+    State(state): State<ApiState>,
+    Query(query): Query<BucketQuery>,
+    Query(timeout): Query<TimeoutParam>,
+) -> Response {
+    if query.to <= query.from {
+        return (StatusCode::BAD_REQUEST, "from must be before to").into_response();
+    }
+    if let Some(response) = too_many_buckets(query.bucket, query.from, query.to) {
+        return response;
+    }
+    let timeout = state.query_timeout(&timeout);
+    match state
+        .pipeline
+        .database()
+        .count_by_time_bucket(query.bucket, query.from, query.to, Some(timeout))
+        .await
+    {
+        Ok(buckets) => {
+            let buckets: Vec<BucketCountResponse> = buckets
+                .into_iter()
+                .map(|(bucket, count)| BucketCountResponse {
+                    bucket: ApiTimestamp::new(bucket, state.timestamp_format),
+                    count,
+                })
+                .collect();
+            Json(buckets).into_response()
+        }
+        Err(e) => query_error(e),
+    }
+}
+
+// A query cancelled by its statement timeout becomes a 504, anything else a 500
+pub(crate) fn query_error(e: anyhow::Error) -> Response {
+    let timed_out = matches!(
+        e.downcast_ref::<sqlx::Error>(),
+        Some(sqlx::Error::Database(db)) if db.code().as_deref() == Some(QUERY_CANCELED)
+    );
+    if timed_out {
+        warn!("API query timed out: {}", e);
+        return (StatusCode::GATEWAY_TIMEOUT, "query timed out").into_response();
+    }
+    internal_error(e)
+}
+
+fn internal_error(e: anyhow::Error) -> Response {
+    error!("API request failed: {}", e);
+    (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn clamps_the_reading_limit() {
+        assert_eq!(query_limit(None, 1000), Ok(100));
+        assert_eq!(query_limit(None, 50), Ok(50));
+        assert_eq!(query_limit(Some(0), 1000), Ok(0));
+        assert_eq!(query_limit(Some(250), 1000), Ok(250));
+        assert_eq!(query_limit(Some(i64::MAX), 1000), Ok(1000));
+        assert!(query_limit(Some(-1), 1000).is_err());
+    }
+}
@@ -0,0 +1,29 @@
+use crate::database::Database;
+use crate::models::AuditEvent;
+use serde_json::Value;
+use tracing::{error, info};
+
+// Writes the event to the `audit` log target and the audit_log table. A failed insert is
+// logged rather than returned since the audited action has already happened.
+pub async fn record(database: &Database, principal: &str, action: &str, parameters: Value, outcome: &str) {
+    let event = AuditEvent {
+        principal: principal.to_string(),
+        action: action.to_string(),
+        parameters,
+        outcome: outcome.to_string(),
+        at: chrono::Utc::now(),
+    };
+    
+    info!(
+        target: "audit",
+        principal = %event.principal,
+        action = %event.action,
+        parameters = %event.parameters,
+        outcome = %event.outcome,
+        "Admin action"
+    );
+    
+    if let Err(e) = database.insert_audit_event(&event).await {
+        error!("Failed to write audit event for {} by {}: {}", event.action, event.principal, e);
+    }
+}
@@ -0,0 +1,328 @@
+use anyhow::{anyhow, Result};
+use crate::config::InsertSortField;
+use crate::models::SensorReadingInput;
+use serde_json::Value;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::io;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::sync::oneshot;
+
+// Readings buffered for one sensor type plus the messages waiting on their write
+#[derive(Default)]
+pub struct PendingBatch {
+    pub readings: Vec<SensorReadingInput>,
+    // Serialized payload bytes of `readings`, tracked only when a byte limit is set
+    pub bytes: usize,
+    waiters: Vec<oneshot::Sender<Result<(), String>>>,
+    opened_at: Option<Instant>,
+}
+
+impl PendingBatch {
+    // Reports the write result to every message with readings in this batch
+    pub fn complete(self, result: &Result<()>) {
+        let outcome = result.as_ref().map(|_| ()).map_err(|e| e.to_string());
+        for waiter in self.waiters {
+            let _ = waiter.send(outcome.clone());
+        }
+    }
+}
+
+// Count and optional serialized-payload-size limits for one batch
+#[derive(Debug, Clone, Copy)]
+pub struct BatchLimits {
+    pub max_readings: usize,
+    pub max_bytes: Option<usize>,
+}
+
+impl BatchLimits {
+    fn reached(&self, readings: usize, bytes: usize) -> bool {
+        readings >= self.max_readings || self.max_bytes.is_some_and(|max| bytes >= max)
+    }
+}
+
+// One type's buffer and when it was last flushed, for the flush interval floor
+#[derive(Default)]
+struct TypeBuffer {
+    pending: PendingBatch,
+    last_flushed: Option<Instant>,
+    // Reached its limits while the floor held it back
+    full: bool,
+}
+
+impl TypeBuffer {
+    fn take(&mut self) -> PendingBatch {
+        self.last_flushed = Some(Instant::now());
+        self.full = false;
+        std::mem::take(&mut self.pending)
+    }
+}
+
+// Per-type buffers that collect readings across messages until the type's batch limits
+// are reached or the oldest reading has waited long enough
+#[derive(Default)]
+pub struct BatchBuffers {
+    buffers: Mutex<HashMap<String, TypeBuffer>>,
+    // Least time between two flushes of one type, so bursts coalesce into larger batches
+    min_interval: Option<Duration>,
+}
+
+impl BatchBuffers {
+    pub fn new(min_interval: Option<Duration>) -> Self {
+        Self {
+            buffers: Mutex::default(),
+            min_interval,
+        }
+    }
+    
+    // Adds readings of one type. Returns the receiver that resolves once they are written,
+    // and the batch to flush now if this push filled it.
+    pub fn push(
+        &self,
+        sensor_type: &str,
+        readings: Vec<SensorReadingInput>,
+        limits: BatchLimits,
+    ) -> (oneshot::Receiver<Result<(), String>>, Option<PendingBatch>) {
+        let (sender, receiver) = oneshot::channel();
+        let bytes: usize = match limits.max_bytes {
+            Some(_) => readings.iter().map(|reading| payload_bytes(&reading.payload)).sum(),
+            None => 0,
+        };
+        let mut buffers = self.buffers.lock().unwrap();
+        let buffer = buffers.entry(sensor_type.to_string()).or_default();
+        let pending = &mut buffer.pending;
+        pending.opened_at.get_or_insert_with(Instant::now);
+        pending.readings.extend(readings);
+        pending.bytes += bytes;
+        pending.waiters.push(sender);
+        
+        // A full buffer still inside the floor keeps growing until the flusher takes it
+        buffer.full = limits.reached(pending.readings.len(), pending.bytes);
+        let full = (buffer.full && self.floor_passed(buffer)).then(|| buffer.take());
+        (receiver, full)
+    }
+    
+    // Removes every buffer whose oldest reading has waited at least `max_wait`, or that
+    // filled up while the flush interval floor held it back, once the floor has passed
+    pub fn take_expired(&self, max_wait: Duration) -> Vec<PendingBatch> {
+        self.take_where(|buffer| {
+            let expired = buffer.pending.opened_at.is_some_and(|opened| opened.elapsed() >= max_wait);
+            (expired || buffer.full) && self.floor_passed(buffer)
+        })
+    }
+    
+    // Ignores the floor: used when everything has to be written now
+    pub fn take_all(&self) -> Vec<PendingBatch> {
+        self.take_where(|buffer| !buffer.pending.readings.is_empty() || !buffer.pending.waiters.is_empty())
+    }
+    
+    fn take_where(&self, mut predicate: impl FnMut(&TypeBuffer) -> bool) -> Vec<PendingBatch> {
+        let mut buffers = self.buffers.lock().unwrap();
+        buffers
+            .values_mut()
+            .filter(|buffer| predicate(buffer))
+            .map(TypeBuffer::take)
+            .collect()
+    }
+    
+    fn floor_passed(&self, buffer: &TypeBuffer) -> bool {
+        match (self.min_interval, buffer.last_flushed) {
+            (Some(min_interval), Some(last_flushed)) => last_flushed.elapsed() >= min_interval,
+            _ => true,
+        }
+    }
+}
+
+// Splits readings into chunks within both limits; a reading larger than `max_bytes` on
+// its own becomes a single-reading chunk
+pub fn chunk(readings: &[SensorReadingInput], limits: BatchLimits) -> Vec<&[SensorReadingInput]> {
+    let max_readings = limits.max_readings.max(1);
+    let Some(max_bytes) = limits.max_bytes else {
+        return readings.chunks(max_readings).collect();
+    };
+    
+    let mut chunks = Vec::new();
+    let (mut start, mut bytes) = (0, 0);
+    for (i, reading) in readings.iter().enumerate() {
+        let size = payload_bytes(&reading.payload);
+        if i > start && (i - start >= max_readings || bytes + size > max_bytes) {
+            chunks.push(&readings[start..i]);
+            (start, bytes) = (i, 0);
+        }
+        bytes += size;
+    }
+    if start < readings.len() {
+        chunks.push(&readings[start..]);
+    }
+    chunks
+}
+
+/// Length of the payload's JSON serialization, computed without buffering it.
+pub fn payload_bytes(payload: &Value) -> usize {
+    struct Counter(usize);
+    
+    impl io::Write for Counter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0 += buf.len();
+            Ok(buf.len())
+        }
+        
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+    
+    let mut counter = Counter(0);
+    let _ = serde_json::to_writer(&mut counter, payload);
+    counter.0
+}
+
+// Waits for every receiver, failing if any of their batches failed
+pub async fn wait_all(receivers: Vec<oneshot::Receiver<Result<(), String>>>) -> Result<()> {
+    let mut first_error = None;
+    for receiver in receivers {
+        let outcome = receiver
+            .await
+            .unwrap_or_else(|_| Err("Batch was dropped before it was written".to_string()));
+        if let Err(e) = outcome {
+            first_error.get_or_insert(e);
+        }
+    }
+    match first_error {
+        Some(e) => Err(anyhow!(e)),
+        None => Ok(()),
+    }
+}
+
+/// Stable sort by the configured fields, so equal keys keep their arrival order.
+pub fn sort_readings(readings: &mut [SensorReadingInput], key: &[InsertSortField]) {
+    if key.is_empty() {
+        return;
+    }
+    readings.sort_by(|a, b| {
+        key.iter()
+            .map(|field| match field {
+                InsertSortField::SensorType => a.sensor_type.cmp(&b.sensor_type),
+                InsertSortField::SensorName => a.sensor_name.cmp(&b.sensor_name),
+                InsertSortField::SourceId => a.source_id.cmp(&b.source_id),
+                InsertSortField::Timestamp => a.timestamp.cmp(&b.timestamp),
+            })
+            .find(|ordering| *ordering != Ordering::Equal)
+            .unwrap_or(Ordering::Equal)
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use serde_json::json;
+    
+    fn readings(count: usize) -> Vec<SensorReadingInput> {
+        (0..count)
+            .map(|i| SensorReadingInput {
+                sensor_type: "temperature".to_string(),
+                sensor_name: format!("sensor-{}", i),
+                payload: json!({ "value": i }),
+                timestamp: Utc::now(),
+                source_id: None,
+                location: None,
+            })
+            .collect()
+    }
+    
+    fn limits(max_readings: usize) -> BatchLimits {
+        BatchLimits { max_readings, max_bytes: None }
+    }
+    
+    #[test]
+    fn flushes_a_type_once_it_reaches_its_limit() {
+        let buffers = BatchBuffers::new(None);
+        
+        let (_first, full) = buffers.push("temperature", readings(2), limits(3));
+        assert!(full.is_none());
+        let (_other, full) = buffers.push("humidity", readings(1), limits(3));
+        assert!(full.is_none());
+        let (_second, full) = buffers.push("temperature", readings(2), limits(3));
+        
+        assert_eq!(full.expect("temperature is full").readings.len(), 4);
+        // Only humidity is left
+        let rest = buffers.take_all();
+        assert_eq!(rest.len(), 1);
+        assert_eq!(rest[0].readings.len(), 1);
+    }
+    
+    #[test]
+    fn holds_a_full_buffer_until_the_floor_passes() {
+        let floor = Duration::from_millis(50);
+        let buffers = BatchBuffers::new(Some(floor));
+        let (_first, full) = buffers.push("temperature", readings(1), limits(1));
+        assert!(full.is_some());
+        
+        // Flushed just now, so the next full batch waits for the floor
+        let (_second, full) = buffers.push("temperature", readings(1), limits(1));
+        assert!(full.is_none());
+        assert!(buffers.take_expired(Duration::from_secs(60)).is_empty());
+        
+        std::thread::sleep(floor);
+        assert_eq!(buffers.take_expired(Duration::from_secs(60)).len(), 1);
+    }
+    
+    #[test]
+    fn takes_buffers_that_waited_too_long() {
+        let buffers = BatchBuffers::new(None);
+        let _receiver = buffers.push("temperature", readings(1), limits(100)).0;
+        assert!(buffers.take_expired(Duration::from_secs(60)).is_empty());
+        assert_eq!(buffers.take_expired(Duration::ZERO).len(), 1);
+    }
+    
+    #[test]
+    fn completes_every_waiter_of_a_batch() {
+        tokio_test::block_on(async {
+            let buffers = BatchBuffers::new(None);
+            let (first, _) = buffers.push("temperature", readings(1), limits(2));
+            let (second, full) = buffers.push("temperature", readings(1), limits(2));
+            
+            full.unwrap().complete(&Err(anyhow!("insert failed")));
+            
+            assert_eq!(first.await.unwrap(), Err("insert failed".to_string()));
+            assert!(wait_all(vec![second]).await.is_err());
+        });
+    }
+    
+    #[test]
+    fn chunks_within_both_limits() {
+        let batch = readings(5);
+        let size = payload_bytes(&batch[0].payload);
+        assert_eq!(size, r#"{"value":0}"#.len());
+        
+        let counts = |limits| chunk(&batch, limits).iter().map(|chunk| chunk.len()).collect::<Vec<_>>();
+        assert_eq!(counts(limits(2)), vec![2, 2, 1]);
+        assert_eq!(counts(BatchLimits { max_readings: 10, max_bytes: Some(size * 3) }), vec![3, 2]);
+        // A reading over the byte limit still goes out, alone
+        assert_eq!(counts(BatchLimits { max_readings: 10, max_bytes: Some(1) }), vec![1, 1, 1, 1, 1]);
+    }
+    
+    #[test]
+    fn sorts_by_each_key_field_in_turn_and_stays_stable() {
+        let mut batch = readings(4);
+        batch[0].sensor_name = "b".to_string();
+        batch[1].sensor_name = "a".to_string();
+        batch[2].sensor_name = "b".to_string();
+        batch[3].sensor_name = "a".to_string();
+        batch[2].sensor_type = "humidity".to_string();
+        batch[3].payload = json!({"order": "last"});
+        let names = |batch: &[SensorReadingInput]| {
+            batch.iter().map(|r| format!("{}/{}", r.sensor_type, r.sensor_name)).collect::<Vec<_>>()
+        };
+        
+        sort_readings(&mut batch, &[]);
+        assert_eq!(batch[0].sensor_name, "b");
+        
+        sort_readings(&mut batch, &[InsertSortField::SensorType, InsertSortField::SensorName]);
+        assert_eq!(names(&batch), ["humidity/b", "temperature/a", "temperature/a", "temperature/b"]);
+        // Equal keys keep their arrival order
+        assert_eq!(batch[2].payload, json!({"order": "last"}));
+    }
+}
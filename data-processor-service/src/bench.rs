@@ -0,0 +1,271 @@
+use anyhow::{Context, Result};
+use crate::config::{Config, QuarantineMode, RabbitMQConfig};
+use crate::database::Database;
+use crate::models::{SensorData, SensorReadingInput};
+use crate::rabbitmq::{RabbitMQConsumer, RabbitMQProducer};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{Mutex, Notify};
+use tracing::{error, info};
+
+#[derive(Debug, Clone)]
+pub struct BenchOptions {
+    pub messages: usize,
+    pub batch: usize,
+    // Write the consumed readings to the configured database
+    pub store: bool,
+}
+
+#[derive(Debug)]
+pub struct BenchReport {
+    pub messages: usize,
+    pub wall_time: Duration,
+    pub batch_latencies: Vec<Duration>,
+    pub inserted: usize,
+    pub insert_time: Duration,
+    pub dry_run: bool,
+}
+
+impl BenchReport {
+    // Nearest-rank percentile over the recorded per-batch latencies
+    pub fn percentile(&self, p: f64) -> Duration {
+        if self.batch_latencies.is_empty() {
+            return Duration::ZERO;
+        }
+        let mut sorted = self.batch_latencies.clone();
+        sorted.sort();
+        let rank = ((p / 100.0) * sorted.len() as f64).ceil() as usize;
+        sorted[rank.clamp(1, sorted.len()) - 1]
+    }
+    
+    pub fn throughput(&self) -> f64 {
+        self.messages as f64 / self.wall_time.as_secs_f64()
+    }
+    
+    pub fn insert_rate(&self) -> f64 {
+        if self.insert_time.is_zero() {
+            return 0.0;
+        }
+        self.inserted as f64 / self.insert_time.as_secs_f64()
+    }
+    
+    pub fn print(&self) {
+        println!("Benchmark results");
+        println!("  messages:        {}", self.messages);
+        println!("  batches:         {}", self.batch_latencies.len());
+        println!("  wall time:       {:.3?}", self.wall_time);
+        println!("  throughput:      {:.2} msg/s", self.throughput());
+        println!("  batch p50:       {:.3?}", self.percentile(50.0));
+        println!("  batch p95:       {:.3?}", self.percentile(95.0));
+        println!("  batch p99:       {:.3?}", self.percentile(99.0));
+        if self.dry_run {
+            println!("  db insert rate:  n/a (dry run)");
+        } else {
+            println!("  db insert rate:  {:.2} rows/s", self.insert_rate());
+        }
+    }
+}
+
+// Sensor types `synthetic_batch` cycles through
+pub const SYNTHETIC_TYPES: [&str; 3] = ["energy", "air_quality", "motion"];
+
+// Deterministic synthetic readings cycling through the known sensor types
+pub fn synthetic_batch(offset: usize, count: usize) -> Vec<SensorData> {
+    (offset..offset + count)
+        .map(|i| synthetic_reading(i, SYNTHETIC_TYPES[i % SYNTHETIC_TYPES.len()]))
+        .collect()
+}
+
+// The `i`-th synthetic reading of a type; unknown types get a generic numeric payload
+pub fn synthetic_reading(i: usize, sensor_type: &str) -> SensorData {
+    let payload = match sensor_type {
+        "energy" => serde_json::json!({ "energy": (i % 1000) as f64 * 1.5 }),
+        "air_quality" => serde_json::json!({
+            "co2": 400 + (i % 600),
+            "pm25": i % 50,
+            "humidity": 30 + (i % 50),
+        }),
+        "motion" => serde_json::json!({ "motionDetected": i.is_multiple_of(2) }),
+        _ => serde_json::json!({ "value": (i % 1000) as f64 }),
+    };
+    SensorData {
+        r#type: sensor_type.to_string(),
+        name: format!("bench-sensor-{}", i % 100),
+        payload: payload.into(),
+        timestamp: None,
+    }
+}
+
+// Suffix of the exchange and queue the benchmark runs through, so it never publishes
+// into, or consumes from, the ones production uses
+pub const BENCH_SUFFIX: &str = ".bench";
+
+// The broker settings for a benchmark: its own exchange and a plain queue, declared here,
+// without dead-lettering, quarantine or message dedup
+pub fn bench_rabbitmq_config(config: &RabbitMQConfig) -> RabbitMQConfig {
+    RabbitMQConfig {
+        exchange_name: format!("{}{}", config.exchange_name, BENCH_SUFFIX),
+        queue_name: format!("{}{}", config.queue_name, BENCH_SUFFIX),
+        manage_topology: true,
+        stream: None,
+        dead_letter: None,
+        quarantine: QuarantineMode::None,
+        message_dedup: None,
+        ..config.clone()
+    }
+}
+
+/// Publishes `messages` synthetic readings through a dedicated bench exchange and queue
+/// and consumes them, timing every consumed batch. Readings are only written to the
+/// configured database with `store`. Fails as soon as publishing, consuming or an insert
+/// does, instead of waiting for readings that will never arrive.
+pub async fn run(config: &Config, options: BenchOptions) -> Result<BenchReport> {
+    anyhow::ensure!(options.messages > 0, "--messages must be greater than zero");
+    let batch = options.batch.max(1);
+    let total = options.messages;
+    let rabbitmq = bench_rabbitmq_config(&config.rabbitmq);
+    
+    let database = if options.store {
+        Some(Arc::new(Database::new(&config.database).await?))
+    } else {
+        None
+    };
+    
+    let mut consumer = RabbitMQConsumer::new(&rabbitmq).await?;
+    let producer = RabbitMQProducer::from_config(&rabbitmq, rabbitmq.exchange_name.clone()).await?;
+    
+    let received = Arc::new(AtomicUsize::new(0));
+    let inserted = Arc::new(AtomicUsize::new(0));
+    let insert_time = Arc::new(Mutex::new(Duration::ZERO));
+    let latencies = Arc::new(Mutex::new(Vec::new()));
+    // First insert error; it ends the run like the last reading does
+    let failure = Arc::new(Mutex::new(None));
+    let done = Arc::new(Notify::new());
+    
+    info!(
+        "Benchmark: publishing {} readings in batches of {} through {}",
+        total, batch, rabbitmq.exchange_name
+    );
+    let started = Instant::now();
+    
+    let routing_key = rabbitmq.routing_key.clone();
+    let mut publisher = tokio::spawn(async move {
+        let mut offset = 0;
+        while offset < total {
+            let count = batch.min(total - offset);
+            producer.send_sensor_data(&routing_key, &synthetic_batch(offset, count)).await?;
+            offset += count;
+        }
+        producer.close().await
+    });
+    
+    let consume = consumer.consume_messages(|sensor_data, context| {
+        let database = database.clone();
+        let received = received.clone();
+        let inserted = inserted.clone();
+        let insert_time = insert_time.clone();
+        let latencies = latencies.clone();
+        let failure = failure.clone();
+        let done = done.clone();
+        
+        async move {
+            let batch_started = Instant::now();
+            let count = sensor_data.len();
+            
+            if let Some(database) = database {
+                let inputs = sensor_data
+                    .into_iter()
+                    .map(|data| SensorReadingInput {
+                        sensor_type: data.r#type,
+                        sensor_name: data.name,
+                        payload: data.payload.into_inner(),
+                        timestamp: chrono::Utc::now(),
+                        source_id: context.source_id.clone(),
+                        location: None,
+                    })
+                    .collect();
+                let insert_started = Instant::now();
+                match database.insert_batch_sensor_readings(inputs).await {
+                    Ok(rows) => {
+                        *insert_time.lock().await += insert_started.elapsed();
+                        inserted.fetch_add(rows.len(), Ordering::Relaxed);
+                    }
+                    Err(e) => {
+                        failure.lock().await.get_or_insert(e);
+                        done.notify_one();
+                        return Ok(());
+                    }
+                }
+            }
+            
+            latencies.lock().await.push(batch_started.elapsed());
+            if received.fetch_add(count, Ordering::Relaxed) + count >= total {
+                done.notify_one();
+            }
+            Ok(())
+        }
+    });
+    
+    let outcome = async {
+        tokio::pin!(consume);
+        let mut publishing = true;
+        loop {
+            tokio::select! {
+                result = &mut consume => {
+                    result?;
+                    anyhow::bail!(
+                        "Consumer stopped after {} of {} readings",
+                        received.load(Ordering::Relaxed),
+                        total
+                    );
+                }
+                _ = done.notified() => break,
+                result = &mut publisher, if publishing => {
+                    result?.context("Benchmark publisher failed")?;
+                    publishing = false;
+                }
+            }
+        }
+        match failure.lock().await.take() {
+            Some(e) => Err(e.context("Benchmark insert failed")),
+            None => Ok(()),
+        }
+    }
+    .await;
+    let wall_time = started.elapsed();
+    
+    publisher.abort();
+    if let Err(e) = consumer.close().await {
+        error!("Failed to close the benchmark consumer: {}", e);
+    }
+    outcome?;
+    
+    let batch_latencies = std::mem::take(&mut *latencies.lock().await);
+    let insert_time = *insert_time.lock().await;
+    Ok(BenchReport {
+        messages: total,
+        wall_time,
+        batch_latencies,
+        inserted: inserted.load(Ordering::Relaxed),
+        insert_time,
+        dry_run: !options.store,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn runs_apart_from_the_production_topology() {
+        let config = Config::default();
+        let rabbitmq = bench_rabbitmq_config(&config.rabbitmq);
+        
+        assert_eq!(rabbitmq.exchange_name, format!("{}.bench", config.rabbitmq.exchange_name));
+        assert_eq!(rabbitmq.queue_name, format!("{}.bench", config.rabbitmq.queue_name));
+        assert!(rabbitmq.manage_topology);
+        assert!(rabbitmq.dead_letter.is_none());
+        assert_eq!(rabbitmq.quarantine, QuarantineMode::None);
+    }
+}
@@ -0,0 +1,105 @@
+use std::collections::BTreeSet;
+
+// Stream offsets received and not yet settled, and the highest offset below which every
+// received delivery has settled. Deliveries finish out of order with several in flight,
+// so only that low-water mark is safe to checkpoint: a restart resumes just after it.
+#[derive(Debug, Default)]
+pub struct OffsetTracker {
+    outstanding: BTreeSet<i64>,
+    // Highest settled offset so far
+    settled: Option<i64>,
+    low_water: Option<i64>,
+}
+
+impl OffsetTracker {
+    // `checkpoint` is where consumption resumed from, if anywhere
+    pub fn new(checkpoint: Option<i64>) -> Self {
+        Self {
+            low_water: checkpoint,
+            ..Self::default()
+        }
+    }
+    
+    pub fn received(&mut self, offset: i64) {
+        self.outstanding.insert(offset);
+    }
+    
+    // Marks the delivery at `offset` processed or dead-lettered. Returns the new low-water
+    // mark when it moved forward.
+    pub fn settled(&mut self, offset: i64) -> Option<i64> {
+        if !self.outstanding.remove(&offset) {
+            return None;
+        }
+        self.settled = self.settled.max(Some(offset));
+        let candidate = match self.outstanding.first() {
+            // Offsets arrive in order, so everything received below it has settled
+            Some(first) => first - 1,
+            None => self.settled?,
+        };
+        if self.low_water.is_some_and(|low_water| candidate <= low_water) {
+            return None;
+        }
+        self.low_water = Some(candidate);
+        self.low_water
+    }
+    
+    pub fn low_water(&self) -> Option<i64> {
+        self.low_water
+    }
+    
+    // Forgets deliveries of a lost channel; they are consumed again from the low-water mark
+    pub fn reset(&mut self) {
+        self.outstanding.clear();
+        self.settled = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn advances_in_order() {
+        let mut offsets = OffsetTracker::new(None);
+        offsets.received(10);
+        offsets.received(11);
+        
+        assert_eq!(offsets.settled(10), Some(10));
+        assert_eq!(offsets.settled(11), Some(11));
+    }
+    
+    #[test]
+    fn holds_behind_an_unsettled_offset() {
+        let mut offsets = OffsetTracker::new(Some(9));
+        for offset in 10..=13 {
+            offsets.received(offset);
+        }
+        
+        assert_eq!(offsets.settled(12), None);
+        assert_eq!(offsets.settled(13), None);
+        assert_eq!(offsets.low_water(), Some(9));
+        assert_eq!(offsets.settled(11), None);
+        assert_eq!(offsets.settled(10), Some(13));
+    }
+    
+    #[test]
+    fn never_moves_backwards() {
+        let mut offsets = OffsetTracker::new(Some(20));
+        offsets.received(15);
+        
+        assert_eq!(offsets.settled(15), None);
+        assert_eq!(offsets.low_water(), Some(20));
+    }
+    
+    #[test]
+    fn ignores_offsets_forgotten_by_reset() {
+        let mut offsets = OffsetTracker::new(None);
+        offsets.received(1);
+        offsets.received(2);
+        assert_eq!(offsets.settled(1), Some(1));
+        offsets.reset();
+        
+        assert_eq!(offsets.settled(2), None);
+        assert_eq!(offsets.low_water(), Some(1));
+    }
+}
@@ -0,0 +1,122 @@
+use anyhow::Result;
+use crate::models::SensorData;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+pub const JSON_CONTENT_TYPE: &str = "application/json";
+
+// Message body format, selected by the delivery's content_type property. Custom formats
+// are registered with `DataProcessor::with_codec` or `RabbitMQConsumer::with_codec`.
+pub trait PayloadCodec: Send + Sync {
+    fn content_type(&self) -> &str;
+    fn decode(&self, data: &[u8]) -> Result<Vec<SensorData>>;
+    fn encode(&self, readings: &[SensorData]) -> Result<Vec<u8>>;
+}
+
+pub struct JsonCodec;
+
+impl PayloadCodec for JsonCodec {
+    fn content_type(&self) -> &str {
+        JSON_CONTENT_TYPE
+    }
+    
+    fn decode(&self, data: &[u8]) -> Result<Vec<SensorData>> {
+        Ok(serde_json::from_slice(data)?)
+    }
+    
+    fn encode(&self, readings: &[SensorData]) -> Result<Vec<u8>> {
+        Ok(serde_json::to_vec(readings)?)
+    }
+}
+
+// Registered codecs by content type; deliveries without a content type use the default
+#[derive(Clone)]
+pub struct Codecs {
+    codecs: HashMap<String, Arc<dyn PayloadCodec>>,
+    default_content_type: String,
+}
+
+impl Codecs {
+    pub fn new(default_content_type: &str) -> Self {
+        let mut codecs = Self {
+            codecs: HashMap::new(),
+            default_content_type: media_type(default_content_type),
+        };
+        codecs.register(Arc::new(JsonCodec));
+        codecs
+    }
+    
+    // Replaces any codec already registered for the same content type
+    pub fn register(&mut self, codec: Arc<dyn PayloadCodec>) {
+        self.codecs.insert(media_type(codec.content_type()), codec);
+    }
+    
+    pub fn for_content_type(&self, content_type: Option<&str>) -> Result<Arc<dyn PayloadCodec>> {
+        let content_type = content_type.map(media_type).unwrap_or_else(|| self.default_content_type.clone());
+        self.codecs
+            .get(&content_type)
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("No codec registered for content type '{}'", content_type))
+    }
+}
+
+// "Application/JSON; charset=utf-8" -> "application/json"
+fn media_type(content_type: &str) -> String {
+    content_type.split(';').next().unwrap_or_default().trim().to_ascii_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    // Comma-separated `type,name` lines with no payload
+    struct CsvCodec;
+    
+    impl PayloadCodec for CsvCodec {
+        fn content_type(&self) -> &str {
+            "text/csv"
+        }
+        
+        fn decode(&self, data: &[u8]) -> Result<Vec<SensorData>> {
+            std::str::from_utf8(data)?
+                .lines()
+                .map(|line| {
+                    let (sensor_type, name) = line.split_once(',').ok_or_else(|| anyhow::anyhow!("bad line"))?;
+                    Ok(SensorData {
+                        r#type: sensor_type.to_string(),
+                        name: name.to_string(),
+                        payload: serde_json::json!({}).into(),
+                        timestamp: None,
+                    })
+                })
+                .collect()
+        }
+        
+        fn encode(&self, readings: &[SensorData]) -> Result<Vec<u8>> {
+            let lines: Vec<String> = readings.iter().map(|r| format!("{},{}", r.r#type, r.name)).collect();
+            Ok(lines.join("\n").into_bytes())
+        }
+    }
+    
+    #[test]
+    fn selects_codecs_by_media_type() {
+        let mut codecs = Codecs::new("application/json");
+        codecs.register(Arc::new(CsvCodec));
+        
+        assert_eq!(codecs.for_content_type(None).unwrap().content_type(), JSON_CONTENT_TYPE);
+        assert_eq!(codecs.for_content_type(Some("Application/JSON; charset=utf-8")).unwrap().content_type(), JSON_CONTENT_TYPE);
+        let csv = codecs.for_content_type(Some("text/csv")).unwrap();
+        let readings = csv.decode(b"temperature,meter-1\nhumidity,meter-2").unwrap();
+        assert_eq!(readings[1].name, "meter-2");
+        assert_eq!(csv.encode(&readings).unwrap(), b"temperature,meter-1\nhumidity,meter-2");
+        assert!(codecs.for_content_type(Some("application/msgpack")).is_err());
+    }
+    
+    #[test]
+    fn a_custom_default_applies_to_untyped_deliveries() {
+        let mut codecs = Codecs::new("text/csv");
+        assert!(codecs.for_content_type(None).is_err());
+        codecs.register(Arc::new(CsvCodec));
+        assert_eq!(codecs.for_content_type(None).unwrap().content_type(), "text/csv");
+    }
+}
@@ -1,54 +1,379 @@
-use anyhow::Result;
-use chrono::{DateTime, Utc};
-use sqlx::PgPool;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Datelike, Months, NaiveDate, Utc};
+use serde_json::Value;
+use futures_util::TryStreamExt;
+use sqlx::pool::PoolConnection;
+use sqlx::postgres::PgPoolOptions;
+use std::borrow::Cow;
+use sqlx::{Connection, PgConnection, PgPool, Postgres, QueryBuilder, Transaction};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+use sqlx::migrate::MigrateError;
+use tracing::{debug, error, info, warn};
 use uuid::Uuid;
-use crate::models::{SensorReading, SensorReadingInput};
+use crate::config::{DatabaseConfig, Failback, RetryJitter};
+use crate::encryption::FieldEncryptor;
+use crate::integrity::{HashedReading, IntegrityReport, ReadBackReport, ReadingHasher};
+use crate::location;
+use crate::logical_batch::BatchPart;
+use crate::retry;
+use crate::write_quota::WriteQuota;
+use crate::diagnostics::TypeSummary;
+use crate::schema::LiveColumn;
+use crate::models::{
+    AuditEvent, BatchCompletedEvent, GapFill, GeoPoint, QuarantinedMessage, ReadingField, ReadingGap, ReadingQuery, RollupBucket, RollupQuery,
+    SensorReading, SensorReadingInput, SensorRegistryEntry, TimeBucket,
+};
+
+// Advisory lock key serializing migrations across replicas ("dps_migr")
+const MIGRATION_LOCK_KEY: i64 = 0x6470_735f_6d69_6772;
+
+// Completed logical batches are remembered this long, so late redeliveries of their parts
+// don't start them again
+const COMPLETED_BATCH_RETENTION: &str = "7 days";
+
+// How often a replica waiting for the migration lock retries it
+const MIGRATION_LOCK_POLL: Duration = Duration::from_millis(500);
+
+// Longest a partition detach waits for its lock on `sensor_readings`, so it never queues
+// inserts behind a long-running query; the next maintenance pass tries again
+const DETACH_LOCK_TIMEOUT: &str = "5s";
+
+// Bind parameters per batch INSERT, well under Postgres's limit of 65535
+const INSERT_BATCH_MAX_PARAMS: usize = 10_000;
+const INSERT_COLUMNS: usize = 11;
 
 pub struct Database {
     pool: PgPool,
+    // Pools the service fails over to, in order of preference after the primary
+    failover: Vec<PgPool>,
+    // Pool everything but migrations currently goes to: 0 is `pool`, n is `failover[n - 1]`
+    write_target: AtomicUsize,
+    failback: Failback,
+    payload_hashing: bool,
+    // Present whenever a hash key is configured, which `payload_hashing` requires
+    hasher: Option<ReadingHasher>,
+    encryptor: Option<FieldEncryptor>,
+    // Per-type connection reservations for inserts, when `reserved_connections` is set
+    write_quota: Option<WriteQuota>,
+    read_back: Option<ReadBack>,
+    read_back_mismatches: AtomicU64,
+    // `sensor_readings.location` exists (PostGIS installed by migration 012)
+    geography: bool,
+    health_ttl: Duration,
+    // Last health check result, only locked to read or replace it
+    last_health: std::sync::Mutex<Option<(Instant, Result<(), String>)>>,
+    // Held by the one health check querying the database
+    health_probe: Mutex<()>,
+}
+
+// Sampled read-after-write verification of batch inserts
+struct ReadBack {
+    sample_rate: f64,
+    // Separate database to read from; None reads from the write target
+    pool: Option<PgPool>,
+}
+
+// Connection drops and statements racing another migrator are worth another attempt;
+// a history that doesn't match this build's migrations is not
+fn retryable_migration_error(error: &anyhow::Error) -> bool {
+    match error.downcast_ref::<MigrateError>() {
+        Some(MigrateError::Execute(_)) | None => true,
+        Some(_) => false,
+    }
 }
 
 impl Database {
-    pub async fn new(database_url: &str) -> Result<Self> {
-        let pool = PgPool::connect(database_url).await?;
+    pub async fn new(config: &DatabaseConfig) -> Result<Self> {
+        let mut database = Self::connect(config).await?;
+        
+        // The lock wait is bounded across attempts, so retries can't stretch a rollout
+        let lock_deadline = Instant::now() + Duration::from_secs(config.migration_lock_timeout_seconds);
+        let max_attempts = config.migration_max_attempts.max(1);
+        let mut delay = Duration::from_millis(config.migration_retry_delay_ms);
+        let mut attempt = 1;
+        loop {
+            match database.run_migrations(lock_deadline, config.partitioning.enabled).await {
+                Ok(()) => break,
+                Err(e) if attempt < max_attempts && Instant::now() < lock_deadline && retryable_migration_error(&e) => {
+                    warn!("Migrations failed (attempt {}/{}), retrying: {:#}", attempt, max_attempts, e);
+                    tokio::time::sleep(retry::jittered_delay(delay, RetryJitter::Equal)).await;
+                    delay *= 2;
+                    attempt += 1;
+                }
+                Err(e) => return Err(e.context("Failed to run database migrations")),
+            }
+        }
+        database.geography = sqlx::query_scalar::<_, bool>(
+            r#"
+            SELECT EXISTS (
+                SELECT 1 FROM information_schema.columns
+                WHERE table_name = 'sensor_readings' AND column_name = 'location'
+            )
+            "#,
+        )
+        .fetch_one(&database.pool)
+        .await?;
+        if database.geography {
+            info!("PostGIS is available, radius queries use sensor_readings.location");
+        }
+        
+        Ok(database)
+    }
+    
+    // Applies pending migrations while holding a session advisory lock, so replicas that
+    // start together run them one at a time and the others find nothing left to do.
+    // With `partition` set, `sensor_readings` is also converted to a partitioned table.
+    async fn run_migrations(&self, lock_deadline: Instant, partition: bool) -> Result<()> {
+        let mut conn = self.pool.acquire().await?;
+        let started = Instant::now();
+        let mut waiting = false;
+        loop {
+            let locked = sqlx::query_scalar::<_, bool>("SELECT pg_try_advisory_lock($1)")
+                .bind(MIGRATION_LOCK_KEY)
+                .fetch_one(&mut *conn)
+                .await?;
+            if locked {
+                break;
+            }
+            if Instant::now() >= lock_deadline {
+                anyhow::bail!(
+                    "Timed out after {:?} waiting for another replica to finish migrations",
+                    started.elapsed()
+                );
+            }
+            if !waiting {
+                info!("Another replica is running migrations, waiting for the lock");
+                waiting = true;
+            }
+            tokio::time::sleep(MIGRATION_LOCK_POLL).await;
+        }
+        if waiting {
+            info!("Migration lock acquired after {:?}", started.elapsed());
+        }
+        
+        // `run` on a borrowed connection makes this future !Send (sqlx's `Acquire` lifetime
+        // issue); `run_direct` is sqlx's own workaround for it
+        let result = match sqlx::migrate!("./migrations").run_direct(&mut *conn).await {
+            Ok(()) if partition => partition_sensor_readings(&mut conn).await,
+            result => result.map_err(Into::into),
+        };
+        
+        // Release explicitly: the connection returns to the pool, so the session lives on
+        let unlocked = sqlx::query("SELECT pg_advisory_unlock($1)")
+            .bind(MIGRATION_LOCK_KEY)
+            .execute(&mut *conn)
+            .await;
+        if let Err(e) = unlocked {
+            // Closing the connection ends the session and frees the lock with it
+            warn!("Failed to release the migration lock, closing its connection: {}", e);
+            drop(conn.detach());
+        }
+        
+        result?;
+        Ok(())
+    }
+    
+    // Connects without running migrations, for tools that inspect the schema as it is
+    pub async fn connect(config: &DatabaseConfig) -> Result<Self> {
+        Self::build(config, false).await
+    }
+    
+    // Opens no connection until the first query, for tests of code paths that must not reach
+    // the database
+    #[cfg(test)]
+    pub(crate) async fn unconnected(config: &DatabaseConfig) -> Result<Self> {
+        Self::build(config, true).await
+    }
+    
+    async fn build(config: &DatabaseConfig, lazy: bool) -> Result<Self> {
+        let mut options = PgPoolOptions::new()
+            .max_connections(config.max_connections)
+            .min_connections(config.min_connections)
+            .acquire_timeout(Duration::from_secs(config.acquire_timeout_seconds));
+        
+        // Recycle connections so server-side state and proxy idle timeouts don't bite
+        if let Some(max_lifetime) = config.max_lifetime_seconds {
+            options = options.max_lifetime(Duration::from_secs(max_lifetime));
+        }
+        if let Some(idle_timeout) = config.idle_timeout_seconds {
+            options = options.idle_timeout(Duration::from_secs(idle_timeout));
+        }
         
-        // Run migrations
-        sqlx::migrate!("./migrations").run(&pool).await?;
+        let hasher = ReadingHasher::from_config(config)?;
+        let encryptor = config
+            .field_encryption
+            .as_ref()
+            .map(FieldEncryptor::from_config)
+            .transpose()?;
+        let (write_quota, shared_connections) = if config.reserved_connections.is_empty() {
+            (None, config.max_connections)
+        } else {
+            let shared = WriteQuota::shared_connections(config.max_connections, &config.reserved_connections)?;
+            let quota = WriteQuota::new(&options, &config.url, &config.reserved_connections)?;
+            (Some(quota), shared)
+        };
+        let read_back = match &config.read_back {
+            Some(read_back) => {
+                if !(0.0..=1.0).contains(&read_back.sample_rate) {
+                    anyhow::bail!("database.read_back.sample_rate must be between 0.0 and 1.0");
+                }
+                let pool = read_back
+                    .url
+                    .as_deref()
+                    .map(|url| options.clone().connect_lazy(url))
+                    .transpose()?;
+                Some(ReadBack { sample_rate: read_back.sample_rate, pool })
+            }
+            None => None,
+        };
+        let shared = options
+            .clone()
+            .max_connections(shared_connections)
+            .min_connections(config.min_connections.min(shared_connections));
+        let pool = if lazy {
+            shared.connect_lazy(&config.url)?
+        } else {
+            shared.connect(&config.url).await?
+        };
+        // Lazy, so a standby that is down doesn't hold up startup
+        let failover = config
+            .failover_targets
+            .iter()
+            .map(|url| options.clone().connect_lazy(url))
+            .collect::<Result<_, _>>()?;
         
-        Ok(Self { pool })
+        Ok(Self {
+            pool,
+            failover,
+            write_target: AtomicUsize::new(0),
+            failback: config.failback,
+            payload_hashing: config.payload_hashing,
+            hasher,
+            encryptor,
+            write_quota,
+            read_back,
+            read_back_mismatches: AtomicU64::new(0),
+            geography: false,
+            health_ttl: Duration::from_millis(config.health_check_ttl_ms),
+            last_health: std::sync::Mutex::new(None),
+            health_probe: Mutex::new(()),
+        })
     }
     
     pub async fn insert_sensor_reading(&self, data: SensorReadingInput) -> Result<SensorReading> {
         let id = Uuid::new_v4();
         let now = Utc::now();
+        let payload = self.seal(&data.sensor_type, &data.payload)?;
+        let mut connection = self.insert_connection(Some(&data.sensor_type)).await?;
         
         let sensor_reading = sqlx::query_as::<_, SensorReading>(
             r#"
-            INSERT INTO sensor_readings (id, sensor_type, sensor_name, payload, timestamp, created_at)
-            VALUES ($1, $2, $3, $4, $5, $6)
+            INSERT INTO sensor_readings (id, sensor_type, sensor_name, payload, timestamp, created_at, source_id, payload_hash, hash_scope, latitude, longitude)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
             RETURNING *
             "#,
         )
         .bind(id)
         .bind(&data.sensor_type)
         .bind(&data.sensor_name)
-        .bind(&data.payload)
+        .bind(payload.as_ref())
         .bind(data.timestamp)
         .bind(now)
-        .fetch_one(&self.pool)
+        .bind(&data.source_id)
+        .bind(self.new_reading_hash(&HashedReading {
+            sensor_type: &data.sensor_type,
+            sensor_name: &data.sensor_name,
+            payload: &payload,
+            timestamp: data.timestamp,
+            source_id: data.source_id.as_deref(),
+            location: data.location,
+        }))
+        .bind(self.new_reading_hash_scope())
+        .bind(data.location.map(|location| location.latitude))
+        .bind(data.location.map(|location| location.longitude))
+        .fetch_one(&mut *connection)
         .await?;
         
-        Ok(sensor_reading)
+        Ok(SensorReading { payload: data.payload, ..sensor_reading })
     }
     
+    // One multi-row INSERT per chunk, all in one transaction so a failed chunk leaves
+    // nothing of the batch behind
     pub async fn insert_batch_sensor_readings(&self, data_batch: Vec<SensorReadingInput>) -> Result<Vec<SensorReading>> {
-        let mut results = Vec::new();
+        let mut results = Vec::with_capacity(data_batch.len());
+        if data_batch.is_empty() {
+            return Ok(results);
+        }
+        // Batches from the processor hold a single sensor type; a mixed one has no reservation
+        let first_type = data_batch[0].sensor_type.as_str();
+        let batch_type = data_batch.iter().all(|data| data.sensor_type == first_type).then_some(first_type);
+        let mut connection = self.insert_connection(batch_type).await?;
+        let mut tx = connection.begin().await?;
+        // One draw per batch, so an unsampled batch costs nothing and a sampled one one query
+        let read_back = self
+            .read_back
+            .as_ref()
+            .is_some_and(|read_back| rand::random::<f64>() < read_back.sample_rate);
+        let mut sampled = Vec::new();
         
-        for data in data_batch {
-            let result = self.insert_sensor_reading(data).await?;
-            results.push(result);
+        for chunk in data_batch.chunks(INSERT_BATCH_MAX_PARAMS / INSERT_COLUMNS) {
+            let rows = chunk
+                .iter()
+                .map(|data| {
+                    let payload = self.seal(&data.sensor_type, &data.payload)?;
+                    let payload_hash = self.new_reading_hash(&HashedReading {
+                        sensor_type: &data.sensor_type,
+                        sensor_name: &data.sensor_name,
+                        payload: &payload,
+                        timestamp: data.timestamp,
+                        source_id: data.source_id.as_deref(),
+                        location: data.location,
+                    });
+                    // Per row, like `insert_sensor_reading`, so created_at still orders the batch
+                    Ok((Uuid::new_v4(), Utc::now(), data, payload, payload_hash))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            
+            let mut query = QueryBuilder::<Postgres>::new(
+                "INSERT INTO sensor_readings (id, sensor_type, sensor_name, payload, timestamp, created_at, source_id, payload_hash, hash_scope, latitude, longitude) ",
+            );
+            query.push_values(&rows, |mut row, (id, created_at, data, payload, payload_hash)| {
+                row.push_bind(*id)
+                    .push_bind(&data.sensor_type)
+                    .push_bind(&data.sensor_name)
+                    .push_bind(payload.as_ref())
+                    .push_bind(data.timestamp)
+                    .push_bind(*created_at)
+                    .push_bind(&data.source_id)
+                    .push_bind(payload_hash.as_deref())
+                    .push_bind(self.new_reading_hash_scope())
+                    .push_bind(data.location.map(|location| location.latitude))
+                    .push_bind(data.location.map(|location| location.longitude));
+            });
+            query.push(" RETURNING *");
+            let inserted = query.build_query_as::<SensorReading>().fetch_all(&mut *tx).await?;
+            if read_back {
+                sampled.extend(inserted.iter().cloned());
+            }
+            // RETURNING order isn't guaranteed, so results follow the input by the ids generated
+            // above. Callers get the plaintext they passed in; nothing is decrypted on the write path.
+            let mut inserted: HashMap<Uuid, SensorReading> = inserted.into_iter().map(|reading| (reading.id, reading)).collect();
+            for (id, _, data, _, _) in &rows {
+                let reading = inserted
+                    .remove(id)
+                    .ok_or_else(|| anyhow::anyhow!("Inserted reading {} was not returned", id))?;
+                results.push(SensorReading { payload: data.payload.clone(), ..reading });
+            }
         }
         
+        tx.commit().await?;
+        drop(connection);
+        self.check_read_back(&sampled).await;
         Ok(results)
     }
     
@@ -57,10 +382,10 @@ impl Database {
             "SELECT * FROM sensor_readings WHERE sensor_type = $1 ORDER BY timestamp DESC"
         )
         .bind(sensor_type)
-        .fetch_all(&self.pool)
+        .fetch_all(self.active_pool())
         .await?;
         
-        Ok(data)
+        self.open_all(data)
     }
     
     pub async fn get_sensor_readings_by_name(&self, sensor_name: &str) -> Result<Vec<SensorReading>> {
@@ -68,10 +393,282 @@ impl Database {
             "SELECT * FROM sensor_readings WHERE sensor_name = $1 ORDER BY timestamp DESC"
         )
         .bind(sensor_name)
-        .fetch_all(&self.pool)
+        .fetch_all(self.active_pool())
         .await?;
         
-        Ok(data)
+        self.open_all(data)
+    }
+    
+    pub async fn get_sensor_readings_by_source(&self, source_id: &str) -> Result<Vec<SensorReading>> {
+        let data = sqlx::query_as::<_, SensorReading>(
+            "SELECT * FROM sensor_readings WHERE source_id = $1 ORDER BY timestamp DESC"
+        )
+        .bind(source_id)
+        .fetch_all(self.active_pool())
+        .await?;
+        
+        self.open_all(data)
+    }
+    
+    // Newest first, at most `limit` rows (default 100); the API caps `limit` before calling
+    // Only the columns of `fields` are read; the others come back as placeholders
+    pub async fn query_sensor_readings(
+        &self,
+        query: &ReadingQuery,
+        fields: &[ReadingField],
+        timeout: Option<Duration>,
+    ) -> Result<Vec<SensorReading>> {
+        let mut tx = self.read_transaction(timeout).await?;
+        // The table name keeps ORDER BY on the column rather than a placeholder alias
+        let sql = format!(
+            r#"
+            SELECT {} FROM sensor_readings
+            WHERE ($1::text IS NULL OR sensor_type = $1)
+                AND ($2::text IS NULL OR sensor_name = $2)
+                AND ($3::text IS NULL OR source_id = $3)
+                AND ($4::timestamptz IS NULL OR timestamp >= $4)
+                AND ($5::timestamptz IS NULL OR timestamp < $5)
+            ORDER BY sensor_readings.timestamp DESC
+            LIMIT $6
+            "#,
+            reading_columns(fields)
+        );
+        let data = sqlx::query_as::<_, SensorReading>(&sql)
+            .bind(&query.sensor_type)
+            .bind(&query.sensor_name)
+            .bind(&query.source_id)
+            .bind(query.from)
+            .bind(query.to)
+            .bind(query.limit.unwrap_or(100))
+            .fetch_all(&mut *tx)
+            .await?;
+        
+        self.open_all(data)
+    }
+    
+    // One page of [from, to) in (timestamp, id) order, starting after `after`
+    pub async fn get_sensor_readings_page(
+        &self,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        after: Option<(DateTime<Utc>, Uuid)>,
+        limit: i64,
+    ) -> Result<Vec<SensorReading>> {
+        let data = sqlx::query_as::<_, SensorReading>(
+            r#"
+            SELECT * FROM sensor_readings
+            WHERE timestamp >= $1 AND timestamp < $2
+                AND ($3::timestamptz IS NULL OR (timestamp, id) > ($3, $4))
+            ORDER BY timestamp, id
+            LIMIT $5
+            "#,
+        )
+        .bind(from)
+        .bind(to)
+        .bind(after.map(|(timestamp, _)| timestamp))
+        .bind(after.map(|(_, id)| id))
+        .bind(limit)
+        .fetch_all(self.active_pool())
+        .await?;
+        
+        self.open_all(data)
+    }
+    
+    // Rewrites the name and payload (and the hash) of each reading in one transaction
+    pub async fn update_readings(&self, readings: &[SensorReading]) -> Result<u64> {
+        let mut tx = self.active_pool().begin().await?;
+        let mut updated = 0;
+        for reading in readings {
+            let payload = self.seal(&reading.sensor_type, &reading.payload)?;
+            let result = sqlx::query(
+                r#"
+                UPDATE sensor_readings
+                SET payload = $3,
+                    sensor_name = $7,
+                    payload_hash = CASE WHEN payload_hash IS NULL AND NOT $5 THEN NULL ELSE $4 END,
+                    hash_scope = CASE WHEN payload_hash IS NULL AND NOT $5 THEN NULL ELSE $6 END
+                WHERE id = $1 AND timestamp = $2
+                "#,
+            )
+            .bind(reading.id)
+            .bind(reading.timestamp)
+            .bind(payload.as_ref())
+            .bind(self.hasher.as_ref().map(|hasher| {
+                hasher.hash(&HashedReading {
+                    sensor_type: &reading.sensor_type,
+                    sensor_name: &reading.sensor_name,
+                    payload: &payload,
+                    timestamp: reading.timestamp,
+                    source_id: reading.source_id.as_deref(),
+                    location: stored_location(reading.latitude, reading.longitude),
+                })
+            }))
+            .bind(self.payload_hashing)
+            .bind(self.hasher.as_ref().map(|hasher| hasher.scope().as_str()))
+            .bind(&reading.sensor_name)
+            .execute(&mut *tx)
+            .await?;
+            updated += result.rows_affected();
+        }
+        tx.commit().await?;
+        
+        Ok(updated)
+    }
+    
+    // Readings in [from, to) within `radius_m` metres of `center`, nearest first. With
+    // PostGIS this is an ST_DWithin search on the `location` geography (distances on the
+    // WGS84 spheroid); otherwise a latitude/longitude bounding box narrows the rows whose
+    // great-circle distance is computed.
+    pub async fn readings_near(
+        &self,
+        center: GeoPoint,
+        radius_m: f64,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        timeout: Option<Duration>,
+    ) -> Result<Vec<SensorReading>> {
+        let mut tx = self.read_transaction(timeout).await?;
+        if self.geography {
+            let data = sqlx::query_as::<_, SensorReading>(
+                r#"
+                SELECT * FROM (
+                    SELECT *, ST_Distance(location, center) AS distance_m
+                    FROM sensor_readings,
+                        (SELECT ST_SetSRID(ST_MakePoint($2, $1), 4326)::geography AS center) c
+                    WHERE ST_DWithin(location, center, $5)
+                        AND timestamp >= $3 AND timestamp < $4
+                ) nearby
+                ORDER BY distance_m
+                "#,
+            )
+            .bind(center.latitude)
+            .bind(center.longitude)
+            .bind(from)
+            .bind(to)
+            .bind(radius_m)
+            .fetch_all(&mut *tx)
+            .await?;
+            
+            return self.open_all(data);
+        }
+        let ((min_lat, max_lat), lon_bounds) = location::bounding_box(center, radius_m);
+        let data = sqlx::query_as::<_, SensorReading>(
+            r#"
+            SELECT * FROM (
+                -- least() keeps rounding near the antipode from pushing asin past 1.0
+                SELECT *, 2 * $5 * asin(least(1.0, sqrt(
+                    power(sin(radians(latitude - $1) / 2), 2)
+                    + cos(radians($1)) * cos(radians(latitude)) * power(sin(radians(longitude - $2) / 2), 2)
+                ))) AS distance_m
+                FROM sensor_readings
+                WHERE latitude IS NOT NULL
+                    AND latitude BETWEEN $6 AND $7
+                    AND ($8::float8 IS NULL OR longitude BETWEEN $8 AND $9)
+                    AND timestamp >= $3 AND timestamp < $4
+            ) nearby
+            WHERE distance_m <= $10
+            ORDER BY distance_m
+            "#,
+        )
+        .bind(center.latitude)
+        .bind(center.longitude)
+        .bind(from)
+        .bind(to)
+        .bind(location::EARTH_RADIUS_M)
+        .bind(min_lat)
+        .bind(max_lat)
+        .bind(lon_bounds.map(|(min, _)| min))
+        .bind(lon_bounds.map(|(_, max)| max))
+        .bind(radius_m)
+        .fetch_all(&mut *tx)
+        .await?;
+        
+        self.open_all(data)
+    }
+    
+    /// Finds stretches in `[from, to)` longer than `expected_interval` without a reading from
+    /// `sensor_name`; the range bounds count as readings, so a silent sensor is one gap.
+    pub async fn detect_gaps(
+        &self,
+        sensor_name: &str,
+        expected_interval: std::time::Duration,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        timeout: Option<Duration>,
+    ) -> Result<Vec<ReadingGap>> {
+        let mut tx = self.read_transaction(timeout).await?;
+        let gaps = sqlx::query_as::<_, ReadingGap>(
+            r#"
+            WITH points AS (
+                SELECT $2::timestamptz AS at
+                UNION ALL
+                SELECT timestamp FROM sensor_readings
+                WHERE sensor_name = $1 AND timestamp >= $2 AND timestamp < $3
+                UNION ALL
+                SELECT $3::timestamptz
+            ), spans AS (
+                SELECT LAG(at) OVER (ORDER BY at) AS start, at AS "end" FROM points
+            )
+            SELECT start, "end" FROM spans
+            WHERE "end" - start > $4 * INTERVAL '1 second'
+            ORDER BY start
+            "#,
+        )
+        .bind(sensor_name)
+        .bind(from)
+        .bind(to)
+        .bind(expected_interval.as_secs_f64())
+        .fetch_all(&mut *tx)
+        .await?;
+        
+        Ok(gaps)
+    }
+    
+    // Transaction for read queries, bounded by `statement_timeout` when a timeout is given.
+    // Nothing is written, so it is simply dropped (rolled back) afterwards.
+    async fn read_transaction(&self, timeout: Option<Duration>) -> Result<Transaction<'static, Postgres>> {
+        let mut tx = self.active_pool().begin().await?;
+        if let Some(timeout) = timeout {
+            // SET takes no bind parameters; the value is an integer so formatting is safe
+            sqlx::query(&format!("SET LOCAL statement_timeout = {}", timeout.as_millis().max(1)))
+                .execute(&mut *tx)
+                .await?;
+        }
+        Ok(tx)
+    }
+    
+    // Columns of `table` in the current schema, in table order
+    pub async fn table_columns(&self, table: &str) -> Result<Vec<LiveColumn>> {
+        let columns = sqlx::query_as::<_, LiveColumn>(
+            r#"
+            SELECT column_name::text AS name,
+                data_type::text AS data_type,
+                character_maximum_length::int4 AS max_length,
+                is_nullable = 'YES' AS nullable
+            FROM information_schema.columns
+            WHERE table_schema = current_schema() AND table_name = $1
+            ORDER BY ordinal_position
+            "#,
+        )
+        .bind(table)
+        .fetch_all(self.active_pool())
+        .await?;
+        
+        Ok(columns)
+    }
+    
+    // One reading by id, with encrypted fields decrypted
+    pub async fn get_sensor_reading(&self, id: Uuid) -> Result<Option<SensorReading>> {
+        let data = sqlx::query_as::<_, SensorReading>("SELECT * FROM sensor_readings WHERE id = $1")
+            .bind(id)
+            .fetch_optional(self.active_pool())
+            .await?;
+        
+        data.map(|reading| self.open(reading)).transpose()
+    }
+    
+    pub fn encrypts_fields(&self) -> bool {
+        self.encryptor.is_some()
     }
     
     pub async fn get_latest_sensor_readings(&self, limit: i64) -> Result<Vec<SensorReading>> {
@@ -79,10 +676,10 @@ impl Database {
             "SELECT * FROM sensor_readings ORDER BY timestamp DESC LIMIT $1"
         )
         .bind(limit)
-        .fetch_all(&self.pool)
+        .fetch_all(self.active_pool())
         .await?;
         
-        Ok(data)
+        self.open_all(data)
     }
     
     pub async fn get_sensor_readings_by_time_range(
@@ -95,16 +692,1504 @@ impl Database {
         )
         .bind(start_time)
         .bind(end_time)
-        .fetch_all(&self.pool)
+        .fetch_all(self.active_pool())
+        .await?;
+        
+        self.open_all(data)
+    }
+    
+    // Reading counts per bucket between `start_time` and `end_time`, with empty buckets zero-filled
+    pub async fn count_by_time_bucket(
+        &self,
+        interval: TimeBucket,
+        start_time: DateTime<Utc>,
+        end_time: DateTime<Utc>,
+        timeout: Option<Duration>,
+    ) -> Result<Vec<(DateTime<Utc>, i64)>> {
+        let mut tx = self.read_transaction(timeout).await?;
+        let data = sqlx::query_as::<_, (DateTime<Utc>, i64)>(
+            r#"
+            SELECT buckets.bucket, COUNT(r.id) AS count
+            FROM generate_series(
+                date_trunc($1, $2::timestamptz, 'UTC'),
+                $3::timestamptz,
+                ('1 ' || $1)::interval
+            ) AS buckets(bucket)
+            LEFT JOIN sensor_readings r
+                ON r.timestamp >= buckets.bucket
+                AND r.timestamp < buckets.bucket + ('1 ' || $1)::interval
+                AND r.timestamp BETWEEN $2 AND $3
+            GROUP BY buckets.bucket
+            ORDER BY buckets.bucket
+            "#,
+        )
+        .bind(interval.as_str())
+        .bind(start_time)
+        .bind(end_time)
+        .fetch_all(&mut *tx)
         .await?;
         
         Ok(data)
     }
     
-    pub async fn health_check(&self) -> Result<()> {
-        sqlx::query("SELECT 1")
-            .fetch_one(&self.pool)
+    /// Count, average, minimum and maximum of a numeric payload field per bucket, one row
+    /// per bucket in `[from, to)` before `query.fill` is applied. Non-numeric values are
+    /// ignored.
+    pub async fn rollup(&self, query: &RollupQuery, timeout: Option<Duration>) -> Result<Vec<RollupBucket>> {
+        let mut tx = self.read_transaction(timeout).await?;
+        let buckets = sqlx::query_as::<_, RollupBucket>(
+            r#"
+            WITH buckets AS (
+                SELECT generate_series(
+                    date_trunc($1, $3::timestamptz, 'UTC'),
+                    $4::timestamptz - INTERVAL '1 microsecond',
+                    ('1 ' || $1)::interval
+                ) AS bucket
+            ), aggregated AS (
+                SELECT date_trunc($1, timestamp, 'UTC') AS bucket,
+                    COUNT(value) AS count, AVG(value) AS avg, MIN(value) AS min, MAX(value) AS max
+                FROM (
+                    SELECT timestamp,
+                        CASE WHEN jsonb_typeof(payload -> $2) = 'number' THEN (payload ->> $2)::float8 END AS value
+                    FROM sensor_readings
+                    WHERE timestamp >= $3 AND timestamp < $4
+                        AND ($5::text IS NULL OR sensor_type = $5)
+                        AND ($6::text IS NULL OR sensor_name = $6)
+                ) readings
+                GROUP BY 1
+            )
+            SELECT buckets.bucket, COALESCE(aggregated.count, 0) AS count,
+                aggregated.avg, aggregated.min, aggregated.max
+            FROM buckets
+            LEFT JOIN aggregated USING (bucket)
+            ORDER BY buckets.bucket
+            "#,
+        )
+        .bind(query.bucket.as_str())
+        .bind(&query.field)
+        .bind(query.from)
+        .bind(query.to)
+        .bind(&query.sensor_type)
+        .bind(&query.sensor_name)
+        .fetch_all(&mut *tx)
+        .await?;
+        
+        Ok(fill_gaps(buckets, query.fill))
+    }
+    
+    // Readings per sensor type since `since`, with each type's latest timestamp
+    pub async fn summarize_types_since(&self, since: DateTime<Utc>) -> Result<Vec<TypeSummary>> {
+        let summary = sqlx::query_as::<_, TypeSummary>(
+            r#"
+            SELECT sensor_type, COUNT(*) AS readings, MAX(timestamp) AS last_reading
+            FROM sensor_readings
+            WHERE timestamp >= $1
+            GROUP BY sensor_type
+            ORDER BY sensor_type
+            "#,
+        )
+        .bind(since)
+        .fetch_all(self.active_pool())
+        .await?;
+        
+        Ok(summary)
+    }
+    
+    // Planner row estimate summed over the partitions (or the plain table); no table scan
+    pub async fn estimated_reading_count(&self) -> Result<i64> {
+        let estimate = sqlx::query_scalar::<_, i64>(
+            r#"
+            SELECT COALESCE(SUM(GREATEST(reltuples, 0)), 0)::bigint FROM pg_class
+            WHERE relkind = 'r'
+                AND (oid = 'sensor_readings'::regclass
+                    OR oid IN (SELECT inhrelid FROM pg_inherits WHERE inhparent = 'sensor_readings'::regclass))
+            "#,
+        )
+        .fetch_one(self.active_pool())
+        .await?;
+        
+        Ok(estimate)
+    }
+    
+    pub async fn last_reading_time(&self, sensor_type: &str) -> Result<Option<DateTime<Utc>>> {
+        let last = sqlx::query_scalar::<_, Option<DateTime<Utc>>>(
+            "SELECT MAX(timestamp) FROM sensor_readings WHERE sensor_type = $1"
+        )
+        .bind(sensor_type)
+        .fetch_one(self.active_pool())
+        .await?;
+        
+        Ok(last)
+    }
+    
+    // Readings since `since` whose numeric payload `field` is above and/or below the bounds
+    pub async fn count_threshold_breaches(
+        &self,
+        sensor_type: &str,
+        field: &str,
+        above: Option<f64>,
+        below: Option<f64>,
+        since: DateTime<Utc>,
+    ) -> Result<i64> {
+        let count = sqlx::query_scalar::<_, i64>(
+            r#"
+            SELECT COUNT(*) FROM (
+                SELECT CASE WHEN jsonb_typeof(payload -> $2) = 'number'
+                    THEN (payload ->> $2)::double precision END AS value
+                FROM sensor_readings
+                WHERE sensor_type = $1 AND timestamp >= $5
+            ) readings
+            WHERE value IS NOT NULL
+                AND ($3::double precision IS NULL OR value > $3)
+                AND ($4::double precision IS NULL OR value < $4)
+            "#,
+        )
+        .bind(sensor_type)
+        .bind(field)
+        .bind(above)
+        .bind(below)
+        .bind(since)
+        .fetch_one(self.active_pool())
+        .await?;
+        
+        Ok(count)
+    }
+    
+    // Creates the monthly partition starting at `month_start` unless it already exists,
+    // moving rows for that month out of the default partition so the attach succeeds.
+    pub async fn ensure_monthly_partition(&self, month_start: NaiveDate) -> Result<bool> {
+        let partition = format!("sensor_readings_y{}m{:02}", month_start.year(), month_start.month());
+        let existing: Option<String> = sqlx::query_scalar("SELECT to_regclass($1)::text")
+            .bind(&partition)
+            .fetch_one(self.active_pool())
+            .await?;
+        if existing.is_some() {
+            return Ok(false);
+        }
+        
+        let month_end = month_start
+            .checked_add_months(Months::new(1))
+            .ok_or_else(|| anyhow::anyhow!("Partition month out of range: {}", month_start))?;
+        let from = month_start.and_hms_opt(0, 0, 0).unwrap().and_utc();
+        let to = month_end.and_hms_opt(0, 0, 0).unwrap().and_utc();
+        
+        let mut tx = self.active_pool().begin().await?;
+        sqlx::query(&format!(
+            "CREATE TABLE {} (LIKE sensor_readings INCLUDING DEFAULTS INCLUDING CONSTRAINTS)",
+            partition
+        ))
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query(&format!(
+            "WITH moved AS (DELETE FROM sensor_readings_default WHERE timestamp >= $1 AND timestamp < $2 RETURNING *) \
+             INSERT INTO {} SELECT * FROM moved",
+            partition
+        ))
+        .bind(from)
+        .bind(to)
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query(&format!(
+            "ALTER TABLE sensor_readings ATTACH PARTITION {} FOR VALUES FROM ('{}') TO ('{}')",
+            partition,
+            from.to_rfc3339(),
+            to.to_rfc3339()
+        ))
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        
+        Ok(true)
+    }
+    
+    // Attached monthly partitions of sensor_readings with the month each one covers
+    pub async fn monthly_partitions(&self) -> Result<Vec<(String, NaiveDate)>> {
+        let names = sqlx::query_scalar::<_, String>(
+            r#"
+            SELECT child.relname::text FROM pg_inherits
+            JOIN pg_class parent ON parent.oid = pg_inherits.inhparent
+            JOIN pg_class child ON child.oid = pg_inherits.inhrelid
+            WHERE parent.relname = 'sensor_readings'
+            "#,
+        )
+        .fetch_all(self.active_pool())
+        .await?;
+        
+        let mut partitions: Vec<(String, NaiveDate)> = names
+            .into_iter()
+            .filter_map(|name| {
+                let month = name.strip_prefix("sensor_readings_y")?;
+                let month = NaiveDate::parse_from_str(&format!("{}01", month), "%Ym%m%d").ok()?;
+                Some((name, month))
+            })
+            .collect();
+        partitions.sort_by_key(|(_, month)| *month);
+        Ok(partitions)
+    }
+    
+    // Drops a monthly partition and every reading in it. It is detached first, so the
+    // DROP itself never locks `sensor_readings`: concurrently where Postgres allows it, and
+    // otherwise (a default partition exists) under a short lock timeout
+    pub async fn drop_monthly_partition(&self, month_start: NaiveDate) -> Result<()> {
+        let partition = format!("sensor_readings_y{}m{:02}", month_start.year(), month_start.month());
+        let pool = self.active_pool();
+        // None once detached; true when an earlier concurrent detach was interrupted
+        let detach_pending = sqlx::query_scalar::<_, bool>(
+            "SELECT inhdetachpending FROM pg_inherits WHERE inhrelid = to_regclass($1) AND inhparent = 'sensor_readings'::regclass"
+        )
+        .bind(&partition)
+        .fetch_optional(pool)
+        .await?;
+        
+        match detach_pending {
+            Some(true) => {
+                sqlx::query(&format!("ALTER TABLE sensor_readings DETACH PARTITION {} FINALIZE", partition))
+                    .execute(pool)
+                    .await?;
+            }
+            Some(false) => {
+                let has_default = sqlx::query_scalar::<_, bool>(
+                    "SELECT partdefid <> 0 FROM pg_partitioned_table WHERE partrelid = 'sensor_readings'::regclass"
+                )
+                .fetch_one(pool)
+                .await?;
+                if has_default {
+                    let mut tx = pool.begin().await?;
+                    sqlx::query(&format!("SET LOCAL lock_timeout = '{}'", DETACH_LOCK_TIMEOUT))
+                        .execute(&mut *tx)
+                        .await?;
+                    sqlx::query(&format!("ALTER TABLE sensor_readings DETACH PARTITION {}", partition))
+                        .execute(&mut *tx)
+                        .await?;
+                    tx.commit().await?;
+                } else {
+                    sqlx::query(&format!("ALTER TABLE sensor_readings DETACH PARTITION {} CONCURRENTLY", partition))
+                        .execute(pool)
+                        .await?;
+                }
+            }
+            None => {}
+        }
+        sqlx::query(&format!("DROP TABLE IF EXISTS {}", partition))
+            .execute(pool)
+            .await?;
+        
+        Ok(())
+    }
+    
+    // Deletes readings older than `before`, optionally only those of one sensor type
+    pub async fn delete_sensor_readings(&self, sensor_type: Option<&str>, before: DateTime<Utc>) -> Result<u64> {
+        let result = sqlx::query(
+            "DELETE FROM sensor_readings WHERE timestamp < $1 AND ($2::text IS NULL OR sensor_type = $2)"
+        )
+        .bind(before)
+        .bind(sensor_type)
+        .execute(self.active_pool())
+        .await?;
+        
+        Ok(result.rows_affected())
+    }
+    
+    // Creates or replaces the registry entry of one sensor
+    pub async fn upsert_registry_entry(&self, entry: &SensorRegistryEntry) -> Result<SensorRegistryEntry> {
+        let entry = sqlx::query_as::<_, SensorRegistryEntry>(
+            r#"
+            INSERT INTO sensor_registry (sensor_name, metadata, latitude, longitude, updated_at)
+            VALUES ($1, $2, $3, $4, NOW())
+            ON CONFLICT (sensor_name) DO UPDATE
+                SET metadata = EXCLUDED.metadata,
+                    latitude = EXCLUDED.latitude,
+                    longitude = EXCLUDED.longitude,
+                    updated_at = EXCLUDED.updated_at
+            RETURNING *
+            "#,
+        )
+        .bind(&entry.sensor_name)
+        .bind(&entry.metadata)
+        .bind(entry.latitude)
+        .bind(entry.longitude)
+        .fetch_one(self.active_pool())
+        .await?;
+        
+        Ok(entry)
+    }
+    
+    // Upserts every entry in one transaction, so a failed import leaves the registry unchanged
+    pub async fn upsert_registry_entries(&self, entries: &[SensorRegistryEntry]) -> Result<u64> {
+        let mut tx = self.active_pool().begin().await?;
+        for entry in entries {
+            sqlx::query(
+                r#"
+                INSERT INTO sensor_registry (sensor_name, metadata, latitude, longitude, updated_at)
+                VALUES ($1, $2, $3, $4, NOW())
+                ON CONFLICT (sensor_name) DO UPDATE
+                    SET metadata = EXCLUDED.metadata,
+                        latitude = EXCLUDED.latitude,
+                        longitude = EXCLUDED.longitude,
+                        updated_at = EXCLUDED.updated_at
+                "#,
+            )
+            .bind(&entry.sensor_name)
+            .bind(&entry.metadata)
+            .bind(entry.latitude)
+            .bind(entry.longitude)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+        
+        Ok(entries.len() as u64)
+    }
+    
+    pub async fn get_registry_entry(&self, sensor_name: &str) -> Result<Option<SensorRegistryEntry>> {
+        let entry = sqlx::query_as::<_, SensorRegistryEntry>(
+            "SELECT * FROM sensor_registry WHERE sensor_name = $1"
+        )
+        .bind(sensor_name)
+        .fetch_optional(self.active_pool())
+        .await?;
+        
+        Ok(entry)
+    }
+    
+    pub async fn get_registry_entries(&self, sensor_names: &[String]) -> Result<Vec<SensorRegistryEntry>> {
+        let entries = sqlx::query_as::<_, SensorRegistryEntry>(
+            "SELECT * FROM sensor_registry WHERE sensor_name = ANY($1)"
+        )
+        .bind(sensor_names)
+        .fetch_all(self.active_pool())
+        .await?;
+        
+        Ok(entries)
+    }
+    
+    pub async fn list_registry_entries(&self) -> Result<Vec<SensorRegistryEntry>> {
+        let entries = sqlx::query_as::<_, SensorRegistryEntry>(
+            "SELECT * FROM sensor_registry ORDER BY sensor_name"
+        )
+        .fetch_all(self.active_pool())
+        .await?;
+        
+        Ok(entries)
+    }
+    
+    // Returns whether an entry was removed
+    pub async fn delete_registry_entry(&self, sensor_name: &str) -> Result<bool> {
+        let result = sqlx::query("DELETE FROM sensor_registry WHERE sensor_name = $1")
+            .bind(sensor_name)
+            .execute(self.active_pool())
             .await?;
+        
+        Ok(result.rows_affected() > 0)
+    }
+    
+    // Recomputes the hash of every hashed reading in [from, to) under the configured key and
+    // scope, and reports those whose stored hash no longer matches. The stored `hash_scope`
+    // is not trusted: a row claiming a narrower scope would otherwise hide metadata edits.
+    pub async fn verify_integrity(&self, from: DateTime<Utc>, to: DateTime<Utc>) -> Result<IntegrityReport> {
+        let Some(hasher) = &self.hasher else {
+            anyhow::bail!("Verifying integrity needs database.hash_key or hash_key_env");
+        };
+        let mut rows = sqlx::query_as::<_, HashedRow>(
+            r#"
+            SELECT id, sensor_type, sensor_name, payload, timestamp, source_id, latitude, longitude, payload_hash
+            FROM sensor_readings
+            WHERE payload_hash IS NOT NULL AND timestamp >= $1 AND timestamp < $2
+            "#,
+        )
+        .bind(from)
+        .bind(to)
+        .fetch(self.active_pool());
+        
+        let mut report = IntegrityReport::default();
+        while let Some(row) = rows.try_next().await? {
+            report.checked += 1;
+            let hash = hasher.hash(&HashedReading {
+                sensor_type: &row.sensor_type,
+                sensor_name: &row.sensor_name,
+                payload: &row.payload,
+                timestamp: row.timestamp,
+                source_id: row.source_id.as_deref(),
+                location: stored_location(row.latitude, row.longitude),
+            });
+            if hash != row.payload_hash {
+                report.mismatches.push((row.id, row.timestamp));
+            }
+        }
+        
+        Ok(report)
+    }
+    
+    /// Reads `written` back by id and reports rows that are missing or differ. `written` is
+    /// as the INSERT returned it, with encrypted fields still sealed.
+    pub async fn verify_read_back(&self, written: &[SensorReading]) -> Result<ReadBackReport> {
+        let pool = match self.read_back.as_ref().and_then(|read_back| read_back.pool.as_ref()) {
+            Some(pool) => pool,
+            None => self.active_pool(),
+        };
+        let ids: Vec<Uuid> = written.iter().map(|reading| reading.id).collect();
+        let stored: HashMap<Uuid, SensorReading> = sqlx::query_as::<_, SensorReading>(
+            "SELECT * FROM sensor_readings WHERE id = ANY($1)",
+        )
+        .bind(&ids)
+        .fetch_all(pool)
+        .await?
+        .into_iter()
+        .map(|reading| (reading.id, reading))
+        .collect();
+        
+        let mut report = ReadBackReport { checked: written.len() as u64, ..Default::default() };
+        for reading in written {
+            match stored.get(&reading.id) {
+                None => report.missing.push(reading.id),
+                Some(stored) if stored != reading => report.changed.push(reading.id),
+                Some(_) => {}
+            }
+        }
+        Ok(report)
+    }
+    
+    // Flags discrepancies without failing the insert, which has already committed
+    async fn check_read_back(&self, sampled: &[SensorReading]) {
+        if sampled.is_empty() {
+            return;
+        }
+        match self.verify_read_back(sampled).await {
+            Ok(report) if report.mismatches() == 0 => {
+                debug!("Read-back verified {} sampled readings", report.checked);
+            }
+            Ok(report) => {
+                self.read_back_mismatches.fetch_add(report.mismatches() as u64, Ordering::Relaxed);
+                error!(
+                    "Read-back verification failed for {} of {} sampled readings (missing: {:?}, changed: {:?})",
+                    report.mismatches(),
+                    report.checked,
+                    report.missing,
+                    report.changed
+                );
+            }
+            Err(e) => warn!("Read-back verification could not run: {}", e),
+        }
+    }
+    
+    pub fn read_back_mismatches(&self) -> u64 {
+        self.read_back_mismatches.load(Ordering::Relaxed)
+    }
+    
+    // Deletes readings older than `before` of every sensor type not in `excluded`
+    pub async fn delete_expired_readings(&self, excluded: &[&str], before: DateTime<Utc>) -> Result<u64> {
+        let result = sqlx::query(
+            "DELETE FROM sensor_readings WHERE timestamp < $1 AND sensor_type <> ALL($2)"
+        )
+        .bind(before)
+        .bind(excluded)
+        .execute(self.active_pool())
+        .await?;
+        
+        Ok(result.rows_affected())
+    }
+    
+    /// Records a persisted part of a logical batch and returns the completion event when it
+    /// was the last one missing. The batch row is locked while its parts are counted, so
+    /// each batch completes once across every instance, even if its parts are redelivered.
+    pub async fn record_batch_part(
+        &self,
+        part: &BatchPart,
+        readings: u64,
+        expire_after: Duration,
+    ) -> Result<Option<BatchCompletedEvent>> {
+        let mut tx = self.active_pool().begin().await?;
+        
+        let expired = sqlx::query_scalar::<_, Option<String>>(
+            r#"
+            DELETE FROM logical_batches
+            WHERE (completed_at IS NULL AND last_part_at < NOW() - $1 * INTERVAL '1 second')
+                OR completed_at < NOW() - $2::interval
+            RETURNING CASE WHEN completed_at IS NULL THEN batch_id END
+            "#,
+        )
+        .bind(expire_after.as_secs_f64())
+        .bind(COMPLETED_BATCH_RETENTION)
+        .fetch_all(&mut *tx)
+        .await?;
+        for batch_id in expired.iter().flatten() {
+            warn!("Dropping incomplete batch {}: no new part within {:?}", batch_id, expire_after);
+        }
+        
+        let (total, first_part_at, completed_at) = sqlx::query_as::<_, (i32, DateTime<Utc>, Option<DateTime<Utc>>)>(
+            r#"
+            INSERT INTO logical_batches (batch_id, total) VALUES ($1, $2)
+            ON CONFLICT (batch_id) DO UPDATE SET last_part_at = NOW()
+            RETURNING total, first_part_at, completed_at
+            "#,
+        )
+        .bind(&part.batch_id)
+        .bind(part.total as i32)
+        .fetch_one(&mut *tx)
+        .await?;
+        if completed_at.is_some() {
+            tx.commit().await?;
+            return Ok(None);
+        }
+        if total as u32 != part.total {
+            warn!(
+                "Part {} of batch {} declares {} parts, expected {}; ignoring it",
+                part.sequence, part.batch_id, part.total, total
+            );
+            tx.commit().await?;
+            return Ok(None);
+        }
+        
+        sqlx::query(
+            "INSERT INTO logical_batch_parts (batch_id, sequence, readings) VALUES ($1, $2, $3) ON CONFLICT DO NOTHING",
+        )
+        .bind(&part.batch_id)
+        .bind(part.sequence as i32)
+        .bind(readings as i64)
+        .execute(&mut *tx)
+        .await?;
+        let (parts, batch_readings) = sqlx::query_as::<_, (i64, i64)>(
+            "SELECT COUNT(*), COALESCE(SUM(readings), 0)::bigint FROM logical_batch_parts WHERE batch_id = $1",
+        )
+        .bind(&part.batch_id)
+        .fetch_one(&mut *tx)
+        .await?;
+        if parts < total as i64 {
+            tx.commit().await?;
+            return Ok(None);
+        }
+        
+        let completed_at = sqlx::query_scalar::<_, DateTime<Utc>>(
+            "UPDATE logical_batches SET completed_at = NOW() WHERE batch_id = $1 RETURNING completed_at",
+        )
+        .bind(&part.batch_id)
+        .fetch_one(&mut *tx)
+        .await?;
+        sqlx::query("DELETE FROM logical_batch_parts WHERE batch_id = $1")
+            .bind(&part.batch_id)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        
+        Ok(Some(BatchCompletedEvent {
+            batch_id: part.batch_id.clone(),
+            parts: part.total,
+            readings: batch_readings as u64,
+            first_part_at,
+            completed_at,
+        }))
+    }
+    
+    // The body is sealed whole when field encryption is on: it may not parse, so the
+    // configured fields can't be picked out of it
+    pub async fn insert_quarantined(&self, message: &QuarantinedMessage) -> Result<()> {
+        let payload = match &self.encryptor {
+            Some(encryptor) => Cow::Owned(encryptor.seal_body(&message.payload)?),
+            None => Cow::Borrowed(message.payload.as_slice()),
+        };
+        sqlx::query(
+            r#"
+            INSERT INTO quarantine
+                (payload, error, exchange, routing_key, message_id, content_type, attempt_count, quarantined_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+            "#,
+        )
+        .bind(payload.as_ref())
+        .bind(&message.error)
+        .bind(&message.exchange)
+        .bind(&message.routing_key)
+        .bind(&message.message_id)
+        .bind(&message.content_type)
+        .bind(message.attempt_count)
+        .bind(message.quarantined_at)
+        .execute(self.active_pool())
+        .await?;
+        
         Ok(())
     }
+    
+    // Newest quarantined messages in [from, to)
+    pub async fn list_quarantined(
+        &self,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+        limit: i64,
+        timeout: Option<Duration>,
+    ) -> Result<Vec<QuarantinedMessage>> {
+        let mut tx = self.read_transaction(timeout).await?;
+        let mut messages = sqlx::query_as::<_, QuarantinedMessage>(
+            r#"
+            SELECT * FROM quarantine
+            WHERE ($1::timestamptz IS NULL OR quarantined_at >= $1)
+                AND ($2::timestamptz IS NULL OR quarantined_at < $2)
+            ORDER BY quarantined_at DESC, id DESC
+            LIMIT $3
+            "#,
+        )
+        .bind(from)
+        .bind(to)
+        .bind(limit)
+        .fetch_all(&mut *tx)
+        .await?;
+        
+        if let Some(encryptor) = &self.encryptor {
+            for message in &mut messages {
+                match encryptor.open_body(&message.payload) {
+                    Ok(body) => message.payload = body,
+                    Err(e) => warn!("Quarantined message {} stays sealed: {}", message.id, e),
+                }
+            }
+        }
+        Ok(messages)
+    }
+    
+    pub async fn insert_audit_event(&self, event: &AuditEvent) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO audit_log (principal, action, parameters, outcome, created_at) VALUES ($1, $2, $3, $4, $5)"
+        )
+        .bind(&event.principal)
+        .bind(&event.action)
+        .bind(&event.parameters)
+        .bind(&event.outcome)
+        .bind(event.at)
+        .execute(self.active_pool())
+        .await?;
+        
+        Ok(())
+    }
+    
+    pub async fn load_checkpoint(&self, queue_name: &str) -> Result<Option<i64>> {
+        let offset = sqlx::query_scalar::<_, i64>(
+            "SELECT stream_offset FROM consumer_checkpoints WHERE queue_name = $1"
+        )
+        .bind(queue_name)
+        .fetch_optional(self.active_pool())
+        .await?;
+        
+        Ok(offset)
+    }
+    
+    pub async fn save_checkpoint(&self, queue_name: &str, stream_offset: i64) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO consumer_checkpoints (queue_name, stream_offset, updated_at)
+            VALUES ($1, $2, NOW())
+            ON CONFLICT (queue_name) DO UPDATE
+                SET stream_offset = EXCLUDED.stream_offset, updated_at = EXCLUDED.updated_at
+                WHERE consumer_checkpoints.stream_offset < EXCLUDED.stream_offset
+            "#,
+        )
+        .bind(queue_name)
+        .bind(stream_offset)
+        .execute(self.active_pool())
+        .await?;
+        
+        Ok(())
+    }
+    
+    // Encrypts the configured fields of a payload about to be stored
+    fn seal<'a>(&self, sensor_type: &str, payload: &'a Value) -> Result<Cow<'a, Value>> {
+        let Some(encryptor) = &self.encryptor else {
+            return Ok(Cow::Borrowed(payload));
+        };
+        let mut sealed = payload.clone();
+        encryptor.encrypt_payload(sensor_type, &mut sealed)?;
+        Ok(Cow::Owned(sealed))
+    }
+    
+    // Decrypts the encrypted fields of a stored reading
+    fn open(&self, mut reading: SensorReading) -> Result<SensorReading> {
+        if let Some(encryptor) = &self.encryptor {
+            encryptor
+                .decrypt_payload(&reading.sensor_type, &mut reading.payload)
+                .with_context(|| format!("Failed to decrypt reading {}", reading.id))?;
+        }
+        Ok(reading)
+    }
+    
+    fn open_all(&self, readings: Vec<SensorReading>) -> Result<Vec<SensorReading>> {
+        readings.into_iter().map(|reading| self.open(reading)).collect()
+    }
+    
+    // `payload_hash` of a new reading, when payload hashing is on
+    fn new_reading_hash(&self, reading: &HashedReading) -> Option<String> {
+        self.hasher
+            .as_ref()
+            .filter(|_| self.payload_hashing)
+            .map(|hasher| hasher.hash(reading))
+    }
+    
+    fn new_reading_hash_scope(&self) -> Option<&'static str> {
+        self.hasher
+            .as_ref()
+            .filter(|_| self.payload_hashing)
+            .map(|hasher| hasher.scope().as_str())
+    }
+    
+    // Reservations are pools on the primary, so they only apply while it is the active one
+    async fn insert_connection(&self, sensor_type: Option<&str>) -> Result<PoolConnection<Postgres>> {
+        match &self.write_quota {
+            Some(quota) if self.write_target.load(Ordering::Relaxed) == 0 => quota.acquire(sensor_type, &self.pool).await,
+            _ => Ok(self.active_pool().acquire().await?),
+        }
+    }
+    
+    pub fn pool(&self) -> &PgPool {
+        &self.pool
+    }
+    
+    fn active_pool(&self) -> &PgPool {
+        match self.write_target.load(Ordering::Relaxed) {
+            0 => &self.pool,
+            target => &self.failover[target - 1],
+        }
+    }
+    
+    /// Re-probes every write target, each bounded by `timeout`, and moves off the active
+    /// one once it is unreachable or read-only. The primary is only taken back on its own
+    /// with `failback: automatic`; otherwise that takes `fail_back`.
+    pub async fn check_write_targets(&self, timeout: Duration) {
+        let mut writable = Vec::with_capacity(1 + self.failover.len());
+        for (target, pool) in std::iter::once(&self.pool).chain(&self.failover).enumerate() {
+            writable.push(probe_writable(pool, target, timeout).await);
+        }
+        
+        let previous = self.write_target.load(Ordering::Relaxed);
+        let automatic = self.failback == Failback::Automatic;
+        let Some(chosen) = choose_write_target(previous, &writable, automatic) else {
+            error!("No write target is reachable and writable, staying on {}", write_target_name(previous));
+            return;
+        };
+        if self.write_target.swap(chosen, Ordering::Relaxed) == chosen {
+            return;
+        }
+        if chosen == 0 {
+            info!("Primary is writable again, failing back from {}", write_target_name(previous));
+        } else {
+            warn!("Failing over from {} to {}", write_target_name(previous), write_target_name(chosen));
+        }
+    }
+    
+    /// Moves back to the primary after a failover, once it is reachable and writable
+    pub async fn fail_back(&self, timeout: Duration) -> Result<()> {
+        if !probe_writable(&self.pool, 0, timeout).await {
+            anyhow::bail!("The primary is not reachable and writable");
+        }
+        let previous = self.write_target.swap(0, Ordering::Relaxed);
+        if previous != 0 {
+            info!("Failing back from {} to the primary", write_target_name(previous));
+        }
+        Ok(())
+    }
+    
+    pub fn write_target_name(&self) -> String {
+        write_target_name(self.write_target.load(Ordering::Relaxed))
+    }
+    
+    // Runs `SELECT 1` at most once per `health_check_ttl_ms`, answering from the cached
+    // result in between. Only one check queries at a time; while it runs, the others get
+    // the previous result instead of queueing behind a slow database.
+    pub async fn health_check(&self) -> Result<()> {
+        let cached = self.cached_health();
+        if let Some((true, result)) = cached {
+            return result.map_err(|e| anyhow::anyhow!(e));
+        }
+        let _probe = match (self.health_probe.try_lock(), cached) {
+            (Ok(probe), _) => probe,
+            (Err(_), Some((_, stale))) => return stale.map_err(|e| anyhow::anyhow!(e)),
+            // Nothing to answer with yet: wait for the running check, which may refresh it
+            (Err(_), None) => {
+                let probe = self.health_probe.lock().await;
+                if let Some((true, result)) = self.cached_health() {
+                    return result.map_err(|e| anyhow::anyhow!(e));
+                }
+                probe
+            }
+        };
+        
+        let result = sqlx::query("SELECT 1")
+            .fetch_one(self.active_pool())
+            .await
+            .map(|_| ())
+            .map_err(|e| e.to_string());
+        *self.last_health.lock().unwrap() = Some((Instant::now(), result.clone()));
+        result.map_err(|e| anyhow::anyhow!(e))
+    }
+    
+    // The cached health check result, and whether it is still within the TTL
+    fn cached_health(&self) -> Option<(bool, Result<(), String>)> {
+        self.last_health
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|(checked_at, result)| (checked_at.elapsed() < self.health_ttl, result.clone()))
+    }
+}
+
+// Re-checks the write targets every `interval` while `database.failover_targets` is set
+pub fn spawn_write_target_monitor(database: Arc<Database>, interval: Duration) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut ticks = tokio::time::interval(interval);
+        ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            ticks.tick().await;
+            database.check_write_targets(interval).await;
+        }
+    })
+}
+
+// Applies `fill` to a rollup with one row per bucket, empty ones having count 0. Gaps
+// before the first bucket with readings stay null under `previous`.
+fn fill_gaps(buckets: Vec<RollupBucket>, fill: GapFill) -> Vec<RollupBucket> {
+    match fill {
+        GapFill::None => buckets.into_iter().filter(|bucket| bucket.count > 0).collect(),
+        GapFill::Null => buckets,
+        GapFill::Previous => {
+            let mut last = None;
+            buckets
+                .into_iter()
+                .map(|mut bucket| {
+                    if bucket.count > 0 {
+                        last = Some((bucket.avg, bucket.min, bucket.max));
+                    } else if let Some((avg, min, max)) = last {
+                        (bucket.avg, bucket.min, bucket.max) = (avg, min, max);
+                    }
+                    bucket
+                })
+                .collect()
+        }
+    }
+}
+
+// Checks a target accepts writes: not a standby in recovery and not read-only by default
+async fn probe_writable(pool: &PgPool, target: usize, timeout: Duration) -> bool {
+    let probe = sqlx::query_scalar::<_, bool>(
+        "SELECT NOT pg_is_in_recovery() AND current_setting('transaction_read_only') = 'off'",
+    )
+    .fetch_one(pool);
+    match tokio::time::timeout(timeout, probe).await {
+        Ok(Ok(true)) => true,
+        Ok(Ok(false)) => {
+            debug!("{} is read-only", write_target_name(target));
+            false
+        }
+        Ok(Err(e)) => {
+            debug!("{} is unreachable: {}", write_target_name(target), e);
+            false
+        }
+        Err(_) => {
+            debug!("{} did not answer within {:?}", write_target_name(target), timeout);
+            false
+        }
+    }
+}
+
+// Keeps the active target while it stays writable, so a recovered primary doesn't take
+// writes back behind the operator's back (two writable databases would split the data),
+// unless failback is automatic. Otherwise picks the first writable target in order.
+fn choose_write_target(active: usize, writable: &[bool], automatic_failback: bool) -> Option<usize> {
+    if automatic_failback && writable.first() == Some(&true) {
+        return Some(0);
+    }
+    if writable.get(active) == Some(&true) {
+        return Some(active);
+    }
+    writable.iter().position(|&ok| ok)
+}
+
+fn write_target_name(target: usize) -> String {
+    match target {
+        0 => "the primary".to_string(),
+        n => format!("failover target {}", n),
+    }
+}
+
+// Stored columns a reading's hash is recomputed from; the payload is read still sealed
+#[derive(sqlx::FromRow)]
+struct HashedRow {
+    id: Uuid,
+    sensor_type: String,
+    sensor_name: String,
+    payload: Value,
+    timestamp: DateTime<Utc>,
+    source_id: Option<String>,
+    latitude: Option<f64>,
+    longitude: Option<f64>,
+    payload_hash: String,
+}
+
+// SELECT list for `SensorReading` rows reading only the columns behind `fields`; the rest
+// are constants of the column's type so the row still decodes
+// Converts `sensor_readings` into a table range-partitioned on timestamp, with a default
+// partition for rows outside every monthly one; a no-op once it is partitioned. Columns,
+// defaults, constraints, indexes and triggers carry over, and every row is copied in one
+// transaction, so the first start with partitioning enabled takes as long as that copy.
+async fn partition_sensor_readings(conn: &mut PgConnection) -> Result<()> {
+    let partitioned = sqlx::query_scalar::<_, bool>("SELECT relkind = 'p' FROM pg_class WHERE oid = 'sensor_readings'::regclass")
+        .fetch_one(&mut *conn)
+        .await?;
+    if partitioned {
+        return Ok(());
+    }
+    info!("Converting sensor_readings into a partitioned table");
+    let started = Instant::now();
+    let mut tx = conn.begin().await?;
+    
+    // Definitions are read under the old name, so replaying them targets the new table
+    let indexes = sqlx::query_as::<_, (String, String)>(
+        r#"
+        SELECT indexrelid::regclass::text, pg_get_indexdef(indexrelid) FROM pg_index
+        WHERE indrelid = 'sensor_readings'::regclass AND NOT indisprimary
+        "#,
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+    let triggers = sqlx::query_as::<_, (String, String)>(
+        "SELECT tgname::text, pg_get_triggerdef(oid) FROM pg_trigger WHERE tgrelid = 'sensor_readings'::regclass AND NOT tgisinternal",
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+    
+    sqlx::query("ALTER TABLE sensor_readings RENAME TO sensor_readings_unpartitioned").execute(&mut *tx).await?;
+    sqlx::query("ALTER TABLE sensor_readings_unpartitioned RENAME CONSTRAINT sensor_readings_pkey TO sensor_readings_unpartitioned_pkey")
+        .execute(&mut *tx)
+        .await?;
+    for (index, _) in &indexes {
+        sqlx::query(&format!("DROP INDEX {}", index)).execute(&mut *tx).await?;
+    }
+    for (trigger, _) in &triggers {
+        sqlx::query(&format!("DROP TRIGGER {} ON sensor_readings_unpartitioned", trigger)).execute(&mut *tx).await?;
+    }
+    // The partition key has to be part of the primary key
+    sqlx::query(
+        r#"
+        CREATE TABLE sensor_readings (
+            LIKE sensor_readings_unpartitioned INCLUDING DEFAULTS INCLUDING CONSTRAINTS,
+            PRIMARY KEY (id, timestamp)
+        ) PARTITION BY RANGE (timestamp)
+        "#,
+    )
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query("CREATE TABLE sensor_readings_default PARTITION OF sensor_readings DEFAULT")
+        .execute(&mut *tx)
+        .await?;
+    // Indexes and row triggers on the parent apply to every partition
+    for (_, definition) in indexes.iter().chain(&triggers) {
+        sqlx::query(definition).execute(&mut *tx).await?;
+    }
+    sqlx::query("INSERT INTO sensor_readings SELECT * FROM sensor_readings_unpartitioned").execute(&mut *tx).await?;
+    sqlx::query("DROP TABLE sensor_readings_unpartitioned").execute(&mut *tx).await?;
+    tx.commit().await?;
+    
+    info!("Partitioned sensor_readings in {:?}", started.elapsed());
+    Ok(())
+}
+
+fn reading_columns(fields: &[ReadingField]) -> String {
+    let columns: [(ReadingField, &str, &str); 9] = [
+        (ReadingField::Id, "id", "'00000000-0000-0000-0000-000000000000'::uuid"),
+        (ReadingField::SensorType, "sensor_type", "''::text"),
+        (ReadingField::SensorName, "sensor_name", "''::text"),
+        (ReadingField::Payload, "payload", "'null'::jsonb"),
+        (ReadingField::Timestamp, "timestamp", "'epoch'::timestamptz"),
+        (ReadingField::CreatedAt, "created_at", "'epoch'::timestamptz"),
+        (ReadingField::SourceId, "source_id", "NULL::text"),
+        (ReadingField::Location, "latitude", "NULL::float8"),
+        (ReadingField::Location, "longitude", "NULL::float8"),
+    ];
+    columns
+        .iter()
+        .map(|(field, column, placeholder)| match fields.contains(field) {
+            true => column.to_string(),
+            false => format!("{} AS {}", placeholder, column),
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn stored_location(latitude: Option<f64>, longitude: Option<f64>) -> Option<GeoPoint> {
+    latitude
+        .zip(longitude)
+        .map(|(latitude, longitude)| GeoPoint { latitude, longitude })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    
+    // Needs a PostgreSQL server; skipped unless TEST_DATABASE_URL is set
+    fn test_database_url() -> Option<String> {
+        std::env::var("TEST_DATABASE_URL").ok()
+    }
+    
+    #[test]
+    fn batch_insert_returns_every_row_in_input_order() {
+        let Some(url) = test_database_url() else {
+            return;
+        };
+        tokio_test::block_on(async {
+            let config = DatabaseConfig { url, ..Config::default().database };
+            let database = Database::new(&config).await.unwrap();
+            let sensor_type = format!("batch-test-{}", Uuid::new_v4());
+            
+            // 500 rows fit one statement; 2000 span several chunks
+            for count in [500, 2000] {
+                let batch: Vec<SensorReadingInput> = (0..count)
+                    .map(|i| SensorReadingInput {
+                        sensor_type: sensor_type.clone(),
+                        sensor_name: format!("sensor-{}", i),
+                        payload: serde_json::json!({ "value": i }),
+                        timestamp: Utc::now(),
+                        source_id: None,
+                        location: None,
+                    })
+                    .collect();
+                let inserted = database.insert_batch_sensor_readings(batch.clone()).await.unwrap();
+                
+                assert_eq!(inserted.len(), count);
+                for (input, reading) in batch.iter().zip(&inserted) {
+                    assert_eq!(reading.sensor_name, input.sensor_name);
+                    assert_eq!(reading.payload, input.payload);
+                }
+            }
+            
+            sqlx::query("DELETE FROM sensor_readings WHERE sensor_type = $1")
+                .bind(&sensor_type)
+                .execute(&database.pool)
+                .await
+                .unwrap();
+        });
+    }
+    
+    #[test]
+    fn stores_source_ids_and_filters_readings_by_them() {
+        let Some(url) = test_database_url() else {
+            return;
+        };
+        tokio_test::block_on(async {
+            let config = DatabaseConfig { url, ..Config::default().database };
+            let database = Database::new(&config).await.unwrap();
+            let sensor_type = format!("source-test-{}", Uuid::new_v4());
+            let source = format!("gateway-{}", Uuid::new_v4());
+            let reading = |name: &str, source_id: Option<&str>| SensorReadingInput {
+                sensor_type: sensor_type.clone(),
+                sensor_name: name.to_string(),
+                payload: serde_json::json!({ "value": 1 }),
+                timestamp: Utc::now(),
+                source_id: source_id.map(str::to_string),
+                location: None,
+            };
+            
+            // Both insert paths keep the source
+            let single = database.insert_sensor_reading(reading("single", Some(&source))).await.unwrap();
+            assert_eq!(single.source_id.as_deref(), Some(source.as_str()));
+            database
+                .insert_batch_sensor_readings(vec![
+                    reading("batched", Some(&source)),
+                    reading("other", Some("another-gateway")),
+                    reading("unsourced", None),
+                ])
+                .await
+                .unwrap();
+            
+            let mut names: Vec<String> = database
+                .get_sensor_readings_by_source(&source)
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|reading| reading.sensor_name)
+                .collect();
+            names.sort();
+            assert_eq!(names, ["batched", "single"]);
+            
+            let query = ReadingQuery {
+                sensor_type: Some(sensor_type.clone()),
+                source_id: Some(source.clone()),
+                ..ReadingQuery::default()
+            };
+            let queried = database.query_sensor_readings(&query, &ReadingField::ALL, None).await.unwrap();
+            assert_eq!(queried.len(), 2);
+            assert!(queried.iter().all(|reading| reading.source_id.as_deref() == Some(source.as_str())));
+            
+            sqlx::query("DELETE FROM sensor_readings WHERE sensor_type = $1")
+                .bind(&sensor_type)
+                .execute(&database.pool)
+                .await
+                .unwrap();
+        });
+    }
+    
+    #[test]
+    fn health_checks_within_the_ttl_share_one_query() {
+        let Some(url) = test_database_url() else {
+            return;
+        };
+        tokio_test::block_on(async {
+            let config = DatabaseConfig { url, health_check_ttl_ms: 60_000, ..Config::default().database };
+            let database = Database::new(&config).await.unwrap();
+            database.health_check().await.unwrap();
+            
+            // With the pool closed any query fails, so these can only be answered from the cache
+            database.pool.close().await;
+            for _ in 0..5 {
+                database.health_check().await.unwrap();
+            }
+            
+            // While another check holds the probe, an expired result is served rather than waited on
+            let expired = Instant::now() - Duration::from_secs(120);
+            database.last_health.lock().unwrap().as_mut().unwrap().0 = expired;
+            let probe = database.health_probe.lock().await;
+            database.health_check().await.unwrap();
+            drop(probe);
+            
+            // Once it has expired and nothing else is checking, the next check queries again
+            assert!(database.health_check().await.is_err());
+        });
+    }
+    
+    // An empty database next to TEST_DATABASE_URL for tests that change the schema, so
+    // they can't disturb tests sharing that one; returns its URL and name
+    async fn scratch_database(url: &str) -> (String, String) {
+        let name = format!("scratch_{}", Uuid::new_v4().simple());
+        let mut admin = PgConnection::connect(url).await.unwrap();
+        sqlx::query(&format!("CREATE DATABASE {}", name)).execute(&mut admin).await.unwrap();
+        let (server, _) = url.rsplit_once('/').unwrap();
+        (format!("{}/{}", server, name), name)
+    }
+    
+    async fn drop_scratch_database(url: &str, name: &str) {
+        let mut admin = PgConnection::connect(url).await.unwrap();
+        sqlx::query(&format!("DROP DATABASE {} WITH (FORCE)", name)).execute(&mut admin).await.unwrap();
+    }
+    
+    fn partitioning(url: String, enabled: bool) -> DatabaseConfig {
+        let mut config = DatabaseConfig { url, ..Config::default().database };
+        config.partitioning.enabled = enabled;
+        config
+    }
+    
+    #[test]
+    fn partitions_sensor_readings_only_when_enabled() {
+        let Some(url) = test_database_url() else {
+            return;
+        };
+        tokio_test::block_on(async {
+            let (scratch_url, name) = scratch_database(&url).await;
+            let relkind = "SELECT relkind::text FROM pg_class WHERE oid = 'sensor_readings'::regclass";
+            let index_count = "SELECT count(*) FROM pg_index WHERE indrelid = 'sensor_readings'::regclass";
+            
+            let plain = Database::new(&partitioning(scratch_url.clone(), false)).await.unwrap();
+            assert_eq!(sqlx::query_scalar::<_, String>(relkind).fetch_one(&plain.pool).await.unwrap(), "r");
+            let indexes: i64 = sqlx::query_scalar(index_count).fetch_one(&plain.pool).await.unwrap();
+            let written = plain
+                .insert_batch_sensor_readings(vec![SensorReadingInput {
+                    sensor_type: "energy".to_string(),
+                    sensor_name: "meter-1".to_string(),
+                    payload: serde_json::json!({ "value": 1 }),
+                    timestamp: Utc::now(),
+                    source_id: Some("site-a".to_string()),
+                    location: Some(GeoPoint { latitude: 52.5, longitude: 13.4 }),
+                }])
+                .await
+                .unwrap();
+            plain.pool.close().await;
+            
+            // Enabling it later converts the table in place, keeping rows and indexes
+            let partitioned = Database::new(&partitioning(scratch_url.clone(), true)).await.unwrap();
+            assert_eq!(sqlx::query_scalar::<_, String>(relkind).fetch_one(&partitioned.pool).await.unwrap(), "p");
+            assert_eq!(sqlx::query_scalar::<_, i64>(index_count).fetch_one(&partitioned.pool).await.unwrap(), indexes);
+            let kept = partitioned.get_sensor_reading(written[0].id).await.unwrap().unwrap();
+            assert_eq!(kept, written[0]);
+            partitioned.pool.close().await;
+            
+            // Already partitioned: nothing left to do
+            let again = Database::new(&partitioning(scratch_url, true)).await.unwrap();
+            assert_eq!(sqlx::query_scalar::<_, String>(relkind).fetch_one(&again.pool).await.unwrap(), "p");
+            again.pool.close().await;
+            
+            drop_scratch_database(&url, &name).await;
+        });
+    }
+    
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn concurrent_startups_both_migrate_successfully() {
+        let Some(url) = test_database_url() else {
+            return;
+        };
+        let (scratch_url, name) = scratch_database(&url).await;
+        let config = DatabaseConfig { url: scratch_url, ..Config::default().database };
+        
+        // Both race for the migration lock on an empty database; the loser waits for it
+        let (first, second) = tokio::join!(
+            tokio::spawn({
+                let config = config.clone();
+                async move { Database::new(&config).await }
+            }),
+            tokio::spawn({
+                let config = config.clone();
+                async move { Database::new(&config).await }
+            }),
+        );
+        let (first, second) = (first.unwrap().unwrap(), second.unwrap().unwrap());
+        let applied: i64 = sqlx::query_scalar("SELECT count(*) FROM _sqlx_migrations WHERE success")
+            .fetch_one(&first.pool)
+            .await
+            .unwrap();
+        assert_eq!(applied as usize, sqlx::migrate!("./migrations").iter().count());
+        first.pool.close().await;
+        second.pool.close().await;
+        
+        drop_scratch_database(&url, &name).await;
+    }
+    
+    #[test]
+    fn drops_a_monthly_partition_after_detaching_it() {
+        let Some(url) = test_database_url() else {
+            return;
+        };
+        tokio_test::block_on(async {
+            let (scratch_url, name) = scratch_database(&url).await;
+            let database = Database::new(&partitioning(scratch_url, true)).await.unwrap();
+            let month = NaiveDate::from_ymd_opt(2001, 1, 1).unwrap();
+            database.ensure_monthly_partition(month).await.unwrap();
+            let reading = SensorReadingInput {
+                sensor_type: format!("partition-test-{}", Uuid::new_v4()),
+                sensor_name: "sensor".to_string(),
+                payload: serde_json::json!({ "value": 1 }),
+                timestamp: month.and_hms_opt(12, 0, 0).unwrap().and_utc(),
+                source_id: None,
+                location: None,
+            };
+            database.insert_batch_sensor_readings(vec![reading.clone()]).await.unwrap();
+            
+            database.drop_monthly_partition(month).await.unwrap();
+            assert!(!database.monthly_partitions().await.unwrap().iter().any(|(_, start)| *start == month));
+            let remaining: i64 = sqlx::query_scalar("SELECT count(*) FROM sensor_readings WHERE sensor_type = $1")
+                .bind(&reading.sensor_type)
+                .fetch_one(&database.pool)
+                .await
+                .unwrap();
+            assert_eq!(remaining, 0);
+            // Already gone: nothing to detach or drop
+            database.drop_monthly_partition(month).await.unwrap();
+            database.pool.close().await;
+            drop_scratch_database(&url, &name).await;
+        });
+    }
+    
+    // Point `distance_m` from `from` along the initial `bearing` (degrees), on the sphere
+    fn destination(from: GeoPoint, bearing: f64, distance_m: f64) -> GeoPoint {
+        let (lat, lon, bearing) = (from.latitude.to_radians(), from.longitude.to_radians(), bearing.to_radians());
+        let angular = distance_m / crate::location::EARTH_RADIUS_M;
+        let dest_lat = (lat.sin() * angular.cos() + lat.cos() * angular.sin() * bearing.cos()).asin();
+        let dest_lon = lon
+            + (bearing.sin() * angular.sin() * lat.cos()).atan2(angular.cos() - lat.sin() * dest_lat.sin());
+        GeoPoint { latitude: dest_lat.to_degrees(), longitude: dest_lon.to_degrees() }
+    }
+    
+    #[test]
+    fn radius_query_returns_the_readings_within_it() {
+        let Some(url) = test_database_url() else {
+            return;
+        };
+        tokio_test::block_on(async {
+            let config = DatabaseConfig { url, ..Config::default().database };
+            let database = Database::new(&config).await.unwrap();
+            let sensor_type = format!("near-test-{}", Uuid::new_v4());
+            // A range no other test writes to, so only these readings can match
+            let from = chrono::DateTime::parse_from_rfc3339("2002-03-01T00:00:00Z").unwrap().with_timezone(&Utc);
+            let to = from + chrono::Duration::hours(1);
+            let center = GeoPoint { latitude: 60.0, longitude: 10.0 };
+            let oslo_area = [
+                ("center", center),
+                ("near", destination(center, 200.0, 50_000.0)),
+                // 990 km at bearing 70: 17.98° east, outside the old cos-scaled box (17.81°)
+                ("far-east", destination(center, 70.0, 990_000.0)),
+                ("outside", destination(center, 70.0, 1_010_000.0)),
+                ("unlocated", center),
+            ];
+            let batch: Vec<SensorReadingInput> = oslo_area
+                .iter()
+                .map(|(name, location)| SensorReadingInput {
+                    sensor_type: sensor_type.clone(),
+                    sensor_name: name.to_string(),
+                    payload: serde_json::json!({}),
+                    timestamp: from,
+                    source_id: None,
+                    location: (*name != "unlocated").then_some(*location),
+                })
+                .collect();
+            database.insert_batch_sensor_readings(batch).await.unwrap();
+            
+            let names = |readings: Vec<SensorReading>| readings.into_iter().map(|reading| reading.sensor_name).collect::<Vec<_>>();
+            let nearby = database.readings_near(center, 1_000_000.0, from, to, None).await.unwrap();
+            assert_eq!(names(nearby), ["center", "near", "far-east"]);
+            let nearby = database.readings_near(center, 100_000.0, from, to, None).await.unwrap();
+            assert_eq!(names(nearby), ["center", "near"]);
+            
+            // Close to the pole the box spans every longitude
+            let north = GeoPoint { latitude: 89.5, longitude: 0.0 };
+            // 100 km due north crosses the pole onto the 180° meridian
+            let across = destination(north, 0.0, 100_000.0);
+            database
+                .insert_batch_sensor_readings(vec![SensorReadingInput {
+                    sensor_type: sensor_type.clone(),
+                    sensor_name: "across-the-pole".to_string(),
+                    payload: serde_json::json!({}),
+                    timestamp: from,
+                    source_id: None,
+                    location: Some(GeoPoint { latitude: across.latitude, longitude: across.longitude.clamp(-180.0, 180.0) }),
+                }])
+                .await
+                .unwrap();
+            let nearby = database.readings_near(north, 200_000.0, from, to, None).await.unwrap();
+            assert_eq!(names(nearby), ["across-the-pole"]);
+            
+            sqlx::query("DELETE FROM sensor_readings WHERE sensor_type = $1")
+                .bind(&sensor_type)
+                .execute(&database.pool)
+                .await
+                .unwrap();
+        });
+    }
+    
+    #[test]
+    fn stores_encrypted_fields_as_ciphertext_and_reads_them_back_in_plaintext() {
+        let Some(url) = test_database_url() else {
+            return;
+        };
+        tokio_test::block_on(async {
+            let sensor_type = format!("encryption-test-{}", Uuid::new_v4());
+            let field_encryption = crate::config::FieldEncryptionConfig {
+                key_id: "test".to_string(),
+                key: Some("A".repeat(43) + "="),
+                key_env: None,
+                fields: std::collections::HashMap::from([(sensor_type.clone(), vec!["ssn".to_string()])]),
+            };
+            let config = DatabaseConfig { url, field_encryption: Some(field_encryption), ..Config::default().database };
+            let database = Database::new(&config).await.unwrap();
+            let payload = serde_json::json!({ "ssn": "123-45-6789", "pulse": 72 });
+            let written = database
+                .insert_batch_sensor_readings(vec![SensorReadingInput {
+                    sensor_type: sensor_type.clone(),
+                    sensor_name: "ward-1".to_string(),
+                    payload: payload.clone(),
+                    timestamp: Utc::now(),
+                    source_id: None,
+                    location: None,
+                }])
+                .await
+                .unwrap();
+            
+            let stored: serde_json::Value = sqlx::query_scalar("SELECT payload FROM sensor_readings WHERE id = $1")
+                .bind(written[0].id)
+                .fetch_one(&database.pool)
+                .await
+                .unwrap();
+            let ciphertext = stored["ssn"].as_str().unwrap();
+            assert!(ciphertext.starts_with("enc:v1:test:") && !ciphertext.contains("123-45-6789"), "{}", stored);
+            assert_eq!(stored["pulse"], 72);
+            
+            let read = database.get_sensor_reading(written[0].id).await.unwrap().unwrap();
+            assert_eq!(read.payload, payload);
+            
+            sqlx::query("DELETE FROM sensor_readings WHERE sensor_type = $1")
+                .bind(&sensor_type)
+                .execute(&database.pool)
+                .await
+                .unwrap();
+        });
+    }
+    
+    #[test]
+    fn read_back_flags_missing_and_changed_rows() {
+        let Some(url) = test_database_url() else {
+            return;
+        };
+        tokio_test::block_on(async {
+            let config = DatabaseConfig { url, ..Config::default().database };
+            let database = Database::new(&config).await.unwrap();
+            let sensor_type = format!("read-back-test-{}", Uuid::new_v4());
+            let batch: Vec<SensorReadingInput> = (0..3)
+                .map(|i| SensorReadingInput {
+                    sensor_type: sensor_type.clone(),
+                    sensor_name: format!("sensor-{}", i),
+                    payload: serde_json::json!({ "value": i }),
+                    timestamp: Utc::now(),
+                    source_id: None,
+                    location: None,
+                })
+                .collect();
+            let written = database.insert_batch_sensor_readings(batch).await.unwrap();
+            
+            let report = database.verify_read_back(&written).await.unwrap();
+            assert_eq!((report.checked, report.mismatches()), (3, 0));
+            
+            sqlx::query("UPDATE sensor_readings SET payload = '{\"value\": 99}' WHERE id = $1")
+                .bind(written[0].id)
+                .execute(&database.pool)
+                .await
+                .unwrap();
+            sqlx::query("DELETE FROM sensor_readings WHERE id = $1")
+                .bind(written[1].id)
+                .execute(&database.pool)
+                .await
+                .unwrap();
+            let report = database.verify_read_back(&written).await.unwrap();
+            assert_eq!(report.changed, vec![written[0].id]);
+            assert_eq!(report.missing, vec![written[1].id]);
+            
+            sqlx::query("DELETE FROM sensor_readings WHERE sensor_type = $1")
+                .bind(&sensor_type)
+                .execute(&database.pool)
+                .await
+                .unwrap();
+        });
+    }
+    
+    #[test]
+    fn fails_over_to_the_first_writable_target() {
+        assert_eq!(choose_write_target(0, &[false, false, true], false), Some(2));
+        assert_eq!(choose_write_target(0, &[false, false], false), None);
+    }
+    
+    #[test]
+    fn stays_on_the_failover_target_when_the_primary_recovers() {
+        assert_eq!(choose_write_target(1, &[true, true], false), Some(1));
+        assert_eq!(choose_write_target(1, &[true, true], true), Some(0));
+    }
+    
+    #[test]
+    fn leaves_a_standby_that_turned_read_only() {
+        // The active standby was demoted again; the primary is the only writable one
+        assert_eq!(choose_write_target(1, &[true, false], false), Some(0));
+        assert_eq!(choose_write_target(2, &[false, true, false], false), Some(1));
+    }
+    
+    fn bucket(minute: u32, count: i64, avg: Option<f64>) -> RollupBucket {
+        RollupBucket {
+            bucket: DateTime::parse_from_rfc3339(&format!("2024-01-01T00:{:02}:00Z", minute))
+                .unwrap()
+                .with_timezone(&Utc),
+            count,
+            avg,
+            min: avg,
+            max: avg,
+        }
+    }
+    
+    #[test]
+    fn fills_gaps_by_policy() {
+        let buckets = vec![bucket(0, 0, None), bucket(1, 2, Some(1.5)), bucket(2, 0, None), bucket(3, 1, Some(4.0))];
+        
+        assert_eq!(fill_gaps(buckets.clone(), GapFill::None), vec![bucket(1, 2, Some(1.5)), bucket(3, 1, Some(4.0))]);
+        assert_eq!(fill_gaps(buckets.clone(), GapFill::Null), buckets);
+        // The leading gap has nothing to carry forward; the middle one keeps count 0
+        assert_eq!(
+            fill_gaps(buckets, GapFill::Previous),
+            vec![bucket(0, 0, None), bucket(1, 2, Some(1.5)), bucket(2, 0, Some(1.5)), bucket(3, 1, Some(4.0))]
+        );
+    }
 }
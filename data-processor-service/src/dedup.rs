@@ -0,0 +1,273 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use crate::config::{DedupKeyField, RedisDedupConfig};
+use crate::models::SensorReadingInput;
+use redis::aio::ConnectionManager;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// Values of the configured key fields for one reading
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum KeyPart {
+    Text(Option<String>),
+    Time(DateTime<Utc>),
+}
+
+fn key_of(reading: &SensorReadingInput, fields: &[DedupKeyField]) -> Vec<KeyPart> {
+    fields
+        .iter()
+        .map(|field| match field {
+            DedupKeyField::SensorType => KeyPart::Text(Some(reading.sensor_type.clone())),
+            DedupKeyField::SensorName => KeyPart::Text(Some(reading.sensor_name.clone())),
+            DedupKeyField::SourceId => KeyPart::Text(reading.source_id.clone()),
+            DedupKeyField::Timestamp => KeyPart::Time(reading.timestamp),
+        })
+        .collect()
+}
+
+/// Collapses readings sharing the same key, keeping the last one at the position of the
+/// first. Returns the remaining readings and how many were collapsed.
+pub fn collapse(readings: Vec<SensorReadingInput>, fields: &[DedupKeyField]) -> (Vec<SensorReadingInput>, usize) {
+    if fields.is_empty() || readings.len() < 2 {
+        return (readings, 0);
+    }
+    
+    let total = readings.len();
+    let mut positions: HashMap<Vec<KeyPart>, usize> = HashMap::new();
+    let mut kept: Vec<SensorReadingInput> = Vec::with_capacity(total);
+    for reading in readings {
+        let key = key_of(&reading, fields);
+        match positions.get(&key) {
+            Some(&position) => kept[position] = reading,
+            None => {
+                positions.insert(key, kept.len());
+                kept.push(reading);
+            }
+        }
+    }
+    let collapsed = total - kept.len();
+    (kept, collapsed)
+}
+
+// Key string for cross-instance dedup; field values joined so distinct keys never collide
+fn key_string(prefix: &str, reading: &SensorReadingInput, fields: &[DedupKeyField]) -> String {
+    let mut key = prefix.to_string();
+    for (i, part) in key_of(reading, fields).into_iter().enumerate() {
+        if i > 0 {
+            key.push('|');
+        }
+        match part {
+            KeyPart::Text(Some(text)) => key.push_str(&text.replace('\\', "\\\\").replace('|', "\\|")),
+            KeyPart::Text(None) => key.push_str("\\0"),
+            KeyPart::Time(time) => key.push_str(&time.timestamp_micros().to_string()),
+        }
+    }
+    key
+}
+
+// Value of a key whose reading is being written; it expires after `pending_ttl_seconds`
+const PENDING: &str = "pending";
+// Value of a key whose reading is stored; it suppresses duplicates for `ttl_seconds`
+const STORED: &str = "stored";
+
+// Suppresses readings already stored by any instance. Each reading's dedup key is claimed
+// with SET NX and a short TTL before it is written and marked stored once it is.
+pub struct RedisDeduplicator {
+    connection: ConnectionManager,
+    fields: Vec<DedupKeyField>,
+    prefix: String,
+    ttl_seconds: u64,
+    pending_ttl: Duration,
+    // Keys this process is writing: key -> (claimed at, whether this process claimed it)
+    in_progress: Mutex<HashMap<String, (Instant, bool)>>,
+}
+
+impl RedisDeduplicator {
+    pub async fn connect(config: &RedisDedupConfig, fields: &[DedupKeyField]) -> Result<Self> {
+        if fields.is_empty() {
+            anyhow::bail!("processing.redis_dedup requires processing.dedup_key");
+        }
+        let client = redis::Client::open(config.url.as_str())?;
+        let connection = ConnectionManager::new(client).await?;
+        Ok(Self {
+            connection,
+            fields: fields.to_vec(),
+            prefix: config.key_prefix.clone(),
+            ttl_seconds: config.ttl_seconds.max(1),
+            pending_ttl: Duration::from_secs(config.pending_ttl_seconds.max(1)),
+            in_progress: Mutex::new(HashMap::new()),
+        })
+    }
+    
+    /// Claims the key of every reading in one round trip. Returns, per reading, its key and
+    /// whether it is a duplicate of an already stored reading. A key another delivery is
+    /// still writing is not a duplicate: that delivery may never finish, so the reading is
+    /// written again rather than risk losing it.
+    pub async fn claim(&self, readings: &[SensorReadingInput]) -> Result<Vec<(String, bool)>> {
+        if readings.is_empty() {
+            return Ok(Vec::new());
+        }
+        let keys: Vec<String> = readings
+            .iter()
+            .map(|reading| key_string(&self.prefix, reading, &self.fields))
+            .collect();
+        
+        let mut pipe = redis::pipe();
+        for key in &keys {
+            pipe.cmd("SET")
+                .arg(key)
+                .arg(PENDING)
+                .arg("NX")
+                .arg("EX")
+                .arg(self.pending_ttl.as_secs());
+            pipe.cmd("GET").arg(key);
+        }
+        let replies: Vec<Option<String>> = pipe.query_async(&mut self.connection.clone()).await?;
+        
+        let now = Instant::now();
+        let mut in_progress = self.in_progress.lock().unwrap();
+        // Claims of writes that never finished, e.g. a cancelled handler, would pile up
+        in_progress.retain(|_, (claimed_at, _)| claimed_at.elapsed() < self.pending_ttl);
+        Ok(keys
+            .into_iter()
+            .zip(replies.chunks(2))
+            .map(|(key, reply)| {
+                let claimed = reply[0].is_some();
+                let duplicate = !claimed && reply[1].as_deref() == Some(STORED);
+                if !duplicate {
+                    in_progress.insert(key.clone(), (now, claimed));
+                }
+                (key, duplicate)
+            })
+            .collect())
+    }
+    
+    /// Settles the claims of readings that were just written (or failed to be): stored
+    /// readings suppress duplicates for `ttl_seconds`, keys of failed ones are freed.
+    pub async fn settle(&self, readings: &[SensorReadingInput], stored: bool) -> Result<()> {
+        let keys: Vec<(String, bool)> = {
+            let mut in_progress = self.in_progress.lock().unwrap();
+            readings
+                .iter()
+                .filter_map(|reading| {
+                    let key = key_string(&self.prefix, reading, &self.fields);
+                    in_progress.remove(&key).map(|(_, claimed)| (key, claimed))
+                })
+                .collect()
+        };
+        if stored {
+            self.mark_stored(keys.into_iter().map(|(key, _)| key)).await
+        } else {
+            self.release(keys).await
+        }
+    }
+    
+    /// Frees the claims among `keys` that no write has settled, e.g. when the message failed
+    /// before its readings reached a sink.
+    pub async fn abandon(&self, keys: &[String]) -> Result<()> {
+        let unsettled: Vec<(String, bool)> = {
+            let mut in_progress = self.in_progress.lock().unwrap();
+            keys.iter()
+                .filter_map(|key| in_progress.remove(key).map(|(_, claimed)| (key.clone(), claimed)))
+                .collect()
+        };
+        self.release(unsettled).await
+    }
+    
+    async fn mark_stored(&self, keys: impl Iterator<Item = String>) -> Result<()> {
+        let keys: Vec<String> = keys.collect();
+        if keys.is_empty() {
+            return Ok(());
+        }
+        let mut pipe = redis::pipe();
+        for key in keys {
+            pipe.cmd("SET").arg(key).arg(STORED).arg("EX").arg(self.ttl_seconds).ignore();
+        }
+        pipe.query_async::<()>(&mut self.connection.clone()).await?;
+        Ok(())
+    }
+    
+    // Only keys this process claimed; a contested key belongs to the delivery that claimed it
+    async fn release(&self, keys: Vec<(String, bool)>) -> Result<()> {
+        let claimed: Vec<String> = keys.into_iter().filter(|(_, claimed)| *claimed).map(|(key, _)| key).collect();
+        if claimed.is_empty() {
+            return Ok(());
+        }
+        // Delete only while still pending, never a key another write has since marked stored
+        let mut pipe = redis::pipe();
+        for key in &claimed {
+            pipe.cmd("EVAL")
+                .arg("if redis.call('GET', KEYS[1]) == ARGV[1] then return redis.call('DEL', KEYS[1]) end return 0")
+                .arg(1)
+                .arg(key)
+                .arg(PENDING)
+                .ignore();
+        }
+        pipe.query_async::<()>(&mut self.connection.clone()).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use serde_json::json;
+    
+    fn reading(name: &str, second: u32, value: i64) -> SensorReadingInput {
+        SensorReadingInput {
+            sensor_type: "meter".to_string(),
+            sensor_name: name.to_string(),
+            payload: json!({ "value": value }),
+            timestamp: Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, second).unwrap(),
+            source_id: None,
+            location: None,
+        }
+    }
+    
+    #[test]
+    fn collapse_keeps_last_duplicate_at_first_position() {
+        let fields = [DedupKeyField::SensorName, DedupKeyField::Timestamp];
+        let readings = vec![reading("a", 0, 1), reading("b", 0, 2), reading("a", 0, 3)];
+        
+        let (kept, collapsed) = collapse(readings, &fields);
+        
+        assert_eq!(collapsed, 1);
+        assert_eq!(kept.len(), 2);
+        assert_eq!(kept[0].sensor_name, "a");
+        assert_eq!(kept[0].payload, json!({ "value": 3 }));
+        assert_eq!(kept[1].sensor_name, "b");
+    }
+    
+    #[test]
+    fn collapse_without_key_fields_keeps_everything() {
+        let readings = vec![reading("a", 0, 1), reading("a", 0, 2)];
+        
+        let (kept, collapsed) = collapse(readings, &[]);
+        
+        assert_eq!(collapsed, 0);
+        assert_eq!(kept.len(), 2);
+    }
+    
+    #[test]
+    fn key_string_distinguishes_missing_and_empty_source() {
+        let fields = [DedupKeyField::SourceId];
+        let mut empty = reading("a", 0, 1);
+        empty.source_id = Some(String::new());
+        
+        assert_ne!(
+            key_string("dedup:", &reading("a", 0, 1), &fields),
+            key_string("dedup:", &empty, &fields)
+        );
+    }
+    
+    #[test]
+    fn key_string_separates_fields() {
+        let fields = [DedupKeyField::SensorName, DedupKeyField::Timestamp];
+        
+        let key = key_string("dedup:", &reading("a", 5, 1), &fields);
+        
+        assert_eq!(key, "dedup:a|1704067205000000");
+    }
+}
@@ -0,0 +1,217 @@
+use anyhow::Result;
+use crate::config::{Config, DerivationFormula, DerivationRule};
+use crate::models::SensorReadingInput;
+use serde_json::{json, Value};
+use tracing::debug;
+
+// Payload key of a derived reading holding the type it was computed from. Provenance
+// only: nothing reads it back, so a producer setting it changes nothing.
+pub const DERIVED_FROM_KEY: &str = "derived_from";
+
+/// Computes the readings the rules derive from `reading`. Derived readings keep the
+/// source's name, timestamp, source id and location. `validate` keeps derived types from
+/// being source types, so a derived reading is never derived from again.
+pub fn derive(rules: &[DerivationRule], reading: &SensorReadingInput) -> Vec<SensorReadingInput> {
+    rules
+        .iter()
+        .filter(|rule| rule.source_type == reading.sensor_type)
+        .filter_map(|rule| {
+            let Some(value) = evaluate(&rule.formula, &reading.payload).filter(|value| value.is_finite()) else {
+                debug!("Reading '{}' lacks the inputs for {}", reading.sensor_name, rule.derived_type);
+                return None;
+            };
+            let mut payload = serde_json::Map::new();
+            payload.insert(rule.field.clone(), json!(value));
+            payload.insert(DERIVED_FROM_KEY.to_string(), json!(reading.sensor_type));
+            Some(SensorReadingInput {
+                sensor_type: rule.derived_type.clone(),
+                sensor_name: reading.sensor_name.clone(),
+                payload: Value::Object(payload),
+                timestamp: reading.timestamp,
+                source_id: reading.source_id.clone(),
+                location: reading.location,
+            })
+        })
+        .collect()
+}
+
+// Rejects rules that could never produce a reading, would loop, would lose their value to
+// the payload field lists, or would store an encrypted input in the clear
+pub fn validate(config: &Config) -> Result<()> {
+    let processing = &config.processing;
+    let rules = &processing.derived_readings;
+    let encrypted = |sensor_type: &str, field: &str| {
+        config.database.field_encryption.as_ref().is_some_and(|encryption| {
+            encryption
+                .fields
+                .get(sensor_type)
+                .is_some_and(|fields| fields.iter().any(|encrypted| encrypted == field))
+        })
+    };
+    for rule in rules {
+        if rules.iter().any(|other| other.source_type == rule.derived_type) {
+            anyhow::bail!("Derived type {} must not be the source type of a rule", rule.derived_type);
+        }
+        if rule.field == DERIVED_FROM_KEY {
+            anyhow::bail!("Derived type {} can't store its value in '{}'", rule.derived_type, DERIVED_FROM_KEY);
+        }
+        if let Some(allowed) = &processing.allowed_sensor_types {
+            if !allowed.contains(&rule.derived_type) {
+                anyhow::bail!("Derived type {} is missing from allowed_sensor_types", rule.derived_type);
+            }
+        }
+        let allowlisted = processing
+            .payload_field_allowlist
+            .get(&rule.derived_type)
+            .is_none_or(|allowed| allowed.contains(&rule.field));
+        let denied = [processing.payload_field_denylist.get(&rule.derived_type), processing.payload_field_denylist.get("*")]
+            .into_iter()
+            .flatten()
+            .any(|denied| denied.contains(&rule.field));
+        if !allowlisted || denied {
+            anyhow::bail!(
+                "Field '{}' of derived type {} would be stripped by the payload field lists",
+                rule.field, rule.derived_type
+            );
+        }
+        // The derived value would reveal an encrypted input unless it is encrypted as well
+        if let Some(input) = inputs(&rule.formula).find(|input| encrypted(&rule.source_type, input)) {
+            if !encrypted(&rule.derived_type, &rule.field) {
+                anyhow::bail!(
+                    "Derived type {} is computed from encrypted field '{}' of {}; encrypt its '{}' field too",
+                    rule.derived_type, input, rule.source_type, rule.field
+                );
+            }
+        }
+        if let DerivationFormula::PiecewiseLinear { points, .. } = &rule.formula {
+            if points.is_empty() || points.windows(2).any(|pair| pair[1][0] < pair[0][0]) {
+                anyhow::bail!("Points of derived type {} must be non-empty and sorted by x", rule.derived_type);
+            }
+        }
+    }
+    Ok(())
+}
+
+// Payload fields the formula reads
+fn inputs(formula: &DerivationFormula) -> Box<dyn Iterator<Item = &str> + '_> {
+    match formula {
+        DerivationFormula::WeightedSum { weights, .. } => Box::new(weights.keys().map(String::as_str)),
+        DerivationFormula::PiecewiseLinear { input, .. } => Box::new(std::iter::once(input.as_str())),
+    }
+}
+
+// None when an input is missing or not a number
+fn evaluate(formula: &DerivationFormula, payload: &Value) -> Option<f64> {
+    let number = |field: &str| payload.get(field).and_then(Value::as_f64);
+    match formula {
+        DerivationFormula::WeightedSum { weights, offset } => weights
+            .iter()
+            .try_fold(*offset, |sum, (field, weight)| Some(sum + weight * number(field)?)),
+        DerivationFormula::PiecewiseLinear { input, points } => {
+            let x = number(input)?;
+            let (first, last) = (points.first()?, points.last()?);
+            if x <= first[0] {
+                return Some(first[1]);
+            }
+            if x >= last[0] {
+                return Some(last[1]);
+            }
+            let segment = points.windows(2).find(|pair| x <= pair[1][0])?;
+            let ([x0, y0], [x1, y1]) = (segment[0], segment[1]);
+            if x1 == x0 {
+                return Some(y1);
+            }
+            Some(y0 + (x - x0) * (y1 - y0) / (x1 - x0))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::FieldEncryptionConfig;
+    use chrono::Utc;
+    use std::collections::HashMap;
+    
+    // The README's US EPA PM2.5 breakpoints
+    fn aqi_rule() -> DerivationRule {
+        DerivationRule {
+            source_type: "air_quality".to_string(),
+            derived_type: "air_quality_index".to_string(),
+            field: "aqi".to_string(),
+            formula: DerivationFormula::PiecewiseLinear {
+                input: "pm25".to_string(),
+                points: vec![[0.0, 0.0], [12.0, 50.0], [35.4, 100.0], [55.4, 150.0], [150.4, 200.0], [250.4, 300.0], [500.4, 500.0]],
+            },
+        }
+    }
+    
+    fn reading(sensor_type: &str, payload: Value) -> SensorReadingInput {
+        SensorReadingInput {
+            sensor_type: sensor_type.to_string(),
+            sensor_name: "station-1".to_string(),
+            payload,
+            timestamp: Utc::now(),
+            source_id: Some("gateway-1".to_string()),
+            location: None,
+        }
+    }
+    
+    #[test]
+    fn derives_an_aqi_reading_from_air_quality() {
+        let source = reading("air_quality", json!({"pm25": 35.4, "co2": 410}));
+        
+        let derived = derive(&[aqi_rule()], &source);
+        
+        assert_eq!(derived.len(), 1);
+        assert_eq!(derived[0].sensor_type, "air_quality_index");
+        assert_eq!(derived[0].payload, json!({"aqi": 100.0, "derived_from": "air_quality"}));
+        assert_eq!((&derived[0].sensor_name, derived[0].timestamp), (&source.sensor_name, source.timestamp));
+        assert_eq!(derived[0].source_id, source.source_id);
+    }
+    
+    #[test]
+    fn interpolates_and_clamps_piecewise_linear() {
+        let formula = aqi_rule().formula;
+        let aqi = |pm25: f64| evaluate(&formula, &json!({ "pm25": pm25 })).unwrap();
+        assert!((aqi(20.0) - (50.0 + 8.0 * 50.0 / 23.4)).abs() < 1e-9);
+        assert_eq!(aqi(-1.0), 0.0);
+        assert_eq!(aqi(900.0), 500.0);
+        assert_eq!(evaluate(&formula, &json!({"pm25": "high"})), None);
+    }
+    
+    #[test]
+    fn producer_marker_does_not_suppress_derivation() {
+        let source = reading("air_quality", json!({"pm25": 12.0, "derived_from": "anything"}));
+        assert_eq!(derive(&[aqi_rule()], &source).len(), 1);
+    }
+    
+    #[test]
+    fn rejects_rules_that_chain_or_leak_encrypted_inputs() {
+        let mut config = Config::default();
+        config.processing.derived_readings = vec![aqi_rule()];
+        assert!(validate(&config).is_ok());
+        
+        let mut chained = aqi_rule();
+        chained.source_type = "air_quality_index".to_string();
+        chained.derived_type = "aqi_band".to_string();
+        config.processing.derived_readings.push(chained);
+        assert!(validate(&config).is_err());
+        config.processing.derived_readings.pop();
+        
+        config.database.field_encryption = Some(FieldEncryptionConfig {
+            key_id: "k1".to_string(),
+            key: None,
+            key_env: None,
+            fields: HashMap::from([("air_quality".to_string(), vec!["pm25".to_string()])]),
+        });
+        assert!(validate(&config).is_err());
+        if let Some(encryption) = &mut config.database.field_encryption {
+            encryption.fields.insert("air_quality_index".to_string(), vec!["aqi".to_string()]);
+        }
+        assert!(validate(&config).is_ok());
+        
+        config.processing.payload_field_denylist = HashMap::from([("*".to_string(), vec!["aqi".to_string()])]);
+        assert!(validate(&config).is_err());
+    }
+}
@@ -0,0 +1,148 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use crate::database::Database;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Key health numbers gathered by the `stats` subcommand
+#[derive(Debug, Serialize)]
+#[serde(tag = "source", rename_all = "snake_case")]
+pub enum Snapshot {
+    // From a running instance's HTTP endpoints
+    Instance {
+        url: String,
+        health: String,
+        stats: Value,
+        // Every sample of /metrics except histogram buckets, keyed by name and labels
+        metrics: BTreeMap<String, f64>,
+    },
+    // From the database alone, for when no instance is reachable
+    Database {
+        healthy: bool,
+        estimated_readings: i64,
+        readings_last_hour: i64,
+        readings_last_24h: i64,
+        // Types with readings in the last 24 hours
+        types: Vec<TypeSummary>,
+    },
+}
+
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct TypeSummary {
+    pub sensor_type: String,
+    pub readings: i64,
+    pub last_reading: Option<DateTime<Utc>>,
+}
+
+impl Snapshot {
+    pub fn print(&self) {
+        match self {
+            Snapshot::Instance { url, health, stats, metrics } => {
+                println!("Instance snapshot ({})", url);
+                println!("  health: {}", health);
+                println!("  stats:");
+                if let Some(stats) = stats.as_object() {
+                    for (name, value) in stats {
+                        println!("    {}: {}", name, value);
+                    }
+                }
+                println!("  metrics:");
+                for (sample, value) in metrics {
+                    println!("    {} {}", sample, value);
+                }
+            }
+            Snapshot::Database { healthy, estimated_readings, readings_last_hour, readings_last_24h, types } => {
+                println!("Database snapshot");
+                println!("  healthy:            {}", healthy);
+                println!("  readings (approx.): {}", estimated_readings);
+                println!("  last hour:          {}", readings_last_hour);
+                println!("  last 24 hours:      {}", readings_last_24h);
+                for summary in types {
+                    println!(
+                        "    {}: {} readings, last at {}",
+                        summary.sensor_type,
+                        summary.readings,
+                        summary.last_reading.map(|at| at.to_rfc3339()).unwrap_or_default()
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// Reads `/health`, `/stats` and `/metrics` of the instance serving at `url`.
+pub async fn from_instance(url: &str) -> Result<Snapshot> {
+    let client = reqwest::Client::builder().timeout(REQUEST_TIMEOUT).build()?;
+    let base = url.trim_end_matches('/');
+    
+    // An unhealthy instance answers 503, which is still worth reporting
+    let health = client.get(format!("{}/health", base)).send().await.context("Failed to reach /health")?;
+    let health = format!("{} {}", health.status().as_u16(), health.text().await?);
+    let stats = client
+        .get(format!("{}/stats", base))
+        .send()
+        .await
+        .context("Failed to reach /stats")?
+        .error_for_status()?
+        .json()
+        .await?;
+    let metrics = client
+        .get(format!("{}/metrics", base))
+        .send()
+        .await
+        .context("Failed to reach /metrics")?
+        .error_for_status()?
+        .text()
+        .await?;
+    
+    Ok(Snapshot::Instance {
+        url: base.to_string(),
+        health,
+        stats,
+        metrics: parse_samples(&metrics),
+    })
+}
+
+/// Summarizes recent ingest from the database. Only index range scans over the last day
+/// are run, so it stays cheap on large tables; the total is the planner's estimate.
+pub async fn from_database(database: &Database) -> Result<Snapshot> {
+    let now = Utc::now();
+    let healthy = database.health_check().await.is_ok();
+    let types = database.summarize_types_since(now - chrono::Duration::hours(24)).await?;
+    let readings_last_24h = types.iter().map(|summary| summary.readings).sum();
+    let readings_last_hour = database
+        .count_by_time_bucket(crate::models::TimeBucket::Hour, now - chrono::Duration::hours(1), now, None)
+        .await?
+        .iter()
+        .map(|(_, count)| count)
+        .sum();
+    
+    Ok(Snapshot::Database {
+        healthy,
+        estimated_readings: database.estimated_reading_count().await?,
+        readings_last_hour,
+        readings_last_24h,
+        types,
+    })
+}
+
+// OpenMetrics text to `name{labels}` -> value, leaving out comments, histogram buckets
+// and exemplars
+fn parse_samples(text: &str) -> BTreeMap<String, f64> {
+    text.lines()
+        .filter(|line| !line.starts_with('#') && !line.is_empty())
+        .filter_map(|line| {
+            let line = line.split(" # ").next()?;
+            let (sample, value) = line.rsplit_once(' ')?;
+            let name = sample.split('{').next()?;
+            if name.ends_with("_bucket") {
+                return None;
+            }
+            Some((sample.to_string(), value.parse().ok()?))
+        })
+        .collect()
+}
@@ -0,0 +1,217 @@
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use anyhow::{anyhow, bail, Context, Result};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use crate::config::FieldEncryptionConfig;
+use serde_json::Value;
+use std::collections::HashMap;
+
+// Encrypted values are stored as "enc:v1:<key_id>:<base64(nonce || ciphertext)>"
+const PREFIX: &str = "enc:v1:";
+const NONCE_LEN: usize = 12;
+// Associated data of sealed message bodies, so they can't pass for a field and vice versa
+const BODY_AAD: &str = "\0body";
+
+// AES-256-GCM encryption of selected top-level payload fields. The field name is bound
+// as associated data, so a ciphertext cannot be moved to another field.
+pub struct FieldEncryptor {
+    cipher: Aes256Gcm,
+    key_id: String,
+    // sensor_type -> fields encrypted before storage
+    fields: HashMap<String, Vec<String>>,
+}
+
+impl FieldEncryptor {
+    pub fn from_config(config: &FieldEncryptionConfig) -> Result<Self> {
+        let encoded = match (&config.key, &config.key_env) {
+            (Some(key), _) => key.clone(),
+            (None, Some(var)) => std::env::var(var)
+                .with_context(|| format!("Field encryption key variable {} is not set", var))?,
+            (None, None) => bail!("database.field_encryption needs `key` or `key_env`"),
+        };
+        let key = STANDARD
+            .decode(encoded.trim())
+            .context("Field encryption key is not valid base64")?;
+        if key.len() != 32 {
+            bail!("Field encryption key must be 32 bytes, got {}", key.len());
+        }
+        if config.key_id.contains(':') {
+            bail!("Field encryption key_id must not contain ':'");
+        }
+        
+        Ok(Self {
+            cipher: Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key)),
+            key_id: config.key_id.clone(),
+            fields: config.fields.clone(),
+        })
+    }
+    
+    /// Replaces the configured fields of a `sensor_type` payload with their ciphertext.
+    /// Values are always encrypted, even ones that look encrypted already: the payload
+    /// comes from producers, so the prefix proves nothing.
+    pub fn encrypt_payload(&self, sensor_type: &str, payload: &mut Value) -> Result<()> {
+        let (Some(fields), Some(object)) = (self.fields.get(sensor_type), payload.as_object_mut()) else {
+            return Ok(());
+        };
+        for field in fields {
+            if let Some(value) = object.get_mut(field) {
+                *value = Value::String(self.encrypt_value(field, value)?);
+            }
+        }
+        Ok(())
+    }
+    
+    /// Restores the configured fields of a `sensor_type` payload. Other fields are never
+    /// decrypted, and configured ones without the prefix (stored before the field was
+    /// listed) are left as they are.
+    pub fn decrypt_payload(&self, sensor_type: &str, payload: &mut Value) -> Result<()> {
+        let (Some(fields), Some(object)) = (self.fields.get(sensor_type), payload.as_object_mut()) else {
+            return Ok(());
+        };
+        for field in fields {
+            let Some(value) = object.get_mut(field) else {
+                continue;
+            };
+            if let Some(encrypted) = value.as_str().filter(|value| value.starts_with(PREFIX)) {
+                *value = self.decrypt_value(field, encrypted)?;
+            }
+        }
+        Ok(())
+    }
+    
+    /// Encrypts a whole raw message body, for bodies that may not even parse and so can't
+    /// have their fields picked out, such as quarantined messages.
+    pub fn seal_body(&self, body: &[u8]) -> Result<Vec<u8>> {
+        Ok(self.seal(BODY_AAD, body).map_err(|_| anyhow!("Failed to encrypt message body"))?.into_bytes())
+    }
+    
+    /// Reverses `seal_body`; bodies stored without the prefix are returned as they are
+    pub fn open_body(&self, stored: &[u8]) -> Result<Vec<u8>> {
+        match std::str::from_utf8(stored).ok().filter(|stored| stored.starts_with(PREFIX)) {
+            Some(sealed) => self.open(BODY_AAD, sealed).context("Failed to decrypt message body"),
+            None => Ok(stored.to_vec()),
+        }
+    }
+    
+    fn encrypt_value(&self, field: &str, value: &Value) -> Result<String> {
+        let plaintext = serde_json::to_vec(value)?;
+        self.seal(field, &plaintext)
+            .map_err(|_| anyhow!("Failed to encrypt field {}", field))
+    }
+    
+    fn decrypt_value(&self, field: &str, encrypted: &str) -> Result<Value> {
+        let plaintext = self
+            .open(field, encrypted)
+            .with_context(|| format!("Failed to decrypt field {}", field))?;
+        Ok(serde_json::from_slice(&plaintext)?)
+    }
+    
+    fn seal(&self, aad: &str, plaintext: &[u8]) -> Result<String> {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .cipher
+            .encrypt(&nonce, Payload { msg: plaintext, aad: aad.as_bytes() })
+            .map_err(|_| anyhow!("Encryption failed"))?;
+        
+        let mut sealed = nonce.to_vec();
+        sealed.extend_from_slice(&ciphertext);
+        Ok(format!("{}{}:{}", PREFIX, self.key_id, STANDARD.encode(sealed)))
+    }
+    
+    fn open(&self, aad: &str, encrypted: &str) -> Result<Vec<u8>> {
+        let (key_id, sealed) = encrypted[PREFIX.len()..]
+            .split_once(':')
+            .ok_or_else(|| anyhow!("Malformed encrypted value"))?;
+        if key_id != self.key_id {
+            bail!("Encrypted with key {}, configured key is {}", key_id, self.key_id);
+        }
+        let sealed = STANDARD.decode(sealed).context("Malformed encrypted value")?;
+        if sealed.len() < NONCE_LEN {
+            bail!("Malformed encrypted value");
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        self.cipher
+            .decrypt(Nonce::from_slice(nonce), Payload { msg: ciphertext, aad: aad.as_bytes() })
+            .map_err(|_| anyhow!("Authentication failed"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    
+    fn encryptor() -> FieldEncryptor {
+        FieldEncryptor::from_config(&FieldEncryptionConfig {
+            key_id: "test".to_string(),
+            key: Some(STANDARD.encode([7u8; 32])),
+            key_env: None,
+            fields: HashMap::from([("patient".to_string(), vec!["ssn".to_string()])]),
+        })
+        .unwrap()
+    }
+    
+    #[test]
+    fn round_trips_configured_fields_only() {
+        let encryptor = encryptor();
+        let mut payload = json!({ "ssn": "123-45-6789", "pulse": 72 });
+        
+        encryptor.encrypt_payload("patient", &mut payload).unwrap();
+        assert!(payload["ssn"].as_str().unwrap().starts_with("enc:v1:test:"));
+        assert_eq!(payload["pulse"], json!(72));
+        
+        encryptor.decrypt_payload("patient", &mut payload).unwrap();
+        assert_eq!(payload, json!({ "ssn": "123-45-6789", "pulse": 72 }));
+    }
+    
+    #[test]
+    fn encrypts_values_that_already_carry_the_prefix() {
+        let encryptor = encryptor();
+        let forged = "enc:v1:test:not-really-encrypted";
+        let mut payload = json!({ "ssn": forged });
+        
+        encryptor.encrypt_payload("patient", &mut payload).unwrap();
+        assert_ne!(payload["ssn"], json!(forged));
+        
+        encryptor.decrypt_payload("patient", &mut payload).unwrap();
+        assert_eq!(payload["ssn"], json!(forged));
+    }
+    
+    #[test]
+    fn leaves_unconfigured_fields_and_types_alone() {
+        let encryptor = encryptor();
+        let mut payload = json!({ "note": "enc:v1:test:garbage", "ssn": "enc:v1:test:garbage" });
+        
+        encryptor.decrypt_payload("meter", &mut payload).unwrap();
+        assert_eq!(payload["ssn"], json!("enc:v1:test:garbage"));
+        
+        let mut payload = json!({ "note": "enc:v1:test:garbage" });
+        encryptor.decrypt_payload("patient", &mut payload).unwrap();
+        assert_eq!(payload["note"], json!("enc:v1:test:garbage"));
+    }
+    
+    #[test]
+    fn ciphertext_is_bound_to_its_field() {
+        let encryptor = encryptor();
+        let sealed = encryptor.encrypt_value("other", &json!("secret")).unwrap();
+        let mut payload = json!({ "ssn": sealed });
+        
+        assert!(encryptor.decrypt_payload("patient", &mut payload).is_err());
+    }
+    
+    #[test]
+    fn seals_whole_message_bodies() {
+        let encryptor = encryptor();
+        let body = br#"{"sensor_type":"patient","payload":{"ssn":"123-45-6789""#;
+        
+        let sealed = encryptor.seal_body(body).unwrap();
+        assert!(!sealed.windows(11).any(|window| window == b"123-45-6789"));
+        assert_eq!(encryptor.open_body(&sealed).unwrap(), body);
+        // Rows stored before encryption was configured read back as they are
+        assert_eq!(encryptor.open_body(b"plain").unwrap(), b"plain");
+        // A sealed body can't be passed off as a field value
+        let mut payload = json!({ "ssn": String::from_utf8(sealed).unwrap() });
+        assert!(encryptor.decrypt_payload("patient", &mut payload).is_err());
+    }
+}
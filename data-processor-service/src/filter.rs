@@ -0,0 +1,38 @@
+use crate::config::ProcessingConfig;
+use std::collections::HashSet;
+
+const WILDCARD: &str = "*";
+
+// Decides which sensor types are stored; readings of other types are acked and dropped
+#[derive(Debug, Clone, Default)]
+pub struct SensorTypeFilter {
+    // None allows every type not explicitly disabled
+    enabled: Option<HashSet<String>>,
+    disabled: HashSet<String>,
+}
+
+impl SensorTypeFilter {
+    pub fn from_config(processing: &ProcessingConfig) -> Self {
+        let enabled = if processing.enabled_sensor_types.is_empty()
+            || processing.enabled_sensor_types.iter().any(|t| t == WILDCARD)
+        {
+            None
+        } else {
+            Some(processing.enabled_sensor_types.iter().cloned().collect())
+        };
+        
+        Self {
+            enabled,
+            disabled: processing.disabled_sensor_types.iter().cloned().collect(),
+        }
+    }
+    
+    pub fn allows(&self, sensor_type: &str) -> bool {
+        if self.disabled.contains(WILDCARD) || self.disabled.contains(sensor_type) {
+            return false;
+        }
+        self.enabled
+            .as_ref()
+            .is_none_or(|enabled| enabled.contains(sensor_type))
+    }
+}
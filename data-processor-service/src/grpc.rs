@@ -0,0 +1,156 @@
+use anyhow::Result;
+use crate::metrics;
+use crate::models::SensorData;
+use crate::processor::Pipeline;
+use crate::rabbitmq::MessageContext;
+use std::net::SocketAddr;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{Request, Response, Status, Streaming};
+use tracing::{debug, error, info};
+
+pub mod proto {
+    tonic::include_proto!("ingest.v1");
+}
+
+use proto::sensor_ingest_server::{SensorIngest, SensorIngestServer};
+use proto::{ReadingAck, SensorReadingMessage};
+
+pub struct IngestService {
+    pipeline: Pipeline,
+}
+
+impl IngestService {
+    pub fn new(pipeline: Pipeline) -> Self {
+        Self { pipeline }
+    }
+}
+
+#[tonic::async_trait]
+impl SensorIngest for IngestService {
+    type StreamReadingsStream = ReceiverStream<Result<ReadingAck, Status>>;
+    
+    async fn stream_readings(
+        &self,
+        request: Request<Streaming<SensorReadingMessage>>,
+    ) -> Result<Response<Self::StreamReadingsStream>, Status> {
+        let source_id = request
+            .metadata()
+            .get("x-source-id")
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string);
+        let trace_id = request
+            .metadata()
+            .get("traceparent")
+            .and_then(|value| value.to_str().ok())
+            .and_then(metrics::traceparent_trace_id);
+        let mut inbound = request.into_inner();
+        let pipeline = self.pipeline.clone();
+        let (tx, rx) = mpsc::channel(64);
+        
+        tokio::spawn(async move {
+            loop {
+                let message = match inbound.message().await {
+                    Ok(Some(message)) => message,
+                    Ok(None) => break,
+                    Err(status) => {
+                        error!("gRPC ingest stream failed: {}", status);
+                        break;
+                    }
+                };
+                
+                let sequence = message.sequence;
+                let context = MessageContext {
+                    routing_key: String::new(),
+                    source_id: source_id.clone(),
+                    trace_id: trace_id.clone(),
+                    ..Default::default()
+                };
+                let ack = match ingest(&pipeline, message, context).await {
+                    Ok(()) => ReadingAck { sequence, ok: true, error: String::new() },
+                    Err(e) => ReadingAck { sequence, ok: false, error: e.to_string() },
+                };
+                
+                if tx.send(Ok(ack)).await.is_err() {
+                    debug!("gRPC client closed the ack stream");
+                    break;
+                }
+            }
+        });
+        
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+}
+
+async fn ingest(pipeline: &Pipeline, message: SensorReadingMessage, context: MessageContext) -> Result<()> {
+    let payload = serde_json::from_str(&message.payload_json)?;
+    let sensor_data = SensorData {
+        r#type: message.r#type,
+        name: message.name,
+        payload,
+        timestamp: None,
+    };
+    pipeline.process_sensor_data(vec![sensor_data], context).await
+}
+
+pub async fn serve(address: SocketAddr, pipeline: Pipeline) -> Result<()> {
+    info!("gRPC ingest listening on {}", address);
+    tonic::transport::Server::builder()
+        .add_service(SensorIngestServer::new(IngestService::new(pipeline)))
+        .serve(address)
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use proto::sensor_ingest_client::SensorIngestClient;
+    
+    #[tokio::test]
+    async fn stores_and_acknowledges_streamed_readings() {
+        let (pipeline, capture) = Pipeline::capturing(Config::default()).await;
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let incoming = futures_util::stream::unfold(listener, |listener| async move {
+            Some((listener.accept().await.map(|(stream, _)| stream), listener))
+        });
+        tokio::spawn(
+            tonic::transport::Server::builder()
+                .add_service(SensorIngestServer::new(IngestService::new(pipeline)))
+                .serve_with_incoming(incoming),
+        );
+        
+        let mut client = SensorIngestClient::connect(format!("http://{}", address)).await.unwrap();
+        let message = |sequence: u64, payload_json: &str| SensorReadingMessage {
+            sequence,
+            r#type: "energy".to_string(),
+            name: format!("meter-{}", sequence),
+            payload_json: payload_json.to_string(),
+        };
+        let messages = vec![
+            message(1, r#"{"energy": 1.5}"#),
+            message(2, r#"{"energy": 2.5}"#),
+            message(3, "not json"),
+            message(4, r#"{"energy": 4.5}"#),
+        ];
+        let mut request = Request::new(tokio_stream::iter(messages));
+        request.metadata_mut().insert("x-source-id", "gateway-7".parse().unwrap());
+        let mut acks = client.stream_readings(request).await.unwrap().into_inner();
+        
+        let mut received = Vec::new();
+        while let Some(ack) = acks.message().await.unwrap() {
+            received.push((ack.sequence, ack.ok));
+        }
+        assert_eq!(received, [(1, true), (2, true), (3, false), (4, true)]);
+        
+        let batches = capture.batches.lock().unwrap();
+        let stored: Vec<(&str, Option<&str>)> = batches
+            .iter()
+            .flatten()
+            .map(|reading| (reading.sensor_name.as_str(), reading.source_id.as_deref()))
+            .collect();
+        assert_eq!(stored, [("meter-1", Some("gateway-7")), ("meter-2", Some("gateway-7")), ("meter-4", Some("gateway-7"))]);
+    }
+}
@@ -0,0 +1,53 @@
+use anyhow::Result;
+use axum::extract::State;
+use axum::http::{header, StatusCode};
+use axum::response::IntoResponse;
+use axum::routing::get;
+use axum::Router;
+use crate::processor::Pipeline;
+use std::net::SocketAddr;
+use tracing::{error, info};
+
+const OPENMETRICS_CONTENT_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+pub fn router(pipeline: Pipeline) -> Router {
+    Router::new()
+        .route("/health", get(health))
+        .route("/live", get(live))
+        .route("/metrics", get(metrics))
+        .with_state(pipeline)
+}
+
+pub async fn serve(address: SocketAddr, app: Router) -> Result<()> {
+    let listener = tokio::net::TcpListener::bind(address).await?;
+    info!("HTTP server listening on {}", address);
+    axum::serve(listener, app).await?;
+    Ok(())
+}
+
+async fn health(State(pipeline): State<Pipeline>) -> impl IntoResponse {
+    match pipeline.health_check().await {
+        Ok(()) => (StatusCode::OK, "OK".to_string()),
+        Err(e) => {
+            error!("Health check failed: {}", e);
+            (StatusCode::SERVICE_UNAVAILABLE, format!("Unhealthy: {}", e))
+        }
+    }
+}
+
+async fn live(State(pipeline): State<Pipeline>) -> impl IntoResponse {
+    match pipeline.liveness_check() {
+        Ok(()) => (StatusCode::OK, "OK".to_string()),
+        Err(e) => {
+            error!("Liveness check failed: {}", e);
+            (StatusCode::SERVICE_UNAVAILABLE, format!("Not live: {}", e))
+        }
+    }
+}
+
+async fn metrics(State(pipeline): State<Pipeline>) -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, OPENMETRICS_CONTENT_TYPE)],
+        pipeline.metrics().encode(),
+    )
+}
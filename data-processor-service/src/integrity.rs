@@ -0,0 +1,207 @@
+use anyhow::{bail, Context, Result};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use chrono::{DateTime, SecondsFormat, SubsecRound, Utc};
+use crate::config::{DatabaseConfig, HashScope};
+use crate::models::GeoPoint;
+use hmac::{Hmac, Mac};
+use serde_json::{json, Value};
+use sha2::Sha256;
+use uuid::Uuid;
+
+// Shortest `hash_key` accepted, in bytes
+const MIN_HASH_KEY_LEN: usize = 32;
+
+#[derive(Debug, Default)]
+pub struct IntegrityReport {
+    pub checked: u64,
+    pub mismatches: Vec<(Uuid, DateTime<Utc>)>,
+}
+
+impl IntegrityReport {
+    pub fn print(&self) {
+        println!("Integrity verification results");
+        println!("  checked:    {}", self.checked);
+        println!("  mismatches: {}", self.mismatches.len());
+        for (id, timestamp) in &self.mismatches {
+            println!("    {} at {}", id, timestamp.to_rfc3339());
+        }
+    }
+}
+
+// Sampled readings read back after an insert that were not found or no longer match
+#[derive(Debug, Default)]
+pub struct ReadBackReport {
+    pub checked: u64,
+    pub missing: Vec<Uuid>,
+    pub changed: Vec<Uuid>,
+}
+
+impl ReadBackReport {
+    pub fn mismatches(&self) -> usize {
+        self.missing.len() + self.changed.len()
+    }
+}
+
+// HMAC-SHA256 of readings for `payload_hash`. Without the key a hash can't be recomputed,
+// so whoever can edit a row can't make its hash match again.
+pub struct ReadingHasher {
+    key: Vec<u8>,
+    scope: HashScope,
+}
+
+impl ReadingHasher {
+    pub fn new(key: Vec<u8>, scope: HashScope) -> Self {
+        Self { key, scope }
+    }
+    
+    /// The hasher for `database.hash_key`/`hash_key_env`, if one is configured. Required
+    /// with `payload_hashing`.
+    pub fn from_config(config: &DatabaseConfig) -> Result<Option<Self>> {
+        let encoded = match (&config.hash_key, &config.hash_key_env) {
+            (Some(key), _) => key.clone(),
+            (None, Some(var)) => std::env::var(var)
+                .with_context(|| format!("Hash key variable {} is not set", var))?,
+            (None, None) if config.payload_hashing => {
+                bail!("database.payload_hashing needs `hash_key` or `hash_key_env`")
+            }
+            (None, None) => return Ok(None),
+        };
+        let key = STANDARD.decode(encoded.trim()).context("Hash key is not valid base64")?;
+        if key.len() < MIN_HASH_KEY_LEN {
+            bail!("Hash key must be at least {} bytes, got {}", MIN_HASH_KEY_LEN, key.len());
+        }
+        Ok(Some(Self::new(key, config.hash_scope)))
+    }
+    
+    pub fn scope(&self) -> HashScope {
+        self.scope
+    }
+    
+    /// Hex HMAC of the reading under the configured scope
+    pub fn hash(&self, reading: &HashedReading) -> String {
+        match self.scope {
+            HashScope::Payload => self.mac(reading.payload),
+            HashScope::Row => self.mac(&row_value(reading)),
+        }
+    }
+    
+    fn mac(&self, value: &Value) -> String {
+        let mut canonical = String::new();
+        write_canonical(value, &mut canonical);
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC accepts keys of any length");
+        mac.update(canonical.as_bytes());
+        mac.finalize()
+            .into_bytes()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+}
+
+// Fields of a reading covered by a row-scope hash
+pub struct HashedReading<'a> {
+    pub sensor_type: &'a str,
+    pub sensor_name: &'a str,
+    pub payload: &'a Value,
+    pub timestamp: DateTime<Utc>,
+    pub source_id: Option<&'a str>,
+    pub location: Option<GeoPoint>,
+}
+
+// The payload and metadata a row-scope hash covers. The timestamp is cut to the
+// microseconds PostgreSQL keeps, so the hash survives the round trip.
+fn row_value(reading: &HashedReading) -> Value {
+    json!({
+        "sensor_type": reading.sensor_type,
+        "sensor_name": reading.sensor_name,
+        "payload": reading.payload,
+        "timestamp": reading.timestamp.trunc_subsecs(6).to_rfc3339_opts(SecondsFormat::Micros, true),
+        "source_id": reading.source_id,
+        "latitude": reading.location.map(|location| location.latitude),
+        "longitude": reading.location.map(|location| location.longitude),
+    })
+}
+
+// Sorted object keys and every number written as f64, so the form survives the JSONB
+// round trip (which reorders keys and normalizes numbers like 1e3 to 1000)
+fn write_canonical(value: &Value, out: &mut String) {
+    match value {
+        Value::Object(map) => {
+            let mut keys: Vec<&String> = map.keys().collect();
+            keys.sort();
+            out.push('{');
+            for (i, key) in keys.into_iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                out.push_str(&Value::String(key.clone()).to_string());
+                out.push(':');
+                write_canonical(&map[key], out);
+            }
+            out.push('}');
+        }
+        Value::Array(items) => {
+            out.push('[');
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_canonical(item, out);
+            }
+            out.push(']');
+        }
+        Value::Number(n) => match n.as_f64() {
+            Some(f) => out.push_str(&f.to_string()),
+            None => out.push_str(&n.to_string()),
+        },
+        other => out.push_str(&other.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn reading(payload: &Value) -> HashedReading<'_> {
+        HashedReading {
+            sensor_type: "energy",
+            sensor_name: "meter-1",
+            payload,
+            timestamp: DateTime::parse_from_rfc3339("2024-05-01T12:00:00.123456789Z").unwrap().into(),
+            source_id: Some("site-a"),
+            location: None,
+        }
+    }
+    
+    #[test]
+    fn detects_a_tampered_payload() {
+        let hasher = ReadingHasher::new(vec![7; 32], HashScope::Payload);
+        let stored = hasher.hash(&reading(&json!({ "energy": 1.5 })));
+        
+        // Key order and number spelling survive the JSONB round trip
+        assert_eq!(hasher.hash(&reading(&json!({ "energy": 1.50 }))), stored);
+        assert_ne!(hasher.hash(&reading(&json!({ "energy": 2.5 }))), stored);
+    }
+    
+    #[test]
+    fn row_scope_covers_metadata() {
+        let hasher = ReadingHasher::new(vec![7; 32], HashScope::Row);
+        let payload = json!({ "energy": 1.5 });
+        let stored = hasher.hash(&reading(&payload));
+        
+        let mut renamed = reading(&payload);
+        renamed.sensor_name = "meter-2";
+        assert_ne!(hasher.hash(&renamed), stored);
+    }
+    
+    #[test]
+    fn a_rehash_without_the_key_does_not_match() {
+        let payload = json!({ "energy": 1.5 });
+        let stored = ReadingHasher::new(vec![7; 32], HashScope::Payload).hash(&reading(&payload));
+        let forged = ReadingHasher::new(vec![8; 32], HashScope::Payload).hash(&reading(&payload));
+        
+        assert_ne!(forged, stored);
+        assert_eq!(stored.len(), 64);
+    }
+}
@@ -0,0 +1,120 @@
+use anyhow::{anyhow, bail, Result};
+use serde::de::DeserializeOwned;
+use serde_json::Deserializer;
+use std::marker::PhantomData;
+
+// Decodes the elements of a top-level JSON array one at a time, so a huge array never has
+// to be held in memory fully parsed
+pub struct ArrayElements<'a, T> {
+    data: &'a [u8],
+    pos: usize,
+    first: bool,
+    done: bool,
+    element: PhantomData<T>,
+}
+
+impl<'a, T: DeserializeOwned> ArrayElements<'a, T> {
+    pub fn new(data: &'a [u8]) -> Result<Self> {
+        let pos = skip_whitespace(data, 0);
+        if data.get(pos) != Some(&b'[') {
+            bail!("expected a JSON array");
+        }
+        Ok(Self {
+            data,
+            pos: pos + 1,
+            first: true,
+            done: false,
+            element: PhantomData,
+        })
+    }
+    
+    fn next_element(&mut self) -> Result<Option<T>> {
+        self.pos = skip_whitespace(self.data, self.pos);
+        if self.first && self.data.get(self.pos) == Some(&b']') {
+            return self.finish().map(|_| None);
+        }
+        
+        let mut values = Deserializer::from_slice(&self.data[self.pos..]).into_iter::<T>();
+        let element = values
+            .next()
+            .ok_or_else(|| anyhow!("unexpected end of JSON array at byte {}", self.pos))??;
+        self.pos += values.byte_offset();
+        self.first = false;
+        
+        self.pos = skip_whitespace(self.data, self.pos);
+        match self.data.get(self.pos) {
+            Some(b',') => self.pos += 1,
+            Some(b']') => self.finish()?,
+            _ => bail!("expected ',' or ']' at byte {}", self.pos),
+        }
+        Ok(Some(element))
+    }
+    
+    // Consumes the closing bracket; only whitespace may follow it
+    fn finish(&mut self) -> Result<()> {
+        self.done = true;
+        let end = skip_whitespace(self.data, self.pos + 1);
+        if end != self.data.len() {
+            bail!("trailing characters after JSON array at byte {}", end);
+        }
+        Ok(())
+    }
+}
+
+impl<T: DeserializeOwned> Iterator for ArrayElements<'_, T> {
+    type Item = Result<T>;
+    
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let element = self.next_element();
+        if element.is_err() {
+            self.done = true;
+        }
+        element.transpose()
+    }
+}
+
+fn skip_whitespace(data: &[u8], mut pos: usize) -> usize {
+    while data.get(pos).is_some_and(|byte| matches!(byte, b' ' | b'\t' | b'\n' | b'\r')) {
+        pos += 1;
+    }
+    pos
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn elements(data: &str) -> Result<Vec<u32>> {
+        ArrayElements::new(data.as_bytes())?.collect()
+    }
+    
+    #[test]
+    fn yields_each_element_in_order() {
+        assert_eq!(elements(" [1, 2 ,\n3] \n").unwrap(), vec![1, 2, 3]);
+        assert_eq!(elements("[]").unwrap(), Vec::<u32>::new());
+        
+        let nested: Vec<serde_json::Value> = ArrayElements::new(br#"[{"a": [1, 2]}, "x,y"]"#)
+            .unwrap()
+            .collect::<Result<_>>()
+            .unwrap();
+        assert_eq!(nested, vec![serde_json::json!({"a": [1, 2]}), serde_json::json!("x,y")]);
+    }
+    
+    #[test]
+    fn rejects_malformed_arrays() {
+        for data in ["{}", "[1, 2", "[1,]", "[1 2]", "[1] x", "[\"a\"]"] {
+            assert!(elements(data).is_err(), "accepted {}", data);
+        }
+    }
+    
+    #[test]
+    fn stops_after_the_first_error() {
+        let mut elements = ArrayElements::<u32>::new(b"[1, x, 3]").unwrap();
+        assert_eq!(elements.next().unwrap().unwrap(), 1);
+        assert!(elements.next().unwrap().is_err());
+        assert!(elements.next().is_none());
+    }
+}
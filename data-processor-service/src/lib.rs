@@ -1,5 +1,50 @@
+pub mod ack_batch;
+pub mod adaptive_batch;
+pub mod admin;
+pub mod alerts;
+pub mod api;
+pub mod audit;
+pub mod batcher;
+pub mod bench;
+pub mod checkpoint;
+pub mod codec;
 pub mod config;
 pub mod database;
+pub mod dedup;
+pub mod derive;
+pub mod diagnostics;
+pub mod encryption;
+pub mod filter;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod http;
+pub mod json_stream;
+pub mod liveness;
+pub mod loadgen;
+pub mod location;
+pub mod log_level;
+pub mod logical_batch;
+pub mod integrity;
 pub mod rabbitmq;
+pub mod message_dedup;
+pub mod metrics;
 pub mod models;
+pub mod partitions;
+pub mod payload;
 pub mod processor;
+pub mod replay;
+pub mod registry;
+pub mod reprocess;
+pub mod retry;
+pub mod sampling;
+pub mod schema;
+pub mod sinks;
+pub mod timestamp;
+pub mod tls_check;
+pub mod top_types;
+pub mod transform;
+pub mod validation;
+pub mod wal;
+pub mod webhooks;
+pub mod worker_pool;
+pub mod write_quota;
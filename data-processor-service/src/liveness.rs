@@ -0,0 +1,47 @@
+use std::sync::atomic::{AtomicBool, AtomicI64, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+// Detects a consumer that is connected but no longer settling deliveries while
+// messages are waiting in its queue
+pub struct LivenessCheck {
+    queue_depth: Arc<AtomicU64>,
+    last_progress: Arc<AtomicI64>,
+    paused: Arc<AtomicBool>,
+    max_idle: Duration,
+}
+
+impl LivenessCheck {
+    pub fn new(
+        queue_depth: Arc<AtomicU64>,
+        last_progress: Arc<AtomicI64>,
+        paused: Arc<AtomicBool>,
+        max_idle: Duration,
+    ) -> Self {
+        Self {
+            queue_depth,
+            last_progress,
+            paused,
+            max_idle,
+        }
+    }
+    
+    /// Fails when the queue has a backlog and nothing was processed for longer than
+    /// `max_idle`. A consumer paused through the admin API is considered live.
+    pub fn check(&self) -> Result<(), String> {
+        let depth = self.queue_depth.load(Ordering::Relaxed);
+        if depth == 0 || self.paused.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+        let last_progress = self.last_progress.load(Ordering::Relaxed);
+        let idle_ms = (chrono::Utc::now().timestamp_millis() - last_progress).max(0) as u128;
+        if idle_ms > self.max_idle.as_millis() {
+            return Err(format!(
+                "no message processed for {}s with {} messages waiting",
+                idle_ms / 1000,
+                depth
+            ));
+        }
+        Ok(())
+    }
+}
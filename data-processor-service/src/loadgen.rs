@@ -0,0 +1,162 @@
+use anyhow::Result;
+use crate::bench;
+use crate::config::Config;
+use crate::models::SensorData;
+use crate::rabbitmq::RabbitMQProducer;
+use std::future::Future;
+use std::time::Duration;
+use tokio::time::Instant;
+use tracing::info;
+
+#[derive(Debug, Clone)]
+pub struct LoadgenOptions {
+    // Messages published per second
+    pub rate: f64,
+    // Readings per message
+    pub batch: usize,
+    // Sensor types the readings cycle through
+    pub types: Vec<String>,
+    pub duration: Duration,
+}
+
+#[derive(Debug, Default)]
+pub struct LoadgenReport {
+    pub messages: u64,
+    pub readings: u64,
+    pub elapsed: Duration,
+    pub requested_rate: f64,
+}
+
+impl LoadgenReport {
+    pub fn publish_rate(&self) -> f64 {
+        self.messages as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON)
+    }
+    
+    pub fn print(&self) {
+        println!("Load generator results");
+        println!("  messages:       {}", self.messages);
+        println!("  readings:       {}", self.readings);
+        println!("  elapsed:        {:.3?}", self.elapsed);
+        println!("  requested rate: {:.2} msg/s", self.requested_rate);
+        println!(
+            "  achieved rate:  {:.2} msg/s ({:.2} readings/s)",
+            self.publish_rate(),
+            self.readings as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON)
+        );
+    }
+}
+
+/// Publishes synthetic readings to the configured exchange at `rate` messages per second
+/// for `duration`.
+pub async fn run(config: &Config, options: LoadgenOptions) -> Result<LoadgenReport> {
+    let producer = RabbitMQProducer::from_config(&config.rabbitmq, config.rabbitmq.exchange_name.clone()).await?;
+    let routing_key = config.rabbitmq.routing_key.clone();
+    info!(
+        "Load generator: {} msg/s of {} readings ({:?}) for {:?}",
+        options.rate, options.batch, options.types, options.duration
+    );
+    
+    let report = generate(&options, |readings| {
+        let producer = &producer;
+        let routing_key = &routing_key;
+        async move { producer.send_sensor_data(routing_key, &readings).await }
+    })
+    .await?;
+    producer.close().await?;
+    Ok(report)
+}
+
+/// Paces `publish` calls so message `n` is sent no earlier than `n / rate` seconds after
+/// the start. When publishing is slower than the rate, messages go out back to back and
+/// the achieved rate in the report falls short of the requested one.
+pub async fn generate<F, Fut>(options: &LoadgenOptions, mut publish: F) -> Result<LoadgenReport>
+where
+    F: FnMut(Vec<SensorData>) -> Fut,
+    Fut: Future<Output = Result<()>>,
+{
+    anyhow::ensure!(options.rate > 0.0, "--rate must be greater than zero");
+    let types: Vec<String> = if options.types.is_empty() {
+        bench::SYNTHETIC_TYPES.iter().map(|sensor_type| sensor_type.to_string()).collect()
+    } else {
+        options.types.clone()
+    };
+    let batch = options.batch.max(1);
+    let interval = Duration::from_secs_f64(1.0 / options.rate);
+    
+    let started = Instant::now();
+    let deadline = started + options.duration;
+    let mut report = LoadgenReport {
+        requested_rate: options.rate,
+        ..LoadgenReport::default()
+    };
+    loop {
+        let due = started + interval.mul_f64(report.messages as f64);
+        if due >= deadline || Instant::now() >= deadline {
+            break;
+        }
+        tokio::time::sleep_until(due).await;
+        
+        let offset = report.readings as usize;
+        let readings = (offset..offset + batch)
+            .map(|i| bench::synthetic_reading(i, &types[i % types.len()]))
+            .collect();
+        publish(readings).await?;
+        report.messages += 1;
+        report.readings += batch as u64;
+    }
+    report.elapsed = started.elapsed();
+    
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn publishes_at_the_requested_rate() {
+        tokio_test::block_on(async {
+            // Paused time advances only through the sleeps, so the count is exact
+            tokio::time::pause();
+            let options = LoadgenOptions {
+                rate: 50.0,
+                batch: 3,
+                types: vec!["temperature".to_string(), "humidity".to_string()],
+                duration: Duration::from_secs(2),
+            };
+            let mut sent = Vec::new();
+            
+            let report = generate(&options, |readings| {
+                sent.push(readings);
+                async { Ok(()) }
+            })
+            .await
+            .unwrap();
+            
+            assert_eq!((report.messages, report.readings), (100, 300));
+            assert_eq!(sent.len(), 100);
+            assert!(sent.iter().all(|readings| readings.len() == 3));
+            assert_eq!(sent[0][1].r#type, "humidity");
+            assert!(report.elapsed < options.duration);
+        });
+    }
+    
+    #[test]
+    fn stops_at_the_first_publish_error() {
+        tokio_test::block_on(async {
+            tokio::time::pause();
+            let options = LoadgenOptions { rate: 10.0, batch: 1, types: Vec::new(), duration: Duration::from_secs(10) };
+            let mut calls = 0;
+            
+            let result = generate(&options, |_| {
+                calls += 1;
+                let fail = calls == 3;
+                async move { if fail { anyhow::bail!("broker gone") } else { Ok(()) } }
+            })
+            .await;
+            
+            assert!(result.is_err());
+            assert_eq!(calls, 3);
+        });
+    }
+}
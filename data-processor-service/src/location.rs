@@ -0,0 +1,75 @@
+use crate::config::LocationConfig;
+use crate::models::GeoPoint;
+use crate::payload::Payload;
+
+// Mean Earth radius (IUGG), matching the distance computed in SQL
+pub const EARTH_RADIUS_M: f64 = 6_371_008.8;
+
+impl GeoPoint {
+    pub fn new(latitude: f64, longitude: f64) -> Option<Self> {
+        ((-90.0..=90.0).contains(&latitude) && (-180.0..=180.0).contains(&longitude))
+            .then_some(Self { latitude, longitude })
+    }
+}
+
+/// Location of a reading: the payload's coordinate fields when both are valid numbers,
+/// otherwise the sensor's entry in `sensor_locations`.
+pub fn resolve(config: &LocationConfig, sensor_name: &str, payload: &Payload) -> Option<GeoPoint> {
+    let from_payload = payload
+        .get_f64(&config.latitude_field)
+        .zip(payload.get_f64(&config.longitude_field))
+        .and_then(|(latitude, longitude)| GeoPoint::new(latitude, longitude));
+    from_payload.or_else(|| config.sensor_locations.get(sensor_name).copied())
+}
+
+// Latitude/longitude bounds enclosing every point within `radius_m`; the longitude
+// bounds are None when the box would reach a pole or cross the antimeridian
+pub fn bounding_box(center: GeoPoint, radius_m: f64) -> ((f64, f64), Option<(f64, f64)>) {
+    let angular = radius_m / EARTH_RADIUS_M;
+    let lat_delta = angular.to_degrees();
+    let lat_bounds = (center.latitude - lat_delta, center.latitude + lat_delta);
+    if lat_bounds.0 <= -90.0 || lat_bounds.1 >= 90.0 {
+        return (lat_bounds, None);
+    }
+    // The circle's widest longitude is at its tangent meridians, asin(sin d / cos lat)
+    // away; the circle wraps every longitude once sin d reaches cos lat
+    let cos_lat = center.latitude.to_radians().cos();
+    if angular.sin() >= cos_lat {
+        return (lat_bounds, None);
+    }
+    let lon_delta = (angular.sin() / cos_lat).asin().to_degrees();
+    let lon_bounds = (center.longitude - lon_delta, center.longitude + lon_delta);
+    if lon_bounds.0 < -180.0 || lon_bounds.1 > 180.0 {
+        return (lat_bounds, None);
+    }
+    (lat_bounds, Some(lon_bounds))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn lon_half_width(latitude: f64, radius_m: f64) -> Option<f64> {
+        let (_, lon_bounds) = bounding_box(GeoPoint { latitude, longitude: 0.0 }, radius_m);
+        lon_bounds.map(|(min, max)| (max - min) / 2.0)
+    }
+    
+    #[test]
+    fn longitude_bounds_reach_the_circles_tangent_meridians() {
+        let at_60 = lon_half_width(60.0, 1_000_000.0).unwrap();
+        assert!((at_60 - 18.218).abs() < 0.001, "{}", at_60);
+        let at_45 = lon_half_width(45.0, 2_000_000.0).unwrap();
+        assert!((at_45 - 25.893).abs() < 0.001, "{}", at_45);
+        // Near the equator the box is as wide as it is tall
+        let at_0 = lon_half_width(0.0, 100_000.0).unwrap();
+        assert!((at_0 - (100_000.0 / EARTH_RADIUS_M).to_degrees()).abs() < 1e-9);
+    }
+    
+    #[test]
+    fn spans_every_longitude_once_the_circle_reaches_a_pole() {
+        // 3000 km from 60°N stops about 3° short of the pole
+        assert!(lon_half_width(60.0, 3_000_000.0).is_some());
+        assert_eq!(lon_half_width(70.0, 2_300_000.0), None);
+        assert_eq!(lon_half_width(-89.0, 200_000.0), None);
+    }
+}
@@ -0,0 +1,66 @@
+use anyhow::Result;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
+use tracing_subscriber::{fmt, reload, EnvFilter, Registry};
+
+// Handle to the process-wide log filter, so the level can be changed without a restart
+#[derive(Clone)]
+pub struct LogLevel {
+    handle: reload::Handle<EnvFilter, Registry>,
+}
+
+impl LogLevel {
+    /// Installs the global subscriber, starting from `RUST_LOG`. Logs go to stderr when
+    /// stdout carries readings.
+    pub fn init(stdout_reserved: bool) -> Self {
+        let (filter, handle) = reload::Layer::new(EnvFilter::from_default_env());
+        let writer = if stdout_reserved {
+            BoxMakeWriter::new(std::io::stderr)
+        } else {
+            BoxMakeWriter::new(std::io::stdout)
+        };
+        tracing_subscriber::registry()
+            .with(filter)
+            .with(fmt::layer().with_writer(writer))
+            .init();
+        Self { handle }
+    }
+    
+    /// Replaces the filter with `directives`, in `RUST_LOG` syntax (e.g. `debug` or
+    /// `info,data_processor_service=trace`).
+    pub fn set(&self, directives: &str) -> Result<()> {
+        let filter = EnvFilter::try_new(directives)?;
+        self.handle.reload(filter)?;
+        Ok(())
+    }
+    
+    pub fn current(&self) -> Result<String> {
+        Ok(self.handle.with_current(|filter| filter.to_string())?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tracing::Level;
+    
+    #[test]
+    fn set_changes_the_current_and_effective_filter() {
+        let (filter, handle) = reload::Layer::new(EnvFilter::new("info"));
+        let level = LogLevel { handle };
+        let subscriber = tracing_subscriber::registry().with(filter);
+        
+        tracing::subscriber::with_default(subscriber, || {
+            assert!(!tracing::enabled!(Level::DEBUG));
+            
+            level.set("debug").unwrap();
+            assert_eq!(level.current().unwrap(), "debug");
+            assert!(tracing::enabled!(Level::DEBUG));
+            
+            // An invalid filter is refused and the current one stays in effect
+            assert!(level.set("info,[unclosed").is_err());
+            assert_eq!(level.current().unwrap(), "debug");
+        });
+    }
+}
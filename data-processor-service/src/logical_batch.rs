@@ -0,0 +1,85 @@
+use anyhow::Result;
+use crate::database::Database;
+use crate::models::BatchCompletedEvent;
+use std::sync::Arc;
+use std::time::Duration;
+
+// One message's place in a logical batch, from the `batch_id`, `sequence` (1-based) and
+// `total` headers
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BatchPart {
+    pub batch_id: String,
+    pub sequence: u32,
+    pub total: u32,
+}
+
+impl BatchPart {
+    pub fn new(batch_id: String, sequence: u32, total: u32) -> Option<Self> {
+        (total > 0 && (1..=total).contains(&sequence)).then_some(Self { batch_id, sequence, total })
+    }
+}
+
+// Tracks which parts of each logical batch have been persisted. The state lives in the
+// database, so parts consumed by different instances count towards the same batch.
+pub struct LogicalBatchTracker {
+    database: Arc<Database>,
+    expire_after: Duration,
+}
+
+impl LogicalBatchTracker {
+    pub fn new(database: Arc<Database>, expire_after: Duration) -> Self {
+        Self { database, expire_after }
+    }
+    
+    /// Records a persisted part and returns the completion event when it was the last one
+    /// missing. Partial batches with no new part for `expire_after` are dropped first.
+    pub async fn record(&self, part: &BatchPart, readings: u64) -> Result<Option<BatchCompletedEvent>> {
+        self.database.record_batch_part(part, readings, self.expire_after).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Config, DatabaseConfig};
+    
+    // Needs a PostgreSQL server; skipped unless TEST_DATABASE_URL is set
+    fn test_database_url() -> Option<String> {
+        std::env::var("TEST_DATABASE_URL").ok()
+    }
+    
+    #[test]
+    fn rejects_parts_outside_the_batch() {
+        assert!(BatchPart::new("b".to_string(), 0, 3).is_none());
+        assert!(BatchPart::new("b".to_string(), 4, 3).is_none());
+        assert!(BatchPart::new("b".to_string(), 3, 3).is_some());
+    }
+    
+    #[test]
+    fn completes_a_three_part_batch_once_across_instances() {
+        let Some(url) = test_database_url() else {
+            return;
+        };
+        tokio_test::block_on(async {
+            let config = DatabaseConfig { url, ..Config::default().database };
+            let database = Arc::new(Database::new(&config).await.unwrap());
+            // Two instances consuming from the same queue
+            let first = LogicalBatchTracker::new(database.clone(), Duration::from_secs(3600));
+            let second = LogicalBatchTracker::new(database, Duration::from_secs(3600));
+            let batch_id = uuid::Uuid::new_v4().to_string();
+            let part = |sequence| BatchPart::new(batch_id.clone(), sequence, 3).unwrap();
+            
+            assert!(first.record(&part(1), 10).await.unwrap().is_none());
+            // A redelivered part is counted once
+            assert!(second.record(&part(1), 10).await.unwrap().is_none());
+            assert!(second.record(&part(3), 5).await.unwrap().is_none());
+            
+            let event = first.record(&part(2), 7).await.unwrap().expect("batch completes");
+            assert_eq!((event.parts, event.readings), (3, 22));
+            
+            // Late redeliveries don't complete it again
+            assert!(second.record(&part(2), 7).await.unwrap().is_none());
+            assert!(first.record(&part(3), 5).await.unwrap().is_none());
+        });
+    }
+}
@@ -1,35 +1,277 @@
 use anyhow::Result;
-use clap::Parser;
+use chrono::{DateTime, Utc};
+use clap::{Parser, Subcommand};
+use data_processor_service::bench::{self, BenchOptions};
+use data_processor_service::admin::{self, AdminState};
+use data_processor_service::api::{self, ApiState};
+use data_processor_service::audit;
 use data_processor_service::config::Config;
+use data_processor_service::database::Database;
+use data_processor_service::diagnostics;
+use data_processor_service::http;
+use data_processor_service::loadgen::{self, LoadgenOptions};
+use data_processor_service::log_level::LogLevel;
 use data_processor_service::processor::DataProcessor;
+use data_processor_service::registry;
+use data_processor_service::replay::{self, ReplayOptions};
+use data_processor_service::reprocess::{self, ReprocessOptions};
+use data_processor_service::schema;
+use serde_json::json;
+use std::sync::Arc;
 use tracing::{info, error};
+use uuid::Uuid;
 
 #[derive(Parser)]
 #[command(name = "data-processor-service")]
 #[command(about = "Data Processor Service for microservices architecture")]
 struct Args {
-    #[arg(short, long, default_value = "config.yaml")]
+    #[arg(short, long, default_value = "config.yaml", global = true)]
     config: String,
+    
+    /// Config profile overlay (e.g. `prod` loads config.prod.yaml over the base config)
+    #[arg(long, env = "APP_ENV", global = true)]
+    profile: Option<String>,
+    
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Run the data processor (default)
+    Run {
+        /// Exit after this many messages, or earlier once the queue is empty
+        #[arg(long)]
+        max_messages: Option<u64>,
+    },
+    /// Publish synthetic messages through the pipeline and report end-to-end throughput
+    Bench {
+        /// Number of synthetic readings to publish
+        #[arg(long, default_value_t = 100000)]
+        messages: usize,
+        /// Readings per published message
+        #[arg(long, default_value_t = 100)]
+        batch: usize,
+        /// Also write the readings to the configured database; they are tagged with the
+        /// `bench-sensor-*` names
+        #[arg(long)]
+        store: bool,
+    },
+    /// Publish synthetic readings at a fixed rate to stress-test a deployment
+    Loadgen {
+        /// Messages published per second
+        #[arg(long)]
+        rate: f64,
+        /// Readings per message
+        #[arg(long, default_value_t = 1)]
+        batch: usize,
+        /// Sensor types to cycle through (comma-separated); defaults to energy, air_quality, motion
+        #[arg(long, value_delimiter = ',')]
+        types: Vec<String>,
+        /// How long to publish, in seconds
+        #[arg(long)]
+        duration: u64,
+    },
+    /// Recompute payload hashes for a time range and report readings that no longer match
+    VerifyIntegrity {
+        /// Start of the range (RFC 3339, inclusive)
+        #[arg(long)]
+        from: DateTime<Utc>,
+        /// End of the range (RFC 3339, exclusive); defaults to now
+        #[arg(long)]
+        to: Option<DateTime<Utc>>,
+    },
+    /// Republish dead-lettered messages to the main exchange
+    DlqReplay {
+        /// Stop after this many messages
+        #[arg(long)]
+        limit: Option<u64>,
+        /// Apply `processing.transforms` to each message before republishing
+        #[arg(long)]
+        transform: bool,
+        /// Republish each sensor's readings in timestamp order, sorting this many
+        /// messages at a time
+        #[arg(long)]
+        ordered: Option<usize>,
+    },
+    /// Re-run `processing.transforms` over stored readings and rewrite the changed payloads
+    ReprocessRange {
+        /// Start of the range (RFC 3339, inclusive)
+        #[arg(long)]
+        start: DateTime<Utc>,
+        /// End of the range (RFC 3339, exclusive)
+        #[arg(long)]
+        end: DateTime<Utc>,
+        /// Readings read and rewritten per transaction
+        #[arg(long, default_value_t = 1000)]
+        batch_size: usize,
+        /// Report how many readings would change without updating them
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Print a stored reading with its encrypted payload fields decrypted (audited)
+    Decrypt {
+        /// Id of the reading
+        #[arg(long)]
+        id: Uuid,
+    },
+    /// Bulk-upsert sensor registry entries from a CSV or JSON file (audited)
+    ImportRegistry {
+        /// `.csv` (sensor_name, latitude, longitude, metadata columns) or `.json` file
+        #[arg(long)]
+        path: std::path::PathBuf,
+    },
+    /// Print key health numbers from a running instance, or from the database
+    Stats {
+        /// Base URL of a running instance (e.g. http://localhost:8080); without it the
+        /// database is summarized instead
+        #[arg(long)]
+        url: Option<String>,
+        /// Print the snapshot as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Compare the live `sensor_readings` columns with the schema the code expects
+    SchemaDiff {
+        /// Also print a migration skeleton for the differences
+        #[arg(long)]
+        emit_migration: bool,
+    },
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    // Initialize tracing
-    tracing_subscriber::fmt()
-        .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
-        .init();
-
     let args = Args::parse();
     
+    // Load configuration first: it decides whether logs may use stdout
+    let config = Config::load_profile(&args.config, args.profile.as_deref())?;
+    
+    // Initialize tracing; the filter can be changed later through /admin/log-level, which
+    // is only mounted when `http.admin_tokens` is set
+    let log_level = LogLevel::init(config.writes_readings_to_stdout());
+    
     info!("Starting Data Processor Service...");
     info!("Config file: {}", args.config);
-    
-    // Load configuration
-    let config = Config::load(&args.config)?;
+    if let Some(profile) = &args.profile {
+        info!("Config profile: {}", profile);
+    }
     info!("Configuration loaded successfully");
     info!("RabbitMQ connection: {}", config.rabbitmq.connection_string);
     info!("Database URL: {}", config.database.url);
     
+    match args.command.unwrap_or(Command::Run { max_messages: None }) {
+        Command::Run { max_messages } => run(config, args.config, args.profile, max_messages, log_level).await,
+        Command::Bench { messages, batch, store } => {
+            let report = bench::run(&config, BenchOptions { messages, batch, store }).await?;
+            report.print();
+            Ok(())
+        }
+        Command::Loadgen { rate, batch, types, duration } => {
+            let options = LoadgenOptions { rate, batch, types, duration: std::time::Duration::from_secs(duration) };
+            let report = loadgen::run(&config, options).await?;
+            report.print();
+            Ok(())
+        }
+        Command::VerifyIntegrity { from, to } => {
+            let database = Database::new(&config.database).await?;
+            let report = database.verify_integrity(from, to.unwrap_or_else(Utc::now)).await?;
+            report.print();
+            if report.mismatches.is_empty() {
+                Ok(())
+            } else {
+                anyhow::bail!("{} readings failed integrity verification", report.mismatches.len())
+            }
+        }
+        Command::DlqReplay { limit, transform, ordered } => {
+            let report = replay::run(&config, ReplayOptions { limit, transform, order_window: ordered }).await?;
+            report.print();
+            Ok(())
+        }
+        Command::ReprocessRange { start, end, batch_size, dry_run } => {
+            let report = reprocess::run(&config, ReprocessOptions { start, end, batch_size, dry_run }).await?;
+            report.print();
+            Ok(())
+        }
+        Command::Decrypt { id } => {
+            let database = Database::new(&config.database).await?;
+            if !database.encrypts_fields() {
+                anyhow::bail!("database.field_encryption is not configured");
+            }
+            let principal = format!("cli:{}", std::env::var("USER").unwrap_or_else(|_| "unknown".to_string()));
+            let result = database.get_sensor_reading(id).await;
+            let outcome = match &result {
+                Ok(Some(_)) => "ok".to_string(),
+                Ok(None) => "not found".to_string(),
+                Err(e) => format!("failed: {}", e),
+            };
+            audit::record(&database, &principal, "decrypt_reading", json!({ "id": id }), &outcome).await;
+            match result? {
+                Some(reading) => {
+                    println!("{}", serde_json::to_string_pretty(&reading)?);
+                    Ok(())
+                }
+                None => anyhow::bail!("Reading {} not found", id),
+            }
+        }
+        Command::ImportRegistry { path } => {
+            let entries = registry::load_registry_file(&path)?;
+            let database = Database::new(&config.database).await?;
+            let principal = format!("cli:{}", std::env::var("USER").unwrap_or_else(|_| "unknown".to_string()));
+            let result = database.upsert_registry_entries(&entries).await;
+            let outcome = match &result {
+                Ok(upserted) => format!("{} entries upserted", upserted),
+                Err(e) => format!("failed: {}", e),
+            };
+            let parameters = json!({ "path": path.display().to_string(), "entries": entries.len() });
+            audit::record(&database, &principal, "import_registry", parameters, &outcome).await;
+            println!("Upserted {} sensor registry entries from {}", result?, path.display());
+            Ok(())
+        }
+        Command::Stats { url, json } => {
+            let snapshot = match url {
+                Some(url) => diagnostics::from_instance(&url).await?,
+                None => diagnostics::from_database(&Database::connect(&config.database).await?).await?,
+            };
+            if json {
+                println!("{}", serde_json::to_string_pretty(&snapshot)?);
+            } else {
+                snapshot.print();
+            }
+            Ok(())
+        }
+        Command::SchemaDiff { emit_migration } => {
+            let database = Database::connect(&config.database).await?;
+            let diff = schema::diff_sensor_readings(&database).await?;
+            diff.print();
+            if emit_migration && !diff.differences.is_empty() {
+                println!();
+                print!("{}", diff.migration_sql());
+            }
+            if diff.differences.is_empty() {
+                Ok(())
+            } else {
+                anyhow::bail!("{} schema differences found", diff.differences.len())
+            }
+        }
+    }
+}
+
+async fn run(
+    config: Config,
+    config_path: String,
+    profile: Option<String>,
+    max_messages: Option<u64>,
+    log_level: LogLevel,
+) -> Result<()> {
+    let http_config = config.http.clone();
+    let snapshot_path = config.metrics.snapshot_on_exit_path.clone();
+    let timestamp_format = config.api.timestamp_format;
+    let max_query_timeout = std::time::Duration::from_millis(config.api.max_query_timeout_ms);
+    let max_query_limit = config.api.max_query_limit;
+    let admin_config = Arc::new(config.clone());
+    #[cfg(feature = "grpc")]
+    let grpc_config = config.grpc.clone();
+    
     // Initialize data processor
     let mut processor = match DataProcessor::new(config).await {
         Ok(p) => {
@@ -42,12 +284,70 @@ async fn main() -> Result<()> {
         }
     };
     
+    #[cfg(unix)]
+    processor.watch_config(config_path, profile)?;
+    
+    if let Some(http_config) = http_config {
+        let address = http_config.bind_address.parse()?;
+        let mut app = http::router(processor.pipeline()).merge(api::router(ApiState {
+            pipeline: processor.pipeline(),
+            timestamp_format,
+            max_query_timeout,
+            max_query_limit,
+        }));
+        if !http_config.admin_tokens.is_empty() {
+            app = app.nest("/admin", admin::router(AdminState {
+                pipeline: processor.pipeline(),
+                config: admin_config,
+                tokens: Arc::new(http_config.admin_tokens),
+                log_level,
+            }));
+        }
+        tokio::spawn(async move {
+            if let Err(e) = http::serve(address, app).await {
+                error!("HTTP server failed: {}", e);
+            }
+        });
+    }
+    
+    #[cfg(feature = "grpc")]
+    if let Some(grpc_config) = grpc_config {
+        let address = grpc_config.bind_address.parse()?;
+        let pipeline = processor.pipeline();
+        tokio::spawn(async move {
+            if let Err(e) = data_processor_service::grpc::serve(address, pipeline).await {
+                error!("gRPC ingest server failed: {}", e);
+            }
+        });
+    }
+    
     // Start data processing
     info!("Starting data processing loop...");
-    if let Err(e) = processor.start().await {
+    let result = processor.start(max_messages).await;
+    processor.notify_shutdown(&result).await;
+    
+    if let Some(path) = snapshot_path {
+        match processor.pipeline().metrics().write_snapshot(&path) {
+            Ok(()) => info!("Metrics snapshot written to {}", path),
+            Err(e) => error!("Failed to write metrics snapshot to {}: {}", path, e),
+        }
+    }
+    
+    if let Err(e) = result {
         error!("Data processor failed: {}", e);
         return Err(e);
     }
     
+    // Only reached for bounded runs, idle shutdown and SIGTERM/SIGINT; otherwise the loop never returns Ok
+    let stats = processor.get_stats().await?;
+    info!(
+        "Run complete: {} readings processed, {} failed, {} dropped by type filter, {} skipped by header filter",
+        stats.processed_messages,
+        stats.failed_messages,
+        stats.disabled_type_dropped,
+        stats.header_filtered_messages
+    );
+    processor.close().await?;
+    
     Ok(())
 }
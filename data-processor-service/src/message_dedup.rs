@@ -0,0 +1,163 @@
+use crate::config::{MessageDedupConfig, MessageDedupMode};
+use std::collections::hash_map::RandomState;
+use std::collections::{HashSet, VecDeque};
+use std::hash::BuildHasher;
+use std::sync::Mutex;
+
+// Message ids of recently processed deliveries, used to ack redeliveries unprocessed
+pub enum SeenMessages {
+    Exact(Mutex<RecentIds>),
+    Bloom(Mutex<RotatingBloom>),
+}
+
+impl SeenMessages {
+    pub fn new(config: &MessageDedupConfig) -> anyhow::Result<Self> {
+        if config.capacity == 0 {
+            anyhow::bail!("rabbitmq.message_dedup.capacity must be positive");
+        }
+        Ok(match config.mode {
+            MessageDedupMode::Exact => Self::Exact(Mutex::new(RecentIds::new(config.capacity))),
+            MessageDedupMode::Bloom => {
+                if !(config.false_positive_rate > 0.0 && config.false_positive_rate < 1.0) {
+                    anyhow::bail!(
+                        "rabbitmq.message_dedup.false_positive_rate must be between 0 and 1, got {}",
+                        config.false_positive_rate
+                    );
+                }
+                Self::Bloom(Mutex::new(RotatingBloom::new(config.capacity, config.false_positive_rate)))
+            }
+        })
+    }
+    
+    /// Whether the id was seen; in bloom mode this can be a false positive.
+    pub fn contains(&self, id: &str) -> bool {
+        match self {
+            Self::Exact(ids) => ids.lock().unwrap().contains(id),
+            Self::Bloom(bloom) => bloom.lock().unwrap().contains(id),
+        }
+    }
+    
+    pub fn insert(&self, id: &str) {
+        match self {
+            Self::Exact(ids) => ids.lock().unwrap().insert(id),
+            Self::Bloom(bloom) => bloom.lock().unwrap().insert(id),
+        }
+    }
+}
+
+// Exact set of the last `capacity` ids; the oldest is evicted first
+pub struct RecentIds {
+    capacity: usize,
+    ids: HashSet<String>,
+    order: VecDeque<String>,
+}
+
+impl RecentIds {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            ids: HashSet::new(),
+            order: VecDeque::new(),
+        }
+    }
+    
+    fn contains(&self, id: &str) -> bool {
+        self.ids.contains(id)
+    }
+    
+    fn insert(&mut self, id: &str) {
+        if !self.ids.insert(id.to_string()) {
+            return;
+        }
+        self.order.push_back(id.to_string());
+        if self.order.len() > self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.ids.remove(&oldest);
+            }
+        }
+    }
+}
+
+// Two bloom filter generations sized for `capacity` ids each. Inserts go to the current
+// one; once it is full it replaces the previous one, so memory stays fixed while the
+// last `capacity` to `2 * capacity` ids are remembered.
+pub struct RotatingBloom {
+    current: BloomFilter,
+    previous: BloomFilter,
+    capacity: usize,
+}
+
+impl RotatingBloom {
+    fn new(capacity: usize, false_positive_rate: f64) -> Self {
+        Self {
+            current: BloomFilter::new(capacity, false_positive_rate),
+            previous: BloomFilter::new(capacity, false_positive_rate),
+            capacity,
+        }
+    }
+    
+    fn contains(&self, id: &str) -> bool {
+        self.current.contains(id) || self.previous.contains(id)
+    }
+    
+    fn insert(&mut self, id: &str) {
+        if self.current.inserted >= self.capacity {
+            let fresh = self.current.emptied();
+            self.previous = std::mem::replace(&mut self.current, fresh);
+        }
+        self.current.insert(id);
+    }
+}
+
+struct BloomFilter {
+    bits: Vec<u64>,
+    bit_count: u64,
+    hash_count: u32,
+    hasher: RandomState,
+    inserted: usize,
+}
+
+impl BloomFilter {
+    // Optimal size for `capacity` entries: m = -n ln(p) / ln(2)^2 bits, k = (m / n) ln(2)
+    fn new(capacity: usize, false_positive_rate: f64) -> Self {
+        let ln2 = std::f64::consts::LN_2;
+        let bit_count = ((-(capacity as f64) * false_positive_rate.ln()) / (ln2 * ln2)).ceil().max(64.0) as u64;
+        let hash_count = ((bit_count as f64 / capacity as f64) * ln2).round().clamp(1.0, 32.0) as u32;
+        Self {
+            bits: vec![0; bit_count.div_ceil(64) as usize],
+            bit_count,
+            hash_count,
+            hasher: RandomState::new(),
+            inserted: 0,
+        }
+    }
+    
+    fn emptied(&self) -> Self {
+        Self {
+            bits: vec![0; self.bits.len()],
+            bit_count: self.bit_count,
+            hash_count: self.hash_count,
+            hasher: RandomState::new(),
+            inserted: 0,
+        }
+    }
+    
+    // Double hashing: the i-th probe is h1 + i * h2
+    fn positions(&self, id: &str) -> impl Iterator<Item = u64> + '_ {
+        let hash = self.hasher.hash_one(id);
+        let (h1, h2) = (hash & 0xffff_ffff, (hash >> 32) | 1);
+        (0..self.hash_count as u64).map(move |i| h1.wrapping_add(i.wrapping_mul(h2)) % self.bit_count)
+    }
+    
+    fn contains(&self, id: &str) -> bool {
+        self.positions(id).all(|bit| self.bits[(bit / 64) as usize] & (1 << (bit % 64)) != 0)
+    }
+    
+    fn insert(&mut self, id: &str) {
+        let positions: Vec<u64> = self.positions(id).collect();
+        for bit in positions {
+            self.bits[(bit / 64) as usize] |= 1 << (bit % 64);
+        }
+        self.inserted += 1;
+    }
+}
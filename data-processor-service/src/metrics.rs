@@ -0,0 +1,328 @@
+use crate::config::{MetricsConfig, PayloadLabelConfig};
+use prometheus_client::encoding::EncodeLabelSet;
+use prometheus_client::metrics::counter::Counter;
+use prometheus_client::metrics::exemplar::HistogramWithExemplars;
+use prometheus_client::metrics::family::Family;
+use prometheus_client::metrics::gauge::Gauge;
+use prometheus_client::metrics::histogram::{exponential_buckets, Histogram};
+use prometheus_client::registry::Registry;
+use serde_json::Value;
+use crate::top_types::TopTypes;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::Mutex;
+
+const OTHER_LABEL_VALUE: &str = "other";
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct SensorTypeLabels {
+    pub sensor_type: String,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct PayloadFieldLabels {
+    pub sensor_type: String,
+    pub field: String,
+    pub value: String,
+}
+
+// Exemplar labels linking an observation to the trace of the message behind it
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct TraceLabels {
+    pub trace_id: String,
+}
+
+pub struct Metrics {
+    registry: Registry,
+    pub readings_processed: Family<SensorTypeLabels, Counter>,
+    pub readings_failed: Family<SensorTypeLabels, Counter>,
+    pub payload_field_readings: Family<PayloadFieldLabels, Counter>,
+    pub processing_duration: HistogramWithExemplars<TraceLabels>,
+    pub batch_size: Histogram,
+    pub in_flight_batches: Gauge,
+    pub effective_batch_size: Gauge,
+    pub batch_limit_waits: Counter,
+    pub logical_batches_completed: Counter,
+    pub overload_sampled: Counter,
+    pub batch_duplicates_collapsed: Counter,
+    pub cross_instance_duplicates: Counter,
+    pub empty_messages: Counter,
+    pub unknown_type_rejected: Counter,
+    pub payload_fields_stripped: Counter,
+    payload_labels: Vec<PayloadLabelConfig>,
+    max_label_values: usize,
+    // Label values seen per (sensor_type, field), bounding label cardinality
+    seen_label_values: Mutex<HashMap<(String, String), HashSet<String>>>,
+    // Busiest sensor types, the only ones with their own `sensor_type` label
+    top_types: Mutex<TopTypes>,
+}
+
+impl Metrics {
+    pub fn new(config: &MetricsConfig) -> Self {
+        let mut registry = Registry::default();
+        
+        let readings_processed = Family::<SensorTypeLabels, Counter>::default();
+        registry.register(
+            "sensor_readings_processed",
+            "Sensor readings stored",
+            readings_processed.clone(),
+        );
+        let readings_failed = Family::<SensorTypeLabels, Counter>::default();
+        registry.register(
+            "sensor_readings_failed",
+            "Sensor readings that could not be stored",
+            readings_failed.clone(),
+        );
+        let payload_field_readings = Family::<PayloadFieldLabels, Counter>::default();
+        registry.register(
+            "sensor_payload_field_readings",
+            "Readings by configured payload field value",
+            payload_field_readings.clone(),
+        );
+        let processing_duration = HistogramWithExemplars::new(exponential_buckets(0.001, 2.0, 14));
+        registry.register(
+            "processing_duration_seconds",
+            "Time to process one delivered message",
+            processing_duration.clone(),
+        );
+        let batch_size = Histogram::new(exponential_buckets(1.0, 2.0, 12));
+        registry.register(
+            "batch_size",
+            "Readings per inserted batch",
+            batch_size.clone(),
+        );
+        let in_flight_batches = Gauge::default();
+        registry.register(
+            "in_flight_batches",
+            "Batches currently being written to the sinks",
+            in_flight_batches.clone(),
+        );
+        let effective_batch_size = Gauge::default();
+        registry.register(
+            "effective_batch_size",
+            "Batch size currently used for types without a per-type size",
+            effective_batch_size.clone(),
+        );
+        let batch_limit_waits = Counter::default();
+        registry.register(
+            "batch_concurrency_limit_waits",
+            "Batches that had to wait for processing.max_concurrent_batches",
+            batch_limit_waits.clone(),
+        );
+        let logical_batches_completed = Counter::default();
+        registry.register(
+            "logical_batches_completed",
+            "Logical batches whose every part has been persisted",
+            logical_batches_completed.clone(),
+        );
+        let overload_sampled = Counter::default();
+        registry.register(
+            "overload_sampled_readings",
+            "Low-priority readings dropped by overload sampling",
+            overload_sampled.clone(),
+        );
+        let batch_duplicates_collapsed = Counter::default();
+        registry.register(
+            "batch_duplicates_collapsed",
+            "Readings collapsed as duplicates of a later reading in the same message",
+            batch_duplicates_collapsed.clone(),
+        );
+        let cross_instance_duplicates = Counter::default();
+        registry.register(
+            "redis_duplicates_suppressed",
+            "Readings dropped because their dedup key was already claimed in Redis",
+            cross_instance_duplicates.clone(),
+        );
+        let empty_messages = Counter::default();
+        registry.register(
+            "empty_messages",
+            "Deliveries whose array held no readings",
+            empty_messages.clone(),
+        );
+        let unknown_type_rejected = Counter::default();
+        registry.register(
+            "unknown_type_rejected",
+            "Deliveries dead-lettered for a sensor type outside processing.allowed_sensor_types",
+            unknown_type_rejected.clone(),
+        );
+        let payload_fields_stripped = Counter::default();
+        registry.register(
+            "payload_fields_stripped",
+            "Payload fields removed by the field allowlist or denylist before storage",
+            payload_fields_stripped.clone(),
+        );
+        
+        Self {
+            registry,
+            readings_processed,
+            readings_failed,
+            payload_field_readings,
+            processing_duration,
+            batch_size,
+            in_flight_batches,
+            effective_batch_size,
+            batch_limit_waits,
+            logical_batches_completed,
+            overload_sampled,
+            batch_duplicates_collapsed,
+            cross_instance_duplicates,
+            empty_messages,
+            unknown_type_rejected,
+            payload_fields_stripped,
+            payload_labels: config.payload_labels.clone(),
+            max_label_values: config.max_label_values,
+            seen_label_values: Mutex::new(HashMap::new()),
+            top_types: Mutex::new(TopTypes::new(config.max_sensor_types)),
+        }
+    }
+    
+    pub fn record_processed(&self, sensor_type: &str, payload: &Value) {
+        self.readings_processed
+            .get_or_create(&self.sensor_type_labels(sensor_type))
+            .inc();
+        
+        for mapping in self.payload_labels.iter().filter(|m| m.sensor_type == sensor_type) {
+            let Some(value) = payload.get(&mapping.field).and_then(label_value) else {
+                continue;
+            };
+            let value = self.bounded_label_value(sensor_type, &mapping.field, value);
+            self.payload_field_readings
+                .get_or_create(&PayloadFieldLabels {
+                    sensor_type: sensor_type.to_string(),
+                    field: mapping.field.clone(),
+                    value,
+                })
+                .inc();
+        }
+    }
+    
+    pub fn record_failed(&self, sensor_type: &str) {
+        self.readings_failed
+            .get_or_create(&self.sensor_type_labels(sensor_type))
+            .inc();
+    }
+    
+    // Approximate readings (stored or failed) per sensor type, lighter types under "other"
+    pub fn readings_by_type(&self) -> BTreeMap<String, u64> {
+        self.top_types.lock().unwrap().snapshot()
+    }
+    
+    // Counts the reading towards its type's volume; a type pushed out of the top N loses
+    // its series, so the number of `sensor_type` label values stays bounded
+    fn sensor_type_labels(&self, sensor_type: &str) -> SensorTypeLabels {
+        let mut top_types = self.top_types.lock().unwrap();
+        if let Some(evicted) = top_types.record(sensor_type) {
+            let evicted = SensorTypeLabels { sensor_type: evicted };
+            self.readings_processed.remove(&evicted);
+            self.readings_failed.remove(&evicted);
+        }
+        let sensor_type = if top_types.is_tracked(sensor_type) { sensor_type } else { OTHER_LABEL_VALUE };
+        SensorTypeLabels { sensor_type: sensor_type.to_string() }
+    }
+    
+    fn bounded_label_value(&self, sensor_type: &str, field: &str, value: String) -> String {
+        let mut seen = self.seen_label_values.lock().unwrap();
+        let values = seen
+            .entry((sensor_type.to_string(), field.to_string()))
+            .or_default();
+        if values.contains(&value) {
+            return value;
+        }
+        if values.len() >= self.max_label_values {
+            return OTHER_LABEL_VALUE.to_string();
+        }
+        values.insert(value.clone());
+        value
+    }
+    
+    // Prometheus/OpenMetrics text exposition of every registered metric
+    pub fn encode(&self) -> String {
+        let mut output = String::new();
+        if let Err(e) = prometheus_client::encoding::text::encode(&mut output, &self.registry) {
+            tracing::error!("Failed to encode metrics: {}", e);
+        }
+        output
+    }
+    
+    pub fn write_snapshot(&self, path: &str) -> anyhow::Result<()> {
+        std::fs::write(path, self.encode())?;
+        Ok(())
+    }
+}
+
+/// Increments a gauge for as long as it is held, so the decrement also happens when the
+/// tracked future errors out early or is dropped (cancellation, shutdown timeout).
+pub struct GaugeGuard(Gauge);
+
+impl GaugeGuard {
+    pub fn track(gauge: &Gauge) -> Self {
+        gauge.inc();
+        Self(gauge.clone())
+    }
+}
+
+impl Drop for GaugeGuard {
+    fn drop(&mut self) {
+        self.0.dec();
+    }
+}
+
+/// Trace id of a W3C `traceparent` value (`00-<trace id>-<span id>-<flags>`), if valid.
+pub fn traceparent_trace_id(traceparent: &str) -> Option<String> {
+    let mut parts = traceparent.trim().split('-');
+    let (_version, trace_id) = (parts.next()?, parts.next()?);
+    let valid = trace_id.len() == 32
+        && trace_id.bytes().all(|byte| byte.is_ascii_hexdigit())
+        && trace_id.bytes().any(|byte| byte != b'0');
+    valid.then(|| trace_id.to_ascii_lowercase())
+}
+
+fn label_value(value: &Value) -> Option<String> {
+    match value {
+        Value::Bool(b) => Some(b.to_string()),
+        Value::Number(n) => Some(n.to_string()),
+        Value::String(s) => Some(s.clone()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn gauge_guard_decrements_when_the_tracked_future_is_dropped() {
+        let gauge = Gauge::default();
+        let tracked = {
+            let gauge = gauge.clone();
+            async move {
+                let _in_flight = GaugeGuard::track(&gauge);
+                std::future::pending::<()>().await;
+            }
+        };
+        let mut tracked = Box::pin(tracked);
+        
+        tokio_test::block_on(async {
+            let polled = futures_util::poll!(tracked.as_mut());
+            assert!(polled.is_pending());
+        });
+        assert_eq!(gauge.get(), 1);
+        
+        drop(tracked);
+        assert_eq!(gauge.get(), 0);
+    }
+    
+    #[test]
+    fn snapshot_holds_the_current_counter_values() {
+        let metrics = Metrics::new(&MetricsConfig::default());
+        let energy = SensorTypeLabels { sensor_type: "energy".to_string() };
+        metrics.readings_processed.get_or_create(&energy).inc_by(7);
+        metrics.empty_messages.inc_by(2);
+        let path = std::env::temp_dir().join(format!("metrics-test-{}.prom", uuid::Uuid::new_v4()));
+        
+        metrics.write_snapshot(path.to_str().unwrap()).unwrap();
+        let snapshot = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(snapshot.contains("sensor_readings_processed_total{sensor_type=\"energy\"} 7"), "{}", snapshot);
+        assert!(snapshot.contains("empty_messages_total 2"), "{}", snapshot);
+    }
+}
@@ -1,13 +1,18 @@
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use uuid::Uuid;
+use crate::payload::Payload;
 
 // New data structures for the incoming JSON format
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SensorData {
     pub r#type: String,
     pub name: String,
-    pub payload: serde_json::Value,
+    pub payload: Payload,
+    // RFC 3339 measurement time; without it `processing.timestamp_keys` or the receive time is used
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<DateTime<Utc>>,
 }
 
 // Energy data structure
@@ -30,8 +35,82 @@ pub struct MotionPayload {
     pub motion_detected: bool,
 }
 
-// Database models
+// Failure context published to the dead-letter exchange when `dlq_wrap: enriched`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeadLetterEnvelope {
+    pub original_payload: String,
+    pub error: String,
+    pub failed_at: DateTime<Utc>,
+    pub attempt_count: u32,
+    pub source_exchange: String,
+    pub source_routing_key: String,
+}
+
+// A message stored in the `quarantine` table instead of (or as well as) being dead-lettered
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct QuarantinedMessage {
+    // Assigned by the database; ignored on insert
+    pub id: i64,
+    pub payload: Vec<u8>,
+    pub error: String,
+    pub exchange: String,
+    pub routing_key: String,
+    pub message_id: Option<String>,
+    pub content_type: Option<String>,
+    pub attempt_count: i32,
+    pub quarantined_at: DateTime<Utc>,
+}
+
+// Metadata registered for one sensor, attached to its readings by `registry::RegistryEnricher`
 #[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct SensorRegistryEntry {
+    pub sensor_name: String,
+    pub metadata: serde_json::Value,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    #[serde(default = "Utc::now")]
+    pub updated_at: DateTime<Utc>,
+}
+
+// Record of an operational action taken through the admin API
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEvent {
+    pub principal: String,
+    pub action: String,
+    pub parameters: serde_json::Value,
+    pub outcome: String,
+    pub at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertState {
+    Triggered,
+    Resolved,
+}
+
+// Published/logged when an alert rule changes state
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Alert {
+    pub rule: String,
+    pub sensor_type: String,
+    pub state: AlertState,
+    pub message: String,
+    pub at: DateTime<Utc>,
+}
+
+// Emitted once every part of a logical batch has been persisted
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BatchCompletedEvent {
+    pub batch_id: String,
+    pub parts: u32,
+    pub readings: u64,
+    pub first_part_at: DateTime<Utc>,
+    pub completed_at: DateTime<Utc>,
+}
+
+// Database models
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, sqlx::FromRow)]
 pub struct SensorReading {
     pub id: Uuid,
     pub sensor_type: String,
@@ -39,6 +118,9 @@ pub struct SensorReading {
     pub payload: serde_json::Value,
     pub timestamp: DateTime<Utc>,
     pub created_at: DateTime<Utc>,
+    pub source_id: Option<String>,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -47,12 +129,206 @@ pub struct SensorReadingInput {
     pub sensor_name: String,
     pub payload: serde_json::Value,
     pub timestamp: DateTime<Utc>,
+    pub source_id: Option<String>,
+    #[serde(default)]
+    pub location: Option<GeoPoint>,
+}
+
+// WGS84 coordinates in degrees
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct GeoPoint {
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
+// Stretch longer than the expected reporting interval with no reading from a sensor
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct ReadingGap {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+}
+
+// Bucket width for time-bucketed queries, named after the `date_trunc` field
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TimeBucket {
+    Minute,
+    Hour,
+    Day,
+    Week,
+    Month,
+}
+
+impl TimeBucket {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TimeBucket::Minute => "minute",
+            TimeBucket::Hour => "hour",
+            TimeBucket::Day => "day",
+            TimeBucket::Week => "week",
+            TimeBucket::Month => "month",
+        }
+    }
+    
+    // Upper bound on the buckets touching [from, to); months count as 28 days so the
+    // estimate never falls short
+    pub fn max_buckets_between(&self, from: DateTime<Utc>, to: DateTime<Utc>) -> i64 {
+        let width = match self {
+            TimeBucket::Minute => 60,
+            TimeBucket::Hour => 3_600,
+            TimeBucket::Day => 86_400,
+            TimeBucket::Week => 7 * 86_400,
+            TimeBucket::Month => 28 * 86_400,
+        };
+        (to - from).num_seconds().max(0) / width + 2
+    }
+}
+
+// How buckets without readings appear in a rollup: left out, with null aggregates, or
+// with the aggregates of the last bucket that had readings
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GapFill {
+    #[default]
+    None,
+    Null,
+    Previous,
+}
+
+// Aggregates of one numeric payload field per time bucket over [from, to)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RollupQuery {
+    pub field: String,
+    pub bucket: TimeBucket,
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    pub sensor_type: Option<String>,
+    pub sensor_name: Option<String>,
+    #[serde(default)]
+    pub fill: GapFill,
+}
+
+// `count` is the number of readings with a numeric value; 0 marks a filled gap
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, sqlx::FromRow)]
+pub struct RollupBucket {
+    pub bucket: DateTime<Utc>,
+    pub count: i64,
+    pub avg: Option<f64>,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+}
+
+// Reading attributes a query can be narrowed to with `fields=`; `location` covers both
+// coordinate columns
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReadingField {
+    Id,
+    SensorType,
+    SensorName,
+    Payload,
+    Timestamp,
+    CreatedAt,
+    SourceId,
+    Location,
+}
+
+impl ReadingField {
+    pub const ALL: [ReadingField; 8] = [
+        ReadingField::Id,
+        ReadingField::SensorType,
+        ReadingField::SensorName,
+        ReadingField::Payload,
+        ReadingField::Timestamp,
+        ReadingField::CreatedAt,
+        ReadingField::SourceId,
+        ReadingField::Location,
+    ];
+    
+    // Key in the reading JSON returned by the API
+    pub fn name(self) -> &'static str {
+        match self {
+            ReadingField::Id => "id",
+            ReadingField::SensorType => "sensor_type",
+            ReadingField::SensorName => "sensor_name",
+            ReadingField::Payload => "payload",
+            ReadingField::Timestamp => "timestamp",
+            ReadingField::CreatedAt => "created_at",
+            ReadingField::SourceId => "source_id",
+            ReadingField::Location => "location",
+        }
+    }
+    
+    // Parses a comma-separated list such as "id,sensor_type,timestamp"
+    pub fn parse_list(list: &str) -> Result<Vec<ReadingField>, String> {
+        let mut fields = Vec::new();
+        for name in list.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+            let field = Self::ALL.into_iter().find(|field| field.name() == name).ok_or_else(|| {
+                let known: Vec<&str> = Self::ALL.iter().map(|field| field.name()).collect();
+                format!("unknown field '{}' (expected one of {})", name, known.join(", "))
+            })?;
+            if !fields.contains(&field) {
+                fields.push(field);
+            }
+        }
+        if fields.is_empty() {
+            return Err("fields must name at least one field".to_string());
+        }
+        Ok(fields)
+    }
+}
+
+// Filters for reading queries; every field is optional and they combine with AND
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ReadingQuery {
+    pub sensor_type: Option<String>,
+    pub sensor_name: Option<String>,
+    pub source_id: Option<String>,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    pub limit: Option<i64>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ProcessingStats {
     pub processed_messages: u64,
     pub failed_messages: u64,
+    // Rendered by `api::StatsResponse` in the configured timestamp format
+    #[serde(skip_serializing)]
     pub last_processed_at: Option<DateTime<Utc>>,
     pub processing_rate_per_second: f64,
+    pub non_finite_rejected: u64,
+    pub non_finite_nulled: u64,
+    pub disabled_type_dropped: u64,
+    pub header_filtered_messages: u64,
+    pub future_skew_clamped: u64,
+    pub future_skew_rejected: u64,
+    pub overload_sampled: u64,
+    pub batch_duplicates_collapsed: u64,
+    pub cross_instance_duplicates: u64,
+    pub empty_messages: u64,
+    pub unknown_type_rejected: u64,
+    // `processing.batch_size`, or the adaptive controller's current size
+    pub effective_batch_size: u64,
+    pub duplicate_messages: u64,
+    pub payload_fields_stripped: u64,
+    pub derived_readings: u64,
+    // Sampled readings that read-back verification found missing or changed
+    pub read_back_mismatches: u64,
+    // Approximate readings of the busiest `metrics.max_sensor_types` types; the rest are under "other"
+    pub readings_by_type: BTreeMap<String, u64>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn bounds_the_buckets_in_a_range() {
+        let from = DateTime::parse_from_rfc3339("2024-01-01T00:00:30Z").unwrap().with_timezone(&Utc);
+        // Two minutes starting mid-minute touch three minute buckets
+        assert!(TimeBucket::Minute.max_buckets_between(from, from + chrono::Duration::minutes(2)) >= 3);
+        // A year is at most 13 monthly buckets
+        let year = TimeBucket::Month.max_buckets_between(from, from + chrono::Duration::days(366));
+        assert!((13..=15).contains(&year));
+    }
 }
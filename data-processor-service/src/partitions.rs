@@ -0,0 +1,79 @@
+use anyhow::Result;
+use chrono::{DateTime, Datelike, Months, NaiveDate, Utc};
+use crate::config::PartitioningConfig;
+use crate::database::Database;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tracing::{error, info};
+
+// Ensures partitions exist for the current month and `months_ahead` months after it,
+// then applies the configured retention
+pub async fn maintain_partitions(database: &Database, config: &PartitioningConfig) -> Result<()> {
+    let today = Utc::now().date_naive();
+    let current_month = NaiveDate::from_ymd_opt(today.year(), today.month(), 1)
+        .ok_or_else(|| anyhow::anyhow!("Invalid current month"))?;
+    
+    for offset in 0..=config.months_ahead {
+        let month_start = current_month
+            .checked_add_months(Months::new(offset))
+            .ok_or_else(|| anyhow::anyhow!("Partition month out of range"))?;
+        if database.ensure_monthly_partition(month_start).await? {
+            info!("Created sensor_readings partition for {}", month_start.format("%Y-%m"));
+        }
+    }
+    
+    apply_retention(database, config, Utc::now()).await
+}
+
+/// Drops monthly partitions that every sensor type's TTL has expired, then deletes the
+/// expired rows of types whose TTL is shorter than that. A partition can only be dropped
+/// once it holds nothing any type still keeps, so with one type kept forever (no
+/// `retention_days` and no entry of its own) nothing is dropped and only DELETEs run.
+pub async fn apply_retention(database: &Database, config: &PartitioningConfig, now: DateTime<Utc>) -> Result<()> {
+    let cutoff = |days: u64| now - chrono::Duration::days(days as i64);
+    
+    if let Some(default_days) = config.retention_days {
+        let longest = config.type_retention_days.values().copied().fold(default_days, u64::max);
+        let drop_before = cutoff(longest);
+        for (partition, month_start) in database.monthly_partitions().await? {
+            let Some(month_end) = month_start.checked_add_months(Months::new(1)) else {
+                continue;
+            };
+            if month_end.and_hms_opt(0, 0, 0).unwrap().and_utc() <= drop_before {
+                database.drop_monthly_partition(month_start).await?;
+                info!("Dropped expired partition {} (retention {} days)", partition, longest);
+            }
+        }
+    }
+    
+    // Rows outside droppable partitions: the default partition, the partly expired month
+    // and types with a shorter TTL
+    if let Some(default_days) = config.retention_days {
+        let excluded: Vec<&str> = config.type_retention_days.keys().map(String::as_str).collect();
+        let deleted = database.delete_expired_readings(&excluded, cutoff(default_days)).await?;
+        if deleted > 0 {
+            info!("Deleted {} readings older than {} days", deleted, default_days);
+        }
+    }
+    for (sensor_type, days) in &config.type_retention_days {
+        let deleted = database.delete_sensor_readings(Some(sensor_type), cutoff(*days)).await?;
+        if deleted > 0 {
+            info!("Deleted {} {} readings older than {} days", deleted, sensor_type, days);
+        }
+    }
+    
+    Ok(())
+}
+
+pub fn spawn_partition_maintenance(database: Arc<Database>, config: PartitioningConfig) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(config.check_interval_seconds.max(1)));
+        loop {
+            interval.tick().await;
+            if let Err(e) = maintain_partitions(&database, &config).await {
+                error!("Partition maintenance failed: {}", e);
+            }
+        }
+    })
+}
@@ -0,0 +1,69 @@
+use crate::validation;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+/// A reading's JSON payload. Serializes as the bare JSON value, so messages and stored rows
+/// are unchanged; reads go through `as_value` and the accessors below.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Payload(Value);
+
+impl Payload {
+    pub fn new(value: Value) -> Self {
+        Self(value)
+    }
+    
+    pub fn as_value(&self) -> &Value {
+        &self.0
+    }
+    
+    pub fn into_inner(self) -> Value {
+        self.0
+    }
+    
+    // Top-level fields, or None when the payload is not an object
+    pub fn fields_mut(&mut self) -> Option<&mut Map<String, Value>> {
+        self.0.as_object_mut()
+    }
+    
+    /// Numeric field as f64; None when missing or not a number.
+    pub fn get_f64(&self, key: &str) -> Option<f64> {
+        self.0.get(key).and_then(Value::as_f64)
+    }
+    
+    // Non-finite JSON numbers anywhere in the payload; strings are never counted
+    pub fn count_non_finite(&self) -> usize {
+        validation::count_non_finite(&self.0)
+    }
+    
+    pub fn null_non_finite(&mut self) -> usize {
+        validation::null_non_finite(&mut self.0)
+    }
+}
+
+impl From<Value> for Payload {
+    fn from(value: Value) -> Self {
+        Self(value)
+    }
+}
+
+impl From<Payload> for Value {
+    fn from(payload: Payload) -> Self {
+        payload.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    
+    #[test]
+    fn serializes_as_the_bare_value() {
+        let payload: Payload = serde_json::from_value(json!({ "lat": 52.5, "name": "x" })).unwrap();
+        
+        assert_eq!(payload.get_f64("lat"), Some(52.5));
+        assert_eq!(payload.get_f64("name"), None);
+        assert_eq!(serde_json::to_value(&payload).unwrap(), json!({ "lat": 52.5, "name": "x" }));
+    }
+}
@@ -1,17 +1,81 @@
 use anyhow::Result;
-use crate::config::Config;
-use crate::database::Database;
-use crate::rabbitmq::RabbitMQConsumer;
+use crate::codec::PayloadCodec;
+use crate::config::{Config, FutureSkewPolicy, ProcessingConfig, StreamStart, WebhookEvent};
+use crate::database::{self, Database};
+use crate::dedup::{self, RedisDeduplicator};
+use crate::derive;
+use crate::filter::SensorTypeFilter;
+use crate::location;
+use crate::metrics::{GaugeGuard, Metrics, TraceLabels};
+use crate::partitions;
+use crate::registry::RegistryEnricher;
+use crate::alerts::{self, AlertEvaluator};
+use crate::rabbitmq::{MessageContext, RabbitMQConsumer, RabbitMQProducer};
 use crate::models::{SensorData, SensorReadingInput};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
-use tokio::sync::Mutex;
-use tracing::{error, info};
+use tokio::sync::{mpsc, oneshot, watch, Mutex, RwLock, Semaphore};
+use tracing::{debug, error, info, warn};
+use crate::retry::RetryPolicy;
+use crate::worker_pool::WorkerPool;
+use crate::sinks::SinkSet;
+use crate::adaptive_batch::AdaptiveBatchSize;
+use crate::batcher::{self, BatchBuffers, BatchLimits, PendingBatch};
+use crate::logical_batch::{BatchPart, LogicalBatchTracker};
+use crate::liveness::LivenessCheck;
+use crate::sampling::OverloadSampler;
+use std::time::{Duration, Instant};
+use crate::timestamp;
+use crate::tls_check;
+use crate::transform::{self, SensorNameNormalizer};
+use crate::wal::{WalEntry, WriteAheadLog};
+use crate::webhooks::{MessageResult, WebhookNotifier};
+use tokio::task::JoinSet;
+use serde_json::json;
+
+// How long shutdown waits for queued webhook events to be delivered
+const WEBHOOK_SHUTDOWN_GRACE: Duration = Duration::from_secs(10);
 
 pub struct DataProcessor {
-    config: Config,
-    database: Arc<Database>,
     consumer: Arc<Mutex<RabbitMQConsumer>>,
+    pipeline: Pipeline,
+}
+
+// Shared processing state; cheap to clone and used by every ingest path
+#[derive(Clone)]
+pub struct Pipeline {
+    database: Arc<Database>,
     stats: Arc<Mutex<ProcessingStats>>,
+    type_filter: Arc<RwLock<SensorTypeFilter>>,
+    processing: Arc<ProcessingConfig>,
+    metrics: Arc<Metrics>,
+    header_filtered: Arc<AtomicU64>,
+    duplicate_messages: Arc<AtomicU64>,
+    paused: Arc<AtomicBool>,
+    sinks: Arc<SinkSet>,
+    buffers: Arc<BatchBuffers>,
+    batch_permits: Option<Arc<Semaphore>>,
+    adaptive_batch_size: Option<Arc<AdaptiveBatchSize>>,
+    logical_batches: Arc<LogicalBatchTracker>,
+    // Publishes logical batch completion events when `completion_exchange` is set
+    batch_events: Option<Arc<RabbitMQProducer>>,
+    sampler: Option<Arc<OverloadSampler>>,
+    liveness: Option<Arc<LivenessCheck>>,
+    redis_dedup: Option<Arc<RedisDeduplicator>>,
+    registry: Option<Arc<RegistryEnricher>>,
+    name_normalizer: Arc<SensorNameNormalizer>,
+    webhooks: Option<Arc<WebhookNotifier>>,
+    wal: Option<Arc<WriteAheadLog>>,
+    // Tasks committing WAL entries once their buffered readings are written
+    wal_commits: Arc<std::sync::Mutex<JoinSet<()>>>,
+    // Single writer task used with `worker_count`
+    writer: Option<mpsc::Sender<WriteRequest>>,
+}
+
+// Readings handed to the writer task, answered once they are written
+struct WriteRequest {
+    readings: Vec<SensorReadingInput>,
+    done: oneshot::Sender<Result<()>>,
 }
 
 #[derive(Debug, Default)]
@@ -19,6 +83,18 @@ struct ProcessingStats {
     processed_messages: u64,
     failed_messages: u64,
     last_processed_at: Option<chrono::DateTime<chrono::Utc>>,
+    non_finite_rejected: u64,
+    non_finite_nulled: u64,
+    disabled_type_dropped: u64,
+    future_skew_clamped: u64,
+    future_skew_rejected: u64,
+    overload_sampled: u64,
+    batch_duplicates_collapsed: u64,
+    cross_instance_duplicates: u64,
+    empty_messages: u64,
+    unknown_type_rejected: u64,
+    payload_fields_stripped: u64,
+    derived_readings: u64,
 }
 
 impl DataProcessor {
@@ -26,95 +102,821 @@ impl DataProcessor {
         info!("Initializing Data Processor...");
         
         // Initialize database
-        let database = Arc::new(Database::new(&config.database.url).await?);
+        let database = Arc::new(Database::new(&config.database).await?);
         info!("Database connection established");
+        tls_check::check_database(database.pool(), "Database", &config.database.url, &config.tls_check).await?;
+        tls_check::check_broker(&config.rabbitmq.connection_string, &config.tls_check)?;
+        
+        if !config.database.failover_targets.is_empty() {
+            let interval = Duration::from_millis(config.database.failover_check_interval_ms.max(100));
+            database::spawn_write_target_monitor(database.clone(), interval);
+            info!("Write failover enabled ({} targets)", config.database.failover_targets.len());
+        }
+        
+        if config.database.partitioning.enabled {
+            partitions::spawn_partition_maintenance(database.clone(), config.database.partitioning.clone());
+            info!("Partition maintenance enabled ({} months ahead)", config.database.partitioning.months_ahead);
+        }
+        
+        if !config.alerts.rules.is_empty() {
+            let producer = match &config.alerts.exchange_name {
+                Some(exchange_name) => Some(
+                    RabbitMQProducer::from_config(&config.rabbitmq, exchange_name.clone()).await?,
+                ),
+                None => None,
+            };
+            let evaluator = AlertEvaluator::new(database.clone(), config.alerts.rules.clone(), producer);
+            alerts::spawn_alert_evaluation(evaluator, &config.alerts);
+            info!("Alert evaluation enabled ({} rules)", config.alerts.rules.len());
+        }
         
         // Initialize RabbitMQ consumer
-        let consumer = RabbitMQConsumer::new(
-            &config.rabbitmq.connection_string,
-            config.rabbitmq.queue_name.clone(),
-            config.rabbitmq.exchange_name.clone(),
-            config.rabbitmq.routing_key.clone(),
-        ).await?;
+        let checkpoint = match &config.rabbitmq.stream {
+            Some(stream) if stream.start_from == StreamStart::Checkpoint => {
+                database.load_checkpoint(&config.rabbitmq.queue_name).await?
+            }
+            _ => None,
+        };
+        let consumer = match checkpoint {
+            Some(checkpoint) => {
+                info!("Resuming stream {} after checkpoint {}", config.rabbitmq.queue_name, checkpoint);
+                RabbitMQConsumer::resume_from(&config.rabbitmq, checkpoint).await?
+            }
+            None => RabbitMQConsumer::new(&config.rabbitmq).await?,
+        };
+        let mut consumer = consumer
+            .with_idle_shutdown(config.processing.idle_shutdown_seconds.map(Duration::from_secs))
+            .with_quarantine(database.clone())
+            .with_checkpoints(database.clone());
+        let liveness_max_idle = config.http.as_ref().and_then(|http| http.max_idle_with_backlog_secs);
+        let queue_depth = if config.processing.overload_sampling.is_some() || liveness_max_idle.is_some() {
+            let interval = Duration::from_millis(config.rabbitmq.queue_depth_check_interval_ms.max(100));
+            Some(consumer.spawn_queue_depth_monitor(interval).await?)
+        } else {
+            None
+        };
+        let sampler = match (&config.processing.overload_sampling, &queue_depth) {
+            (Some(sampling), Some(queue_depth)) => {
+                info!(
+                    "Overload sampling enabled above queue depth {} for {:?}",
+                    sampling.overload_queue_depth, sampling.sensor_types
+                );
+                Some(Arc::new(OverloadSampler::new(sampling.clone(), queue_depth.clone())?))
+            }
+            _ => None,
+        };
+        let liveness = match (liveness_max_idle, &queue_depth) {
+            (Some(max_idle), Some(queue_depth)) => Some(Arc::new(LivenessCheck::new(
+                queue_depth.clone(),
+                consumer.last_progress(),
+                consumer.pause_flag(),
+                Duration::from_secs(max_idle),
+            ))),
+            _ => None,
+        };
+        let redis_dedup = match &config.processing.redis_dedup {
+            Some(redis_dedup) => {
+                let deduplicator = RedisDeduplicator::connect(redis_dedup, &config.processing.dedup_key).await?;
+                info!("Cross-instance deduplication enabled (TTL {}s)", redis_dedup.ttl_seconds);
+                Some(Arc::new(deduplicator))
+            }
+            None => None,
+        };
+        let registry = config.processing.registry_enrichment.as_ref().map(|enrichment| {
+            info!("Sensor registry enrichment enabled (payload field '{}')", enrichment.payload_field);
+            Arc::new(RegistryEnricher::new(database.clone(), enrichment))
+        });
+        let name_normalizer = Arc::new(SensorNameNormalizer::new(
+            config.processing.sensor_name_normalization,
+            &config.processing.sensor_name_rewrites,
+        )?);
+        let webhooks = match &config.webhooks {
+            Some(webhooks) => {
+                info!("Webhook notifications enabled for {:?}", webhooks.events);
+                Some(Arc::new(WebhookNotifier::new(webhooks)?))
+            }
+            None => None,
+        };
+        derive::validate(&config)?;
+        if config.processing.min_batch_interval_ms.is_some() && config.processing.max_batch_wait_ms.is_none() {
+            anyhow::bail!("processing.min_batch_interval_ms requires processing.max_batch_wait_ms");
+        }
+        let (wal, wal_entries) = match &config.processing.write_ahead_log_path {
+            Some(_) if config.processing.max_batch_wait_ms.is_none() => {
+                anyhow::bail!("processing.write_ahead_log_path requires processing.max_batch_wait_ms");
+            }
+            Some(path) => {
+                let (wal, entries) = WriteAheadLog::open(path).await?;
+                info!("Write-ahead log {} opened ({} entries to replay)", path, entries.len());
+                (Some(Arc::new(wal)), entries)
+            }
+            None => (None, Vec::new()),
+        };
+        match config.processing.worker_count {
+            Some(0) => anyhow::bail!("processing.worker_count must be at least 1"),
+            Some(_) if config.processing.max_batch_wait_ms.is_some() => {
+                anyhow::bail!("processing.worker_count cannot be combined with processing.max_batch_wait_ms");
+            }
+            _ => {}
+        }
+        let consumer = consumer.with_webhooks(webhooks.clone());
+        let header_filtered = consumer.header_filtered_messages();
+        let duplicate_messages = consumer.duplicate_messages();
+        let paused = consumer.pause_flag();
         let consumer = Arc::new(Mutex::new(consumer));
         info!("RabbitMQ consumer initialized");
         
+        let sinks = Arc::new(SinkSet::from_config(&config, database.clone()).await?);
         let stats = Arc::new(Mutex::new(ProcessingStats::default()));
+        let type_filter = Arc::new(RwLock::new(SensorTypeFilter::from_config(&config.processing)));
+        let buffers = Arc::new(BatchBuffers::new(
+            config.processing.min_batch_interval_ms.map(Duration::from_millis),
+        ));
+        let batch_permits = config.processing.max_concurrent_batches.map(|max| Arc::new(Semaphore::new(max)));
+        let adaptive_batch_size = match &config.processing.adaptive_batching {
+            Some(adaptive) => {
+                let controller = AdaptiveBatchSize::new(adaptive.clone(), config.processing.batch_size)?;
+                info!(
+                    "Adaptive batch size enabled ({}..={}, starting at {})",
+                    adaptive.min_batch_size, adaptive.max_batch_size, controller.current()
+                );
+                Some(Arc::new(controller))
+            }
+            None => None,
+        };
+        let logical_batches = Arc::new(LogicalBatchTracker::new(
+            database.clone(),
+            Duration::from_secs(config.processing.logical_batches.expire_after_seconds),
+        ));
+        let batch_events = match &config.processing.logical_batches.completion_exchange {
+            Some(exchange_name) => Some(Arc::new(
+                RabbitMQProducer::from_config(&config.rabbitmq, exchange_name.clone()).await?,
+            )),
+            None => None,
+        };
         
-        Ok(Self {
-            config,
+        let (writer, write_requests) = match config.processing.worker_count {
+            Some(workers) => {
+                let (sender, receiver) = mpsc::channel(workers);
+                (Some(sender), Some(receiver))
+            }
+            None => (None, None),
+        };
+        
+        let pipeline = Pipeline {
             database,
-            consumer,
             stats,
+            type_filter,
+            processing: Arc::new(config.processing),
+            metrics: Arc::new(Metrics::new(&config.metrics)),
+            header_filtered,
+            duplicate_messages,
+            paused,
+            sinks,
+            buffers,
+            batch_permits,
+            adaptive_batch_size,
+            logical_batches,
+            batch_events,
+            sampler,
+            liveness,
+            redis_dedup,
+            registry,
+            name_normalizer,
+            webhooks,
+            wal,
+            wal_commits: Arc::new(std::sync::Mutex::new(JoinSet::new())),
+            writer,
+        };
+        pipeline.metrics.effective_batch_size.set(pipeline.effective_batch_size() as i64);
+        pipeline.replay_wal(wal_entries).await?;
+        
+        if let Some(max_wait) = pipeline.processing.max_batch_wait_ms {
+            let min_interval = pipeline.processing.min_batch_interval_ms.unwrap_or(max_wait);
+            spawn_batch_flusher(pipeline.clone(), Duration::from_millis(max_wait.min(min_interval)));
+            info!("Per-type batch buffering enabled (max wait {} ms)", max_wait);
+        }
+        if let Some(requests) = write_requests {
+            spawn_writer(pipeline.clone(), requests);
+        }
+        
+        Ok(Self {
+            consumer,
+            pipeline,
         })
     }
     
-    pub async fn start(&mut self) -> Result<()> {
+    // Decodes deliveries of the codec's content type with it, e.g. a custom binary format
+    pub async fn with_codec(self, codec: Arc<dyn PayloadCodec>) -> Self {
+        self.consumer.lock().await.register_codec(codec);
+        self
+    }
+    
+    pub fn pipeline(&self) -> Pipeline {
+        self.pipeline.clone()
+    }
+    
+    // Re-reads the config file on SIGHUP and applies the settings that can change live
+    #[cfg(unix)]
+    pub fn watch_config(&self, path: String, profile: Option<String>) -> Result<()> {
+        use tokio::signal::unix::{signal, SignalKind};
+        
+        let mut hangup = signal(SignalKind::hangup())?;
+        let type_filter = self.pipeline.type_filter.clone();
+        
+        tokio::spawn(async move {
+            while hangup.recv().await.is_some() {
+                info!("SIGHUP received, reloading configuration from {}", path);
+                match Config::load_profile(&path, profile.as_deref()) {
+                    Ok(config) => {
+                        *type_filter.write().await = SensorTypeFilter::from_config(&config.processing);
+                        info!(
+                            "Sensor type filter reloaded (enabled: {:?}, disabled: {:?})",
+                            config.processing.enabled_sensor_types,
+                            config.processing.disabled_sensor_types
+                        );
+                    }
+                    Err(e) => error!("Failed to reload configuration, keeping current settings: {}", e),
+                }
+            }
+        });
+        
+        Ok(())
+    }
+    
+    // Runs until the consumer fails, or until `max_messages` messages have been handled
+    pub async fn start(&mut self, max_messages: Option<u64>) -> Result<()> {
         info!("Starting data processing...");
         
         let mut consumer = self.consumer.lock().await;
         
-        consumer.consume_messages(|sensor_data| {
-            let database = self.database.clone();
-            let stats = self.stats.clone();
-            let batch_size = self.config.processing.batch_size;
-            
-            async move {
-                Self::process_sensor_data(database, stats, sensor_data, batch_size).await
-            }
-        }).await?;
+        let pipeline = self.pipeline.clone();
+        let handler = move |sensor_data, context| handle_message(pipeline.clone(), sensor_data, context);
+        if let Some(workers) = self.pipeline.processing.worker_count {
+            consumer.set_worker_pool(Some(WorkerPool::spawn(workers, handler.clone())));
+            info!("Processing deliveries on {} workers", workers);
+        }
+        
+        let shutdown = spawn_shutdown_listener(Duration::from_secs(self.pipeline.processing.shutdown_timeout_seconds))?;
+        consumer.set_shutdown(Some(shutdown.clone()));
         
+        let result = consumer.consume_messages_limited(max_messages, handler).await;
+        consumer.set_worker_pool(None);
+        consumer.set_shutdown(None);
+        result?;
+        
+        // After a signal, buffered readings get whatever is left of the shutdown timeout
+        let deadline = *shutdown.borrow();
+        match deadline {
+            Some(deadline) => {
+                if tokio::time::timeout_at(deadline.into(), self.pipeline.flush_all()).await.is_err() {
+                    warn!("Shutdown timeout reached before buffered readings were written");
+                }
+            }
+            None => self.pipeline.flush_all().await,
+        }
         Ok(())
     }
     
-    async fn process_sensor_data(
-        database: Arc<Database>,
-        stats: Arc<Mutex<ProcessingStats>>,
-        sensor_data: Vec<SensorData>,
-        batch_size: usize,
-    ) -> Result<()> {
+    pub async fn get_stats(&self) -> Result<crate::models::ProcessingStats> {
+        self.pipeline.get_stats().await
+    }
+    
+    // Sends the `shutdown` webhook event and waits briefly for queued events to go out
+    pub async fn notify_shutdown(&self, outcome: &Result<()>) {
+        let Some(webhooks) = &self.pipeline.webhooks else {
+            return;
+        };
+        let stats = self.pipeline.get_stats().await.ok();
+        let details = json!({
+            "error": outcome.as_ref().err().map(|e| e.to_string()),
+            "processed_messages": stats.as_ref().map(|stats| stats.processed_messages),
+            "failed_messages": stats.as_ref().map(|stats| stats.failed_messages),
+        });
+        webhooks.shutdown(details, WEBHOOK_SHUTDOWN_GRACE).await;
+    }
+    
+    pub async fn close(&self) -> Result<()> {
+        self.consumer.lock().await.close().await
+    }
+    
+    pub async fn health_check(&self) -> Result<()> {
+        self.pipeline.health_check().await
+    }
+}
+
+impl Pipeline {
+    pub fn metrics(&self) -> Arc<Metrics> {
+        self.metrics.clone()
+    }
+    
+    pub fn database(&self) -> Arc<Database> {
+        self.database.clone()
+    }
+    
+    // Pausing stops AMQP consumption only; other ingest paths keep running
+    pub fn set_paused(&self, paused: bool) {
+        self.paused.store(paused, Ordering::Relaxed);
+    }
+    
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+    
+    pub async fn reset_stats(&self) {
+        *self.stats.lock().await = ProcessingStats::default();
+        self.header_filtered.store(0, Ordering::Relaxed);
+    }
+    
+    pub async fn process_sensor_data(&self, sensor_data: Vec<SensorData>, context: MessageContext) -> Result<()> {
+        let stats = &self.stats;
+        let processing = &self.processing;
         let start_time = std::time::Instant::now();
         
+        // Nothing to store, so skip the whole pipeline
+        if sensor_data.is_empty() {
+            stats.lock().await.empty_messages += 1;
+            self.metrics.empty_messages.inc();
+            debug!("Acking empty message from {}", context.routing_key);
+            // An empty part still counts towards completing its logical batch
+            if let Some(part) = &context.batch_part {
+                self.record_batch_part(part, 0).await;
+            }
+            return Ok(());
+        }
+        
+        // Rejected whole, before anything is stored, so the message can be replayed as it was
+        if let Some(allowed) = &processing.allowed_sensor_types {
+            if let Some(data) = sensor_data.iter().find(|data| !allowed.contains(&data.r#type)) {
+                stats.lock().await.unknown_type_rejected += 1;
+                self.metrics.unknown_type_rejected.inc();
+                return Err(anyhow::anyhow!("Unknown sensor type '{}' of reading '{}'", data.r#type, data.name));
+            }
+        }
+        
+        if let Some(webhooks) = &self.webhooks {
+            webhooks.first_message(json!({
+                "readings": sensor_data.len(),
+                "routing_key": context.routing_key,
+                "source_id": context.source_id,
+            }));
+        }
+        
         // Convert sensor data to database input format
         let mut sensor_reading_inputs = Vec::new();
         let messages_count = sensor_data.len();
+        let mut non_finite_rejected = 0u64;
+        let mut non_finite_nulled = 0u64;
+        let mut disabled_type_dropped = 0u64;
+        let mut future_skew_clamped = 0u64;
+        let mut future_skew_rejected = 0u64;
+        let mut overload_sampled = 0u64;
+        let mut payload_fields_stripped = 0u64;
+        let type_filter = self.type_filter.read().await.clone();
+        let sampler = self.sampler.as_deref().filter(|sampler| sampler.engaged());
         
-        for data in sensor_data {
+        for mut data in sensor_data {
+            if !type_filter.allows(&data.r#type) {
+                debug!("Dropping reading '{}' of disabled sensor type {}", data.name, data.r#type);
+                disabled_type_dropped += 1;
+                continue;
+            }
+            
+            if sampler.is_some_and(|sampler| sampler.drops(&data.r#type)) {
+                debug!("Sampling out {} reading '{}' under overload", data.r#type, data.name);
+                self.metrics.overload_sampled.inc();
+                overload_sampled += 1;
+                continue;
+            }
+            
+            let rewrite = transform::rewrite(processing, &self.name_normalizer, &mut data);
+            payload_fields_stripped += rewrite.fields_stripped as u64;
+            non_finite_nulled += rewrite.non_finite_nulled as u64;
+            if rewrite.rejected {
+                warn!("Rejecting {} reading '{}': payload contains non-finite numbers", data.r#type, data.name);
+                non_finite_rejected += 1;
+                continue;
+            }
+            
+            // The producer's own timestamp keeps the measurement time of messages that sat queued
+            let timestamp = match (data.timestamp, processing.timestamp_keys.get(&data.r#type)) {
+                (Some(timestamp), _) => timestamp,
+                (None, Some(key)) => timestamp::extract(data.payload.as_value(), key).unwrap_or_else(|| {
+                    warn!("Reading '{}' has no usable '{}' timestamp, using receive time", data.name, key);
+                    chrono::Utc::now()
+                }),
+                (None, None) => chrono::Utc::now(),
+            };
+            
+            // Producer clock skew would otherwise put readings ahead of every time-range query
+            let now = chrono::Utc::now();
+            let timestamp = match processing.max_future_skew_seconds {
+                Some(max_skew) if timestamp > now + chrono::Duration::seconds(max_skew as i64) => {
+                    match processing.future_skew_policy {
+                        FutureSkewPolicy::Clamp => {
+                            debug!("Clamping future timestamp {} of reading '{}'", timestamp, data.name);
+                            future_skew_clamped += 1;
+                            now
+                        }
+                        FutureSkewPolicy::Reject => {
+                            warn!("Rejecting reading '{}': timestamp {} is too far in the future", data.name, timestamp);
+                            future_skew_rejected += 1;
+                            continue;
+                        }
+                    }
+                }
+                _ => timestamp,
+            };
+            let timestamp = match processing.timestamp_precision {
+                Some(precision) => timestamp::truncate(timestamp, precision),
+                None => timestamp,
+            };
+            
+            let location = processing
+                .location
+                .as_ref()
+                .and_then(|config| location::resolve(config, &data.name, &data.payload));
             let input = SensorReadingInput {
                 sensor_type: data.r#type,
                 sensor_name: data.name,
-                payload: data.payload,
-                timestamp: chrono::Utc::now(), // Use current timestamp since it's not provided in the JSON
+                payload: data.payload.into_inner(),
+                timestamp,
+                source_id: context.source_id.clone(),
+                location,
             };
             sensor_reading_inputs.push(input);
         }
         
+        // Duplicates within one statement would make an upsert fail, so collapse them first
+        let (sensor_reading_inputs, batch_duplicates_collapsed) =
+            dedup::collapse(sensor_reading_inputs, &processing.dedup_key);
+        if batch_duplicates_collapsed > 0 {
+            debug!("Collapsed {} duplicate readings within the message", batch_duplicates_collapsed);
+            self.metrics.batch_duplicates_collapsed.inc_by(batch_duplicates_collapsed as u64);
+        }
+        let (mut sensor_reading_inputs, claimed_keys, duplicate_keys) =
+            self.claim_readings(sensor_reading_inputs).await;
+        let cross_instance_duplicates = duplicate_keys.len() as u64;
+        let duplicate = !duplicate_keys.is_empty() && sensor_reading_inputs.is_empty();
+        
+        // From the readings that survived dedup, so a duplicate never derives twice. Derived
+        // types go through the same type filter and payload field lists as received ones.
+        let mut derived = Vec::new();
+        for mut reading in sensor_reading_inputs
+            .iter()
+            .flat_map(|reading| derive::derive(&processing.derived_readings, reading))
+        {
+            if !type_filter.allows(&reading.sensor_type) {
+                disabled_type_dropped += 1;
+                continue;
+            }
+            if let Some(fields) = reading.payload.as_object_mut() {
+                payload_fields_stripped += transform::strip_fields(
+                    &processing.payload_field_allowlist,
+                    &processing.payload_field_denylist,
+                    &reading.sensor_type,
+                    fields,
+                ) as u64;
+            }
+            derived.push(reading);
+        }
+        let derived_readings = derived.len() as u64;
+        sensor_reading_inputs.extend(derived);
+        
+        // A registry outage should not stop ingest, so readings are then stored as they are
+        if let Some(registry) = &self.registry {
+            if let Err(e) = registry.enrich(&mut sensor_reading_inputs).await {
+                warn!("Sensor registry lookup failed, storing readings unenriched: {}", e);
+            }
+        }
+        
+        if non_finite_rejected > 0
+            || non_finite_nulled > 0
+            || disabled_type_dropped > 0
+            || future_skew_clamped > 0
+            || future_skew_rejected > 0
+            || overload_sampled > 0
+            || batch_duplicates_collapsed > 0
+            || cross_instance_duplicates > 0
+            || payload_fields_stripped > 0
+            || derived_readings > 0
+        {
+            let mut stats = stats.lock().await;
+            stats.non_finite_rejected += non_finite_rejected;
+            stats.non_finite_nulled += non_finite_nulled;
+            stats.disabled_type_dropped += disabled_type_dropped;
+            stats.future_skew_clamped += future_skew_clamped;
+            stats.future_skew_rejected += future_skew_rejected;
+            stats.overload_sampled += overload_sampled;
+            stats.batch_duplicates_collapsed += batch_duplicates_collapsed as u64;
+            stats.cross_instance_duplicates += cross_instance_duplicates;
+            stats.payload_fields_stripped += payload_fields_stripped;
+            stats.derived_readings += derived_readings;
+        }
+        self.metrics.payload_fields_stripped.inc_by(payload_fields_stripped);
+        
+        let stored = sensor_reading_inputs.len() as u64;
+        let result = match (&self.writer, processing.max_batch_wait_ms) {
+            (Some(writer), _) => write_via(writer, sensor_reading_inputs).await,
+            (None, Some(_)) => self.buffer_and_wait(sensor_reading_inputs).await,
+            (None, None) => self.write_batches(sensor_reading_inputs).await,
+        };
+        
+        if result.is_err() {
+            if let Some(dedup) = &self.redis_dedup {
+                if let Err(e) = dedup.abandon(&claimed_keys).await {
+                    warn!("Failed to release dedup keys of unstored readings: {}", e);
+                }
+            }
+        }
+        
+        if let (Ok(()), Some(part)) = (&result, &context.batch_part) {
+            self.record_batch_part(part, stored).await;
+        }
+        if let (Ok(()), Some(webhooks)) = (&result, &self.webhooks) {
+            webhooks.message_result(MessageResult {
+                message_id: context.message_id.clone(),
+                routing_key: context.routing_key.clone(),
+                readings: messages_count,
+                stored: stored as usize,
+                duplicate,
+                duplicate_keys,
+            });
+        }
+        
+        let processing_time = start_time.elapsed();
+        let exemplar = context.trace_id.clone().map(|trace_id| TraceLabels { trace_id });
+        self.metrics.processing_duration.observe(processing_time.as_secs_f64(), exemplar);
+        let processing_rate = messages_count as f64 / processing_time.as_secs_f64();
+        
+        info!(
+            "Processed {} sensor readings in {:?} (rate: {:.2} msg/s, non-finite: {} rejected, {} nulled)",
+            messages_count,
+            processing_time,
+            processing_rate,
+            non_finite_rejected,
+            non_finite_nulled
+        );
+        
+        // Fail the message so it is dead-lettered rather than acked with readings missing
+        result
+    }
+    
+    // Drops readings whose dedup key is marked stored in Redis, returning the kept readings,
+    // the keys claimed for them and the keys found stored. When Redis is unreachable every
+    // reading is kept, trading possible duplicates for no data loss.
+    async fn claim_readings(&self, readings: Vec<SensorReadingInput>) -> (Vec<SensorReadingInput>, Vec<String>, Vec<String>) {
+        let Some(dedup) = &self.redis_dedup else {
+            return (readings, Vec::new(), Vec::new());
+        };
+        let claims = match dedup.claim(&readings).await {
+            Ok(claims) => claims,
+            Err(e) => {
+                warn!("Redis deduplication unavailable, storing readings unchecked: {}", e);
+                return (readings, Vec::new(), Vec::new());
+            }
+        };
+        
+        let mut kept = Vec::with_capacity(readings.len());
+        let mut keys = Vec::with_capacity(readings.len());
+        let mut duplicate_keys = Vec::new();
+        for (reading, (key, duplicate)) in readings.into_iter().zip(claims) {
+            if duplicate {
+                duplicate_keys.push(key);
+            } else {
+                kept.push(reading);
+                keys.push(key);
+            }
+        }
+        if !duplicate_keys.is_empty() {
+            debug!("Suppressed {} readings already stored by another delivery", duplicate_keys.len());
+            self.metrics.cross_instance_duplicates.inc_by(duplicate_keys.len() as u64);
+        }
+        (kept, keys, duplicate_keys)
+    }
+    
+    async fn record_batch_part(&self, part: &BatchPart, readings: u64) {
+        // The part's readings are already stored, so a tracking failure only loses the event
+        let event = match self.logical_batches.record(part, readings).await {
+            Ok(Some(event)) => event,
+            Ok(None) => return,
+            Err(e) => {
+                error!("Failed to record part {} of batch {}: {}", part.sequence, part.batch_id, e);
+                return;
+            }
+        };
+        info!(
+            "Logical batch {} complete: {} parts, {} readings",
+            event.batch_id, event.parts, event.readings
+        );
+        self.metrics.logical_batches_completed.inc();
+        if let Some(producer) = &self.batch_events {
+            if let Err(e) = producer.publish_json("batches.completed", &event).await {
+                error!("Failed to publish completion of batch {}: {}", event.batch_id, e);
+            }
+        }
+    }
+    
+    // Batch size for types without a per-type size
+    fn effective_batch_size(&self) -> usize {
+        match &self.adaptive_batch_size {
+            Some(adaptive) => adaptive.current(),
+            None => self.processing.batch_size,
+        }
+    }
+    
+    // Per-type batch size, falling back to the effective batch size, plus the byte limit
+    fn batch_limits_for(&self, sensor_type: &str) -> BatchLimits {
+        let max_readings = self
+            .processing
+            .type_batch_sizes
+            .get(sensor_type)
+            .copied()
+            .unwrap_or_else(|| self.effective_batch_size())
+            .max(1);
+        BatchLimits {
+            max_readings,
+            max_bytes: self.processing.max_batch_bytes,
+        }
+    }
+    
+    // Adds the readings to the per-type buffers and waits until every one of them is written,
+    // or only until they are in the write-ahead log when one is configured
+    async fn buffer_and_wait(&self, readings: Vec<SensorReadingInput>) -> Result<()> {
+        let wal_entry = match &self.wal {
+            Some(wal) => Some((wal.clone(), wal.append(&readings).await?)),
+            None => None,
+        };
+        let mut receivers = Vec::new();
+        for (sensor_type, readings) in group_by_type(readings) {
+            let limits = self.batch_limits_for(&sensor_type);
+            let (receiver, full) = self.buffers.push(&sensor_type, readings, limits);
+            receivers.push(receiver);
+            if let Some(batch) = full {
+                self.flush(batch).await;
+            }
+        }
+        let Some((wal, id)) = wal_entry else {
+            return batcher::wait_all(receivers).await;
+        };
+        
+        // The readings survive a crash now, so the message can be acked before they are written
+        let mut commits = self.wal_commits.lock().unwrap();
+        while commits.try_join_next().is_some() {}
+        commits.spawn(async move {
+            match batcher::wait_all(receivers).await {
+                Ok(()) => {
+                    if let Err(e) = wal.commit(id).await {
+                        warn!("Failed to commit write-ahead log entry {}: {}", id, e);
+                    }
+                }
+                Err(e) => error!("Readings of write-ahead log entry {} not written, replaying on restart: {}", id, e),
+            }
+        });
+        Ok(())
+    }
+    
+    // Writes readings that were acked but not stored before the last shutdown
+    async fn replay_wal(&self, entries: Vec<WalEntry>) -> Result<()> {
+        let Some(wal) = &self.wal else {
+            return Ok(());
+        };
+        for entry in entries {
+            let readings = entry.readings.len();
+            self.write_batches(entry.readings).await?;
+            wal.commit(entry.id).await?;
+            info!("Replayed write-ahead log entry {} ({} readings)", entry.id, readings);
+        }
+        Ok(())
+    }
+    
+    async fn flush(&self, mut batch: PendingBatch) {
+        let readings = std::mem::take(&mut batch.readings);
+        let result = self.write_batches(readings).await;
+        batch.complete(&result);
+    }
+    
+    // Writes buffered batches whose oldest reading has waited `max_batch_wait_ms`
+    pub async fn flush_expired(&self) {
+        let Some(max_wait) = self.processing.max_batch_wait_ms else {
+            return;
+        };
+        for batch in self.buffers.take_expired(Duration::from_millis(max_wait)) {
+            self.flush(batch).await;
+        }
+    }
+    
+    // Writes everything still buffered, e.g. before exiting
+    pub async fn flush_all(&self) {
+        for batch in self.buffers.take_all() {
+            self.flush(batch).await;
+        }
+        let mut commits = std::mem::take(&mut *self.wal_commits.lock().unwrap());
+        while commits.join_next().await.is_some() {}
+        if let Some(wal) = &self.wal {
+            let pending = wal.pending().await;
+            if pending > 0 {
+                warn!("{} write-ahead log entries left to replay on restart", pending);
+            }
+        }
+    }
+    
+    // Writes readings to the sinks in chunks of their type's batch size
+    async fn write_batches(&self, readings: Vec<SensorReadingInput>) -> Result<()> {
+        let stats = &self.stats;
+        let processing = &self.processing;
+        let retry = RetryPolicy::new(
+            processing.retry_attempts,
+            processing.retry_delay_ms,
+            processing.retry_jitter,
+        );
+        
         // Process in batches
-        for chunk in sensor_reading_inputs.chunks(batch_size) {
-            match database.insert_batch_sensor_readings(chunk.to_vec()).await {
+        let flush_started = Instant::now();
+        let mut sink_error = None;
+        let mut batches = 0usize;
+        let mut groups = group_by_type(readings);
+        for (_, readings) in &mut groups {
+            batcher::sort_readings(readings, &processing.insert_sort_key);
+        }
+        let chunks = groups
+            .iter()
+            .flat_map(|(sensor_type, readings)| batcher::chunk(readings, self.batch_limits_for(sensor_type)));
+        for chunk in chunks {
+            let _permit = match &self.batch_permits {
+                Some(permits) => Some(match permits.clone().try_acquire_owned() {
+                    Ok(permit) => permit,
+                    Err(_) => {
+                        // Backpressure: every permit is taken, wait for a batch to finish
+                        self.metrics.batch_limit_waits.inc();
+                        permits.clone().acquire_owned().await?
+                    }
+                }),
+                None => None,
+            };
+            let in_flight = GaugeGuard::track(&self.metrics.in_flight_batches);
+            let started = Instant::now();
+            let result = self.sinks.write(chunk, &retry).await;
+            drop(in_flight);
+            let adaptive = self
+                .adaptive_batch_size
+                .as_ref()
+                .filter(|_| !processing.type_batch_sizes.contains_key(&chunk[0].sensor_type));
+            if let Some(adaptive) = adaptive {
+                adaptive.observe(chunk.len(), started.elapsed(), result.is_ok());
+                self.metrics.effective_batch_size.set(adaptive.current() as i64);
+            }
+            self.metrics.batch_size.observe(chunk.len() as f64);
+            batches += 1;
+            // Per chunk, since earlier chunks stay stored when a later one fails
+            if let Some(dedup) = &self.redis_dedup {
+                if let Err(e) = dedup.settle(chunk, result.is_ok()).await {
+                    warn!("Failed to settle dedup keys of written readings: {}", e);
+                }
+            }
+            match result {
                 Ok(_) => {
+                    for reading in chunk {
+                        self.metrics.record_processed(&reading.sensor_type, &reading.payload);
+                    }
                     let mut stats = stats.lock().await;
                     stats.processed_messages += chunk.len() as u64;
                     stats.last_processed_at = Some(chrono::Utc::now());
                 }
                 Err(e) => {
-                    error!("Failed to insert batch: {}", e);
+                    error!("Failed to write batch after {} retries: {}", processing.retry_attempts, e);
+                    if let Some(webhooks) = &self.webhooks {
+                        webhooks.notify(WebhookEvent::BatchFailure, json!({
+                            "sensor_type": chunk.first().map(|reading| reading.sensor_type.clone()),
+                            "readings": chunk.len(),
+                            "error": e.to_string(),
+                        }));
+                    }
+                    for reading in chunk {
+                        self.metrics.record_failed(&reading.sensor_type);
+                    }
                     let mut stats = stats.lock().await;
                     stats.failed_messages += chunk.len() as u64;
+                    sink_error = Some(e);
                 }
             }
         }
         
-        let processing_time = start_time.elapsed();
-        let processing_rate = messages_count as f64 / processing_time.as_secs_f64();
-        
-        info!(
-            "Processed {} sensor readings in {:?} (rate: {:.2} msg/s)",
-            messages_count,
-            processing_time,
-            processing_rate
-        );
-        
+        if let Some(e) = sink_error {
+            return Err(e);
+        }
+        if let Some(webhooks) = self.webhooks.as_ref().filter(|_| batches > 0) {
+            let sensor_types: serde_json::Map<String, serde_json::Value> = groups
+                .iter()
+                .map(|(sensor_type, readings)| (sensor_type.clone(), json!(readings.len())))
+                .collect();
+            webhooks.notify(WebhookEvent::BatchWritten, json!({
+                "readings": groups.iter().map(|(_, readings)| readings.len()).sum::<usize>(),
+                "batches": batches,
+                "sensor_types": sensor_types,
+                "duration_ms": flush_started.elapsed().as_millis() as u64,
+            }));
+        }
         Ok(())
     }
     
@@ -125,9 +927,34 @@ impl DataProcessor {
             failed_messages: stats.failed_messages,
             last_processed_at: stats.last_processed_at,
             processing_rate_per_second: 0.0, // Calculate based on recent activity
+            non_finite_rejected: stats.non_finite_rejected,
+            non_finite_nulled: stats.non_finite_nulled,
+            disabled_type_dropped: stats.disabled_type_dropped,
+            header_filtered_messages: self.header_filtered.load(Ordering::Relaxed),
+            future_skew_clamped: stats.future_skew_clamped,
+            future_skew_rejected: stats.future_skew_rejected,
+            overload_sampled: stats.overload_sampled,
+            batch_duplicates_collapsed: stats.batch_duplicates_collapsed,
+            cross_instance_duplicates: stats.cross_instance_duplicates,
+            empty_messages: stats.empty_messages,
+            unknown_type_rejected: stats.unknown_type_rejected,
+            effective_batch_size: self.effective_batch_size() as u64,
+            duplicate_messages: self.duplicate_messages.load(Ordering::Relaxed),
+            payload_fields_stripped: stats.payload_fields_stripped,
+            derived_readings: stats.derived_readings,
+            read_back_mismatches: self.database.read_back_mismatches(),
+            readings_by_type: self.metrics.readings_by_type(),
         })
     }
     
+    // Consumer progress check behind /live; always passes unless configured
+    pub fn liveness_check(&self) -> Result<(), String> {
+        match &self.liveness {
+            Some(liveness) => liveness.check(),
+            None => Ok(()),
+        }
+    }
+    
     pub async fn health_check(&self) -> Result<()> {
         // Check database health
         self.database.health_check().await?;
@@ -138,3 +965,205 @@ impl DataProcessor {
         Ok(())
     }
 }
+
+#[cfg(test)]
+impl Pipeline {
+    // A pipeline without a broker that writes to `sinks`; batch buffering, the writer task and
+    // the WAL are left off
+    pub(crate) async fn for_tests(config: Config, database: Arc<Database>, sinks: SinkSet) -> Result<Self> {
+        let webhooks = config.webhooks.as_ref().map(WebhookNotifier::new).transpose()?.map(Arc::new);
+        let redis_dedup = match &config.processing.redis_dedup {
+            Some(redis_dedup) => Some(Arc::new(
+                RedisDeduplicator::connect(redis_dedup, &config.processing.dedup_key).await?,
+            )),
+            None => None,
+        };
+        Ok(Pipeline {
+            database: database.clone(),
+            stats: Arc::new(Mutex::new(ProcessingStats::default())),
+            type_filter: Arc::new(RwLock::new(SensorTypeFilter::from_config(&config.processing))),
+            metrics: Arc::new(Metrics::new(&config.metrics)),
+            header_filtered: Arc::new(AtomicU64::new(0)),
+            duplicate_messages: Arc::new(AtomicU64::new(0)),
+            paused: Arc::new(AtomicBool::new(false)),
+            sinks: Arc::new(sinks),
+            buffers: Arc::new(BatchBuffers::new(None)),
+            batch_permits: None,
+            adaptive_batch_size: None,
+            logical_batches: Arc::new(LogicalBatchTracker::new(
+                database,
+                Duration::from_secs(config.processing.logical_batches.expire_after_seconds),
+            )),
+            batch_events: None,
+            sampler: None,
+            liveness: None,
+            redis_dedup,
+            registry: None,
+            name_normalizer: Arc::new(SensorNameNormalizer::new(
+                config.processing.sensor_name_normalization,
+                &config.processing.sensor_name_rewrites,
+            )?),
+            webhooks,
+            wal: None,
+            wal_commits: Arc::new(std::sync::Mutex::new(JoinSet::new())),
+            writer: None,
+            processing: Arc::new(config.processing),
+        })
+    }
+    
+    // A pipeline writing to a CaptureSink, whose database is never connected to: nothing
+    // listens on port 1, so any query would fail
+    pub(crate) async fn capturing(config: Config) -> (Self, crate::sinks::CaptureSink) {
+        let database = crate::config::DatabaseConfig {
+            url: "postgres://localhost:1/unused".to_string(),
+            min_connections: 0,
+            ..config.database.clone()
+        };
+        let database = Arc::new(Database::unconnected(&database).await.unwrap());
+        let capture = crate::sinks::CaptureSink::default();
+        let sinks = SinkSet::of(vec![Box::new(capture.clone())]);
+        let pipeline = Self::for_tests(config, database, sinks).await.unwrap();
+        (pipeline, capture)
+    }
+}
+
+// Periodically writes buffered batches that have waited out `max_batch_wait_ms`, or
+// filled up and waited out `min_batch_interval_ms`; `period` is the shorter of the two
+fn spawn_batch_flusher(pipeline: Pipeline, period: Duration) {
+    let tick = (period / 4).max(Duration::from_millis(10));
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(tick);
+        loop {
+            interval.tick().await;
+            pipeline.flush_expired().await;
+        }
+    });
+}
+
+// Handles one AMQP delivery; the consumer checkpoints its stream offset once it settles
+async fn handle_message(pipeline: Pipeline, sensor_data: Vec<SensorData>, context: MessageContext) -> Result<()> {
+    pipeline.process_sensor_data(sensor_data, context).await
+}
+
+// Sets the drain deadline, `timeout` from now, on the first SIGTERM or Ctrl-C, and exits
+// at once on a second one. Once tokio handles a signal its default action is gone, so the
+// listener has to stay up for a second Ctrl-C to do anything.
+fn spawn_shutdown_listener(timeout: Duration) -> Result<watch::Receiver<Option<Instant>>> {
+    let (sender, receiver) = watch::channel(None);
+    #[cfg(unix)]
+    let mut terminate = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())?;
+    
+    tokio::spawn(async move {
+        let mut draining = false;
+        loop {
+            #[cfg(unix)]
+            let signal = tokio::select! {
+                _ = tokio::signal::ctrl_c() => "SIGINT",
+                _ = terminate.recv() => "SIGTERM",
+            };
+            #[cfg(not(unix))]
+            let signal = {
+                let _ = tokio::signal::ctrl_c().await;
+                "Ctrl-C"
+            };
+            if draining {
+                warn!("{} received again, exiting without waiting for in-flight messages", signal);
+                std::process::exit(130);
+            }
+            info!("{} received, shutting down within {:?}; send it again to exit now", signal, timeout);
+            let _ = sender.send(Some(Instant::now() + timeout));
+            draining = true;
+        }
+    });
+    Ok(receiver)
+}
+
+// Writes the readings of every request queued at once in a single pass, so readings of
+// messages handled in parallel by the workers share batches
+fn spawn_writer(pipeline: Pipeline, mut requests: mpsc::Receiver<WriteRequest>) {
+    tokio::spawn(async move {
+        while let Some(first) = requests.recv().await {
+            let mut pending = vec![first];
+            while let Ok(request) = requests.try_recv() {
+                pending.push(request);
+            }
+            let readings = pending
+                .iter_mut()
+                .flat_map(|request| std::mem::take(&mut request.readings))
+                .collect::<Vec<_>>();
+            debug!("Writing {} readings of {} messages", readings.len(), pending.len());
+            let result = pipeline.write_batches(readings).await;
+            for request in pending {
+                let result = match &result {
+                    Ok(()) => Ok(()),
+                    Err(e) => Err(anyhow::anyhow!("{}", e)),
+                };
+                let _ = request.done.send(result);
+            }
+        }
+    });
+}
+
+// Hands readings to the writer task and waits until they are written
+async fn write_via(writer: &mpsc::Sender<WriteRequest>, readings: Vec<SensorReadingInput>) -> Result<()> {
+    let (done, written) = oneshot::channel();
+    writer
+        .send(WriteRequest { readings, done })
+        .await
+        .map_err(|_| anyhow::anyhow!("Writer task stopped"))?;
+    written.await.map_err(|_| anyhow::anyhow!("Writer task stopped before writing the readings"))?
+}
+
+// Splits readings by sensor type, keeping first-seen type order and reading order
+fn group_by_type(readings: Vec<SensorReadingInput>) -> Vec<(String, Vec<SensorReadingInput>)> {
+    let mut groups: Vec<(String, Vec<SensorReadingInput>)> = Vec::new();
+    for reading in readings {
+        match groups.iter_mut().find(|(sensor_type, _)| *sensor_type == reading.sensor_type) {
+            Some((_, group)) => group.push(reading),
+            None => groups.push((reading.sensor_type.clone(), vec![reading])),
+        }
+    }
+    groups
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[tokio::test]
+    async fn acks_an_empty_message_without_touching_the_database() {
+        let (pipeline, capture) = Pipeline::capturing(Config::default()).await;
+        
+        pipeline.process_sensor_data(Vec::new(), MessageContext::default()).await.unwrap();
+        
+        assert_eq!(pipeline.database().pool().size(), 0);
+        assert!(capture.batches.lock().unwrap().is_empty());
+        assert_eq!(pipeline.get_stats().await.unwrap().empty_messages, 1);
+        assert_eq!(pipeline.metrics().empty_messages.get(), 1);
+    }
+    
+    fn reading(sensor_type: &str) -> SensorData {
+        SensorData {
+            r#type: sensor_type.to_string(),
+            name: format!("{}-1", sensor_type),
+            payload: json!({ "value": 1 }).into(),
+            timestamp: None,
+        }
+    }
+    
+    #[tokio::test]
+    async fn rejects_unknown_sensor_types_only_when_they_are_whitelisted() {
+        let mut whitelisted = Config::default();
+        whitelisted.processing.allowed_sensor_types = Some(vec!["energy".to_string()]);
+        let (pipeline, capture) = Pipeline::capturing(whitelisted).await;
+        
+        let result = pipeline.process_sensor_data(vec![reading("energy"), reading("unknown")], MessageContext::default()).await;
+        assert!(result.unwrap_err().to_string().contains("Unknown sensor type 'unknown'"));
+        assert!(capture.batches.lock().unwrap().is_empty());
+        assert_eq!(pipeline.get_stats().await.unwrap().unknown_type_rejected, 1);
+        
+        let (pipeline, capture) = Pipeline::capturing(Config::default()).await;
+        pipeline.process_sensor_data(vec![reading("unknown")], MessageContext::default()).await.unwrap();
+        assert_eq!(capture.batches.lock().unwrap()[0][0].sensor_type, "unknown");
+    }
+}
@@ -1,129 +1,1235 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
 use lapin::{
-    options::*, publisher_confirm::Confirmation, types::FieldTable, Connection,
-    ConnectionProperties, Consumer, ExchangeKind, BasicProperties,
+    message::{BasicGetMessage, Delivery}, options::*, publisher_confirm::Confirmation,
+    types::{AMQPValue, FieldTable}, Connection, ConnectionProperties, Consumer,
+    ExchangeKind, BasicProperties,
 };
 use futures_lite::stream::StreamExt;
-use std::time::Duration;
+use futures_util::future::Either;
+use futures_util::stream::FuturesUnordered;
+use serde::Serialize;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicI64, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
 use tokio::time::timeout;
-use tracing::{debug, error, info};
-use crate::models::SensorData;
+use tracing::{debug, error, info, warn};
+use crate::ack_batch::AckBatcher;
+use crate::checkpoint::OffsetTracker;
+use crate::codec::{Codecs, JsonCodec, PayloadCodec, JSON_CONTENT_TYPE};
+use crate::config::{
+    DeadLetterConfig, DeliveryMode, DlqWrap, ExchangeType, HeaderFilterConfig, HeadersBindingConfig, HeadersMatch,
+    JsonLimitsConfig, QuarantineMode, RabbitMQConfig, RetryJitter, SourceIdConfig, StreamStart, StreamingParseConfig,
+};
+use crate::database::Database;
+use crate::json_stream::ArrayElements;
+use crate::logical_batch::BatchPart;
+use crate::message_dedup::SeenMessages;
+use crate::metrics;
+use crate::models::{DeadLetterEnvelope, QuarantinedMessage, SensorData};
+use crate::retry;
+use crate::validation;
+use crate::webhooks::{MessageResult, WebhookNotifier};
+use crate::worker_pool::{DecodeError, WorkerPool};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(1000);
+
+// A basic_get in progress. Dropping one whose get-ok is already on the wire would strand
+// the message unacked on the channel, so it is kept until it resolves.
+type PendingGet = Pin<Box<dyn Future<Output = lapin::Result<Option<BasicGetMessage>>> + Send>>;
+
+// Delivery metadata handed to the message handler alongside the decoded readings
+#[derive(Debug, Clone, Default)]
+pub struct MessageContext {
+    pub routing_key: String,
+    pub source_id: Option<String>,
+    // Set when consuming a stream queue
+    pub stream_offset: Option<i64>,
+    // Set when the message is one part of a logical batch
+    pub batch_part: Option<BatchPart>,
+    // From the W3C `traceparent` header, attached to metric exemplars
+    pub trace_id: Option<String>,
+    pub message_id: Option<String>,
+}
 
 pub struct RabbitMQConsumer {
     connection: Connection,
-    consumer: Consumer,
+    channel: lapin::Channel,
+    // None in pull mode, where deliveries are fetched with basic_get
+    consumer: Option<Consumer>,
+    // Kept to redo the connect/declare/consume setup after the connection is lost
+    config: RabbitMQConfig,
+    // Stream offsets in flight and the settled low-water mark, where a reconnect resumes from
+    offsets: Option<OffsetTracker>,
+    // Set with `with_checkpoints`; the low-water mark is saved here as it advances
+    checkpoints: Option<Arc<Database>>,
+    // Poll interval and shared depth of the queue depth monitor, if one was spawned
+    depth_monitor: Option<(Duration, Arc<AtomicU64>)>,
     queue_name: String,
+    source_id: SourceIdConfig,
+    dead_letter: Option<DeadLetterConfig>,
+    quarantine_mode: QuarantineMode,
+    // Set with `with_quarantine`; failed messages are stored here per `quarantine_mode`
+    quarantine: Option<Arc<Database>>,
+    header_filter: Option<HeaderFilterConfig>,
+    header_filtered: Arc<AtomicU64>,
+    seen_messages: Option<SeenMessages>,
+    duplicate_messages: Arc<AtomicU64>,
+    // Unix time in ms at which a delivery was last settled (or the consumer started)
+    last_progress: Arc<AtomicI64>,
+    paused: Arc<AtomicBool>,
+    codecs: Codecs,
+    json_limits: JsonLimitsConfig,
+    streaming_parse: Option<StreamingParseConfig>,
+    max_in_flight: usize,
+    confirm_timeout: Duration,
+    // Stop consuming once no delivery has arrived for this long
+    idle_shutdown: Option<Duration>,
+    // Told about deliveries skipped as already processed message ids
+    webhooks: Option<Arc<WebhookNotifier>>,
+    // Decodes and handles deliveries off the consumer task when set
+    worker_pool: Option<WorkerPool>,
+    // Holds the drain deadline once shutdown has been requested
+    shutdown: Option<watch::Receiver<Option<Instant>>>,
+    // Set when `ack_batch_size` is above 1
+    acks: Option<AckBatcher>,
+    // Bumped on every reconnect, so deliveries from a lost channel can be told apart
+    generation: u64,
 }
 
 impl RabbitMQConsumer {
-    pub async fn new(
-        connection_string: &str,
-        queue_name: String,
-        exchange_name: String,
-        routing_key: String,
-    ) -> Result<Self> {
-        info!("Connecting to RabbitMQ at: {}", connection_string);
-        
-        let connection = Connection::connect(connection_string, ConnectionProperties::default()).await?;
-        let channel = connection.create_channel().await?;
-        
-        // Declare exchange
-        channel
-            .exchange_declare(
-                &exchange_name,
-                ExchangeKind::Topic,
-                ExchangeDeclareOptions {
-                    durable: true,
-                    ..Default::default()
-                },
-                FieldTable::default(),
-            )
-            .await?;
-        
-        // Declare queue
-        let _queue = channel
-            .queue_declare(
-                &queue_name,
-                QueueDeclareOptions {
-                    durable: true,
-                    ..Default::default()
-                },
-                FieldTable::default(),
-            )
-            .await?;
-        
-        // Bind queue to exchange
-        channel
-            .queue_bind(
-                &queue_name,
-                &exchange_name,
-                &routing_key,
-                QueueBindOptions::default(),
-                FieldTable::default(),
-            )
-            .await?;
-        
-        // Create consumer
-        let consumer = channel
-            .basic_consume(
-                &queue_name,
-                "data-processor",
-                BasicConsumeOptions::default(),
-                FieldTable::default(),
-            )
-            .await?;
+    pub async fn new(config: &RabbitMQConfig) -> Result<Self> {
+        Self::connect(config, None).await
+    }
+    
+    // For stream queues, starts consuming just after `checkpoint` instead of at
+    // the configured `start_from` position
+    pub async fn resume_from(config: &RabbitMQConfig, checkpoint: i64) -> Result<Self> {
+        Self::connect(config, Some(checkpoint)).await
+    }
+    
+    async fn connect(config: &RabbitMQConfig, checkpoint: Option<i64>) -> Result<Self> {
+        check_prefetch(config)?;
+        let (connection, channel, consumer) = open(config, checkpoint).await?;
         
         Ok(Self {
             connection,
+            channel,
             consumer,
-            queue_name,
+            config: config.clone(),
+            offsets: config.stream.as_ref().map(|_| OffsetTracker::new(checkpoint)),
+            checkpoints: None,
+            depth_monitor: None,
+            queue_name: config.queue_name.clone(),
+            source_id: config.source_id.clone(),
+            dead_letter: config.dead_letter.clone(),
+            quarantine_mode: config.quarantine,
+            quarantine: None,
+            header_filter: config.header_filter.clone(),
+            header_filtered: Arc::new(AtomicU64::new(0)),
+            seen_messages: config.message_dedup.as_ref().map(SeenMessages::new).transpose()?,
+            duplicate_messages: Arc::new(AtomicU64::new(0)),
+            last_progress: Arc::new(AtomicI64::new(chrono::Utc::now().timestamp_millis())),
+            paused: Arc::new(AtomicBool::new(false)),
+            codecs: Codecs::new(&config.default_content_type),
+            json_limits: config.json_limits.clone(),
+            streaming_parse: config.streaming_parse.clone(),
+            max_in_flight: config.max_in_flight_messages.max(1),
+            confirm_timeout: Duration::from_millis(config.publish_confirm_timeout_ms),
+            idle_shutdown: None,
+            webhooks: None,
+            worker_pool: None,
+            shutdown: None,
+            acks: (config.ack_batch_size > 1)
+                .then(|| AckBatcher::new(config.ack_batch_size, Duration::from_millis(config.ack_interval_ms))),
+            generation: 0,
         })
     }
     
-    pub async fn consume_messages<F, Fut>(&mut self, mut handler: F) -> Result<()>
+    pub fn with_idle_shutdown(mut self, idle_shutdown: Option<Duration>) -> Self {
+        self.idle_shutdown = idle_shutdown;
+        self
+    }
+    
+    pub fn with_webhooks(mut self, webhooks: Option<Arc<WebhookNotifier>>) -> Self {
+        self.webhooks = webhooks;
+        self
+    }
+    
+    // Saves the stream offset every delivery up to has settled in `database`, so a restart
+    // resumes from there (stream queues only)
+    pub fn with_checkpoints(mut self, database: Arc<Database>) -> Self {
+        if self.offsets.is_some() {
+            self.checkpoints = Some(database);
+        }
+        self
+    }
+    
+    // Stores failed messages in `database` unless `rabbitmq.quarantine` is none
+    pub fn with_quarantine(mut self, database: Arc<Database>) -> Self {
+        if self.quarantine_mode != QuarantineMode::None {
+            self.quarantine = Some(database);
+        }
+        self
+    }
+    
+    // Decodes deliveries whose content_type matches the codec's with it
+    pub fn with_codec(mut self, codec: Arc<dyn PayloadCodec>) -> Self {
+        self.register_codec(codec);
+        self
+    }
+    
+    pub fn register_codec(&mut self, codec: Arc<dyn PayloadCodec>) {
+        info!("Registered payload codec for {}", codec.content_type());
+        self.codecs.register(codec);
+    }
+    
+    // Hands deliveries to `pool` instead of decoding them on the consumer task. The
+    // in-flight limit is raised to the worker count so every worker can be kept busy, and
+    // goes back to `max_in_flight_messages` when the pool is removed.
+    pub fn set_worker_pool(&mut self, pool: Option<WorkerPool>) {
+        let configured = self.config.max_in_flight_messages.max(1);
+        self.max_in_flight = match &pool {
+            Some(pool) => configured.max(pool.workers()),
+            None => configured,
+        };
+        self.worker_pool = pool;
+    }
+    
+    // Once `shutdown` holds a deadline, the consume loop stops taking deliveries and
+    // returns when those in flight have settled or the deadline passes
+    pub fn set_shutdown(&mut self, shutdown: Option<watch::Receiver<Option<Instant>>>) {
+        self.shutdown = shutdown;
+    }
+    
+    // Unix time in ms at which the last delivery was acked, rejected or dead-lettered
+    pub fn last_progress(&self) -> Arc<AtomicI64> {
+        self.last_progress.clone()
+    }
+    
+    fn record_progress(&self) {
+        self.last_progress.store(chrono::Utc::now().timestamp_millis(), Ordering::Relaxed);
+    }
+    
+    // Polls the queue's ready message count on a dedicated channel every `interval`;
+    // restarted on the new connection after a reconnect
+    pub async fn spawn_queue_depth_monitor(&mut self, interval: Duration) -> Result<Arc<AtomicU64>> {
+        let depth = Arc::new(AtomicU64::new(0));
+        self.start_queue_depth_monitor(interval, depth.clone()).await?;
+        self.depth_monitor = Some((interval, depth.clone()));
+        Ok(depth)
+    }
+    
+    async fn start_queue_depth_monitor(&self, interval: Duration, monitored: Arc<AtomicU64>) -> Result<()> {
+        let channel = self.connection.create_channel().await?;
+        let queue_name = self.queue_name.clone();
+        
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                let options = QueueDeclareOptions {
+                    passive: true,
+                    ..QueueDeclareOptions::default()
+                };
+                match channel.queue_declare(&queue_name, options, FieldTable::default()).await {
+                    Ok(queue) => monitored.store(queue.message_count() as u64, Ordering::Relaxed),
+                    Err(e) => {
+                        // A failed declare closes the channel, so later polls could not succeed
+                        error!("Stopping queue depth monitor for {}: {}", queue_name, e);
+                        break;
+                    }
+                }
+            }
+        });
+        
+        Ok(())
+    }
+    
+    // While set, the consume loop stops taking deliveries off the channel
+    pub fn pause_flag(&self) -> Arc<AtomicBool> {
+        self.paused.clone()
+    }
+    
+    // Number of messages acked without processing because they failed the header filter
+    pub fn header_filtered_messages(&self) -> Arc<AtomicU64> {
+        self.header_filtered.clone()
+    }
+    
+    // Number of messages acked without processing because their message_id was already seen
+    pub fn duplicate_messages(&self) -> Arc<AtomicU64> {
+        self.duplicate_messages.clone()
+    }
+    
+    fn passes_header_filter(&self, delivery: &Delivery) -> bool {
+        let Some(filter) = &self.header_filter else {
+            return true;
+        };
+        delivery
+            .properties
+            .headers()
+            .as_ref()
+            .and_then(|headers| headers.inner().get(filter.key.as_str()))
+            .and_then(header_value_to_string)
+            .is_some_and(|value| filter.allowed_values.contains(&value))
+    }
+    
+    fn message_context(&self, delivery: &Delivery) -> MessageContext {
+        MessageContext {
+            routing_key: delivery.routing_key.to_string(),
+            source_id: resolve_source_id(&self.source_id, delivery),
+            stream_offset: stream_offset(delivery),
+            batch_part: batch_part(delivery),
+            trace_id: delivery
+                .properties
+                .headers()
+                .as_ref()
+                .and_then(|headers| headers.inner().get("traceparent"))
+                .and_then(header_value_to_string)
+                .and_then(|traceparent| metrics::traceparent_trace_id(&traceparent)),
+            message_id: delivery.properties.message_id().as_ref().map(|id| id.to_string()),
+        }
+    }
+    
+    pub async fn consume_messages<F, Fut>(&mut self, handler: F) -> Result<()>
     where
-        F: FnMut(Vec<SensorData>) -> Fut,
+        F: FnMut(Vec<SensorData>, MessageContext) -> Fut,
         Fut: std::future::Future<Output = Result<()>>,
     {
+        self.consume_messages_limited(None, handler).await
+    }
+    
+    // Like `consume_messages`, but returns once `max_messages` deliveries have been handled
+    // or, when a limit is set, once the queue stays empty for a full poll interval
+    pub async fn consume_messages_limited<F, Fut>(&mut self, max_messages: Option<u64>, handler: F) -> Result<()>
+    where
+        F: FnMut(Vec<SensorData>, MessageContext) -> Fut,
+        Fut: std::future::Future<Output = Result<()>>,
+    {
+        let result = self.consume_loop(max_messages, handler).await;
+        // However the loop ended, ack what was processed before the connection closes
+        self.flush_acks().await;
+        result
+    }
+    
+    async fn consume_loop<F, Fut>(&mut self, max_messages: Option<u64>, mut handler: F) -> Result<()>
+    where
+        F: FnMut(Vec<SensorData>, MessageContext) -> Fut,
+        Fut: std::future::Future<Output = Result<()>>,
+    {
+        info!("Consuming messages from queue: {}", self.queue_name);
+        // Fail at startup rather than dead-letter everything when the default has no codec
+        self.codecs.for_content_type(None)?;
+        let mut handled = 0u64;
+        // Handler futures for deliveries being processed, each resolving to its delivery
+        let mut in_flight = FuturesUnordered::new();
+        let mut last_delivery = Instant::now();
+        let mut shutdown = self.shutdown.clone();
+        let mut drain_deadline: Option<Instant> = None;
+        let mut pending_get: Option<PendingGet> = None;
+        
         loop {
-            match timeout(Duration::from_millis(1000), self.consumer.next()).await {
-                Ok(Some(delivery)) => {
-                    let delivery = delivery?;
+            // Also catches a shutdown requested while reconnecting
+            if drain_deadline.is_none() {
+                drain_deadline = shutdown.as_ref().and_then(|shutdown| *shutdown.borrow());
+            }
+            if let Some(deadline) = drain_deadline {
+                if in_flight.is_empty() {
+                    info!("Stopping consumer after {} messages, nothing left in flight", handled);
+                    return Ok(());
+                }
+                if Instant::now() >= deadline {
+                    // Unacked deliveries go back to the queue once the connection closes
+                    warn!("Shutdown timeout reached with {} messages in flight, leaving them for redelivery", in_flight.len());
+                    return Ok(());
+                }
+            }
+            let limit_reached = max_messages.is_some_and(|max| handled >= max);
+            if limit_reached && in_flight.is_empty() {
+                info!("Reached message limit of {}, stopping consumer", handled);
+                return Ok(());
+            }
+            let can_receive = !limit_reached
+                && drain_deadline.is_none()
+                && in_flight.len() < self.max_in_flight
+                && !self.paused.load(Ordering::Relaxed);
+            let ack_flush_at = self.acks.as_ref().and_then(AckBatcher::flush_at);
+            
+            tokio::select! {
+                deadline = shutdown_requested(&mut shutdown), if drain_deadline.is_none() => {
+                    info!("Shutdown requested, finishing {} messages in flight", in_flight.len());
+                    drain_deadline = Some(deadline);
+                }
+                _ = tokio::time::sleep_until(drain_deadline.unwrap_or_else(Instant::now).into()), if drain_deadline.is_some() => {}
+                _ = tokio::time::sleep_until(ack_flush_at.unwrap_or_else(Instant::now).into()), if ack_flush_at.is_some() => {
+                    self.flush_acks().await;
+                }
+                Some((delivery, generation, result)) = in_flight.next(), if !in_flight.is_empty() => {
+                    self.finish(&delivery, generation, result).await;
+                }
+                // A started fetch is finished even while paused or draining, so its message isn't stranded
+                next = self.next_delivery(&mut pending_get), if can_receive || pending_get.is_some() => {
+                    let delivery = match next {
+                        // Here rather than in `next_delivery`, so a finishing handler can't cancel it
+                        Err(e) => {
+                            pending_get = None;
+                            self.reconnect(e).await?;
+                            continue;
+                        }
+                        Ok(Some(delivery)) => delivery,
+                        Ok(None) if max_messages.is_some() && in_flight.is_empty() => {
+                            info!("Queue is empty after {} messages, stopping consumer", handled);
+                            return Ok(());
+                        }
+                        Ok(None) if in_flight.is_empty()
+                            && self.idle_shutdown.is_some_and(|idle| last_delivery.elapsed() >= idle) =>
+                        {
+                            info!(
+                                "No deliveries for {}s after {} messages, stopping consumer",
+                                last_delivery.elapsed().as_secs(),
+                                handled
+                            );
+                            return Ok(());
+                        }
+                        // Nothing arrived within the poll interval, continue polling
+                        Ok(None) => continue,
+                    };
+                    handled += 1;
+                    last_delivery = Instant::now();
                     
-                    match serde_json::from_slice::<Vec<SensorData>>(&delivery.data) {
+                    if !self.passes_header_filter(&delivery) {
+                        debug!("Skipping message that does not match the header filter");
+                        self.header_filtered.fetch_add(1, Ordering::Relaxed);
+                        if let Err(e) = delivery.ack(BasicAckOptions::default()).await {
+                            error!("Failed to acknowledge filtered message: {}", e);
+                        }
+                        self.record_progress();
+                        self.settle_offset(&delivery).await;
+                        continue;
+                    }
+                    
+                    let message_id = delivery.properties.message_id().as_ref().map(|id| id.to_string());
+                    let seen = self.seen_messages.as_ref().zip(message_id.as_deref());
+                    if seen.is_some_and(|(seen, id)| seen.contains(id)) {
+                        debug!("Skipping already processed message {:?}", message_id);
+                        self.duplicate_messages.fetch_add(1, Ordering::Relaxed);
+                        if let Some(webhooks) = &self.webhooks {
+                            webhooks.message_result(MessageResult {
+                                message_id: message_id.clone(),
+                                routing_key: delivery.routing_key.to_string(),
+                                readings: 0,
+                                stored: 0,
+                                duplicate: true,
+                                duplicate_keys: message_id.into_iter().collect(),
+                            });
+                        }
+                        if let Err(e) = delivery.ack(BasicAckOptions::default()).await {
+                            error!("Failed to acknowledge duplicate message: {}", e);
+                        }
+                        self.record_progress();
+                        self.settle_offset(&delivery).await;
+                        continue;
+                    }
+                    
+                    let content_type = delivery.properties.content_type().as_ref().map(|content_type| content_type.as_str());
+                    let codec = match self.codecs.for_content_type(content_type) {
+                        Ok(codec) => codec,
+                        Err(e) => {
+                            warn!("Rejecting message: {}", e);
+                            self.dead_letter(&delivery, &e.to_string()).await;
+                            self.settle_offset(&delivery).await;
+                            continue;
+                        }
+                    };
+                    let is_json = codec.content_type() == JSON_CONTENT_TYPE;
+                    
+                    let limits = &self.json_limits;
+                    if is_json {
+                        if let Err(e) = validation::check_json_complexity(&delivery.data, limits.max_depth, limits.max_elements) {
+                            warn!("Rejecting oversized message: {}", e);
+                            self.dead_letter(&delivery, &e).await;
+                            self.settle_offset(&delivery).await;
+                            continue;
+                        }
+                    }
+                    
+                    let chunk_size = self
+                        .streaming_parse
+                        .as_ref()
+                        .filter(|streaming| is_json && delivery.data.len() >= streaming.threshold_bytes)
+                        .map(|streaming| streaming.chunk_size);
+                    if let Some(chunk_size) = chunk_size {
+                        info!("Parsing {} byte message incrementally", delivery.data.len());
+                        let context = self.message_context(&delivery);
+                        self.track(&delivery);
+                        let result = handle_in_chunks(&delivery.data, chunk_size, context, &mut handler).await;
+                        self.finish(&delivery, self.generation, result).await;
+                        continue;
+                    }
+                    
+                    let generation = self.generation;
+                    if let Some(pool) = &self.worker_pool {
+                        let context = self.message_context(&delivery);
+                        let pool = pool.clone();
+                        let data = delivery.data.clone();
+                        self.track(&delivery);
+                        in_flight.push(Either::Right(async move {
+                            (delivery, generation, pool.submit(codec, data, context).await)
+                        }));
+                        continue;
+                    }
+                    
+                    match codec.decode(&delivery.data) {
                         Ok(sensor_data) => {
                             debug!("Received sensor data: {:?}", sensor_data);
+                            let context = self.message_context(&delivery);
                             
                             // Process sensor data
-                            if let Err(e) = handler(sensor_data).await {
-                                error!("Failed to process sensor data: {}", e);
-                            }
-                            
-                            // Acknowledge message
-                            if let Err(e) = delivery.ack(BasicAckOptions::default()).await {
-                                error!("Failed to acknowledge message: {}", e);
-                            }
+                            let processing = handler(sensor_data, context);
+                            self.track(&delivery);
+                            in_flight.push(Either::Left(async move { (delivery, generation, processing.await) }));
                         }
                         Err(e) => {
-                            error!("Failed to deserialize sensor data: {}", e);
-                            
-                            // Reject message
-                            if let Err(e) = delivery.reject(BasicRejectOptions::default()).await {
-                                error!("Failed to reject message: {}", e);
-                            }
+                            self.reject_undecodable(&delivery, &e).await;
+                            self.settle_offset(&delivery).await;
                         }
                     }
                 }
-                Ok(None) => {
-                    // No message received, continue
-                    continue;
+                // Paused with nothing in flight; time spent paused does not count as idle
+                _ = tokio::time::sleep(Duration::from_millis(200)), if !can_receive && in_flight.is_empty() => {
+                    last_delivery = Instant::now();
                 }
-                Err(_) => {
-                    // Timeout, continue polling
-                    continue;
+            }
+        }
+    }
+    
+    // Waits up to one poll interval for the next delivery. Pull mode fetches a single
+    // message per call, so at most `max_in_flight` are ever held unacked; the fetch lives in
+    // `pending_get` so a call cancelled by another select branch resumes it next time.
+    // Errors mean the connection, channel or consumer is gone.
+    async fn next_delivery(&mut self, pending_get: &mut Option<PendingGet>) -> Result<Option<Delivery>> {
+        let next = match &mut self.consumer {
+            Some(consumer) => match timeout(POLL_INTERVAL, consumer.next()).await {
+                Ok(Some(delivery)) => delivery.map(Some).map_err(anyhow::Error::from),
+                Ok(None) => Err(anyhow::anyhow!("Consumer was cancelled")),
+                Err(_) => Ok(None),
+            },
+            None => {
+                let fetch = pending_get.get_or_insert_with(|| {
+                    let channel = self.channel.clone();
+                    let queue_name = self.queue_name.clone();
+                    Box::pin(async move { channel.basic_get(&queue_name, BasicGetOptions::default()).await })
+                });
+                let fetched = fetch.await;
+                *pending_get = None;
+                match fetched {
+                    Ok(Some(message)) => Ok(Some(message.delivery)),
+                    Ok(None) => {
+                        tokio::time::sleep(POLL_INTERVAL).await;
+                        Ok(None)
+                    }
+                    Err(e) => Err(e.into()),
+                }
+            }
+        };
+        let next = match next {
+            Ok(None) if !self.connection.status().connected() || !self.channel.status().connected() => {
+                Err(anyhow::anyhow!("Connection to RabbitMQ was closed"))
+            }
+            next => next,
+        };
+        let offset = next.as_ref().ok().and_then(Option::as_ref).and_then(stream_offset);
+        if let (Some(offsets), Some(offset)) = (&mut self.offsets, offset) {
+            offsets.received(offset);
+        }
+        next
+    }
+    
+    // Redoes the connect/declare/consume setup with doubling delays until the broker is
+    // back. Deliveries still in flight on the old channel can't be acked and are
+    // redelivered by the broker; stream queues resume after the settled low-water mark.
+    async fn reconnect(&mut self, cause: anyhow::Error) -> Result<()> {
+        let reconnect = self.config.reconnect.clone();
+        warn!("Lost RabbitMQ consumer on {}: {}", self.queue_name, cause);
+        // Unacked deliveries went with the channel; the broker redelivers them
+        self.generation += 1;
+        if let Some(acks) = &mut self.acks {
+            acks.clear();
+        }
+        // Offsets past the mark may still be in flight, so they are consumed again
+        if let Some(offsets) = &mut self.offsets {
+            offsets.reset();
+        }
+        let resume_from = self.offsets.as_ref().and_then(OffsetTracker::low_water);
+        let mut delay = Duration::from_millis(reconnect.initial_delay_ms);
+        let mut attempt = 1;
+        loop {
+            if reconnect.max_attempts.is_some_and(|max| max > 0 && attempt > max) {
+                return Err(cause.context(format!("Failed to reconnect to RabbitMQ after {} attempts", attempt - 1)));
+            }
+            let wait = retry::jittered_delay(delay, RetryJitter::Equal);
+            warn!("Reconnecting to RabbitMQ in {:?} (attempt {})", wait, attempt);
+            let mut shutdown = self.shutdown.clone();
+            tokio::select! {
+                _ = tokio::time::sleep(wait) => {}
+                _ = shutdown_requested(&mut shutdown) => {
+                    info!("Shutdown requested, no longer reconnecting");
+                    return Ok(());
+                }
+            }
+            match open(&self.config, resume_from).await {
+                Ok((connection, channel, consumer)) => {
+                    self.connection = connection;
+                    self.channel = channel;
+                    self.consumer = consumer;
+                    info!("Reconnected to RabbitMQ after {} attempt(s)", attempt);
+                    if let Some((interval, depth)) = self.depth_monitor.clone() {
+                        if let Err(e) = self.start_queue_depth_monitor(interval, depth).await {
+                            error!("Failed to restart queue depth monitor for {}: {}", self.queue_name, e);
+                        }
+                    }
+                    return Ok(());
+                }
+                Err(e) => warn!("Reconnect attempt {} failed: {:#}", attempt, e),
+            }
+            delay = (delay * 2).min(Duration::from_millis(reconnect.max_delay_ms));
+            attempt += 1;
+        }
+    }
+    
+    // Registers a delivery about to be processed with the ack batcher
+    fn track(&mut self, delivery: &Delivery) {
+        if let Some(acks) = &mut self.acks {
+            acks.received(delivery.delivery_tag);
+        }
+    }
+    
+    // Acks a processed delivery, or dead-letters it when its handler failed. `generation`
+    // is the channel it arrived on; one lost to a reconnect is no longer batched, and its
+    // stream offset is consumed again.
+    async fn finish(&mut self, delivery: &Delivery, generation: u64, result: Result<()>) {
+        self.record_progress();
+        let current = generation == self.generation;
+        match result {
+            // A worker pool decodes off this task; its decode failures are rejected like inline ones
+            Err(e) if e.is::<DecodeError>() => {
+                self.reject_undecodable(delivery, &e).await;
+                if let Some(acks) = self.acks.as_mut().filter(|_| current) {
+                    acks.settled(delivery.delivery_tag);
+                }
+            }
+            result => self.settle(delivery, current, result).await,
+        }
+        if current {
+            self.settle_offset(delivery).await;
+        }
+    }
+    
+    async fn settle(&mut self, delivery: &Delivery, current: bool, result: Result<()>) {
+        if let Err(e) = result {
+            error!("Failed to process sensor data: {}", e);
+            self.dead_letter(delivery, &e.to_string()).await;
+            if let Some(acks) = self.acks.as_mut().filter(|_| current) {
+                acks.settled(delivery.delivery_tag);
+            }
+            return;
+        }
+        
+        // Only processed messages are remembered, so a dead-lettered one can still be replayed
+        if let (Some(seen), Some(id)) = (&self.seen_messages, delivery.properties.message_id()) {
+            seen.insert(id.as_str());
+        }
+        
+        if let Some(acks) = self.acks.as_mut().filter(|_| current) {
+            acks.processed(delivery.delivery_tag, delivery.acker.clone());
+            if acks.due() {
+                self.flush_acks().await;
+            }
+            return;
+        }
+        
+        // Acknowledge message
+        if let Err(e) = delivery.ack(BasicAckOptions::default()).await {
+            error!("Failed to acknowledge message: {}", e);
+        }
+    }
+    
+    async fn reject_undecodable(&self, delivery: &Delivery, error: &anyhow::Error) {
+        error!("Failed to deserialize sensor data: {}", error);
+        self.dead_letter(delivery, &format!("Failed to deserialize sensor data: {}", error)).await;
+    }
+    
+    // Records a settled stream delivery and checkpoints the low-water mark if it advanced
+    async fn settle_offset(&mut self, delivery: &Delivery) {
+        let (Some(offsets), Some(offset)) = (&mut self.offsets, stream_offset(delivery)) else {
+            return;
+        };
+        let (Some(low_water), Some(database)) = (offsets.settled(offset), &self.checkpoints) else {
+            return;
+        };
+        if let Err(e) = database.save_checkpoint(&self.queue_name, low_water).await {
+            error!("Failed to save checkpoint {} for {}: {}", low_water, self.queue_name, e);
+        }
+    }
+    
+    // Acks, with one multiple ack, every batched delivery that can be covered by one
+    async fn flush_acks(&mut self) {
+        let Some((acker, count)) = self.acks.as_mut().and_then(AckBatcher::take) else {
+            return;
+        };
+        debug!("Acknowledging {} messages", count);
+        if let Err(e) = acker.ack(BasicAckOptions { multiple: true }).await {
+            error!("Failed to acknowledge {} messages: {}", count, e);
+        }
+    }
+    
+    // Publishes the failed delivery to the configured dead-letter exchange and acks it,
+    // falling back to a plain reject when no exchange is configured or publishing fails.
+    async fn dead_letter(&self, delivery: &Delivery, error_message: &str) {
+        self.record_progress();
+        if self.quarantine(delivery, error_message).await && self.quarantine_mode == QuarantineMode::Only {
+            if let Err(e) = delivery.ack(BasicAckOptions::default()).await {
+                error!("Failed to acknowledge quarantined message: {}", e);
+            }
+            return;
+        }
+        if let Some(dead_letter) = &self.dead_letter {
+            match self.publish_dead_letter(dead_letter, delivery, error_message).await {
+                Ok(()) => {
+                    if let Err(e) = delivery.ack(BasicAckOptions::default()).await {
+                        error!("Failed to acknowledge dead-lettered message: {}", e);
+                    }
+                    return;
                 }
+                Err(e) => error!("Failed to publish message to dead-letter exchange: {}", e),
+            }
+        }
+        
+        // Reject message
+        if let Err(e) = delivery.reject(BasicRejectOptions::default()).await {
+            error!("Failed to reject message: {}", e);
+        }
+    }
+    
+    // Stores the failed delivery in the quarantine table; false when none is configured or
+    // the insert failed, so the message is dead-lettered instead of lost
+    async fn quarantine(&self, delivery: &Delivery, error_message: &str) -> bool {
+        let Some(database) = &self.quarantine else {
+            return false;
+        };
+        match database.insert_quarantined(&quarantined_message(delivery, error_message)).await {
+            Ok(()) => true,
+            Err(e) => {
+                error!("Failed to quarantine message: {}", e);
+                false
+            }
+        }
+    }
+    
+    async fn publish_dead_letter(
+        &self,
+        dead_letter: &DeadLetterConfig,
+        delivery: &Delivery,
+        error_message: &str,
+    ) -> Result<()> {
+        let (payload, properties) = match dead_letter.dlq_wrap {
+            DlqWrap::Raw => (delivery.data.clone(), delivery.properties.clone()),
+            DlqWrap::Enriched => {
+                let envelope = DeadLetterEnvelope {
+                    original_payload: String::from_utf8_lossy(&delivery.data).into_owned(),
+                    error: error_message.to_string(),
+                    failed_at: chrono::Utc::now(),
+                    attempt_count: delivery_attempt_count(delivery),
+                    source_exchange: delivery.exchange.to_string(),
+                    source_routing_key: delivery.routing_key.to_string(),
+                };
+                let properties = BasicProperties::default()
+                    .with_content_type("application/json".into())
+                    .with_delivery_mode(2);
+                (serde_json::to_vec(&envelope)?, properties)
+            }
+        };
+        
+        let pending = self
+            .channel
+            .basic_publish(
+                &dead_letter.exchange_name,
+                &dead_letter.routing_key,
+                BasicPublishOptions::default(),
+                &payload,
+                properties,
+            )
+            .await?;
+        await_confirm(pending, Some(self.confirm_timeout), "Dead-letter publish").await?;
+        
+        debug!("Message dead-lettered to exchange: {}", dead_letter.exchange_name);
+        Ok(())
+    }
+    
+    pub async fn close(&self) -> Result<()> {
+        if self.channel.status().connected() {
+            self.channel.close(200, "Consumer closed").await?;
+        }
+        self.connection.close(200, "Consumer closed").await?;
+        Ok(())
+    }
+}
+
+// Waits for the broker to confirm one publish. Channels are put in confirm mode when
+// opened, so a nack, a missing confirmation (`NotRequested`) or no answer within
+// `confirm_timeout` all fail the publish and leave it to the caller's retry.
+async fn await_confirm<F>(pending: F, confirm_timeout: Option<Duration>, target: &str) -> Result<()>
+where
+    F: Future<Output = lapin::Result<Confirmation>>,
+{
+    let confirm = match confirm_timeout {
+        Some(confirm_timeout) => timeout(confirm_timeout, pending)
+            .await
+            .map_err(|_| anyhow::anyhow!("{} was not confirmed within {:?}", target, confirm_timeout))??,
+        None => pending.await?,
+    };
+    match confirm {
+        Confirmation::Ack(_) => Ok(()),
+        Confirmation::Nack(_) => Err(anyhow::anyhow!("{} was not acknowledged by RabbitMQ", target)),
+        Confirmation::NotRequested => Err(anyhow::anyhow!("{} was sent without confirm mode", target)),
+    }
+}
+
+// Resolves with the drain deadline once one is set; never resolves without a receiver
+async fn shutdown_requested(shutdown: &mut Option<watch::Receiver<Option<Instant>>>) -> Instant {
+    if let Some(receiver) = shutdown {
+        if let Ok(deadline) = receiver.wait_for(Option::is_some).await {
+            if let Some(deadline) = *deadline {
+                return deadline;
+            }
+        }
+    }
+    std::future::pending().await
+}
+
+// Connects and runs the declare/bind/consume setup; used at startup and on every reconnect
+async fn open(config: &RabbitMQConfig, checkpoint: Option<i64>) -> Result<(Connection, lapin::Channel, Option<Consumer>)> {
+    let queue_name = &config.queue_name;
+    
+    info!("Connecting to RabbitMQ at: {}", config.connection_string);
+    
+    let connection = Connection::connect(&config.connection_string, ConnectionProperties::default()).await?;
+    let channel = connection.create_channel().await?;
+    // Dead letters are published on this channel and must be confirmed by the broker
+    channel.confirm_select(ConfirmSelectOptions::default()).await?;
+    
+    if config.manage_topology {
+        declare_topology(&channel, config).await?;
+    } else {
+        // Topology is provisioned out of band; fail at boot if the queue isn't there
+        channel
+            .queue_declare(
+                queue_name,
+                QueueDeclareOptions {
+                    passive: true,
+                    ..Default::default()
+                },
+                FieldTable::default(),
+            )
+            .await
+            .with_context(|| format!("Queue '{}' does not exist and rabbitmq.manage_topology is disabled", queue_name))?;
+        if let Some(dead_letter) = &config.dead_letter {
+            channel
+                .exchange_declare(
+                    &dead_letter.exchange_name,
+                    ExchangeKind::Topic,
+                    ExchangeDeclareOptions {
+                        passive: true,
+                        ..Default::default()
+                    },
+                    FieldTable::default(),
+                )
+                .await
+                .with_context(|| {
+                    format!(
+                        "Dead-letter exchange '{}' does not exist and rabbitmq.manage_topology is disabled",
+                        dead_letter.exchange_name
+                    )
+                })?;
+        }
+    }
+    
+    // Bound what the broker pushes ahead of our acks; basic_get ignores the prefetch window
+    if config.delivery_mode == DeliveryMode::Push {
+        let qos = BasicQosOptions { global: config.qos_global };
+        channel.basic_qos(prefetch_count(config), qos).await?;
+    }
+    
+    let mut consume_args = FieldTable::default();
+    if let Some(stream) = &config.stream {
+        let offset = match (checkpoint, stream.start_from) {
+            (Some(checkpoint), _) => AMQPValue::LongLongInt(checkpoint + 1),
+            (None, StreamStart::First) => AMQPValue::LongString("first".into()),
+            (None, StreamStart::Next | StreamStart::Checkpoint) => AMQPValue::LongString("next".into()),
+        };
+        info!("Consuming stream {} from offset {:?}", queue_name, offset);
+        consume_args.insert("x-stream-offset".into(), offset);
+    }
+    
+    // Create consumer
+    let consumer = match config.delivery_mode {
+        DeliveryMode::Push => Some(
+            channel
+                .basic_consume(
+                    queue_name,
+                    "data-processor",
+                    BasicConsumeOptions::default(),
+                    consume_args,
+                )
+                .await?,
+        ),
+        DeliveryMode::Pull if config.stream.is_some() => {
+            anyhow::bail!("rabbitmq.delivery_mode pull is not supported for stream queues");
+        }
+        DeliveryMode::Pull => None,
+    };
+    
+    Ok((connection, channel, consumer))
+}
+
+// Stream queues keep their own prefetch setting
+fn prefetch_count(config: &RabbitMQConfig) -> u16 {
+    config.stream.as_ref().map_or(config.prefetch_count, |stream| stream.prefetch_count)
+}
+
+// In push mode the broker stops at the prefetch count of unacked deliveries, so more
+// in flight could never be reached and a larger ack batch would only ever flush on
+// `ack_interval_ms`
+fn check_prefetch(config: &RabbitMQConfig) -> Result<()> {
+    let prefetch_count = usize::from(prefetch_count(config));
+    if prefetch_count == 0 || config.delivery_mode != DeliveryMode::Push {
+        return Ok(());
+    }
+    if config.max_in_flight_messages > prefetch_count {
+        anyhow::bail!(
+            "rabbitmq.max_in_flight_messages ({}) exceeds the prefetch count ({})",
+            config.max_in_flight_messages,
+            prefetch_count
+        );
+    }
+    if config.ack_batch_size > prefetch_count {
+        anyhow::bail!(
+            "rabbitmq.ack_batch_size ({}) exceeds the prefetch count ({})",
+            config.ack_batch_size,
+            prefetch_count
+        );
+    }
+    Ok(())
+}
+
+async fn declare_topology(channel: &lapin::Channel, config: &RabbitMQConfig) -> Result<()> {
+    let queue_name = &config.queue_name;
+    let exchange_name = &config.exchange_name;
+    let routing_key = &config.routing_key;
+    
+    // Declare exchange
+    channel
+        .exchange_declare(
+            exchange_name,
+            exchange_kind(config.exchange_kind),
+            ExchangeDeclareOptions {
+                durable: true,
+                ..Default::default()
+            },
+            FieldTable::default(),
+        )
+        .await?;
+    
+    // Declare queue
+    let mut queue_args = FieldTable::default();
+    if config.stream.is_some() {
+        queue_args.insert("x-queue-type".into(), AMQPValue::LongString("stream".into()));
+    }
+    let _queue = channel
+        .queue_declare(
+            queue_name,
+            QueueDeclareOptions {
+                durable: true,
+                ..Default::default()
+            },
+            queue_args,
+        )
+        .await?;
+    
+    // Bind queue to exchange; a headers exchange matches on the binding arguments
+    if config.binding_headers.is_some() && config.exchange_kind != ExchangeType::Headers {
+        warn!("rabbitmq.binding_headers only affect routing on a headers exchange");
+    }
+    let binding_args = match &config.binding_headers {
+        Some(binding) => binding_arguments(binding),
+        None => FieldTable::default(),
+    };
+    channel
+        .queue_bind(
+            queue_name,
+            exchange_name,
+            routing_key,
+            QueueBindOptions::default(),
+            binding_args,
+        )
+        .await?;
+    
+    // Declare dead-letter exchange and queue
+    if let Some(dead_letter) = &config.dead_letter {
+        declare_dead_letter_topology(channel, dead_letter).await?;
+    }
+    
+    Ok(())
+}
+
+pub(crate) fn exchange_kind(exchange_type: ExchangeType) -> ExchangeKind {
+    match exchange_type {
+        ExchangeType::Topic => ExchangeKind::Topic,
+        ExchangeType::Direct => ExchangeKind::Direct,
+        ExchangeType::Fanout => ExchangeKind::Fanout,
+        ExchangeType::Headers => ExchangeKind::Headers,
+    }
+}
+
+// `x-match` plus the header key/value pairs, as `queue_bind` arguments
+fn binding_arguments(binding: &HeadersBindingConfig) -> FieldTable {
+    let mut args = FieldTable::default();
+    let x_match = match binding.x_match {
+        HeadersMatch::All => "all",
+        HeadersMatch::Any => "any",
+    };
+    args.insert("x-match".into(), AMQPValue::LongString(x_match.into()));
+    for (key, value) in &binding.headers {
+        args.insert(key.as_str().into(), AMQPValue::LongString(value.as_str().into()));
+    }
+    args
+}
+
+async fn declare_dead_letter_topology(channel: &lapin::Channel, dead_letter: &DeadLetterConfig) -> Result<()> {
+    channel
+        .exchange_declare(
+            &dead_letter.exchange_name,
+            ExchangeKind::Topic,
+            ExchangeDeclareOptions {
+                durable: true,
+                ..Default::default()
+            },
+            FieldTable::default(),
+        )
+        .await?;
+    
+    channel
+        .queue_declare(
+            &dead_letter.queue_name,
+            QueueDeclareOptions {
+                durable: true,
+                ..Default::default()
+            },
+            FieldTable::default(),
+        )
+        .await?;
+    
+    channel
+        .queue_bind(
+            &dead_letter.queue_name,
+            &dead_letter.exchange_name,
+            &dead_letter.routing_key,
+            QueueBindOptions::default(),
+            FieldTable::default(),
+        )
+        .await?;
+    
+    Ok(())
+}
+
+// Quorum queues report prior deliveries in `x-delivery-count`; classic queues only
+// expose the redelivered flag.
+fn quarantined_message(delivery: &Delivery, error_message: &str) -> QuarantinedMessage {
+    QuarantinedMessage {
+        id: 0,
+        payload: delivery.data.clone(),
+        error: error_message.to_string(),
+        exchange: delivery.exchange.to_string(),
+        routing_key: delivery.routing_key.to_string(),
+        message_id: delivery.properties.message_id().as_ref().map(|id| id.to_string()),
+        content_type: delivery.properties.content_type().as_ref().map(|content_type| content_type.to_string()),
+        attempt_count: delivery_attempt_count(delivery) as i32,
+        quarantined_at: chrono::Utc::now(),
+    }
+}
+
+fn delivery_attempt_count(delivery: &Delivery) -> u32 {
+    let delivery_count = delivery
+        .properties
+        .headers()
+        .as_ref()
+        .and_then(|headers| headers.inner().get("x-delivery-count"))
+        .and_then(header_value_to_string)
+        .and_then(|count| count.parse::<u32>().ok());
+    
+    match delivery_count {
+        Some(count) => count + 1,
+        None if delivery.redelivered => 2,
+        None => 1,
+    }
+}
+
+fn resolve_source_id(source: &SourceIdConfig, delivery: &Delivery) -> Option<String> {
+    match source {
+        SourceIdConfig::None => None,
+        SourceIdConfig::Header { name } => delivery
+            .properties
+            .headers()
+            .as_ref()
+            .and_then(|headers| headers.inner().get(name.as_str()))
+            .and_then(header_value_to_string),
+        SourceIdConfig::RoutingKeySegment { index } => delivery
+            .routing_key
+            .as_str()
+            .split('.')
+            .nth(*index)
+            .filter(|segment| !segment.is_empty())
+            .map(str::to_string),
+        SourceIdConfig::UserId => delivery.properties.user_id().as_ref().map(|id| id.to_string()),
+        SourceIdConfig::AppId => delivery.properties.app_id().as_ref().map(|id| id.to_string()),
+    }
+}
+
+// Set on deliveries from a stream queue
+fn stream_offset(delivery: &Delivery) -> Option<i64> {
+    delivery
+        .properties
+        .headers()
+        .as_ref()
+        .and_then(|headers| headers.inner().get("x-stream-offset"))
+        .and_then(header_value_to_string)
+        .and_then(|offset| offset.parse().ok())
+}
+
+// Hands a large message to the handler one chunk of readings at a time, decoding the next
+// chunk only after the previous one is processed. Runs inline, so other deliveries wait
+// until it is done. Chunks stored before a failure stay stored when the message fails.
+async fn handle_in_chunks<F, Fut>(data: &[u8], chunk_size: usize, context: MessageContext, handler: &mut F) -> Result<()>
+where
+    F: FnMut(Vec<SensorData>, MessageContext) -> Fut,
+    Fut: std::future::Future<Output = Result<()>>,
+{
+    let chunk_size = chunk_size.max(1);
+    let mut elements = ArrayElements::<SensorData>::new(data)
+        .map_err(|e| anyhow::anyhow!("Failed to deserialize sensor data: {}", e))?
+        .peekable();
+    let mut chunks = 0u64;
+    loop {
+        let mut chunk = Vec::with_capacity(chunk_size);
+        while chunk.len() < chunk_size {
+            match elements.next() {
+                Some(reading) => chunk.push(
+                    reading.map_err(|e| anyhow::anyhow!("Failed to deserialize sensor data: {}", e))?,
+                ),
+                None => break,
             }
         }
+        let last = elements.peek().is_none();
+        
+        // The logical batch part is recorded once, with the final chunk
+        let mut chunk_context = context.clone();
+        if !last {
+            chunk_context.batch_part = None;
+        }
+        handler(chunk, chunk_context).await?;
+        chunks += 1;
+        if last {
+            debug!("Handled message in {} chunks", chunks);
+            return Ok(());
+        }
+    }
+}
+
+fn batch_part(delivery: &Delivery) -> Option<BatchPart> {
+    let header = |name: &str| {
+        delivery
+            .properties
+            .headers()
+            .as_ref()
+            .and_then(|headers| headers.inner().get(name))
+            .and_then(header_value_to_string)
+    };
+    let batch_id = header("batch_id")?;
+    let part = header("sequence")
+        .zip(header("total"))
+        .and_then(|(sequence, total)| Some((sequence.parse().ok()?, total.parse().ok()?)))
+        .and_then(|(sequence, total)| BatchPart::new(batch_id.clone(), sequence, total));
+    if part.is_none() {
+        warn!("Message of batch {} has a missing or invalid sequence/total header", batch_id);
+    }
+    part
+}
+
+pub(crate) fn header_value_to_string(value: &AMQPValue) -> Option<String> {
+    match value {
+        AMQPValue::LongString(s) => Some(s.to_string()),
+        AMQPValue::ShortString(s) => Some(s.to_string()),
+        AMQPValue::Boolean(b) => Some(b.to_string()),
+        AMQPValue::ShortShortInt(n) => Some(n.to_string()),
+        AMQPValue::ShortShortUInt(n) => Some(n.to_string()),
+        AMQPValue::ShortInt(n) => Some(n.to_string()),
+        AMQPValue::ShortUInt(n) => Some(n.to_string()),
+        AMQPValue::LongInt(n) => Some(n.to_string()),
+        AMQPValue::LongUInt(n) => Some(n.to_string()),
+        AMQPValue::LongLongInt(n) => Some(n.to_string()),
+        _ => None,
+    }
+}
+
+// Routing key with `{type}` and `{name}` placeholders filled in per reading
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RoutingKeyTemplate {
+    template: String,
+}
+
+impl RoutingKeyTemplate {
+    pub fn parse(template: &str) -> Result<Self> {
+        let mut rest = template;
+        while let Some(start) = rest.find('{') {
+            let end = rest[start..]
+                .find('}')
+                .ok_or_else(|| anyhow::anyhow!("Unclosed placeholder in routing key template '{}'", template))?;
+            let placeholder = &rest[start + 1..start + end];
+            if placeholder != "type" && placeholder != "name" {
+                anyhow::bail!("Unknown placeholder '{{{}}}' in routing key template '{}'", placeholder, template);
+            }
+            rest = &rest[start + end + 1..];
+        }
+        if rest.contains('}') {
+            anyhow::bail!("Unmatched '}}' in routing key template '{}'", template);
+        }
+        
+        Ok(Self { template: template.to_string() })
+    }
+    
+    pub fn render(&self, sensor_type: &str, sensor_name: &str) -> String {
+        self.template.replace("{type}", sensor_type).replace("{name}", sensor_name)
+    }
+    
+    // One group per rendered key, in order of first appearance, with readings in their
+    // original order; `type_and_name` picks the placeholders' values out of an item
+    pub fn group<'a, T>(
+        &self,
+        items: &'a [T],
+        type_and_name: impl Fn(&T) -> (&str, &str),
+    ) -> Vec<(String, Vec<&'a T>)> {
+        let mut groups: Vec<(String, Vec<&T>)> = Vec::new();
+        for item in items {
+            let (sensor_type, sensor_name) = type_and_name(item);
+            let key = self.render(sensor_type, sensor_name);
+            match groups.iter_mut().find(|(existing, _)| *existing == key) {
+                Some((_, grouped)) => grouped.push(item),
+                None => groups.push((key, vec![item])),
+            }
+        }
+        groups
     }
 }
 
@@ -131,63 +1237,326 @@ pub struct RabbitMQProducer {
     connection: Connection,
     channel: lapin::Channel,
     exchange_name: String,
+    routing_key_template: Option<RoutingKeyTemplate>,
+    confirm_timeout: Option<Duration>,
+    // Encodes the readings published by `send_sensor_data`
+    codec: Arc<dyn PayloadCodec>,
+    // Added to every publish that lacks them, so messages match a headers binding
+    default_headers: Option<FieldTable>,
 }
 
 impl RabbitMQProducer {
-    pub async fn new(connection_string: &str, exchange_name: String) -> Result<Self> {
+    // With `manage_topology` off the exchange is only checked passively, so a missing
+    // exchange fails here instead of on every publish
+    pub async fn new(
+        connection_string: &str,
+        exchange_name: String,
+        kind: ExchangeKind,
+        manage_topology: bool,
+    ) -> Result<Self> {
         info!("Connecting to RabbitMQ at: {}", connection_string);
         
         let connection = Connection::connect(connection_string, ConnectionProperties::default()).await?;
         let channel = connection.create_channel().await?;
+        channel.confirm_select(ConfirmSelectOptions::default()).await?;
         
         // Declare exchange
         channel
             .exchange_declare(
                 &exchange_name,
-                ExchangeKind::Topic,
+                kind,
                 ExchangeDeclareOptions {
+                    passive: !manage_topology,
                     durable: true,
                     ..Default::default()
                 },
                 FieldTable::default(),
             )
-            .await?;
+            .await
+            .with_context(|| {
+                if manage_topology {
+                    format!("Failed to declare exchange '{}'", exchange_name)
+                } else {
+                    format!("Exchange '{}' does not exist and rabbitmq.manage_topology is disabled", exchange_name)
+                }
+            })?;
         
         Ok(Self {
             connection,
             channel,
             exchange_name,
+            routing_key_template: None,
+            confirm_timeout: None,
+            codec: Arc::new(JsonCodec),
+            default_headers: None,
         })
     }
     
+    // Producer for `exchange_name` using the connection, topology and confirm settings of `config`
+    // Publishing to the consumed exchange uses its configured kind, binding headers and
+    // `publish_routing_key_template`; any other exchange is a topic exchange
+    pub async fn from_config(config: &RabbitMQConfig, exchange_name: String) -> Result<Self> {
+        let is_source = exchange_name == config.exchange_name;
+        let kind = if is_source { exchange_kind(config.exchange_kind) } else { ExchangeKind::Topic };
+        // Checked before connecting so a typo fails at startup
+        let template = match &config.publish_routing_key_template {
+            Some(template) if is_source => Some(RoutingKeyTemplate::parse(template)?),
+            _ => None,
+        };
+        let mut producer = Self::new(&config.connection_string, exchange_name, kind, config.manage_topology).await?;
+        producer.routing_key_template = template;
+        if is_source {
+            producer.default_headers = config.binding_headers.as_ref().map(|binding| {
+                let mut headers = FieldTable::default();
+                for (key, value) in &binding.headers {
+                    headers.insert(key.as_str().into(), AMQPValue::LongString(value.as_str().into()));
+                }
+                headers
+            });
+        }
+        Ok(producer.with_confirm_timeout(Duration::from_millis(config.publish_confirm_timeout_ms)))
+    }
+    
+    // Fails a publish whose broker confirmation takes longer than `confirm_timeout`
+    pub fn with_confirm_timeout(mut self, confirm_timeout: Duration) -> Self {
+        self.confirm_timeout = Some(confirm_timeout);
+        self
+    }
+    
+    // Makes `send_sensor_data` route each reading by the template instead of the given key
+    pub fn with_routing_key_template(mut self, template: RoutingKeyTemplate) -> Self {
+        self.routing_key_template = Some(template);
+        self
+    }
+    
+    // Makes `send_sensor_data` publish readings in the codec's format instead of JSON
+    pub fn with_codec(mut self, codec: Arc<dyn PayloadCodec>) -> Self {
+        self.codec = codec;
+        self
+    }
+    
     pub async fn send_sensor_data(&self, routing_key: &str, sensor_data: &[SensorData]) -> Result<()> {
-        let payload = serde_json::to_vec(sensor_data)?;
+        let Some(template) = &self.routing_key_template else {
+            return self.publish_encoded(routing_key, sensor_data).await;
+        };
         
-        let confirm = self
+        // One message per rendered key
+        for (key, readings) in template.group(sensor_data, |data| (&data.r#type, &data.name)) {
+            let readings: Vec<SensorData> = readings.into_iter().cloned().collect();
+            self.publish_encoded(&key, &readings).await?;
+        }
+        Ok(())
+    }
+    
+    async fn publish_encoded(&self, routing_key: &str, readings: &[SensorData]) -> Result<()> {
+        let payload = self.codec.encode(readings)?;
+        let properties = BasicProperties::default().with_content_type(self.codec.content_type().into());
+        self.publish(routing_key, &payload, properties).await
+    }
+    
+    pub async fn publish_json<T: Serialize + ?Sized>(&self, routing_key: &str, message: &T) -> Result<()> {
+        let payload = serde_json::to_vec(message)?;
+        let properties = BasicProperties::default().with_content_type("application/json".into());
+        self.publish(routing_key, &payload, properties).await
+    }
+    
+    pub async fn publish(&self, routing_key: &str, payload: &[u8], mut properties: BasicProperties) -> Result<()> {
+        if let Some(defaults) = &self.default_headers {
+            let mut headers = properties.headers().clone().unwrap_or_default();
+            for (key, value) in defaults.inner() {
+                if !headers.inner().contains_key(key) {
+                    headers.insert(key.clone(), value.clone());
+                }
+            }
+            properties = properties.with_headers(headers);
+        }
+        let pending = self
             .channel
             .basic_publish(
                 &self.exchange_name,
                 routing_key,
                 BasicPublishOptions::default(),
-                &payload,
-                BasicProperties::default(),
+                payload,
+                properties,
             )
-            .await?
             .await?;
+        let target = format!("Publish to exchange {}", self.exchange_name);
+        if let Err(e) = await_confirm(pending, self.confirm_timeout, &target).await {
+            error!("{}", e);
+            return Err(e);
+        }
         
-        match confirm {
-            Confirmation::Ack(_) => {
-                debug!("Sensor data sent successfully to exchange: {} with routing key: {}", self.exchange_name, routing_key);
-                Ok(())
-            }
-            Confirmation::Nack(_) => {
-                error!("Sensor data was not acknowledged by RabbitMQ");
-                Err(anyhow::anyhow!("Sensor data was not acknowledged"))
-            }
-            Confirmation::NotRequested => {
-                debug!("Sensor data sent successfully to exchange: {} with routing key: {} (no confirmation requested)", self.exchange_name, routing_key);
-                Ok(())
-            }
+        debug!("Message sent successfully to exchange: {} with routing key: {}", self.exchange_name, routing_key);
+        Ok(())
+    }
+    
+    pub async fn close(&self) -> Result<()> {
+        self.connection.close(200, "Producer closed").await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Config, RetryJitter};
+    use crate::retry::RetryPolicy;
+    use lapin::acker::Acker;
+    
+    fn delivery(data: &[u8], properties: BasicProperties) -> Delivery {
+        Delivery {
+            delivery_tag: 1,
+            exchange: "sensors".into(),
+            routing_key: "sensor.temperature".into(),
+            redelivered: true,
+            properties,
+            data: data.to_vec(),
+            acker: Acker::default(),
         }
     }
+    
+    #[test]
+    fn rejects_settings_the_prefetch_window_cannot_reach() {
+        let mut config = Config::default().rabbitmq;
+        config.prefetch_count = 10;
+        config.max_in_flight_messages = 10;
+        config.ack_batch_size = 10;
+        assert!(check_prefetch(&config).is_ok());
+        
+        config.max_in_flight_messages = 11;
+        assert!(check_prefetch(&config).is_err());
+        config.max_in_flight_messages = 1;
+        config.ack_batch_size = 11;
+        assert!(check_prefetch(&config).is_err());
+        
+        // No window to exceed without a limit or in pull mode
+        config.prefetch_count = 0;
+        assert!(check_prefetch(&config).is_ok());
+        config.prefetch_count = 10;
+        config.delivery_mode = DeliveryMode::Pull;
+        assert!(check_prefetch(&config).is_ok());
+    }
+    
+    #[test]
+    fn rejects_unknown_or_unbalanced_placeholders() {
+        assert!(RoutingKeyTemplate::parse("processed.{type}.{name}").is_ok());
+        assert!(RoutingKeyTemplate::parse("processed.{sensor_type}").is_err());
+        assert!(RoutingKeyTemplate::parse("processed.{type").is_err());
+        assert!(RoutingKeyTemplate::parse("processed.type}").is_err());
+    }
+    
+    #[test]
+    fn groups_readings_by_rendered_key_in_order() {
+        let template = RoutingKeyTemplate::parse("processed.{type}").unwrap();
+        let readings = [("energy", "a"), ("motion", "b"), ("energy", "c")];
+        let groups = template.group(&readings, |(sensor_type, name)| (sensor_type, name));
+        
+        let keys: Vec<&str> = groups.iter().map(|(key, _)| key.as_str()).collect();
+        assert_eq!(keys, ["processed.energy", "processed.motion"]);
+        let names: Vec<&str> = groups[0].1.iter().map(|(_, name)| *name).collect();
+        assert_eq!(names, ["a", "c"]);
+    }
+    
+    #[test]
+    fn quarantines_a_poison_message_as_received() {
+        let poison = b"\xff{not json";
+        let properties = BasicProperties::default()
+            .with_message_id("m-1".into())
+            .with_content_type("application/json".into());
+        let message = quarantined_message(&delivery(poison, properties), "Failed to deserialize");
+        
+        assert_eq!(message.payload, poison);
+        assert_eq!(message.error, "Failed to deserialize");
+        assert_eq!(message.exchange, "sensors");
+        assert_eq!(message.routing_key, "sensor.temperature");
+        assert_eq!(message.message_id.as_deref(), Some("m-1"));
+        assert_eq!(message.content_type.as_deref(), Some("application/json"));
+        assert_eq!(message.attempt_count, 2);
+    }
+    
+    #[test]
+    fn fails_a_publish_the_broker_never_confirms_and_retries_it() {
+        tokio_test::block_on(async {
+            tokio::time::pause();
+            // A channel that never confirms: the pending confirmation never resolves
+            let never_confirmed = || std::future::pending::<lapin::Result<Confirmation>>();
+            let error = await_confirm(never_confirmed(), Some(Duration::from_millis(500)), "Publish to exchange sensors")
+                .await
+                .unwrap_err();
+            assert!(error.to_string().contains("was not confirmed within 500ms"), "{}", error);
+            
+            // Each timed-out attempt goes back to the retry policy, which gives up after its limit
+            let attempts = AtomicU64::new(0);
+            let retry = RetryPolicy::new(2, 100, RetryJitter::None);
+            let result = retry
+                .run("Publish to exchange sensors", || {
+                    attempts.fetch_add(1, Ordering::Relaxed);
+                    await_confirm(never_confirmed(), Some(Duration::from_millis(500)), "Publish to exchange sensors")
+                })
+                .await;
+            assert!(result.is_err());
+            assert_eq!(attempts.load(Ordering::Relaxed), 3);
+        });
+    }
+    
+    #[test]
+    fn only_an_ack_confirms_a_publish() {
+        tokio_test::block_on(async {
+            let confirm = |confirmation: Confirmation| async move { Ok(confirmation) };
+            assert!(await_confirm(confirm(Confirmation::Ack(None)), None, "Publish").await.is_ok());
+            assert!(await_confirm(confirm(Confirmation::Nack(None)), None, "Publish").await.is_err());
+            assert!(await_confirm(confirm(Confirmation::NotRequested), None, "Publish").await.is_err());
+        });
+    }
+    
+    // Needs a RabbitMQ broker; skipped unless TEST_RABBITMQ_URL is set
+    fn test_rabbitmq_url() -> Option<String> {
+        std::env::var("TEST_RABBITMQ_URL").ok()
+    }
+    
+    #[tokio::test]
+    async fn stops_consuming_once_idle_for_idle_shutdown() {
+        let Some(url) = test_rabbitmq_url() else {
+            return;
+        };
+        let name = format!("idle-test-{}", uuid::Uuid::new_v4());
+        let config = RabbitMQConfig {
+            connection_string: url,
+            exchange_name: name.clone(),
+            queue_name: name.clone(),
+            routing_key: name.clone(),
+            ..Config::default().rabbitmq
+        };
+        let mut consumer = RabbitMQConsumer::new(&config)
+            .await
+            .unwrap()
+            .with_idle_shutdown(Some(Duration::from_secs(2)));
+        let producer = RabbitMQProducer::from_config(&config, name.clone()).await.unwrap();
+        let reading = SensorData {
+            r#type: "energy".to_string(),
+            name: "meter-1".to_string(),
+            payload: serde_json::json!({ "energy": 1.5 }).into(),
+            timestamp: None,
+        };
+        for _ in 0..3 {
+            producer.send_sensor_data(&name, std::slice::from_ref(&reading)).await.unwrap();
+        }
+        
+        let handled = AtomicU64::new(0);
+        let consumed = timeout(
+            Duration::from_secs(30),
+            consumer.consume_messages(|_, _| {
+                handled.fetch_add(1, Ordering::Relaxed);
+                async { Ok(()) }
+            }),
+        )
+        .await;
+        assert!(consumed.expect("consume loop kept running while idle").is_ok());
+        assert_eq!(handled.load(Ordering::Relaxed), 3);
+        
+        consumer.channel.queue_delete(&name, QueueDeleteOptions::default()).await.unwrap();
+        consumer.channel.exchange_delete(&name, ExchangeDeleteOptions::default()).await.unwrap();
+        producer.close().await.unwrap();
+        consumer.close().await.unwrap();
+    }
 }
@@ -0,0 +1,359 @@
+use crate::config::RegistryEnrichmentConfig;
+use crate::database::Database;
+use crate::models::{GeoPoint, SensorReadingInput, SensorRegistryEntry};
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::Utc;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::RwLock;
+
+// When an entry was loaded, and the entry itself or None for an unregistered sensor
+type CachedEntry = (Instant, Option<Arc<SensorRegistryEntry>>);
+
+// Registry entries by sensor name, held for `ttl` and at most `max_entries` names. Names
+// come from producers, so without a bound a stream of unique names grows it forever.
+struct RegistryCache {
+    ttl: Duration,
+    max_entries: usize,
+    entries: HashMap<String, CachedEntry>,
+}
+
+impl RegistryCache {
+    fn new(ttl: Duration, max_entries: usize) -> Self {
+        Self { ttl, max_entries: max_entries.max(1), entries: HashMap::new() }
+    }
+    
+    // Some(entry or its absence) while fresh, None when it has to be loaded
+    fn get(&self, name: &str) -> Option<Option<Arc<SensorRegistryEntry>>> {
+        self.entries
+            .get(name)
+            .filter(|(cached_at, _)| cached_at.elapsed() < self.ttl)
+            .map(|(_, entry)| entry.clone())
+    }
+    
+    fn insert(&mut self, name: String, entry: Option<Arc<SensorRegistryEntry>>, now: Instant) {
+        if self.entries.len() >= self.max_entries && !self.entries.contains_key(&name) {
+            self.make_room();
+        }
+        self.entries.insert(name, (now, entry));
+    }
+    
+    // Drops expired entries, and the oldest ones if that isn't enough, down to 90% of the
+    // bound so a full cache isn't pruned again on every insert
+    fn make_room(&mut self) {
+        let ttl = self.ttl;
+        self.entries.retain(|_, (cached_at, _)| cached_at.elapsed() < ttl);
+        let keep = self.max_entries - self.max_entries.div_ceil(10);
+        if self.entries.len() <= keep {
+            return;
+        }
+        let mut ages: Vec<Instant> = self.entries.values().map(|(cached_at, _)| *cached_at).collect();
+        let cutoff_index = self.entries.len() - keep;
+        let (_, cutoff, _) = ages.select_nth_unstable(cutoff_index - 1);
+        let cutoff = *cutoff;
+        let mut excess = cutoff_index;
+        self.entries.retain(|_, (cached_at, _)| {
+            if excess > 0 && *cached_at <= cutoff {
+                excess -= 1;
+                false
+            } else {
+                true
+            }
+        });
+    }
+}
+
+// Attaches `sensor_registry` metadata to readings. Entries, including the absence of one,
+// are cached for `cache_ttl_seconds` so a message costs at most one lookup query. Registry
+// imports run in another process, so changes reach running enrichers as entries expire.
+pub struct RegistryEnricher {
+    database: Arc<Database>,
+    payload_field: String,
+    cache: RwLock<RegistryCache>,
+}
+
+impl RegistryEnricher {
+    pub fn new(database: Arc<Database>, config: &RegistryEnrichmentConfig) -> Self {
+        Self {
+            database,
+            payload_field: config.payload_field.clone(),
+            cache: RwLock::new(RegistryCache::new(
+                Duration::from_secs(config.cache_ttl_seconds),
+                config.cache_max_entries,
+            )),
+        }
+    }
+    
+    /// Adds each registered sensor's metadata under `payload_field` (unless the payload
+    /// already has that field) and its registry location to readings without one.
+    /// Returns how many readings were enriched.
+    pub async fn enrich(&self, readings: &mut [SensorReadingInput]) -> Result<usize> {
+        let entries = self.lookup(readings.iter().map(|reading| reading.sensor_name.as_str())).await?;
+        let mut enriched = 0;
+        for reading in readings {
+            let Some(entry) = entries.get(&reading.sensor_name) else {
+                continue;
+            };
+            if let Some(payload) = reading.payload.as_object_mut() {
+                payload
+                    .entry(self.payload_field.clone())
+                    .or_insert_with(|| entry.metadata.clone());
+            }
+            if reading.location.is_none() {
+                reading.location = entry.latitude.zip(entry.longitude).and_then(|(lat, lon)| GeoPoint::new(lat, lon));
+            }
+            enriched += 1;
+        }
+        Ok(enriched)
+    }
+    
+    async fn lookup<'a>(
+        &self,
+        names: impl Iterator<Item = &'a str>,
+    ) -> Result<HashMap<String, Arc<SensorRegistryEntry>>> {
+        let names: HashSet<&str> = names.collect();
+        let mut found = HashMap::new();
+        let mut missing = Vec::new();
+        {
+            let cache = self.cache.read().await;
+            for name in names {
+                match cache.get(name) {
+                    Some(Some(entry)) => {
+                        found.insert(name.to_string(), entry);
+                    }
+                    Some(None) => {}
+                    None => missing.push(name.to_string()),
+                }
+            }
+        }
+        if missing.is_empty() {
+            return Ok(found);
+        }
+        
+        let loaded = self.database.get_registry_entries(&missing).await?;
+        let mut loaded: HashMap<String, Arc<SensorRegistryEntry>> = loaded
+            .into_iter()
+            .map(|entry| (entry.sensor_name.clone(), Arc::new(entry)))
+            .collect();
+        let now = Instant::now();
+        let mut cache = self.cache.write().await;
+        for name in missing {
+            let entry = loaded.remove(&name);
+            if let Some(entry) = &entry {
+                found.insert(name.clone(), entry.clone());
+            }
+            cache.insert(name, entry, now);
+        }
+        Ok(found)
+    }
+}
+
+// Matches the `sensor_registry.sensor_name` column
+const MAX_SENSOR_NAME_LENGTH: usize = 255;
+
+// One entry of a JSON registry file
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RegistryRecord {
+    sensor_name: String,
+    #[serde(default)]
+    metadata: Map<String, Value>,
+    #[serde(default)]
+    latitude: Option<f64>,
+    #[serde(default)]
+    longitude: Option<f64>,
+}
+
+/// Reads registry entries from a `.csv` or `.json` file and validates every one of them,
+/// so an import either applies the whole file or nothing.
+///
+/// CSV files need a header row with a `sensor_name` column; `latitude` and `longitude` are
+/// optional, and every other non-empty column becomes a string metadata field. JSON files
+/// hold an array of `{"sensor_name", "metadata", "latitude", "longitude"}` objects.
+pub fn load_registry_file(path: &Path) -> Result<Vec<SensorRegistryEntry>> {
+    let records = match path.extension().and_then(|extension| extension.to_str()) {
+        Some("csv") => read_csv(path)?,
+        Some("json") => {
+            let file = std::fs::File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+            serde_json::from_reader::<_, Vec<RegistryRecord>>(std::io::BufReader::new(file))
+                .with_context(|| format!("Failed to parse {}", path.display()))?
+        }
+        _ => bail!("Unsupported registry file {}, expected a .csv or .json file", path.display()),
+    };
+    
+    let mut names = HashSet::new();
+    let mut entries = Vec::with_capacity(records.len());
+    for (i, record) in records.into_iter().enumerate() {
+        let entry = validate(record).with_context(|| format!("Invalid registry entry {}", i + 1))?;
+        if !names.insert(entry.sensor_name.clone()) {
+            bail!("Sensor '{}' appears more than once in {}", entry.sensor_name, path.display());
+        }
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+fn read_csv(path: &Path) -> Result<Vec<RegistryRecord>> {
+    let mut reader = csv::Reader::from_path(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    let headers = reader.headers()?.clone();
+    let column = |name: &str| headers.iter().position(|header| header == name);
+    let name_column = column("sensor_name").ok_or_else(|| anyhow!("{} has no sensor_name column", path.display()))?;
+    let (latitude_column, longitude_column) = (column("latitude"), column("longitude"));
+    
+    let mut records = Vec::new();
+    for (i, row) in reader.records().enumerate() {
+        let row = row?;
+        // Header is line 1
+        let line = i + 2;
+        let coordinate = |column: Option<usize>| -> Result<Option<f64>> {
+            match column.and_then(|column| row.get(column)).map(str::trim) {
+                None | Some("") => Ok(None),
+                Some(value) => value
+                    .parse()
+                    .map(Some)
+                    .with_context(|| format!("Line {}: '{}' is not a number", line, value)),
+            }
+        };
+        let metadata = headers
+            .iter()
+            .zip(row.iter())
+            .enumerate()
+            .filter(|(column, (_, value))| {
+                ![Some(name_column), latitude_column, longitude_column].contains(&Some(*column)) && !value.is_empty()
+            })
+            .map(|(_, (header, value))| (header.to_string(), Value::String(value.to_string())))
+            .collect();
+        records.push(RegistryRecord {
+            sensor_name: row.get(name_column).unwrap_or_default().trim().to_string(),
+            metadata,
+            latitude: coordinate(latitude_column)?,
+            longitude: coordinate(longitude_column)?,
+        });
+    }
+    Ok(records)
+}
+
+fn validate(record: RegistryRecord) -> Result<SensorRegistryEntry> {
+    if record.sensor_name.is_empty() {
+        bail!("sensor_name is required");
+    }
+    if record.sensor_name.len() > MAX_SENSOR_NAME_LENGTH {
+        bail!("sensor_name '{}' is longer than {} bytes", record.sensor_name, MAX_SENSOR_NAME_LENGTH);
+    }
+    match (record.latitude, record.longitude) {
+        (None, None) => {}
+        (Some(latitude), Some(longitude)) if GeoPoint::new(latitude, longitude).is_some() => {}
+        (Some(_), Some(_)) => bail!("location of '{}' is out of range", record.sensor_name),
+        _ => bail!("'{}' needs both latitude and longitude, or neither", record.sensor_name),
+    }
+    Ok(SensorRegistryEntry {
+        sensor_name: record.sensor_name,
+        metadata: Value::Object(record.metadata),
+        latitude: record.latitude,
+        longitude: record.longitude,
+        updated_at: Utc::now(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn entry(name: &str) -> Option<Arc<SensorRegistryEntry>> {
+        Some(Arc::new(SensorRegistryEntry {
+            sensor_name: name.to_string(),
+            metadata: Value::Object(Map::new()),
+            latitude: None,
+            longitude: None,
+            updated_at: Utc::now(),
+        }))
+    }
+    
+    #[test]
+    fn caches_entries_and_misses_until_they_expire() {
+        let mut cache = RegistryCache::new(Duration::from_secs(60), 10);
+        let now = Instant::now();
+        cache.insert("known".to_string(), entry("known"), now);
+        cache.insert("unknown".to_string(), None, now);
+        
+        assert!(matches!(cache.get("known"), Some(Some(_))));
+        assert!(matches!(cache.get("unknown"), Some(None)));
+        assert!(cache.get("other").is_none());
+        
+        cache.insert("stale".to_string(), entry("stale"), now - Duration::from_secs(61));
+        assert!(cache.get("stale").is_none());
+    }
+    
+    #[test]
+    fn stays_within_max_entries() {
+        let mut cache = RegistryCache::new(Duration::from_secs(3600), 100);
+        let start = Instant::now();
+        for i in 0..1000 {
+            cache.insert(format!("sensor-{}", i), None, start + Duration::from_millis(i));
+            assert!(cache.entries.len() <= 100);
+        }
+        // The newest names survive pruning
+        assert!(cache.get("sensor-999").is_some());
+        assert!(cache.get("sensor-0").is_none());
+    }
+    
+    #[test]
+    fn prunes_expired_entries_first() {
+        let mut cache = RegistryCache::new(Duration::from_secs(60), 3);
+        let now = Instant::now();
+        cache.insert("expired".to_string(), None, now - Duration::from_secs(120));
+        cache.insert("a".to_string(), None, now);
+        cache.insert("b".to_string(), None, now);
+        cache.insert("c".to_string(), None, now);
+        
+        assert!(!cache.entries.contains_key("expired"));
+        assert_eq!(cache.entries.len(), 3);
+    }
+    
+    // Writes `contents` to a fresh temp file with the given extension
+    fn registry_file(extension: &str, contents: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("registry-test-{}.{}", uuid::Uuid::new_v4(), extension));
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+    
+    #[test]
+    fn loads_csv_with_location_and_metadata_columns() {
+        let path = registry_file("csv", "sensor_name,site,latitude,longitude,floor\nmeter-1,plant-a,52.1,4.3,\nmeter-2,plant-b,,,3\n");
+        let entries = load_registry_file(&path);
+        std::fs::remove_file(&path).unwrap();
+        let entries = entries.unwrap();
+        
+        assert_eq!(entries.len(), 2);
+        assert_eq!((entries[0].latitude, entries[0].longitude), (Some(52.1), Some(4.3)));
+        // Empty cells are left out of the metadata
+        assert_eq!(entries[0].metadata, serde_json::json!({"site": "plant-a"}));
+        assert_eq!(entries[1].metadata, serde_json::json!({"site": "plant-b", "floor": "3"}));
+        assert_eq!(entries[1].latitude, None);
+    }
+    
+    #[test]
+    fn loads_json_and_rejects_invalid_entries() {
+        let path = registry_file("json", r#"[{"sensor_name": "meter-1", "metadata": {"site": "a"}}]"#);
+        let entries = load_registry_file(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(entries.unwrap()[0].sensor_name, "meter-1");
+        
+        for (extension, contents) in [
+            ("json", r#"[{"sensor_name": "a"}, {"sensor_name": "a"}]"#),
+            ("json", r#"[{"sensor_name": "a", "latitude": 52.1}]"#),
+            ("csv", "sensor_name,latitude,longitude\na,91,0\n"),
+            ("csv", "name\na\n"),
+            ("txt", "a"),
+        ] {
+            let path = registry_file(extension, contents);
+            let result = load_registry_file(&path);
+            std::fs::remove_file(&path).unwrap();
+            assert!(result.is_err(), "{} accepted: {}", extension, contents);
+        }
+    }
+}
@@ -0,0 +1,230 @@
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use crate::config::Config;
+use crate::models::{DeadLetterEnvelope, SensorData};
+use crate::rabbitmq::RabbitMQProducer;
+use crate::timestamp;
+use crate::transform;
+use lapin::{message::Delivery, options::*, types::FieldTable, BasicProperties, Connection, ConnectionProperties};
+use std::collections::BTreeMap;
+use tracing::{info, warn};
+
+#[derive(Debug, Clone, Default)]
+pub struct ReplayOptions {
+    pub limit: Option<u64>,
+    // Apply `processing.transforms` before republishing
+    pub transform: bool,
+    // Collect this many messages at a time and republish their readings in timestamp order
+    pub order_window: Option<usize>,
+}
+
+#[derive(Debug, Default)]
+pub struct ReplayReport {
+    pub replayed: u64,
+    pub transformed: u64,
+    pub skipped: u64,
+}
+
+impl ReplayReport {
+    pub fn print(&self) {
+        println!("DLQ replay results");
+        println!("  replayed:    {}", self.replayed);
+        println!("  transformed: {}", self.transformed);
+        println!("  skipped:     {}", self.skipped);
+    }
+}
+
+// Moves messages from the dead-letter queue back onto the main exchange. Messages that
+// cannot be replayed stay unacked and return to the DLQ when the channel closes.
+pub async fn run(config: &Config, options: ReplayOptions) -> Result<ReplayReport> {
+    let dead_letter = config
+        .rabbitmq
+        .dead_letter
+        .as_ref()
+        .ok_or_else(|| anyhow!("rabbitmq.dead_letter is not configured"))?;
+    
+    let connection = Connection::connect(&config.rabbitmq.connection_string, ConnectionProperties::default()).await?;
+    let channel = connection.create_channel().await?;
+    let producer = RabbitMQProducer::from_config(&config.rabbitmq, config.rabbitmq.exchange_name.clone()).await?;
+    
+    info!("Replaying messages from {} to {}", dead_letter.queue_name, config.rabbitmq.exchange_name);
+    let mut report = ReplayReport::default();
+    let mut window = Vec::new();
+    
+    while options.limit.is_none_or(|limit| report.replayed + report.skipped + (window.len() as u64) < limit) {
+        let Some(message) = channel.basic_get(&dead_letter.queue_name, BasicGetOptions::default()).await? else {
+            break;
+        };
+        let Some(message) = prepare(config, &options, message.delivery, &mut report) else {
+            continue;
+        };
+        
+        match options.order_window {
+            Some(size) => {
+                window.push(message);
+                if window.len() >= size.max(1) {
+                    publish_ordered(config, &producer, std::mem::take(&mut window), &mut report).await?;
+                }
+            }
+            None => {
+                if let Err(e) = producer.publish(&message.routing_key, &message.payload, message.properties).await {
+                    warn!("Leaving message in the DLQ, republish failed: {}", e);
+                    report.skipped += 1;
+                    continue;
+                }
+                message.delivery.ack(BasicAckOptions::default()).await?;
+                report.replayed += 1;
+            }
+        }
+    }
+    if !window.is_empty() {
+        publish_ordered(config, &producer, window, &mut report).await?;
+    }
+    
+    producer.close().await?;
+    connection.close(200, "DLQ replay finished").await?;
+    
+    Ok(report)
+}
+
+// A dead-lettered message ready to be republished
+struct ReplayMessage {
+    delivery: Delivery,
+    payload: Vec<u8>,
+    routing_key: String,
+    properties: BasicProperties,
+}
+
+// Unwraps the original message and applies the transforms; None when it has to stay in the DLQ
+fn prepare(config: &Config, options: &ReplayOptions, delivery: Delivery, report: &mut ReplayReport) -> Option<ReplayMessage> {
+    // Enriched envelopes carry the original body and routing key; raw messages are the body
+    let (mut payload, routing_key, mut properties) = match serde_json::from_slice::<DeadLetterEnvelope>(&delivery.data) {
+        Ok(envelope) => (
+            envelope.original_payload.into_bytes(),
+            envelope.source_routing_key,
+            BasicProperties::default().with_content_type("application/json".into()),
+        ),
+        Err(_) => (delivery.data.clone(), config.rabbitmq.routing_key.clone(), delivery.properties.clone()),
+    };
+    
+    if options.transform {
+        match transform_payload(config, &payload) {
+            Ok((transformed, changed)) => {
+                payload = transformed;
+                properties = properties.with_content_type("application/json".into());
+                if changed {
+                    report.transformed += 1;
+                }
+            }
+            Err(e) => {
+                warn!("Leaving message in the DLQ, transform failed: {}", e);
+                report.skipped += 1;
+                return None;
+            }
+        }
+    }
+    
+    Some(ReplayMessage { delivery, payload, routing_key, properties })
+}
+
+// A reading of an ordered window with the index of the message it came from
+struct OrderedReading {
+    timestamp: Option<DateTime<Utc>>,
+    message: usize,
+    reading: SensorData,
+}
+
+// Republishes a window of messages so that each sensor's readings arrive in timestamp
+// order. Readings are sorted per sensor_name and sent as runs of readings from the same
+// original message, keeping that message's routing key and properties. Readings without
+// a `timestamp` or `processing.timestamp_keys` timestamp are stamped on receipt, so they go last.
+async fn publish_ordered(
+    config: &Config,
+    producer: &RabbitMQProducer,
+    window: Vec<ReplayMessage>,
+    report: &mut ReplayReport,
+) -> Result<()> {
+    let mut failed = vec![false; window.len()];
+    let mut by_sensor: BTreeMap<String, Vec<OrderedReading>> = BTreeMap::new();
+    for (index, message) in window.iter().enumerate() {
+        let readings = match serde_json::from_slice::<Vec<SensorData>>(&message.payload) {
+            Ok(readings) => readings,
+            Err(_) => {
+                // Not a reading array, so there is nothing to order; send it as it was
+                if let Err(e) = producer.publish(&message.routing_key, &message.payload, message.properties.clone()).await {
+                    warn!("Leaving message in the DLQ, republish failed: {}", e);
+                    failed[index] = true;
+                }
+                continue;
+            }
+        };
+        for reading in readings {
+            let timestamp = reading.timestamp.or_else(|| {
+                config
+                    .processing
+                    .timestamp_keys
+                    .get(&reading.r#type)
+                    .and_then(|key| timestamp::extract(reading.payload.as_value(), key))
+            });
+            by_sensor.entry(reading.name.clone()).or_default().push(OrderedReading { timestamp, message: index, reading });
+        }
+    }
+    
+    let mut parts = vec![0usize; window.len()];
+    for mut readings in by_sensor.into_values() {
+        readings.sort_by_key(|ordered| (ordered.timestamp.is_none(), ordered.timestamp));
+        for run in readings.chunk_by(|a, b| a.message == b.message) {
+            let index = run[0].message;
+            let message = &window[index];
+            let body: Vec<&SensorData> = run.iter().map(|ordered| &ordered.reading).collect();
+            let properties = split_properties(&message.properties, parts[index]);
+            parts[index] += 1;
+            if let Err(e) = producer.publish(&message.routing_key, &serde_json::to_vec(&body)?, properties).await {
+                warn!("Leaving message in the DLQ, republish of its readings failed: {}", e);
+                failed[index] = true;
+            }
+        }
+    }
+    
+    for (message, failed) in window.into_iter().zip(failed) {
+        if failed {
+            report.skipped += 1;
+        } else {
+            message.delivery.ack(BasicAckOptions::default()).await?;
+            report.replayed += 1;
+        }
+    }
+    Ok(())
+}
+
+// Properties for the `part`-th message cut from one original message. Later parts get a
+// distinct message_id so `rabbitmq.message_dedup` does not drop them, and no part keeps
+// the logical batch headers, since none of them is the whole part any more.
+fn split_properties(properties: &BasicProperties, part: usize) -> BasicProperties {
+    let mut properties = properties.clone();
+    if part > 0 {
+        if let Some(id) = properties.message_id().clone() {
+            properties = properties.with_message_id(format!("{}-{}", id, part).into());
+        }
+    }
+    if let Some(headers) = properties.headers().clone() {
+        let mut headers = headers.inner().clone();
+        let removed = ["batch_id", "sequence", "total"]
+            .iter()
+            .filter(|name| headers.remove(**name).is_some())
+            .count();
+        if removed > 0 {
+            properties = properties.with_headers(FieldTable::from(headers));
+        }
+    }
+    properties
+}
+
+fn transform_payload(config: &Config, payload: &[u8]) -> Result<(Vec<u8>, bool)> {
+    let mut readings: Vec<SensorData> = serde_json::from_slice(payload)?;
+    let mut changed = false;
+    for reading in &mut readings {
+        changed |= transform::apply(&config.processing.transforms, reading) > 0;
+    }
+    Ok((serde_json::to_vec(&readings)?, changed))
+}
@@ -0,0 +1,89 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use crate::config::Config;
+use crate::database::Database;
+use crate::models::SensorData;
+use crate::transform::{self, SensorNameNormalizer};
+use tracing::{info, warn};
+
+#[derive(Debug, Clone)]
+pub struct ReprocessOptions {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    // Rows read and rewritten per transaction
+    pub batch_size: usize,
+    // Count the rows that would change without updating them
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct ReprocessReport {
+    pub scanned: u64,
+    pub changed: u64,
+    // Rows the non-finite policy would now reject; they are left as stored
+    pub rejected: u64,
+}
+
+impl ReprocessReport {
+    pub fn print(&self) {
+        println!("Reprocess results");
+        println!("  scanned: {}", self.scanned);
+        println!("  changed: {}", self.changed);
+        println!("  rejected: {}", self.rejected);
+    }
+}
+
+// Re-runs the ingest rewrite stages (see `transform::rewrite`) over stored readings in
+// [start, end) and rewrites the names and payloads they change, one page at a time.
+// Derived readings are not re-derived.
+pub async fn run(config: &Config, options: ReprocessOptions) -> Result<ReprocessReport> {
+    let processing = &config.processing;
+    let normalizer = SensorNameNormalizer::new(
+        processing.sensor_name_normalization,
+        &processing.sensor_name_rewrites,
+    )?;
+    let database = Database::new(&config.database).await?;
+    let page_size = options.batch_size.max(1) as i64;
+    
+    let mut report = ReprocessReport::default();
+    let mut after = None;
+    loop {
+        let page = database
+            .get_sensor_readings_page(options.start, options.end, after, page_size)
+            .await?;
+        let Some(last) = page.last() else {
+            break;
+        };
+        after = Some((last.timestamp, last.id));
+        report.scanned += page.len() as u64;
+        
+        let mut changed = Vec::new();
+        for mut reading in page {
+            let mut data = SensorData {
+                r#type: reading.sensor_type.clone(),
+                name: reading.sensor_name.clone(),
+                payload: reading.payload.clone().into(),
+                timestamp: Some(reading.timestamp),
+            };
+            if transform::rewrite(processing, &normalizer, &mut data).rejected {
+                warn!("Leaving {} reading {}: payload contains non-finite numbers", reading.sensor_type, reading.id);
+                report.rejected += 1;
+                continue;
+            }
+            if data.name != reading.sensor_name || data.payload.as_value() != &reading.payload {
+                reading.sensor_name = data.name;
+                reading.payload = data.payload.into_inner();
+                changed.push(reading);
+            }
+        }
+        
+        if options.dry_run {
+            report.changed += changed.len() as u64;
+        } else if !changed.is_empty() {
+            report.changed += database.update_readings(&changed).await?;
+        }
+        info!("Reprocessed {} readings, {} changed", report.scanned, report.changed);
+    }
+    
+    Ok(report)
+}
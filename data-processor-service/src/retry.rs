@@ -0,0 +1,110 @@
+use anyhow::Result;
+use crate::config::RetryJitter;
+use rand::Rng;
+use std::future::Future;
+use std::time::Duration;
+use tracing::warn;
+
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    pub retry_attempts: u32,
+    pub retry_delay: Duration,
+    pub jitter: RetryJitter,
+}
+
+impl RetryPolicy {
+    pub fn new(retry_attempts: u32, retry_delay_ms: u64, jitter: RetryJitter) -> Self {
+        Self {
+            retry_attempts,
+            retry_delay: Duration::from_millis(retry_delay_ms),
+            jitter,
+        }
+    }
+    
+    pub fn delay(&self) -> Duration {
+        jittered_delay(self.retry_delay, self.jitter)
+    }
+    
+    /// Runs `operation`, retrying up to `retry_attempts` more times with a jittered delay.
+    pub async fn run<T, F, Fut>(&self, operation_name: &str, mut operation: F) -> Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let mut attempt = 0;
+        loop {
+            match operation().await {
+                Ok(value) => return Ok(value),
+                Err(e) if attempt < self.retry_attempts => {
+                    attempt += 1;
+                    let delay = self.delay();
+                    warn!(
+                        "{} failed (retry {}/{} in {:?}): {}",
+                        operation_name, attempt, self.retry_attempts, delay, e
+                    );
+                    tokio::time::sleep(delay).await;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+pub fn jittered_delay(base: Duration, jitter: RetryJitter) -> Duration {
+    let base_ms = base.as_millis() as u64;
+    if base_ms == 0 {
+        return base;
+    }
+    
+    let mut rng = rand::thread_rng();
+    let delay_ms = match jitter {
+        RetryJitter::None => base_ms,
+        RetryJitter::Full => rng.gen_range(0..=base_ms),
+        RetryJitter::Equal => base_ms / 2 + rng.gen_range(0..=base_ms - base_ms / 2),
+    };
+    Duration::from_millis(delay_ms)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn jitter_stays_within_its_range() {
+        let base = Duration::from_millis(1000);
+        assert_eq!(jittered_delay(base, RetryJitter::None), base);
+        assert_eq!(jittered_delay(Duration::ZERO, RetryJitter::Full), Duration::ZERO);
+        for _ in 0..1000 {
+            assert!(jittered_delay(base, RetryJitter::Full) <= base);
+            let equal = jittered_delay(base, RetryJitter::Equal);
+            assert!(equal >= base / 2 && equal <= base);
+        }
+    }
+    
+    #[test]
+    fn retries_up_to_the_attempt_limit() {
+        tokio_test::block_on(async {
+            tokio::time::pause();
+            let policy = RetryPolicy::new(2, 100, RetryJitter::None);
+            let mut calls = 0;
+            let result: Result<()> = policy
+                .run("test", || {
+                    calls += 1;
+                    async { anyhow::bail!("down") }
+                })
+                .await;
+            assert!(result.is_err());
+            assert_eq!(calls, 3);
+            
+            let mut calls = 0;
+            let result = policy
+                .run("test", || {
+                    calls += 1;
+                    let succeed = calls == 2;
+                    async move { if succeed { Ok(calls) } else { anyhow::bail!("down") } }
+                })
+                .await;
+            assert_eq!(result.unwrap(), 2);
+        });
+    }
+}
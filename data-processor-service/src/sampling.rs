@@ -0,0 +1,84 @@
+use crate::config::OverloadSamplingConfig;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use tracing::{info, warn};
+
+// Drops a share of low-priority readings while the queue backlog is above the threshold
+pub struct OverloadSampler {
+    config: OverloadSamplingConfig,
+    queue_depth: Arc<AtomicU64>,
+    engaged: AtomicBool,
+}
+
+impl OverloadSampler {
+    pub fn new(config: OverloadSamplingConfig, queue_depth: Arc<AtomicU64>) -> anyhow::Result<Self> {
+        if !(0.0..=100.0).contains(&config.drop_percent) {
+            anyhow::bail!(
+                "processing.overload_sampling.drop_percent must be between 0 and 100, got {}",
+                config.drop_percent
+            );
+        }
+        Ok(Self {
+            config,
+            queue_depth,
+            engaged: AtomicBool::new(false),
+        })
+    }
+    
+    /// Whether the backlog currently exceeds `overload_queue_depth`; logs each change.
+    pub fn engaged(&self) -> bool {
+        let depth = self.queue_depth.load(Ordering::Relaxed);
+        let engaged = depth > self.config.overload_queue_depth;
+        if self.engaged.swap(engaged, Ordering::Relaxed) != engaged {
+            if engaged {
+                warn!(
+                    "Queue depth {} above {}, sampling {}% of {:?} readings",
+                    depth, self.config.overload_queue_depth, self.config.drop_percent, self.config.sensor_types
+                );
+            } else {
+                info!("Queue depth {} back under {}, sampling disengaged", depth, self.config.overload_queue_depth);
+            }
+        }
+        engaged
+    }
+    
+    // Randomly selects `drop_percent` of the readings of the configured types
+    pub fn drops(&self, sensor_type: &str) -> bool {
+        self.config.sensor_types.iter().any(|t| t == sensor_type)
+            && rand::random::<f64>() * 100.0 < self.config.drop_percent
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn sampler(drop_percent: f64, queue_depth: Arc<AtomicU64>) -> anyhow::Result<OverloadSampler> {
+        let config = OverloadSamplingConfig {
+            overload_queue_depth: 1000,
+            drop_percent,
+            sensor_types: vec!["motion".to_string()],
+        };
+        OverloadSampler::new(config, queue_depth)
+    }
+    
+    #[test]
+    fn engages_only_above_the_queue_depth() {
+        let depth = Arc::new(AtomicU64::new(1000));
+        let sampler = sampler(50.0, depth.clone()).unwrap();
+        assert!(!sampler.engaged());
+        depth.store(1001, Ordering::Relaxed);
+        assert!(sampler.engaged());
+        depth.store(10, Ordering::Relaxed);
+        assert!(!sampler.engaged());
+    }
+    
+    #[test]
+    fn drops_only_configured_types_at_the_configured_share() {
+        let depth = Arc::new(AtomicU64::new(0));
+        assert!((0..1000).all(|_| sampler(100.0, depth.clone()).unwrap().drops("motion")));
+        assert!(!(0..1000).any(|_| sampler(0.0, depth.clone()).unwrap().drops("motion")));
+        assert!(!(0..1000).any(|_| sampler(100.0, depth.clone()).unwrap().drops("energy")));
+        assert!(sampler(101.0, depth).is_err());
+    }
+}
@@ -0,0 +1,160 @@
+use crate::database::Database;
+use anyhow::Result;
+
+// Column the code expects in `sensor_readings`, in `information_schema` terms
+#[derive(Debug, Clone, Copy)]
+pub struct ExpectedColumn {
+    pub name: &'static str,
+    pub data_type: &'static str,
+    pub max_length: Option<i32>,
+    pub nullable: bool,
+    // Type and constraints used when generating an `ADD COLUMN`
+    pub definition: &'static str,
+}
+
+// Keep in step with `SensorReading` and the migrations
+pub const SENSOR_READINGS_COLUMNS: &[ExpectedColumn] = &[
+    ExpectedColumn { name: "id", data_type: "uuid", max_length: None, nullable: false, definition: "UUID NOT NULL DEFAULT uuid_generate_v4()" },
+    ExpectedColumn { name: "sensor_type", data_type: "character varying", max_length: Some(100), nullable: false, definition: "VARCHAR(100) NOT NULL" },
+    ExpectedColumn { name: "sensor_name", data_type: "character varying", max_length: Some(255), nullable: false, definition: "VARCHAR(255) NOT NULL" },
+    ExpectedColumn { name: "payload", data_type: "jsonb", max_length: None, nullable: false, definition: "JSONB NOT NULL" },
+    ExpectedColumn { name: "timestamp", data_type: "timestamp with time zone", max_length: None, nullable: false, definition: "TIMESTAMPTZ NOT NULL" },
+    ExpectedColumn { name: "created_at", data_type: "timestamp with time zone", max_length: None, nullable: false, definition: "TIMESTAMPTZ NOT NULL DEFAULT NOW()" },
+    ExpectedColumn { name: "source_id", data_type: "character varying", max_length: Some(255), nullable: true, definition: "VARCHAR(255)" },
+    ExpectedColumn { name: "payload_hash", data_type: "character", max_length: Some(64), nullable: true, definition: "CHAR(64)" },
+    ExpectedColumn { name: "hash_scope", data_type: "character varying", max_length: Some(16), nullable: true, definition: "VARCHAR(16)" },
+    ExpectedColumn { name: "latitude", data_type: "double precision", max_length: None, nullable: true, definition: "DOUBLE PRECISION" },
+    ExpectedColumn { name: "longitude", data_type: "double precision", max_length: None, nullable: true, definition: "DOUBLE PRECISION" },
+];
+
+// Column as found in the live database
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct LiveColumn {
+    pub name: String,
+    pub data_type: String,
+    pub max_length: Option<i32>,
+    pub nullable: bool,
+}
+
+#[derive(Debug, Clone)]
+pub enum ColumnDifference {
+    Missing(ExpectedColumn),
+    Unexpected(LiveColumn),
+    TypeMismatch { expected: ExpectedColumn, live: LiveColumn },
+    NullabilityMismatch { expected: ExpectedColumn, live: LiveColumn },
+}
+
+#[derive(Debug)]
+pub struct SchemaDiff {
+    pub table: String,
+    pub differences: Vec<ColumnDifference>,
+}
+
+impl SchemaDiff {
+    pub fn print(&self) {
+        println!("Schema diff for {}", self.table);
+        if self.differences.is_empty() {
+            println!("  no differences");
+        }
+        for difference in &self.differences {
+            match difference {
+                ColumnDifference::Missing(expected) => {
+                    println!("  missing column {} ({})", expected.name, expected.definition)
+                }
+                ColumnDifference::Unexpected(live) => {
+                    println!("  unexpected column {} ({})", live.name, describe(&live.data_type, live.max_length))
+                }
+                ColumnDifference::TypeMismatch { expected, live } => println!(
+                    "  column {} is {}, expected {}",
+                    live.name,
+                    describe(&live.data_type, live.max_length),
+                    describe(expected.data_type, expected.max_length)
+                ),
+                ColumnDifference::NullabilityMismatch { expected, live } => println!(
+                    "  column {} is {}, expected {}",
+                    live.name,
+                    nullability(live.nullable),
+                    nullability(expected.nullable)
+                ),
+            }
+        }
+    }
+    
+    /// SQL skeleton that would bring the live table in line with the code. Drops and type
+    /// changes are left commented out since they can lose data.
+    pub fn migration_sql(&self) -> String {
+        let mut sql = String::from("-- Migration: TODO\n-- Description: TODO\n\n");
+        for difference in &self.differences {
+            let statement = match difference {
+                ColumnDifference::Missing(expected) => format!(
+                    "ALTER TABLE {} ADD COLUMN IF NOT EXISTS {} {};",
+                    self.table, expected.name, expected.definition
+                ),
+                ColumnDifference::Unexpected(live) => {
+                    format!("-- ALTER TABLE {} DROP COLUMN {};", self.table, live.name)
+                }
+                ColumnDifference::TypeMismatch { expected, live } => format!(
+                    "-- ALTER TABLE {} ALTER COLUMN {} TYPE {};",
+                    self.table,
+                    live.name,
+                    describe(expected.data_type, expected.max_length)
+                ),
+                ColumnDifference::NullabilityMismatch { expected, live } => format!(
+                    "ALTER TABLE {} ALTER COLUMN {} {} NOT NULL;",
+                    self.table,
+                    live.name,
+                    if expected.nullable { "DROP" } else { "SET" }
+                ),
+            };
+            sql.push_str(&statement);
+            sql.push('\n');
+        }
+        sql
+    }
+}
+
+/// Compares the live columns of `sensor_readings` with `SENSOR_READINGS_COLUMNS`.
+pub async fn diff_sensor_readings(database: &Database) -> Result<SchemaDiff> {
+    let live = database.table_columns("sensor_readings").await?;
+    Ok(SchemaDiff {
+        table: "sensor_readings".to_string(),
+        differences: diff(SENSOR_READINGS_COLUMNS, &live),
+    })
+}
+
+pub fn diff(expected: &[ExpectedColumn], live: &[LiveColumn]) -> Vec<ColumnDifference> {
+    let mut differences = Vec::new();
+    for column in expected {
+        match live.iter().find(|live| live.name == column.name) {
+            None => differences.push(ColumnDifference::Missing(*column)),
+            Some(live) if live.data_type != column.data_type || live.max_length != column.max_length => {
+                differences.push(ColumnDifference::TypeMismatch { expected: *column, live: live.clone() })
+            }
+            Some(live) if live.nullable != column.nullable => {
+                differences.push(ColumnDifference::NullabilityMismatch { expected: *column, live: live.clone() })
+            }
+            Some(_) => {}
+        }
+    }
+    for column in live {
+        if !expected.iter().any(|expected| expected.name == column.name) {
+            differences.push(ColumnDifference::Unexpected(column.clone()));
+        }
+    }
+    differences
+}
+
+fn describe(data_type: &str, max_length: Option<i32>) -> String {
+    match max_length {
+        Some(length) => format!("{}({})", data_type, length),
+        None => data_type.to_string(),
+    }
+}
+
+fn nullability(nullable: bool) -> &'static str {
+    if nullable {
+        "nullable"
+    } else {
+        "NOT NULL"
+    }
+}
@@ -0,0 +1,402 @@
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use crate::config::{Config, SinkConfig, SinkKind, TeeMode};
+use crate::database::Database;
+use crate::encryption::FieldEncryptor;
+use crate::models::SensorReadingInput;
+use crate::rabbitmq::{RabbitMQProducer, RoutingKeyTemplate};
+use crate::retry::RetryPolicy;
+use crate::tls_check;
+use sqlx::postgres::PgPoolOptions;
+use sqlx::{Executor, PgPool, Postgres, QueryBuilder};
+use std::sync::Arc;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+use tracing::{error, warn};
+
+// Destination for processed batches
+#[async_trait]
+pub trait Sink: Send + Sync {
+    fn name(&self) -> &str;
+    async fn write(&self, batch: &[SensorReadingInput]) -> Result<()>;
+}
+
+pub struct PostgresSink {
+    database: Arc<Database>,
+}
+
+#[async_trait]
+impl Sink for PostgresSink {
+    fn name(&self) -> &str {
+        "postgres"
+    }
+    
+    // Safe to retry: the batch insert is one transaction, so a failed attempt stores none
+    // of the batch and the next attempt cannot duplicate its earlier statement chunks
+    async fn write(&self, batch: &[SensorReadingInput]) -> Result<()> {
+        self.database.insert_batch_sensor_readings(batch.to_vec()).await?;
+        Ok(())
+    }
+}
+
+pub struct ExchangeSink {
+    name: String,
+    producer: RabbitMQProducer,
+    routing_key: String,
+    routing_key_template: Option<RoutingKeyTemplate>,
+}
+
+#[async_trait]
+impl Sink for ExchangeSink {
+    fn name(&self) -> &str {
+        &self.name
+    }
+    
+    async fn write(&self, batch: &[SensorReadingInput]) -> Result<()> {
+        let Some(template) = &self.routing_key_template else {
+            return self.producer.publish_json(&self.routing_key, batch).await;
+        };
+        
+        for (key, readings) in template.group(batch, |reading| (&reading.sensor_type, &reading.sensor_name)) {
+            self.producer.publish_json(&key, &readings).await?;
+        }
+        Ok(())
+    }
+}
+
+pub struct FileSink {
+    name: String,
+    file: Mutex<tokio::fs::File>,
+}
+
+#[async_trait]
+impl Sink for FileSink {
+    fn name(&self) -> &str {
+        &self.name
+    }
+    
+    async fn write(&self, batch: &[SensorReadingInput]) -> Result<()> {
+        let mut lines = Vec::new();
+        for reading in batch {
+            serde_json::to_writer(&mut lines, reading)?;
+            lines.push(b'\n');
+        }
+        let mut file = self.file.lock().await;
+        file.write_all(&lines).await?;
+        file.flush().await?;
+        Ok(())
+    }
+}
+
+pub struct StdoutSink {
+    stdout: Mutex<tokio::io::Stdout>,
+}
+
+#[async_trait]
+impl Sink for StdoutSink {
+    fn name(&self) -> &str {
+        "stdout"
+    }
+    
+    async fn write(&self, batch: &[SensorReadingInput]) -> Result<()> {
+        let mut lines = Vec::new();
+        for reading in batch {
+            serde_json::to_writer(&mut lines, reading)?;
+            lines.push(b'\n');
+        }
+        let mut stdout = self.stdout.lock().await;
+        stdout.write_all(&lines).await?;
+        stdout.flush().await?;
+        Ok(())
+    }
+}
+
+// Time-series sink: numeric payload fields become (time, sensor, field, value) rows
+pub struct TimescaleSink {
+    pool: PgPool,
+}
+
+impl TimescaleSink {
+    pub async fn connect(url: &str, max_connections: u32) -> Result<Self> {
+        let pool = PgPoolOptions::new().max_connections(max_connections).connect(url).await?;
+        pool.execute(include_str!("../migrations_timescale/001_sensor_metrics_hypertable.sql")).await?;
+        Ok(Self { pool })
+    }
+}
+
+#[async_trait]
+impl Sink for TimescaleSink {
+    fn name(&self) -> &str {
+        "timescale"
+    }
+    
+    async fn write(&self, batch: &[SensorReadingInput]) -> Result<()> {
+        let rows: Vec<(&SensorReadingInput, &String, f64)> = batch
+            .iter()
+            .filter_map(|reading| reading.payload.as_object().map(|fields| (reading, fields)))
+            .flat_map(|(reading, fields)| {
+                fields
+                    .iter()
+                    .filter_map(move |(field, value)| value.as_f64().map(|value| (reading, field, value)))
+            })
+            .collect();
+        
+        // Stay under the 65535 bind parameter limit, five per row
+        for chunk in rows.chunks(10_000) {
+            let mut query = QueryBuilder::<Postgres>::new(
+                "INSERT INTO sensor_metrics (time, sensor_type, sensor_name, field, value) ",
+            );
+            query.push_values(chunk, |mut row, (reading, field, value)| {
+                row.push_bind(reading.timestamp)
+                    .push_bind(&reading.sensor_type)
+                    .push_bind(&reading.sensor_name)
+                    .push_bind(*field)
+                    .push_bind(*value);
+            });
+            query.build().execute(&self.pool).await?;
+        }
+        Ok(())
+    }
+}
+
+struct ConfiguredSink {
+    sink: Box<dyn Sink>,
+    primary: bool,
+    // False for the postgres sink, which encrypts the configured fields as it inserts
+    seal: bool,
+}
+
+// `sinks` (or just postgres) with `processing.tee` applied
+fn configured_sinks(config: &Config) -> Result<Vec<SinkConfig>> {
+    let mut configured = if config.sinks.is_empty() {
+        vec![SinkConfig { kind: SinkKind::Postgres, primary: true }]
+    } else {
+        config.sinks.clone()
+    };
+    // A second stdout sink would print every reading twice
+    if config.processing.tee != TeeMode::None && configured.iter().any(|sink| matches!(sink.kind, SinkKind::Stdout)) {
+        anyhow::bail!("processing.tee can't be combined with a stdout sink in `sinks`; use one or the other");
+    }
+    match config.processing.tee {
+        TeeMode::None => {}
+        // Stands in for the database, so a broken pipe fails the message
+        TeeMode::Stdout => {
+            configured.retain(|sink| !matches!(sink.kind, SinkKind::Postgres));
+            configured.push(SinkConfig { kind: SinkKind::Stdout, primary: true });
+        }
+        TeeMode::Both => configured.push(SinkConfig { kind: SinkKind::Stdout, primary: false }),
+    }
+    Ok(configured)
+}
+
+// The configured sinks, written in order for every batch
+pub struct SinkSet {
+    sinks: Vec<ConfiguredSink>,
+    // `database.field_encryption`, applied to what every other sink receives
+    encryptor: Option<FieldEncryptor>,
+}
+
+// Keeps every batch it is given, standing in for real sinks in tests
+#[cfg(test)]
+#[derive(Clone, Default)]
+pub(crate) struct CaptureSink {
+    pub(crate) batches: Arc<std::sync::Mutex<Vec<Vec<SensorReadingInput>>>>,
+}
+
+#[cfg(test)]
+#[async_trait]
+impl Sink for CaptureSink {
+    fn name(&self) -> &str {
+        "capture"
+    }
+    
+    async fn write(&self, batch: &[SensorReadingInput]) -> Result<()> {
+        self.batches.lock().unwrap().push(batch.to_vec());
+        Ok(())
+    }
+}
+
+impl SinkSet {
+    // Writes to `sinks`, all primary, with nothing encrypted
+    #[cfg(test)]
+    pub(crate) fn of(sinks: Vec<Box<dyn Sink>>) -> Self {
+        let sinks = sinks
+            .into_iter()
+            .map(|sink| ConfiguredSink { sink, primary: true, seal: false })
+            .collect();
+        Self { sinks, encryptor: None }
+    }
+    
+    pub async fn from_config(config: &Config, database: Arc<Database>) -> Result<Self> {
+        let configured = configured_sinks(config)?;
+        if !configured.iter().any(|sink| sink.primary) {
+            warn!("No sink is marked primary; sink failures will never fail a message");
+        }
+        
+        let mut sinks = Vec::with_capacity(configured.len());
+        for sink_config in configured {
+            let seal = !matches!(sink_config.kind, SinkKind::Postgres);
+            let sink: Box<dyn Sink> = match sink_config.kind {
+                SinkKind::Postgres => Box::new(PostgresSink { database: database.clone() }),
+                SinkKind::Exchange { exchange_name, routing_key, routing_key_template } => {
+                    // Validate the template before connecting so a typo fails at startup
+                    let routing_key_template = routing_key_template
+                        .as_deref()
+                        .map(RoutingKeyTemplate::parse)
+                        .transpose()?;
+                    if routing_key_template.is_none() && routing_key.is_empty() {
+                        anyhow::bail!(
+                            "Exchange sink '{}' needs a routing_key or routing_key_template",
+                            exchange_name
+                        );
+                    }
+                    Box::new(ExchangeSink {
+                        name: format!("exchange:{}", exchange_name),
+                        producer: RabbitMQProducer::from_config(&config.rabbitmq, exchange_name).await?,
+                        routing_key,
+                        routing_key_template,
+                    })
+                }
+                SinkKind::Timescale { url, max_connections } => {
+                    let sink = TimescaleSink::connect(&url, max_connections).await?;
+                    tls_check::check_database(&sink.pool, "Timescale", &url, &config.tls_check).await?;
+                    Box::new(sink)
+                }
+                SinkKind::File { path } => {
+                    let file = tokio::fs::OpenOptions::new()
+                        .create(true)
+                        .append(true)
+                        .open(&path)
+                        .await?;
+                    Box::new(FileSink { name: format!("file:{}", path), file: Mutex::new(file) })
+                }
+                SinkKind::Stdout => Box::new(StdoutSink { stdout: Mutex::new(tokio::io::stdout()) }),
+            };
+            sinks.push(ConfiguredSink { sink, primary: sink_config.primary, seal });
+        }
+        let encryptor = config
+            .database
+            .field_encryption
+            .as_ref()
+            .map(FieldEncryptor::from_config)
+            .transpose()?;
+        
+        Ok(Self { sinks, encryptor })
+    }
+    
+    // Writes the batch to every sink with retries; errors only if a primary sink failed,
+    // with each such sink's last error. Sinks that succeeded are not remembered, so a
+    // redelivered or replayed batch reaches them again: delivery is at-least-once per sink
+    pub async fn write(&self, batch: &[SensorReadingInput], retry: &RetryPolicy) -> Result<()> {
+        let sealed = self.seal(batch)?;
+        let mut failed_primaries = Vec::new();
+        for configured in &self.sinks {
+            let sink = &configured.sink;
+            let batch = match (&sealed, configured.seal) {
+                (Some(sealed), true) => sealed.as_slice(),
+                _ => batch,
+            };
+            let operation = format!("Write to {} sink", sink.name());
+            if let Err(e) = retry.run(&operation, || sink.write(batch)).await {
+                if configured.primary {
+                    error!("Primary sink {} failed: {}", sink.name(), e);
+                    failed_primaries.push(format!("{}: {}", sink.name(), e));
+                } else {
+                    warn!("Sink {} failed, continuing: {}", sink.name(), e);
+                }
+            }
+        }
+        
+        if failed_primaries.is_empty() {
+            Ok(())
+        } else {
+            Err(anyhow!("Primary sink(s) failed: {}", failed_primaries.join("; ")))
+        }
+    }
+    
+    // The batch with its sensitive fields encrypted, when any sink besides postgres needs it
+    fn seal(&self, batch: &[SensorReadingInput]) -> Result<Option<Vec<SensorReadingInput>>> {
+        let Some(encryptor) = &self.encryptor else {
+            return Ok(None);
+        };
+        if !self.sinks.iter().any(|configured| configured.seal) {
+            return Ok(None);
+        }
+        let mut sealed = batch.to_vec();
+        for reading in &mut sealed {
+            encryptor.encrypt_payload(&reading.sensor_type, &mut reading.payload)?;
+        }
+        Ok(Some(sealed))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{FieldEncryptionConfig, RetryJitter};
+    use std::collections::HashMap;
+    
+    #[test]
+    fn other_sinks_receive_encrypted_fields() {
+        let batches = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let encryptor = FieldEncryptor::from_config(&FieldEncryptionConfig {
+            key_id: "test".to_string(),
+            key: Some("A".repeat(43) + "="),
+            key_env: None,
+            fields: HashMap::from([("patient".to_string(), vec!["ssn".to_string()])]),
+        })
+        .unwrap();
+        let sinks = SinkSet {
+            sinks: vec![ConfiguredSink {
+                sink: Box::new(CaptureSink { batches: batches.clone() }),
+                primary: true,
+                seal: true,
+            }],
+            encryptor: Some(encryptor),
+        };
+        let reading = SensorReadingInput {
+            sensor_type: "patient".to_string(),
+            sensor_name: "ward-1".to_string(),
+            payload: serde_json::json!({ "ssn": "123-45-6789", "pulse": 72 }),
+            timestamp: chrono::Utc::now(),
+            source_id: None,
+            location: None,
+        };
+        
+        tokio_test::block_on(sinks.write(&[reading], &RetryPolicy::new(0, 0, RetryJitter::None))).unwrap();
+        let batches = batches.lock().unwrap();
+        let payload = &batches[0][0].payload;
+        assert!(payload["ssn"].as_str().unwrap().starts_with("enc:v1:test:"), "{}", payload);
+        assert_eq!(payload["pulse"], 72);
+    }
+    
+    #[test]
+    fn tee_adds_or_replaces_with_a_stdout_sink() {
+        let mut config = Config::default();
+        config.processing.tee = TeeMode::Both;
+        let sinks = configured_sinks(&config).unwrap();
+        assert!(matches!(sinks[..], [
+            SinkConfig { kind: SinkKind::Postgres, primary: true },
+            SinkConfig { kind: SinkKind::Stdout, primary: false },
+        ]));
+        
+        config.processing.tee = TeeMode::Stdout;
+        let sinks = configured_sinks(&config).unwrap();
+        assert!(matches!(sinks[..], [SinkConfig { kind: SinkKind::Stdout, primary: true }]));
+    }
+    
+    #[test]
+    fn rejects_tee_with_a_stdout_sink() {
+        let mut config = Config {
+            sinks: vec![
+                SinkConfig { kind: SinkKind::Postgres, primary: true },
+                SinkConfig { kind: SinkKind::Stdout, primary: false },
+            ],
+            ..Config::default()
+        };
+        assert!(configured_sinks(&config).is_ok());
+        
+        config.processing.tee = TeeMode::Both;
+        assert!(configured_sinks(&config).is_err());
+    }
+}
@@ -0,0 +1,38 @@
+use chrono::{DateTime, SubsecRound, TimeZone, Utc};
+use crate::config::TimestampPrecision;
+use serde_json::Value;
+
+// Epoch values above this are taken as milliseconds (1e11 seconds is the year 5138)
+const EPOCH_MILLIS_THRESHOLD: f64 = 1e11;
+
+/// Reads the reading time from `payload[key]`: an RFC 3339 string, or epoch seconds
+/// or milliseconds as a number or numeric string.
+pub fn extract(payload: &Value, key: &str) -> Option<DateTime<Utc>> {
+    match payload.get(key)? {
+        Value::String(s) => DateTime::parse_from_rfc3339(s.trim())
+            .map(|t| t.with_timezone(&Utc))
+            .ok()
+            .or_else(|| s.trim().parse::<f64>().ok().and_then(from_epoch)),
+        Value::Number(n) => n.as_f64().and_then(from_epoch),
+        _ => None,
+    }
+}
+
+fn from_epoch(value: f64) -> Option<DateTime<Utc>> {
+    if !value.is_finite() {
+        return None;
+    }
+    let millis = if value.abs() >= EPOCH_MILLIS_THRESHOLD { value } else { value * 1000.0 };
+    Utc.timestamp_millis_opt(millis.round() as i64).single()
+}
+
+/// Drops sub-second digits below `precision`, so the same reading sent with differing
+/// precision gets one timestamp.
+pub fn truncate(timestamp: DateTime<Utc>, precision: TimestampPrecision) -> DateTime<Utc> {
+    let digits = match precision {
+        TimestampPrecision::Micros => 6,
+        TimestampPrecision::Millis => 3,
+        TimestampPrecision::Secs => 0,
+    };
+    timestamp.trunc_subsecs(digits)
+}
@@ -0,0 +1,69 @@
+use anyhow::{anyhow, Result};
+use crate::config::TlsCheckConfig;
+use lapin::uri::{AMQPScheme, AMQPUri};
+use sqlx::postgres::PgConnectOptions;
+use sqlx::PgPool;
+use std::net::IpAddr;
+use std::str::FromStr;
+use tracing::{info, warn};
+
+// TLS state of the current session as the PostgreSQL server reports it
+#[derive(sqlx::FromRow)]
+struct SslStatus {
+    ssl: bool,
+    version: Option<String>,
+    cipher: Option<String>,
+}
+
+/// Logs whether the pool's connections to `url` negotiated TLS, with the protocol version
+/// and cipher the server reports.
+pub async fn check_database(pool: &PgPool, name: &str, url: &str, config: &TlsCheckConfig) -> Result<()> {
+    if !config.enabled {
+        return Ok(());
+    }
+    let host = PgConnectOptions::from_str(url)?.get_host().to_string();
+    let status = sqlx::query_as::<_, SslStatus>(
+        "SELECT ssl, version, cipher FROM pg_stat_ssl WHERE pid = pg_backend_pid()",
+    )
+    .fetch_one(pool)
+    .await?;
+    let tls = status.ssl.then(|| {
+        format!(
+            "{}, {}",
+            status.version.as_deref().unwrap_or("unknown version"),
+            status.cipher.as_deref().unwrap_or("unknown cipher")
+        )
+    });
+    report(config, name, &host, tls)
+}
+
+/// Logs whether connections to the broker use TLS. lapin doesn't expose the negotiated
+/// session, so this goes by the URI scheme.
+pub fn check_broker(connection_string: &str, config: &TlsCheckConfig) -> Result<()> {
+    if !config.enabled {
+        return Ok(());
+    }
+    let uri = AMQPUri::from_str(connection_string).map_err(|e| anyhow!("Invalid RabbitMQ URI: {}", e))?;
+    let tls = (uri.scheme == AMQPScheme::AMQPS).then(|| "amqps".to_string());
+    report(config, "RabbitMQ", &uri.authority.host, tls)
+}
+
+fn report(config: &TlsCheckConfig, name: &str, host: &str, tls: Option<String>) -> Result<()> {
+    match tls {
+        Some(details) => info!("{} connection to {} uses TLS ({})", name, host, details),
+        None if is_local(host) => info!("{} connection to {} is unencrypted (local host)", name, host),
+        None if config.require_remote_tls => {
+            anyhow::bail!("{} connection to remote host {} is not encrypted (tls_check.require_remote_tls)", name, host)
+        }
+        None => warn!("{} connection to remote host {} is NOT encrypted", name, host),
+    }
+    Ok(())
+}
+
+// Loopback addresses and Unix sockets never leave the machine
+fn is_local(host: &str) -> bool {
+    host.is_empty()
+        || host.starts_with('/')
+        || host.eq_ignore_ascii_case("localhost")
+        || host.trim_matches(|c| c == '[' || c == ']').parse::<IpAddr>().is_ok_and(|ip| ip.is_loopback())
+}
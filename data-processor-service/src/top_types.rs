@@ -0,0 +1,118 @@
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::BuildHasher;
+
+// Count-min sketch size: an estimate overshoots by at most e / width of the total
+// with probability 1 - e^-depth
+const SKETCH_WIDTH: usize = 1024;
+const SKETCH_DEPTH: usize = 4;
+
+// Bucket the volume of untracked types is reported under
+pub const OTHER_TYPES: &str = "other";
+
+// Per-type reading volume in bounded memory: a count-min sketch estimates every type's
+// count and only the `capacity` heaviest types are kept by name, so a flood of typo'd or
+// made-up types can't grow it
+pub struct TopTypes {
+    capacity: usize,
+    sketch: Vec<u64>,
+    hashers: [RandomState; SKETCH_DEPTH],
+    // Estimated count of each tracked type as of its last reading
+    top: HashMap<String, u64>,
+    total: u64,
+}
+
+impl TopTypes {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            sketch: vec![0; SKETCH_WIDTH * SKETCH_DEPTH],
+            hashers: std::array::from_fn(|_| RandomState::new()),
+            top: HashMap::new(),
+            total: 0,
+        }
+    }
+    
+    /// Counts one reading of `sensor_type`. Returns the type it displaced from the tracked
+    /// set, if its estimate overtook the lightest tracked type.
+    pub fn record(&mut self, sensor_type: &str) -> Option<String> {
+        self.total += 1;
+        let mut estimate = u64::MAX;
+        for (row, hasher) in self.hashers.iter().enumerate() {
+            let cell = row * SKETCH_WIDTH + (hasher.hash_one(sensor_type) % SKETCH_WIDTH as u64) as usize;
+            self.sketch[cell] += 1;
+            estimate = estimate.min(self.sketch[cell]);
+        }
+        
+        if let Some(count) = self.top.get_mut(sensor_type) {
+            *count = estimate;
+            return None;
+        }
+        if self.top.len() < self.capacity {
+            self.top.insert(sensor_type.to_string(), estimate);
+            return None;
+        }
+        let (lightest, lightest_count) = self
+            .top
+            .iter()
+            .min_by_key(|(_, count)| **count)
+            .map(|(name, count)| (name.clone(), *count))?;
+        if estimate <= lightest_count {
+            return None;
+        }
+        self.top.remove(&lightest);
+        self.top.insert(sensor_type.to_string(), estimate);
+        Some(lightest)
+    }
+    
+    pub fn is_tracked(&self, sensor_type: &str) -> bool {
+        self.top.contains_key(sensor_type)
+    }
+    
+    /// Approximate readings per tracked type, with everything else under `other`.
+    pub fn snapshot(&self) -> BTreeMap<String, u64> {
+        let mut counts: BTreeMap<String, u64> = self.top.iter().map(|(name, count)| (name.clone(), *count)).collect();
+        let other = self.total.saturating_sub(self.top.values().sum());
+        if other > 0 {
+            *counts.entry(OTHER_TYPES.to_string()).or_default() += other;
+        }
+        counts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn keeps_the_heaviest_types_and_buckets_the_rest() {
+        let mut top = TopTypes::new(2);
+        for _ in 0..100 {
+            top.record("temperature");
+        }
+        for _ in 0..50 {
+            top.record("humidity");
+        }
+        // Two hundred one-off types stay out of the tracked set
+        for i in 0..200 {
+            assert_eq!(top.record(&format!("typo-{}", i)), None);
+        }
+        
+        assert!(top.is_tracked("temperature") && top.is_tracked("humidity"));
+        let snapshot = top.snapshot();
+        assert_eq!(snapshot.len(), 3);
+        assert_eq!(snapshot.values().sum::<u64>(), 350);
+        // Count-min estimates never undercount
+        assert!(snapshot["temperature"] >= 100 && snapshot["humidity"] >= 50);
+    }
+    
+    #[test]
+    fn a_type_that_overtakes_the_lightest_displaces_it() {
+        let mut top = TopTypes::new(1);
+        top.record("temperature");
+        let displaced = (0..3).filter_map(|_| top.record("motion")).collect::<Vec<_>>();
+        
+        assert_eq!(displaced, vec!["temperature".to_string()]);
+        assert!(top.is_tracked("motion") && !top.is_tracked("temperature"));
+    }
+}
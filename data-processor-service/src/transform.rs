@@ -0,0 +1,214 @@
+use anyhow::{Context, Result};
+use crate::config::{NonFinitePolicy, ProcessingConfig, SensorNameNormalization, SensorNameRewrite, TransformConfig};
+use crate::models::SensorData;
+use regex::Regex;
+use serde_json::{Map, Value};
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+/// What `rewrite` did to a reading.
+#[derive(Debug, Default, PartialEq)]
+pub struct Rewrite {
+    pub fields_stripped: usize,
+    pub non_finite_nulled: usize,
+    // The payload holds non-finite numbers under `non_finite_policy: reject`
+    pub rejected: bool,
+}
+
+/// Runs the stages that rewrite a reading in place, in pipeline order: name normalization,
+/// transforms, field stripping and the non-finite policy. Ingest and `reprocess-range`
+/// both go through here so stored readings are corrected the way new ones are written.
+pub fn rewrite(processing: &ProcessingConfig, normalizer: &SensorNameNormalizer, data: &mut SensorData) -> Rewrite {
+    // Before anything keyed by the name: dedup keys, location lookup, the registry
+    normalizer.apply(&mut data.name);
+    apply(&processing.transforms, data);
+    // Before anything reads the payload, so prohibited fields (e.g. coordinates) can't
+    // reach a column either
+    let (allowlist, denylist) = (&processing.payload_field_allowlist, &processing.payload_field_denylist);
+    let fields_stripped = data
+        .payload
+        .fields_mut()
+        .map_or(0, |fields| strip_fields(allowlist, denylist, &data.r#type, fields));
+    
+    // Guard against NaN/Infinity values that would break numeric aggregation later
+    let mut rewrite = Rewrite { fields_stripped, ..Rewrite::default() };
+    match processing.non_finite_policy {
+        NonFinitePolicy::Reject => rewrite.rejected = data.payload.count_non_finite() > 0,
+        NonFinitePolicy::Null => rewrite.non_finite_nulled = data.payload.null_non_finite(),
+        NonFinitePolicy::Store => {}
+    }
+    rewrite
+}
+
+/// Applies each transform in order, returning how many changed the reading.
+pub fn apply(transforms: &[TransformConfig], data: &mut SensorData) -> usize {
+    transforms
+        .iter()
+        .filter(|transform| apply_one(transform, data))
+        .count()
+}
+
+/// Removes payload fields missing from the type's allowlist, then those on its denylist or
+/// the `*` denylist, returning how many were removed.
+pub fn strip_fields(
+    allowlist: &HashMap<String, Vec<String>>,
+    denylist: &HashMap<String, Vec<String>>,
+    sensor_type: &str,
+    fields: &mut Map<String, Value>,
+) -> usize {
+    let before = fields.len();
+    if let Some(allowed) = allowlist.get(sensor_type) {
+        fields.retain(|field, _| allowed.contains(field));
+    }
+    for denied in [denylist.get(sensor_type), denylist.get("*")].into_iter().flatten().flatten() {
+        fields.remove(denied);
+    }
+    before - fields.len()
+}
+
+fn apply_one(transform: &TransformConfig, data: &mut SensorData) -> bool {
+    let (sensor_type, payload) = (&data.r#type, &mut data.payload);
+    match transform {
+        TransformConfig::RenameField { sensor_type: only, from, to } => {
+            if only.as_ref().is_some_and(|t| t != sensor_type) {
+                return false;
+            }
+            let Some(fields) = payload.fields_mut() else {
+                return false;
+            };
+            match fields.remove(from) {
+                Some(value) => {
+                    fields.insert(to.clone(), value);
+                    true
+                }
+                None => false,
+            }
+        }
+        TransformConfig::RemoveField { sensor_type: only, field } => {
+            if only.as_ref().is_some_and(|t| t != sensor_type) {
+                return false;
+            }
+            payload
+                .fields_mut()
+                .is_some_and(|fields| fields.remove(field).is_some())
+        }
+    }
+}
+
+// Canonical form of sensor names, so `Meter-01` and `meter-01` end up as one sensor
+pub struct SensorNameNormalizer {
+    normalization: SensorNameNormalization,
+    rewrites: Vec<(Regex, String)>,
+}
+
+impl SensorNameNormalizer {
+    pub fn new(normalization: SensorNameNormalization, rewrites: &[SensorNameRewrite]) -> Result<Self> {
+        let rewrites = rewrites
+            .iter()
+            .map(|rewrite| {
+                let pattern = Regex::new(&rewrite.pattern)
+                    .with_context(|| format!("Invalid sensor name rewrite pattern '{}'", rewrite.pattern))?;
+                Ok((pattern, rewrite.replacement.clone()))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { normalization, rewrites })
+    }
+    
+    /// Normalizes `name` in place, returning whether it changed. A name that would end up
+    /// empty is left as it was.
+    pub fn apply(&self, name: &mut String) -> bool {
+        let mut normalized = match self.normalization {
+            SensorNameNormalization::None => Cow::Borrowed(name.as_str()),
+            SensorNameNormalization::Lowercase => Cow::Owned(name.to_lowercase()),
+            SensorNameNormalization::Uppercase => Cow::Owned(name.to_uppercase()),
+            SensorNameNormalization::Trim => Cow::Borrowed(name.trim()),
+        };
+        for (pattern, replacement) in &self.rewrites {
+            if let Cow::Owned(rewritten) = pattern.replace(&normalized, replacement.as_str()) {
+                normalized = Cow::Owned(rewritten);
+            }
+        }
+        if normalized.is_empty() || normalized == name.as_str() {
+            return false;
+        }
+        let normalized = normalized.into_owned();
+        *name = normalized;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use serde_json::json;
+    
+    #[test]
+    fn rewrite_runs_the_ingest_stages_in_order() {
+        let mut processing = Config::default().processing;
+        processing.sensor_name_normalization = SensorNameNormalization::Lowercase;
+        processing.transforms = vec![TransformConfig::RenameField {
+            sensor_type: None,
+            from: "temp".to_string(),
+            to: "temperature".to_string(),
+        }];
+        // Stripping sees the renamed field
+        processing.payload_field_denylist = HashMap::from([("*".to_string(), vec!["gps".to_string()])]);
+        let normalizer = SensorNameNormalizer::new(processing.sensor_name_normalization, &[]).unwrap();
+        let mut data = SensorData {
+            r#type: "temperature".to_string(),
+            name: "Meter-01".to_string(),
+            payload: json!({"temp": 21.5, "gps": "52.1,4.3"}).into(),
+            timestamp: None,
+        };
+        
+        let rewrite = rewrite(&processing, &normalizer, &mut data);
+        
+        assert_eq!(rewrite, Rewrite { fields_stripped: 1, ..Rewrite::default() });
+        assert_eq!(data.name, "meter-01");
+        assert_eq!(data.payload.as_value(), &json!({"temperature": 21.5}));
+    }
+    
+    #[test]
+    fn normalizes_then_rewrites_names() {
+        let rewrites = [SensorNameRewrite { pattern: "^meter_(\\d+)$".to_string(), replacement: "meter-$1".to_string() }];
+        let normalizer = SensorNameNormalizer::new(SensorNameNormalization::Lowercase, &rewrites).unwrap();
+        let mut name = "METER_01".to_string();
+        
+        assert!(normalizer.apply(&mut name));
+        assert_eq!(name, "meter-01");
+        assert!(!normalizer.apply(&mut name));
+    }
+    
+    #[test]
+    fn keeps_a_name_that_would_end_up_empty() {
+        let normalizer = SensorNameNormalizer::new(SensorNameNormalization::Trim, &[]).unwrap();
+        let mut name = "   ".to_string();
+        assert!(!normalizer.apply(&mut name));
+        assert_eq!(name, "   ");
+        
+        let invalid = [SensorNameRewrite { pattern: "(".to_string(), replacement: String::new() }];
+        assert!(SensorNameNormalizer::new(SensorNameNormalization::None, &invalid).is_err());
+    }
+    
+    #[test]
+    fn strips_by_allowlist_then_denylists() {
+        let allowlist = HashMap::from([("gps".to_string(), vec!["lat".to_string(), "lon".to_string(), "owner".to_string()])]);
+        let denylist = HashMap::from([
+            ("gps".to_string(), vec!["owner".to_string()]),
+            ("*".to_string(), vec!["serial".to_string()]),
+        ]);
+        let strip = |sensor_type: &str, payload: Value| {
+            let mut payload = payload;
+            let stripped = strip_fields(&allowlist, &denylist, sensor_type, payload.as_object_mut().unwrap());
+            (stripped, payload)
+        };
+        
+        assert_eq!(
+            strip("gps", json!({"lat": 1, "lon": 2, "owner": "x", "speed": 3})),
+            (2, json!({"lat": 1, "lon": 2}))
+        );
+        // Types without an allowlist keep everything but the `*` denylist
+        assert_eq!(strip("energy", json!({"kwh": 1, "serial": "s"})), (1, json!({"kwh": 1})));
+    }
+}
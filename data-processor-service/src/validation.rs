@@ -0,0 +1,107 @@
+use serde_json::Value;
+
+// Only JSON numbers count: strings such as "NaN" or "inf" are ordinary text payload
+// values (a sensor name, a status) and are stored as sent.
+fn is_non_finite(value: &Value) -> bool {
+    match value {
+        Value::Number(n) => n.as_f64().is_some_and(|f| !f.is_finite()),
+        _ => false,
+    }
+}
+
+/// Counts the non-finite numeric values anywhere in the payload.
+pub fn count_non_finite(payload: &Value) -> usize {
+    match payload {
+        Value::Array(items) => items.iter().map(count_non_finite).sum(),
+        Value::Object(map) => map.values().map(count_non_finite).sum(),
+        value => usize::from(is_non_finite(value)),
+    }
+}
+
+/// Replaces every non-finite numeric value with `null`, returning how many were replaced.
+pub fn null_non_finite(payload: &mut Value) -> usize {
+    match payload {
+        Value::Array(items) => items.iter_mut().map(null_non_finite).sum(),
+        Value::Object(map) => map.values_mut().map(null_non_finite).sum(),
+        value if is_non_finite(value) => {
+            *value = Value::Null;
+            1
+        }
+        _ => 0,
+    }
+}
+
+/// Scans raw JSON without parsing it and returns an error when nesting goes deeper than
+/// `max_depth` or the number of elements (containers plus separated items) exceeds
+/// `max_elements`, so pathological payloads never reach `serde_json`.
+pub fn check_json_complexity(bytes: &[u8], max_depth: usize, max_elements: usize) -> Result<(), String> {
+    let mut depth = 0usize;
+    let mut elements = 0usize;
+    let mut in_string = false;
+    let mut escaped = false;
+    
+    for &byte in bytes {
+        if in_string {
+            match byte {
+                _ if escaped => escaped = false,
+                b'\\' => escaped = true,
+                b'"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match byte {
+            b'"' => in_string = true,
+            b'{' | b'[' => {
+                depth += 1;
+                elements += 1;
+                if depth > max_depth {
+                    return Err(format!("JSON nesting exceeds maximum depth of {}", max_depth));
+                }
+            }
+            b'}' | b']' => depth = depth.saturating_sub(1),
+            b',' => elements += 1,
+            _ => {}
+        }
+        if elements > max_elements {
+            return Err(format!("JSON exceeds maximum of {} elements", max_elements));
+        }
+    }
+    
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn leaves_non_finite_looking_strings_alone() {
+        let mut payload = serde_json::json!({
+            "status": "NaN",
+            "readings": ["inf", "-Infinity", 1.5],
+            "label": " infinity ",
+        });
+        let original = payload.clone();
+        
+        assert_eq!(count_non_finite(&payload), 0);
+        assert_eq!(null_non_finite(&mut payload), 0);
+        assert_eq!(payload, original);
+    }
+    
+    #[test]
+    fn limits_nesting_depth() {
+        assert!(check_json_complexity(br#"[{"a": [1]}]"#, 3, 100).is_ok());
+        assert!(check_json_complexity(br#"[{"a": [[1]]}]"#, 3, 100).is_err());
+        // Brackets inside strings are not structure
+        assert!(check_json_complexity(br#"[{"a": "[[[[\"]]]]"}]"#, 2, 100).is_ok());
+    }
+    
+    #[test]
+    fn limits_element_count() {
+        // One array plus three items separated by two commas
+        assert!(check_json_complexity(b"[1, 2, 3]", 10, 3).is_ok());
+        assert!(check_json_complexity(b"[1, 2, 3, 4]", 10, 3).is_err());
+        assert!(check_json_complexity(br#"["a,b,c,d,e"]"#, 10, 1).is_ok());
+    }
+}
@@ -0,0 +1,269 @@
+use anyhow::{Context, Result};
+use crate::models::SensorReadingInput;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use tokio::fs::{self, File, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+use tracing::warn;
+
+// One JSON line of the log; an entry is pending from its `append` until its `commit`
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum WalRecord {
+    Append { id: u64, readings: Vec<SensorReadingInput> },
+    Commit { id: u64 },
+}
+
+// Readings of one message that were acked but not confirmed written
+#[derive(Debug, Clone)]
+pub struct WalEntry {
+    pub id: u64,
+    pub readings: Vec<SensorReadingInput>,
+}
+
+// The log is rewritten with just the pending entries once it is at least this large and
+// more than twice their size, so a steady stream of commits can't grow it without bound
+const COMPACT_MIN_BYTES: u64 = 16 * 1024 * 1024;
+
+struct WalState {
+    path: PathBuf,
+    file: File,
+    // Bytes in the file, committed records included
+    len: u64,
+    next_id: u64,
+    // Serialized append line of each pending entry, kept to rewrite the log with
+    pending: BTreeMap<u64, Vec<u8>>,
+    pending_bytes: u64,
+}
+
+impl WalState {
+    fn should_compact(&self, min_bytes: u64) -> bool {
+        self.len >= min_bytes && self.len > 2 * self.pending_bytes
+    }
+    
+    async fn compact(&mut self) -> Result<()> {
+        self.file = compact(&self.path, self.pending.values()).await?;
+        self.len = self.pending_bytes;
+        Ok(())
+    }
+}
+
+// Append-only local file holding buffered readings whose message was already acked,
+// so a crash before they reach the database does not lose them
+pub struct WriteAheadLog {
+    state: Mutex<WalState>,
+    compact_min_bytes: u64,
+}
+
+impl WriteAheadLog {
+    /// Opens (or creates) the log at `path` and returns the entries still pending from a
+    /// previous run. The file is compacted to just those entries.
+    pub async fn open(path: impl AsRef<Path>) -> Result<(Self, Vec<WalEntry>)> {
+        Self::open_with_compaction(path, COMPACT_MIN_BYTES).await
+    }
+    
+    async fn open_with_compaction(path: impl AsRef<Path>, compact_min_bytes: u64) -> Result<(Self, Vec<WalEntry>)> {
+        let path = path.as_ref();
+        let mut entries = BTreeMap::new();
+        let mut next_id = 0;
+        match fs::read_to_string(path).await {
+            Ok(contents) => {
+                for (number, line) in contents.lines().enumerate() {
+                    if line.trim().is_empty() {
+                        continue;
+                    }
+                    // A crash mid-append leaves a torn last line; its message was never acked
+                    match serde_json::from_str::<WalRecord>(line) {
+                        Ok(WalRecord::Append { id, readings }) => {
+                            next_id = next_id.max(id + 1);
+                            entries.insert(id, readings);
+                        }
+                        Ok(WalRecord::Commit { id }) => {
+                            entries.remove(&id);
+                        }
+                        Err(e) => warn!("Skipping unreadable line {} of {}: {}", number + 1, path.display(), e),
+                    }
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        }
+        
+        let mut pending = BTreeMap::new();
+        let mut pending_bytes = 0;
+        for (id, readings) in &entries {
+            let line = append_line(*id, readings)?;
+            pending_bytes += line.len() as u64;
+            pending.insert(*id, line);
+        }
+        let file = compact(path, pending.values()).await?;
+        let state = WalState {
+            path: path.to_path_buf(),
+            file,
+            len: pending_bytes,
+            next_id,
+            pending,
+            pending_bytes,
+        };
+        let entries = entries
+            .into_iter()
+            .map(|(id, readings)| WalEntry { id, readings })
+            .collect();
+        Ok((Self { state: Mutex::new(state), compact_min_bytes }, entries))
+    }
+    
+    /// Durably records `readings` and returns their entry id; the message may be acked
+    /// once this returns.
+    pub async fn append(&self, readings: &[SensorReadingInput]) -> Result<u64> {
+        let mut state = self.state.lock().await;
+        let id = state.next_id;
+        let line = append_line(id, readings)?;
+        state.file.write_all(&line).await?;
+        state.file.sync_data().await?;
+        state.next_id += 1;
+        state.len += line.len() as u64;
+        state.pending_bytes += line.len() as u64;
+        state.pending.insert(id, line);
+        Ok(id)
+    }
+    
+    /// Marks an entry as written to the database. Commits are not synced: losing one only
+    /// replays readings that are already stored.
+    pub async fn commit(&self, id: u64) -> Result<()> {
+        let mut state = self.state.lock().await;
+        let Some(line) = state.pending.remove(&id) else {
+            return Ok(());
+        };
+        state.pending_bytes -= line.len() as u64;
+        if state.pending.is_empty() {
+            // Nothing left to replay, so start the file over instead of letting it grow
+            state.file.set_len(0).await?;
+            state.len = 0;
+            return Ok(());
+        }
+        let mut line = serde_json::to_vec(&WalRecord::Commit { id })?;
+        line.push(b'\n');
+        state.file.write_all(&line).await?;
+        state.file.flush().await?;
+        state.len += line.len() as u64;
+        if state.should_compact(self.compact_min_bytes) {
+            state.compact().await?;
+        }
+        Ok(())
+    }
+    
+    pub async fn pending(&self) -> usize {
+        self.state.lock().await.pending.len()
+    }
+}
+
+fn append_line(id: u64, readings: &[SensorReadingInput]) -> Result<Vec<u8>> {
+    let mut line = serde_json::to_vec(&WalRecord::Append { id, readings: readings.to_vec() })?;
+    line.push(b'\n');
+    Ok(line)
+}
+
+// Rewrites the log with only the given append lines and opens it for appending
+async fn compact<'a>(path: &Path, lines: impl Iterator<Item = &'a Vec<u8>>) -> Result<File> {
+    let mut temp_path = PathBuf::from(path);
+    temp_path.set_extension("compact");
+    let mut temp = File::create(&temp_path).await?;
+    for line in lines {
+        temp.write_all(line).await?;
+    }
+    temp.sync_all().await?;
+    fs::rename(&temp_path, path)
+        .await
+        .with_context(|| format!("Failed to replace {}", path.display()))?;
+    
+    Ok(OpenOptions::new().append(true).open(path).await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use serde_json::json;
+    
+    fn reading(name: &str) -> SensorReadingInput {
+        SensorReadingInput {
+            sensor_type: "temperature".to_string(),
+            sensor_name: name.to_string(),
+            payload: json!({ "value": 21.5 }),
+            timestamp: Utc::now(),
+            source_id: None,
+            location: None,
+        }
+    }
+    
+    fn temp_path() -> PathBuf {
+        std::env::temp_dir().join(format!("wal-test-{}.wal", uuid::Uuid::new_v4()))
+    }
+    
+    #[test]
+    fn replays_uncommitted_entries_after_a_crash() {
+        tokio_test::block_on(async {
+            let path = temp_path();
+            let (wal, entries) = WriteAheadLog::open(&path).await.unwrap();
+            assert!(entries.is_empty());
+            let first = wal.append(&[reading("a")]).await.unwrap();
+            let second = wal.append(&[reading("b"), reading("c")]).await.unwrap();
+            wal.append(&[reading("d")]).await.unwrap();
+            wal.commit(first).await.unwrap();
+            // Crash: drop without committing the rest, then a torn half-written line
+            drop(wal);
+            let mut file = OpenOptions::new().append(true).open(&path).await.unwrap();
+            file.write_all(b"{\"op\":\"append\",\"id\":9").await.unwrap();
+            drop(file);
+            
+            let (wal, entries) = WriteAheadLog::open(&path).await.unwrap();
+            let names: Vec<Vec<&str>> = entries
+                .iter()
+                .map(|entry| entry.readings.iter().map(|r| r.sensor_name.as_str()).collect())
+                .collect();
+            assert_eq!(names, vec![vec!["b", "c"], vec!["d"]]);
+            assert_eq!(entries[0].id, second);
+            assert_eq!(wal.pending().await, 2);
+            // Ids keep increasing across restarts
+            assert!(wal.append(&[reading("e")]).await.unwrap() > entries[1].id);
+            
+            fs::remove_file(&path).await.unwrap();
+        });
+    }
+    
+    #[test]
+    fn truncates_once_everything_is_committed() {
+        tokio_test::block_on(async {
+            let path = temp_path();
+            let (wal, _) = WriteAheadLog::open(&path).await.unwrap();
+            let id = wal.append(&[reading("a")]).await.unwrap();
+            wal.commit(id).await.unwrap();
+            
+            assert_eq!(fs::metadata(&path).await.unwrap().len(), 0);
+            fs::remove_file(&path).await.unwrap();
+        });
+    }
+    
+    #[test]
+    fn compacts_while_an_entry_stays_pending() {
+        tokio_test::block_on(async {
+            let path = temp_path();
+            let (wal, _) = WriteAheadLog::open_with_compaction(&path, 4096).await.unwrap();
+            // One entry never commits, so the log never empties on its own
+            let stuck = wal.append(&[reading("stuck")]).await.unwrap();
+            for _ in 0..200 {
+                let id = wal.append(&[reading("flowing")]).await.unwrap();
+                wal.commit(id).await.unwrap();
+            }
+            
+            assert!(fs::metadata(&path).await.unwrap().len() < 4096 * 2);
+            drop(wal);
+            let (_, entries) = WriteAheadLog::open(&path).await.unwrap();
+            assert_eq!(entries.len(), 1);
+            assert_eq!(entries[0].id, stuck);
+            fs::remove_file(&path).await.unwrap();
+        });
+    }
+}
@@ -0,0 +1,306 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use crate::config::{RetryJitter, WebhookEvent, WebhooksConfig};
+use crate::retry;
+use serde::Serialize;
+use serde_json::Value;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tracing::{debug, error, warn};
+
+// Body POSTed to the webhook
+#[derive(Debug, Clone, Serialize)]
+pub struct WebhookPayload {
+    pub event: WebhookEvent,
+    pub at: DateTime<Utc>,
+    pub details: Value,
+}
+
+// Details of a `message_result` event. `duplicate` is set when the message id was already
+// processed or every reading's dedup key was already claimed; `duplicate_keys` lists the
+// keys found taken (the message id, or the Redis keys of duplicate readings)
+#[derive(Debug, Clone, Serialize)]
+pub struct MessageResult {
+    pub message_id: Option<String>,
+    pub routing_key: String,
+    pub readings: usize,
+    pub stored: usize,
+    pub duplicate: bool,
+    pub duplicate_keys: Vec<String>,
+}
+
+// Queues selected events for a background task that POSTs them, so a slow or failing
+// endpoint never holds up message processing
+pub struct WebhookNotifier {
+    events: Vec<WebhookEvent>,
+    // Taken on shutdown, which ends the worker once the queue is drained
+    sender: Mutex<Option<mpsc::Sender<WebhookPayload>>>,
+    worker: Mutex<Option<JoinHandle<()>>>,
+    first_message_sent: AtomicBool,
+}
+
+impl WebhookNotifier {
+    pub fn new(config: &WebhooksConfig) -> Result<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_millis(config.timeout_ms))
+            .build()?;
+        let (sender, receiver) = mpsc::channel(config.queue_capacity.max(1));
+        let worker = tokio::spawn(deliver(client, config.clone(), receiver));
+        
+        Ok(Self {
+            events: config.events.clone(),
+            sender: Mutex::new(Some(sender)),
+            worker: Mutex::new(Some(worker)),
+            first_message_sent: AtomicBool::new(false),
+        })
+    }
+    
+    /// Queues `event` if it is enabled; drops it with a warning when the queue is full.
+    pub fn notify(&self, event: WebhookEvent, details: Value) {
+        if !self.events.contains(&event) {
+            return;
+        }
+        let payload = WebhookPayload { event, at: Utc::now(), details };
+        let sender = self.sender.lock().unwrap();
+        let Some(sender) = sender.as_ref() else {
+            return;
+        };
+        if let Err(e) = sender.try_send(payload) {
+            warn!("Dropping {:?} webhook event: {}", event, e);
+        }
+    }
+    
+    pub fn message_result(&self, result: MessageResult) {
+        if !self.events.contains(&WebhookEvent::MessageResult) {
+            return;
+        }
+        match serde_json::to_value(result) {
+            Ok(details) => self.notify(WebhookEvent::MessageResult, details),
+            Err(e) => warn!("Failed to serialize message result: {}", e),
+        }
+    }
+    
+    // Fires `first_message` for the first call only
+    pub fn first_message(&self, details: Value) {
+        if !self.first_message_sent.swap(true, Ordering::Relaxed) {
+            self.notify(WebhookEvent::FirstMessage, details);
+        }
+    }
+    
+    /// Sends `shutdown` and waits up to `grace` for queued events to be delivered.
+    pub async fn shutdown(&self, details: Value, grace: Duration) {
+        self.notify(WebhookEvent::Shutdown, details);
+        self.sender.lock().unwrap().take();
+        let Some(mut worker) = self.worker.lock().unwrap().take() else {
+            return;
+        };
+        if tokio::time::timeout(grace, &mut worker).await.is_err() {
+            warn!("Webhook events still queued after {:?}, dropping them", grace);
+            worker.abort();
+        }
+    }
+}
+
+async fn deliver(client: reqwest::Client, config: WebhooksConfig, mut receiver: mpsc::Receiver<WebhookPayload>) {
+    while let Some(payload) = receiver.recv().await {
+        let mut delay = Duration::from_millis(config.retry_delay_ms);
+        for attempt in 1..=config.max_attempts.max(1) {
+            match post(&client, &config, &payload).await {
+                Ok(()) => {
+                    debug!("Delivered {:?} webhook event", payload.event);
+                    break;
+                }
+                Err(e) if attempt < config.max_attempts => {
+                    warn!("Webhook delivery failed (attempt {}/{}): {}", attempt, config.max_attempts, e);
+                    tokio::time::sleep(retry::jittered_delay(delay, RetryJitter::Equal)).await;
+                    delay *= 2;
+                }
+                Err(e) => error!("Giving up on {:?} webhook event: {}", payload.event, e),
+            }
+        }
+    }
+}
+
+async fn post(client: &reqwest::Client, config: &WebhooksConfig, payload: &WebhookPayload) -> Result<()> {
+    let mut request = client.post(&config.url).json(payload);
+    if let Some(auth_header) = &config.auth_header {
+        request = request.header(reqwest::header::AUTHORIZATION, auth_header);
+    }
+    request.send().await?.error_for_status()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Config, DedupKeyField, RedisDedupConfig};
+    use crate::models::SensorData;
+    use crate::processor::Pipeline;
+    use crate::rabbitmq::MessageContext;
+    use axum::extract::State;
+    use axum::http::StatusCode;
+    use axum::Json;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::Arc;
+    
+    // Webhook endpoint that fails the first `failures` requests and records what it accepts
+    #[derive(Clone, Default)]
+    struct MockEndpoint {
+        failures: Arc<AtomicUsize>,
+        requests: Arc<AtomicUsize>,
+        delivered: Arc<Mutex<Vec<Value>>>,
+    }
+    
+    impl MockEndpoint {
+        async fn start(failures: usize) -> (Self, String) {
+            let endpoint = Self::default();
+            endpoint.failures.store(failures, Ordering::Relaxed);
+            let app = axum::Router::new()
+                .route("/hook", axum::routing::post(receive))
+                .with_state(endpoint.clone());
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let url = format!("http://{}/hook", listener.local_addr().unwrap());
+            tokio::spawn(async move { axum::serve(listener, app).await });
+            (endpoint, url)
+        }
+        
+        // Waits for `count` accepted deliveries
+        async fn delivered(&self, count: usize) -> Vec<Value> {
+            for _ in 0..500 {
+                if self.delivered.lock().unwrap().len() >= count {
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            self.delivered.lock().unwrap().clone()
+        }
+    }
+    
+    async fn receive(State(endpoint): State<MockEndpoint>, Json(body): Json<Value>) -> StatusCode {
+        endpoint.requests.fetch_add(1, Ordering::Relaxed);
+        let failing = endpoint.failures.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |left| left.checked_sub(1));
+        if failing.is_ok() {
+            return StatusCode::INTERNAL_SERVER_ERROR;
+        }
+        endpoint.delivered.lock().unwrap().push(body);
+        StatusCode::OK
+    }
+    
+    fn webhooks(url: String, events: Vec<WebhookEvent>) -> WebhooksConfig {
+        WebhooksConfig {
+            url,
+            events,
+            auth_header: None,
+            timeout_ms: 5000,
+            max_attempts: 3,
+            retry_delay_ms: 10,
+            queue_capacity: 100,
+        }
+    }
+    
+    fn reading(sensor_type: &str) -> SensorData {
+        SensorData {
+            r#type: sensor_type.to_string(),
+            name: format!("{}-1", sensor_type),
+            payload: serde_json::json!({ "value": 1 }).into(),
+            timestamp: None,
+        }
+    }
+    
+    #[tokio::test]
+    async fn posts_one_webhook_per_flushed_batch_and_retries_failed_deliveries() {
+        let (endpoint, url) = MockEndpoint::start(1).await;
+        let config = Config {
+            webhooks: Some(webhooks(url, vec![WebhookEvent::BatchWritten])),
+            ..Config::default()
+        };
+        let (pipeline, _) = Pipeline::capturing(config).await;
+        
+        let messages = [
+            vec![reading("energy"), reading("energy")],
+            vec![reading("energy"), reading("air_quality"), reading("air_quality")],
+        ];
+        for message in messages {
+            pipeline.process_sensor_data(message, MessageContext::default()).await.unwrap();
+        }
+        
+        let delivered = endpoint.delivered(2).await;
+        assert_eq!(delivered.len(), 2);
+        assert!(delivered.iter().all(|payload| payload["event"] == "batch_written"));
+        assert_eq!((&delivered[0]["details"]["readings"], &delivered[0]["details"]["batches"]), (&2.into(), &1.into()));
+        assert_eq!((&delivered[1]["details"]["readings"], &delivered[1]["details"]["batches"]), (&3.into(), &2.into()));
+        // The first delivery was refused once and retried
+        assert_eq!(endpoint.requests.load(Ordering::Relaxed), 3);
+    }
+    
+    // Needs a Redis server; skipped unless TEST_REDIS_URL is set
+    fn test_redis_url() -> Option<String> {
+        std::env::var("TEST_REDIS_URL").ok()
+    }
+    
+    #[tokio::test]
+    async fn reports_a_duplicate_batch_with_its_dedup_keys() {
+        let Some(url) = test_redis_url() else {
+            return;
+        };
+        let (endpoint, webhook_url) = MockEndpoint::start(0).await;
+        let prefix = format!("dedup-test-{}:", uuid::Uuid::new_v4());
+        let mut config = Config {
+            webhooks: Some(webhooks(webhook_url, vec![WebhookEvent::MessageResult])),
+            ..Config::default()
+        };
+        config.processing.dedup_key = vec![DedupKeyField::SensorName, DedupKeyField::Timestamp];
+        config.processing.redis_dedup = Some(RedisDedupConfig {
+            url,
+            ttl_seconds: 60,
+            pending_ttl_seconds: 60,
+            key_prefix: prefix.clone(),
+        });
+        let (pipeline, capture) = Pipeline::capturing(config).await;
+        let timestamp = DateTime::parse_from_rfc3339("2024-01-01T00:00:05Z").unwrap().with_timezone(&Utc);
+        let batch = || vec![SensorData { timestamp: Some(timestamp), ..reading("energy") }];
+        
+        for message_id in ["first", "redelivered"] {
+            let context = MessageContext { message_id: Some(message_id.to_string()), ..MessageContext::default() };
+            pipeline.process_sensor_data(batch(), context).await.unwrap();
+        }
+        
+        assert_eq!(capture.batches.lock().unwrap().len(), 1);
+        let delivered = endpoint.delivered(2).await;
+        let details: Vec<&Value> = delivered.iter().map(|payload| &payload["details"]).collect();
+        assert_eq!(delivered[1]["event"], "message_result");
+        assert_eq!((&details[0]["duplicate"], &details[0]["stored"]), (&false.into(), &1.into()));
+        assert_eq!(details[1]["message_id"], "redelivered");
+        assert_eq!((&details[1]["duplicate"], &details[1]["stored"]), (&true.into(), &0.into()));
+        assert_eq!(details[1]["duplicate_keys"], serde_json::json!([format!("{}energy-1|1704067205000000", prefix)]));
+    }
+    
+    #[tokio::test]
+    async fn message_results_are_only_sent_when_enabled() {
+        let (endpoint, url) = MockEndpoint::start(0).await;
+        let result = MessageResult {
+            message_id: Some("m-1".to_string()),
+            routing_key: "sensor.energy".to_string(),
+            readings: 1,
+            stored: 0,
+            duplicate: true,
+            duplicate_keys: vec!["m-1".to_string()],
+        };
+        
+        WebhookNotifier::new(&webhooks(url.clone(), vec![WebhookEvent::BatchWritten]))
+            .unwrap()
+            .message_result(result.clone());
+        let notifier = WebhookNotifier::new(&webhooks(url, vec![WebhookEvent::MessageResult])).unwrap();
+        notifier.message_result(result);
+        notifier.shutdown(Value::Null, Duration::from_secs(5)).await;
+        
+        let delivered = endpoint.delivered(1).await;
+        assert_eq!(delivered.len(), 1);
+        assert_eq!(delivered[0]["event"], "message_result");
+        assert_eq!(delivered[0]["details"]["duplicate"], true);
+        assert_eq!(delivered[0]["details"]["duplicate_keys"], serde_json::json!(["m-1"]));
+    }
+}
@@ -0,0 +1,144 @@
+use anyhow::Result;
+use crate::codec::PayloadCodec;
+use crate::models::SensorData;
+use crate::rabbitmq::MessageContext;
+use std::future::Future;
+use std::sync::Arc;
+use tokio::sync::{mpsc, oneshot, Mutex};
+
+// A delivery body its codec could not decode, so the consumer rejects it the way it does
+// undecodable deliveries it decodes itself rather than as a processing failure
+#[derive(Debug)]
+pub struct DecodeError(pub anyhow::Error);
+
+impl std::fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+struct Job {
+    codec: Arc<dyn PayloadCodec>,
+    data: Vec<u8>,
+    context: MessageContext,
+    done: oneshot::Sender<Result<()>>,
+}
+
+// Decodes and handles raw deliveries on `workers` tasks pulling from one bounded queue,
+// so an idle worker always takes the next job and a slow message never holds up the rest.
+// The workers stop once every clone of the pool is dropped and the queue has drained.
+#[derive(Clone)]
+pub struct WorkerPool {
+    jobs: mpsc::Sender<Job>,
+    workers: usize,
+}
+
+impl WorkerPool {
+    pub fn spawn<F, Fut>(workers: usize, handler: F) -> Self
+    where
+        F: Fn(Vec<SensorData>, MessageContext) -> Fut + Clone + Send + 'static,
+        Fut: Future<Output = Result<()>> + Send,
+    {
+        let workers = workers.max(1);
+        let (jobs, receiver) = mpsc::channel::<Job>(workers);
+        let receiver = Arc::new(Mutex::new(receiver));
+        for _ in 0..workers {
+            let receiver = receiver.clone();
+            let handler = handler.clone();
+            tokio::spawn(async move {
+                loop {
+                    // The lock is only held while waiting for a job, not while handling it
+                    let Some(job) = receiver.lock().await.recv().await else {
+                        break;
+                    };
+                    // Releasing the lock woke the next worker onto this thread; yield so it
+                    // can run, and so an idle thread can steal this one before the decode
+                    tokio::task::yield_now().await;
+                    let result = match job.codec.decode(&job.data) {
+                        Ok(sensor_data) => handler(sensor_data, job.context).await,
+                        Err(e) => Err(DecodeError(e).into()),
+                    };
+                    let _ = job.done.send(result);
+                }
+            });
+        }
+        Self { jobs, workers }
+    }
+    
+    pub fn workers(&self) -> usize {
+        self.workers
+    }
+    
+    // Queues a delivery body, waiting for room when every worker is busy, and resolves
+    // once a worker has handled it
+    pub async fn submit(&self, codec: Arc<dyn PayloadCodec>, data: Vec<u8>, context: MessageContext) -> Result<()> {
+        let (done, result) = oneshot::channel();
+        self.jobs
+            .send(Job { codec, data, context, done })
+            .await
+            .map_err(|_| anyhow::anyhow!("Worker pool is shut down"))?;
+        result.await.map_err(|_| anyhow::anyhow!("Worker stopped before handling the message"))?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    
+    // One reading per line, named after the line; fails on an empty body
+    struct LinesCodec;
+    
+    impl PayloadCodec for LinesCodec {
+        fn content_type(&self) -> &str {
+            "text/plain"
+        }
+        
+        fn decode(&self, data: &[u8]) -> Result<Vec<SensorData>> {
+            let text = std::str::from_utf8(data)?;
+            anyhow::ensure!(!text.is_empty(), "empty body");
+            Ok(text
+                .lines()
+                .map(|line| SensorData {
+                    r#type: "text".to_string(),
+                    name: line.to_string(),
+                    payload: serde_json::json!({}).into(),
+                    timestamp: None,
+                })
+                .collect())
+        }
+        
+        fn encode(&self, _readings: &[SensorData]) -> Result<Vec<u8>> {
+            unimplemented!()
+        }
+    }
+    
+    #[test]
+    fn decodes_with_the_delivery_codec_and_flags_decode_failures() {
+        tokio_test::block_on(async {
+            let handled = Arc::new(AtomicUsize::new(0));
+            let counter = handled.clone();
+            let pool = WorkerPool::spawn(2, move |readings: Vec<SensorData>, _context| {
+                let counter = counter.clone();
+                async move {
+                    counter.fetch_add(readings.len(), Ordering::Relaxed);
+                    anyhow::ensure!(readings.iter().all(|reading| reading.name != "bad"), "handler failed");
+                    Ok(())
+                }
+            });
+            let codec: Arc<dyn PayloadCodec> = Arc::new(LinesCodec);
+            
+            pool.submit(codec.clone(), b"a\nb".to_vec(), MessageContext::default()).await.unwrap();
+            assert_eq!(handled.load(Ordering::Relaxed), 2);
+            
+            // Undecodable bodies never reach the handler and are told apart from its failures
+            let error = pool.submit(codec.clone(), Vec::new(), MessageContext::default()).await.unwrap_err();
+            assert!(error.is::<DecodeError>());
+            let error = pool.submit(codec, b"bad".to_vec(), MessageContext::default()).await.unwrap_err();
+            assert!(!error.is::<DecodeError>());
+            assert_eq!(handled.load(Ordering::Relaxed), 3);
+        });
+    }
+}
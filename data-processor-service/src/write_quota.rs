@@ -0,0 +1,100 @@
+use anyhow::Result;
+use sqlx::pool::PoolConnection;
+use sqlx::postgres::PgPoolOptions;
+use sqlx::{PgPool, Postgres};
+use std::collections::HashMap;
+
+// Splits `max_connections` into a pool of its own per reserved sensor type and the shared
+// pool everything else uses (other types' inserts, queries, checkpoints, ...), so neither a
+// flood of one type nor a burst of other work can take the connections a type reserved.
+pub struct WriteQuota {
+    reserved: HashMap<String, PgPool>,
+}
+
+impl WriteQuota {
+    // Connections left to the shared pool once the reservations are taken out
+    pub fn shared_connections(max_connections: u32, reserved: &HashMap<String, u32>) -> Result<u32> {
+        let total: u32 = reserved.values().sum();
+        if total >= max_connections {
+            anyhow::bail!(
+                "database.reserved_connections reserves {} of {} connections; at least one must stay shared",
+                total,
+                max_connections
+            );
+        }
+        Ok(max_connections - total)
+    }
+    
+    // Lazy pools, so reservations cost no connections until their type is written
+    pub fn new(options: &PgPoolOptions, url: &str, reserved: &HashMap<String, u32>) -> Result<Self> {
+        let reserved = reserved
+            .iter()
+            .filter(|(_, connections)| **connections > 0)
+            .map(|(sensor_type, connections)| {
+                let pool = options
+                    .clone()
+                    .min_connections(0)
+                    .max_connections(*connections)
+                    .connect_lazy(url)?;
+                Ok((sensor_type.clone(), pool))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { reserved })
+    }
+    
+    // Connection for one insert of `sensor_type` readings (None for a mix of types, which
+    // only the shared pool serves). A reserved type takes whichever of its own or a shared
+    // connection frees up first.
+    pub async fn acquire(&self, sensor_type: Option<&str>, shared: &PgPool) -> Result<PoolConnection<Postgres>> {
+        let Some(reserved) = sensor_type.and_then(|sensor_type| self.reserved.get(sensor_type)) else {
+            return Ok(shared.acquire().await?);
+        };
+        if let Some(connection) = reserved.try_acquire() {
+            return Ok(connection);
+        }
+        let connection = tokio::select! {
+            connection = reserved.acquire() => connection?,
+            connection = shared.acquire() => connection?,
+        };
+        Ok(connection)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    
+    // Needs a PostgreSQL server; skipped unless TEST_DATABASE_URL is set
+    fn test_database_url() -> Option<String> {
+        std::env::var("TEST_DATABASE_URL").ok()
+    }
+    
+    #[test]
+    fn keeps_at_least_one_shared_connection() {
+        let reserved = HashMap::from([("motion".to_string(), 3), ("energy".to_string(), 2)]);
+        assert_eq!(WriteQuota::shared_connections(10, &reserved).unwrap(), 5);
+        assert!(WriteQuota::shared_connections(5, &reserved).is_err());
+    }
+    
+    #[test]
+    fn reserved_type_writes_while_the_shared_pool_is_exhausted() {
+        let Some(url) = test_database_url() else {
+            return;
+        };
+        tokio_test::block_on(async {
+            let options = PgPoolOptions::new().acquire_timeout(Duration::from_secs(2));
+            let shared = options.clone().max_connections(2).connect(&url).await.unwrap();
+            let quota = WriteQuota::new(&options, &url, &HashMap::from([("motion".to_string(), 1)])).unwrap();
+            
+            // Other work and a flood of another type hold every shared connection
+            let _held = (shared.acquire().await.unwrap(), shared.acquire().await.unwrap());
+            
+            let mut connection = quota.acquire(Some("motion"), &shared).await.unwrap();
+            sqlx::query("SELECT 1").execute(&mut *connection).await.unwrap();
+            // Unreserved types and mixed batches wait for the shared pool
+            assert!(quota.acquire(Some("energy"), &shared).await.is_err());
+            assert!(quota.acquire(None, &shared).await.is_err());
+        });
+    }
+}